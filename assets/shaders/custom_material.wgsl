@@ -0,0 +1,33 @@
+// water surface: the image behind the surface, shifted by the surface normals for a cheap refraction,
+// tinted by the vertex colors of the surface update and blended towards the tint at grazing angles
+
+#import bevy_pbr::{
+    forward_io::VertexOutput,
+    mesh_view_bindings::view,
+}
+
+@group(2) @binding(0) var<uniform> color: vec4<f32>;
+// x: refraction strength
+@group(2) @binding(1) var<uniform> params: vec4<f32>;
+@group(2) @binding(2) var refraction_texture: texture_2d<f32>;
+@group(2) @binding(3) var refraction_sampler: sampler;
+
+@fragment
+fn fragment(mesh: VertexOutput) -> @location(0) vec4<f32> {
+    let normal = normalize(mesh.world_normal);
+    let screen_uv = (mesh.position.xy - view.viewport.xy) / view.viewport.zw;
+    let refracted_uv = clamp(screen_uv + normal.xz * params.x, vec2<f32>(0.0), vec2<f32>(1.0));
+    let background = textureSample(refraction_texture, refraction_sampler, refracted_uv).rgb;
+
+#ifdef VERTEX_COLORS
+    let tint = mesh.color;
+#else
+    let tint = color;
+#endif
+
+    // more of the tint (less of the background) the flatter the view onto the surface
+    let view_dir = normalize(view.world_position.xyz - mesh.world_position.xyz);
+    let fresnel = pow(1.0 - abs(dot(normal, view_dir)), 3.0);
+    let rgb = mix(background * (0.5 + 0.5 * tint.rgb), tint.rgb, fresnel);
+    return vec4<f32>(rgb, max(tint.a, fresnel));
+}
@@ -0,0 +1,79 @@
+// composite of the screen space fluid: bilateral blur of the particle depth,
+// normal reconstruction from the blurred depth and a simple glossy shading
+
+#import bevy_ui::ui_vertex_output::UiVertexOutput
+
+@group(1) @binding(0) var<uniform> color: vec4<f32>;
+// x: blur radius (texels), y: depth falloff, z: tan(fov/2), w: aspect ratio
+@group(1) @binding(1) var<uniform> params: vec4<f32>;
+@group(1) @binding(2) var depth_texture: texture_2d<f32>;
+
+const LIGHT_DIR: vec3<f32> = vec3<f32>(0.3, 0.8, 0.5);
+const SKY: vec3<f32> = vec3<f32>(0.8, 0.9, 1.0);
+
+fn load(p: vec2<i32>, size: vec2<i32>) -> vec4<f32> {
+    return textureLoad(depth_texture, clamp(p, vec2<i32>(0), size - 1), 0);
+}
+
+// blurred depth (x) and coverage (y); neighbors across a depth edge barely contribute
+fn bilateral_depth(p: vec2<i32>, size: vec2<i32>) -> vec2<f32> {
+    let center = load(p, size);
+    if center.a < 0.5 {
+        return vec2<f32>(0.0, 0.0);
+    }
+    let radius = i32(params.x);
+    let sigma = max(params.x, 1.0) * 0.5;
+    var sum = 0.0;
+    var weights = 0.0;
+    for (var y = -radius; y <= radius; y++) {
+        for (var x = -radius; x <= radius; x++) {
+            let sample = load(p + vec2<i32>(x, y), size);
+            if sample.a < 0.5 {
+                continue;
+            }
+            let spatial = exp(-f32(x * x + y * y) / (2.0 * sigma * sigma));
+            let difference = (sample.r - center.r) * params.y;
+            let weight = spatial * exp(-difference * difference);
+            sum += sample.r * weight;
+            weights += weight;
+        }
+    }
+    return vec2<f32>(sum / weights, 1.0);
+}
+
+// view space position of a screen location at the given linear depth
+fn view_position(uv: vec2<f32>, depth: f32) -> vec3<f32> {
+    let ndc = uv * 2.0 - 1.0;
+    return vec3<f32>(ndc.x * params.z * params.w * depth, -ndc.y * params.z * depth, -depth);
+}
+
+@fragment
+fn fragment(in: UiVertexOutput) -> @location(0) vec4<f32> {
+    let size = vec2<i32>(textureDimensions(depth_texture));
+    let texel = 1.0 / vec2<f32>(size);
+    let p = vec2<i32>(in.uv * vec2<f32>(size));
+
+    let center = bilateral_depth(p, size);
+    if center.y < 0.5 {
+        return vec4<f32>(0.0);
+    }
+    // neighbors outside of the fluid fall back to the center depth (flat towards the edge)
+    let right = bilateral_depth(p + vec2<i32>(1, 0), size);
+    let down = bilateral_depth(p + vec2<i32>(0, 1), size);
+    let depth_right = select(center.x, right.x, right.y > 0.5);
+    let depth_down = select(center.x, down.x, down.y > 0.5);
+
+    let position = view_position(in.uv, center.x);
+    let dx = view_position(in.uv + vec2<f32>(texel.x, 0.0), depth_right) - position;
+    let dy = view_position(in.uv + vec2<f32>(0.0, texel.y), depth_down) - position;
+    let normal = normalize(cross(dy, dx));
+
+    let view_dir = normalize(-position);
+    let light = normalize(LIGHT_DIR);
+    let diffuse = max(dot(normal, light), 0.0) * 0.6 + 0.4;
+    let specular = pow(max(dot(normal, normalize(light + view_dir)), 0.0), 64.0);
+    let fresnel = 0.1 + 0.9 * pow(1.0 - max(dot(normal, view_dir), 0.0), 5.0);
+
+    let shaded = mix(color.rgb * diffuse, SKY, fresnel) + vec3<f32>(specular);
+    return vec4<f32>(shaded, mix(color.a, 1.0, fresnel));
+}
@@ -0,0 +1,23 @@
+// depth pass of the screen space fluid: a sphere per particle billboard
+//   red: linear view depth of the sphere surface, alpha: coverage
+
+#import bevy_pbr::{
+    forward_io::VertexOutput,
+    view_transformations::position_world_to_view,
+}
+
+// x: particle radius (world units)
+@group(2) @binding(0) var<uniform> params: vec4<f32>;
+
+@fragment
+fn fragment(in: VertexOutput) -> @location(0) vec4<f32> {
+    let xy = in.uv * 2.0 - 1.0;
+    let r2 = dot(xy, xy);
+    if r2 > 1.0 {
+        discard;
+    }
+    // the billboard sits at the particle center; move towards the camera onto the sphere
+    let view_position = position_world_to_view(in.world_position.xyz);
+    let depth = -view_position.z - sqrt(1.0 - r2) * params.x;
+    return vec4<f32>(depth, 0.0, 0.0, 1.0);
+}
@@ -0,0 +1,44 @@
+// instanced point cloud: the unit quad of the mesh turns towards the camera at every particle
+//   instance: xyz world position, w radius; color
+//   the fragment cuts a sphere out of the quad and shades it with a light from the camera
+
+#import bevy_pbr::view_transformations::{position_world_to_view, position_view_to_clip}
+
+struct Vertex {
+    @location(0) position: vec3<f32>,
+    @location(1) normal: vec3<f32>,
+    @location(2) uv: vec2<f32>,
+
+    @location(3) i_position_radius: vec4<f32>,
+    @location(4) i_color: vec4<f32>,
+};
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) uv: vec2<f32>,
+    @location(1) color: vec4<f32>,
+};
+
+@vertex
+fn vertex(vertex: Vertex) -> VertexOutput {
+    let center = position_world_to_view(vertex.i_position_radius.xyz);
+    let corner = center + vec3<f32>(vertex.position.xy * vertex.i_position_radius.w, 0.0);
+    var out: VertexOutput;
+    out.clip_position = position_view_to_clip(corner);
+    out.uv = vertex.uv;
+    out.color = vertex.i_color;
+    return out;
+}
+
+@fragment
+fn fragment(in: VertexOutput) -> @location(0) vec4<f32> {
+    let xy = in.uv * 2.0 - 1.0;
+    let r2 = dot(xy, xy);
+    if r2 > 1.0 {
+        discard;
+    }
+    // view space normal of the sphere; the camera looks along -z
+    let normal = vec3<f32>(xy.x, -xy.y, sqrt(1.0 - r2));
+    let light = 0.3 + 0.7 * normal.z;
+    return vec4<f32>(in.color.rgb * light, in.color.a);
+}
@@ -0,0 +1,88 @@
+/*
+    Copyright 2023 github.com/programaquatic
+
+   Licensed under the Apache License, Version 2.0 (the "License");
+   you may not use this file except in compliance with the License.
+   You may obtain a copy of the License at
+
+       http://www.apache.org/licenses/LICENSE-2.0
+
+   Unless required by applicable law or agreed to in writing, software
+   distributed under the License is distributed on an "AS IS" BASIS,
+   WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+   See the License for the specific language governing permissions and
+   limitations under the License.
+*/
+
+use bevy::{
+    prelude::*,
+    render::{
+        mesh::Indices,
+        render_asset::RenderAssetUsages,
+        render_resource::PrimitiveTopology,
+    },
+};
+
+// billboard corners and their uv (the shaders cut the sphere out of the quad)
+const QUAD: [(Vec2, Vec2); 4] = [
+    (Vec2::new( -1.0, -1.0 ), Vec2::new( 0.0, 1.0 )),
+    (Vec2::new( 1.0, -1.0 ), Vec2::new( 1.0, 1.0 )),
+    (Vec2::new( 1.0, 1.0 ), Vec2::new( 1.0, 0.0 )),
+    (Vec2::new( -1.0, 1.0 ), Vec2::new( 0.0, 0.0 )),
+];
+const QUAD_INDICES: [u32; 6] = [ 0, 1, 2,  0, 2, 3 ];
+
+
+/// one camera-facing quad per position; right and up span the image plane of the camera
+pub fn billboard_mesh(positions: &[Vec3], radius: f32, right: Vec3, up: Vec3) -> Mesh {
+    let mut vertices = Vec::with_capacity( positions.len() * QUAD.len() );
+    let mut uvs = Vec::with_capacity( positions.len() * QUAD.len() );
+    let mut indices = Vec::with_capacity( positions.len() * QUAD_INDICES.len() );
+    for (i, position) in positions.iter().enumerate() {
+        let base = (i * QUAD.len()) as u32;
+        for (corner, uv) in QUAD {
+            vertices.push( *position + (right * corner.x + up * corner.y) * radius );
+            uvs.push( uv );
+        }
+        indices.extend( QUAD_INDICES.iter().map(| idx | base + idx) );
+    }
+    let mut mesh = Mesh::new( PrimitiveTopology::TriangleList, RenderAssetUsages::default() );
+    mesh.insert_attribute( Mesh::ATTRIBUTE_POSITION, vertices );
+    mesh.insert_attribute( Mesh::ATTRIBUTE_NORMAL, vec![ right.cross( up ); positions.len() * QUAD.len() ] );
+    mesh.insert_attribute( Mesh::ATTRIBUTE_UV_0, uvs );
+    mesh.insert_indices( Indices::U32( indices ) );
+    mesh
+}
+
+
+
+#[cfg(test)]
+mod test
+{
+    use super::*;
+    use bevy::render::mesh::VertexAttributeValues;
+
+    #[test]
+    fn test_billboard_mesh() {
+        let positions = [ Vec3::ZERO, Vec3::new( 5.0, 1.0, 2.0 ) ];
+        // camera looking along -x
+        let (right, up) = (Vec3::Z, Vec3::Y);
+        let mesh = billboard_mesh( &positions, 0.5, right, up );
+        assert_eq!( mesh.count_vertices(), 8 );
+        assert_eq!( mesh.indices().unwrap().len(), 12 );
+
+        if let Some( VertexAttributeValues::Float32x3( vertices ) ) = mesh.attribute( Mesh::ATTRIBUTE_POSITION ) {
+            for (i, position) in positions.iter().enumerate() {
+                let corners: Vec<Vec3> = vertices[ i * 4..i * 4 + 4 ].iter().map(| v | Vec3::from( *v )).collect();
+                let center = corners.iter().fold( Vec3::ZERO, | acc, v | acc + *v ) / 4.0;
+                assert!( center.abs_diff_eq( *position, 1e-5 ) );
+                // the quad faces the camera, i.e. lies in the plane spanned by right and up
+                assert!( corners.iter().all(| c | (c.x - position.x).abs() < 1e-5 ) );
+                assert!( corners.iter().all(| c | (*c - *position).abs().max_element() <= 0.5 + 1e-5 ) );
+            }
+        } else {
+            panic!( "billboard mesh has no positions" );
+        }
+        assert_eq!( billboard_mesh( &[], 0.5, right, up ).count_vertices(), 0 );
+    }
+}
@@ -0,0 +1,105 @@
+/*
+    Copyright 2023 github.com/programaquatic
+
+   Licensed under the Apache License, Version 2.0 (the "License");
+   you may not use this file except in compliance with the License.
+   You may obtain a copy of the License at
+
+       http://www.apache.org/licenses/LICENSE-2.0
+
+   Unless required by applicable law or agreed to in writing, software
+   distributed under the License is distributed on an "AS IS" BASIS,
+   WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+   See the License for the specific language governing permissions and
+   limitations under the License.
+*/
+
+use bevy::prelude::*;
+use serde::{Serialize, Deserialize};
+
+
+/// Common aquarium light spectra
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum LightPreset {
+    Daylight6500K,
+    Marine10000K,
+    ActinicBlue,
+}
+
+/// Color of a light either as preset, color temperature or plain rgb
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum LightColor {
+    Preset(LightPreset),
+    Kelvin(f32),
+    Rgb(Vec3),
+}
+
+impl Default for LightColor {
+    fn default() -> Self {
+        LightColor::Rgb(Vec3::ONE)
+    }
+}
+
+impl LightColor {
+    pub fn to_color(&self) -> Color {
+        match self {
+            LightColor::Preset(LightPreset::Daylight6500K) => kelvin_to_rgb(6500.),
+            LightColor::Preset(LightPreset::Marine10000K) => kelvin_to_rgb(10000.),
+            // actinic lights peak at ~420nm which is outside of what a color temperature can express
+            LightColor::Preset(LightPreset::ActinicBlue) => Color::srgb(0.25, 0.3, 1.0),
+            LightColor::Kelvin(kelvin) => kelvin_to_rgb(*kelvin),
+            LightColor::Rgb(rgb) => Color::srgb(rgb.x, rgb.y, rgb.z),
+        }
+    }
+}
+
+
+/// approximate the color of a black body at the given temperature
+///   based on Tanner Helland's curve fit; valid from 1000K to 40000K
+pub fn kelvin_to_rgb(kelvin: f32) -> Color {
+    let t = kelvin.clamp(1000., 40000.) / 100.;
+
+    let red = if t <= 66. {
+        255.
+    } else {
+        329.698_73 * (t - 60.).powf(-0.133_204_76)
+    };
+    let green = if t <= 66. {
+        99.470_8 * t.ln() - 161.119_57
+    } else {
+        288.122_17 * (t - 60.).powf(-0.075_514_85)
+    };
+    let blue = if t >= 66. {
+        255.
+    } else if t <= 19. {
+        0.
+    } else {
+        138.517_73 * (t - 10.).ln() - 305.044_8
+    };
+
+    Color::srgb(
+        red.clamp(0., 255.) / 255.,
+        green.clamp(0., 255.) / 255.,
+        blue.clamp(0., 255.) / 255.,
+    )
+}
+
+
+
+#[cfg(test)]
+mod test
+{
+    use super::*;
+
+    #[test]
+    fn test_kelvin_to_rgb() {
+        let daylight = kelvin_to_rgb(6500.).to_srgba();
+        assert!(daylight.red > 0.95 && daylight.green > 0.95 && daylight.blue > 0.95);
+
+        let warm = kelvin_to_rgb(2000.).to_srgba();
+        assert!(warm.red > warm.blue);
+
+        let marine = LightColor::Preset(LightPreset::Marine10000K).to_color().to_srgba();
+        assert!(marine.blue > marine.red);
+    }
+}
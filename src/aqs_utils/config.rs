@@ -15,9 +15,75 @@
 */
 
 use serde::{Deserialize};
-use std::fs;
-    
-pub fn read_json<T>(file: String) -> Result<T, String>
+use std::{fmt, fs};
+
+
+/// Reading a config file failed
+#[derive(Debug)]
+pub enum ConfigError {
+    /// the file can't be read
+    Io { file: String, source: std::io::Error },
+    /// the content is no valid json for the config (line and column within the file)
+    Parse { file: String, line: usize, column: usize, message: String },
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::Io { file, source } => write!(f, "failed to open config file {}: {}", file, source),
+            ConfigError::Parse { file, line, column, message } =>
+                write!(f, "format error in {} at line {}, column {}: {}", file, line, column, message),
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ConfigError::Io { source, .. } => Some( source ),
+            ConfigError::Parse { .. } => None,
+        }
+    }
+}
+
+/// remove // and /* */ comments outside of string literals;
+///   a block comment separates tokens like a space, newlines inside comments are kept to keep the line numbers of the file
+pub fn strip_comments(content: &str) -> String {
+    enum State { Code, String, Escape, LineComment, BlockComment }
+
+    let mut state = State::Code;
+    let mut out = String::with_capacity( content.len() );
+    let mut chars = content.chars().peekable();
+    while let Some( c ) = chars.next() {
+        match state {
+            State::Code => match c {
+                '"' => { state = State::String; out.push( c ); },
+                '/' if chars.peek() == Some( &'/' ) => { chars.next(); state = State::LineComment; },
+                '/' if chars.peek() == Some( &'*' ) => { chars.next(); state = State::BlockComment; out.push( ' ' ); },
+                _ => out.push( c ),
+            },
+            State::String => {
+                match c {
+                    '\\' => state = State::Escape,
+                    '"' => state = State::Code,
+                    _ => (),
+                }
+                out.push( c );
+            },
+            State::Escape => { state = State::String; out.push( c ); },
+            State::LineComment => if c == '\n' { state = State::Code; out.push( c ); },
+            State::BlockComment => match c {
+                '*' if chars.peek() == Some( &'/' ) => { chars.next(); state = State::Code; },
+                '\n' => out.push( c ),
+                _ => (),
+            },
+        }
+    }
+    out
+}
+
+/// read a json config file; // and /* */ are comments
+pub fn read_json<T>(file: String) -> Result<T, ConfigError>
     where
         T: for<'de> Deserialize<'de>
 {
@@ -45,12 +111,75 @@ pub fn read_json<T>(file: String) -> Result<T, String>
     
     // let ostr = serde_json::to_string_pretty(&intank).unwrap();
     // println!("{}",ostr);
-    let cfg_content_iter = fs::read_to_string(file).expect("Failed to open config config file");
-    let rem_lines = cfg_content_iter.lines().filter(|l| ! l.trim_start().starts_with("//") );
-    
-    let mut cfg_json = String::from("");
-    rem_lines.for_each(|l| cfg_json.push_str(l) );
-    
-    let data: T = serde_json::from_str(&cfg_json).expect("Format error in json");
-    Ok(data)
+    let cfg_content = fs::read_to_string(&file)
+        .map_err(|source| ConfigError::Io { file: file.clone(), source })?;
+    let cfg_json = strip_comments( &cfg_content );
+
+    serde_json::from_str(&cfg_json).map_err(|e| ConfigError::Parse {
+        file,
+        line: e.line(),
+        column: e.column(),
+        message: e.to_string(),
+    })
+}
+
+
+
+#[cfg(test)]
+mod test
+{
+    use super::*;
+    use crate::aqs_utils::constants::Constants;
+
+    #[test]
+    fn test_missing_file() {
+        let result = read_json::<Constants>(String::from("assets/does_not_exist.json"));
+        match result {
+            Err( ConfigError::Io { file, source } ) => {
+                assert_eq!( file, "assets/does_not_exist.json" );
+                assert_eq!( source.kind(), std::io::ErrorKind::NotFound );
+            },
+            other => panic!("expected an io error, got {:?}", other.map(|_| ())),
+        }
+    }
+
+    #[test]
+    fn test_block_comment() {
+        let json = "{\n  /* a\n     block */ \"a\": 1, /* inline */ \"b\": 2 // trailing\n}\n";
+        let stripped = strip_comments( json );
+        // line count is unchanged
+        assert_eq!( stripped.lines().count(), json.lines().count() );
+        let value: serde_json::Value = serde_json::from_str( &stripped ).unwrap();
+        assert_eq!( value["a"], 1 );
+        assert_eq!( value["b"], 2 );
+
+        // a block comment doesn't join the tokens around it
+        assert_eq!( strip_comments( "1/**/2" ), "1 2" );
+        assert!( serde_json::from_str::<serde_json::Value>( &strip_comments( "[ 1/**/2 ]" ) ).is_err() );
+    }
+
+    #[test]
+    fn test_comments_in_strings() {
+        let json = r#"{ "path": "a // b /* c */", "quote": "x\" // y", "url": "http://example.com/x" } // done"#;
+        let value: serde_json::Value = serde_json::from_str( &strip_comments( json ) ).unwrap();
+        assert_eq!( value["path"], "a // b /* c */" );
+        assert_eq!( value["quote"], "x\" // y" );
+        assert_eq!( value["url"], "http://example.com/x" );
+    }
+
+    #[test]
+    fn test_malformed_json() {
+        let path = std::env::temp_dir().join( format!("lisal_malformed_{}.json", std::process::id()) );
+        // the error is in line 4 of the file, after a comment line
+        fs::write( &path, "// comment\n{\n    \"a\": 1,\n    \"b\": ,\n}\n" ).unwrap();
+        let result = read_json::<serde_json::Value>( path.to_str().unwrap().to_string() );
+        fs::remove_file( &path ).unwrap();
+        match result {
+            Err( ConfigError::Parse { line, column, .. } ) => {
+                assert_eq!( line, 4 );
+                assert!( column > 0 );
+            },
+            other => panic!("expected a parse error, got {:?}", other),
+        }
+    }
 }
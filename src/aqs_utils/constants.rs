@@ -16,17 +16,22 @@
 
 use bevy::{
     prelude::*,
-    math::Mat3A,
+    math::{Mat3A, Vec3A},
 };
 use serde::{Serialize, Deserialize};
 
-use crate::aqs_utils::{
-    config as cfg,
+use crate::{
+    aqs_utils::{
+        config as cfg,
+        color::LightColor,
+    },
+    tech::templates::SelectedTemplate,
 };
 
 
 // fluid constitutive model properties
-#[derive(Resource, Serialize, Deserialize, Debug, Default)]
+#[derive(Resource, Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
 pub struct FluidModel {
     pub rest_density: f32,
     pub dynamic_viscosity: f32,
@@ -34,6 +39,17 @@ pub struct FluidModel {
     pub eos_power: f32,
 }
 
+impl Default for FluidModel {
+    fn default() -> Self {
+        FluidModel {
+            rest_density: 1.0,
+            dynamic_viscosity: 0.001,
+            eos_stiffness: 10.,
+            eos_power: 4.,
+        }
+    }
+}
+
 #[derive(Resource, Serialize, Deserialize, Debug, Default)]
 pub struct NeoHookeanHyperElasticModel {
     pub deformation_gradient: Mat3A,
@@ -61,52 +77,821 @@ impl Default for ParticleVisibilityConf {
 }
 
 
+/// initial water level of the tank
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum FillSpec {
+    /// fraction of the grid height
+    Fraction(f32),
+    /// height above the tank bottom in cm
+    Absolute(f32),
+}
+
+impl FillSpec {
+    /// fill height in grid units; tank_scale converts cm into grid units (see Tank::scale)
+    pub fn grid_height(&self, grid_height: f32, tank_scale: f32) -> f32 {
+        match self {
+            FillSpec::Fraction( fraction ) => fraction * grid_height,
+            FillSpec::Absolute( cm ) => cm * tank_scale,
+        }
+    }
+}
+
+
+/// shape of the initial fill (in cm like the tank dimensions); replaces the flat fill level
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum FillRegion {
+    /// axis-aligned box between the min and max corner
+    Box { min: Vec3, max: Vec3 },
+    Sphere { center: Vec3, radius: f32 },
+    /// everything on the side of the plane the normal points away from (point . normal <= offset)
+    HalfSpace { normal: Vec3, offset: f32 },
+}
+
+impl FillRegion {
+    /// whether a location in grid units is inside; tank_scale converts cm into grid units (see Tank::scale)
+    pub fn contains(&self, location: Vec3, tank_scale: f32) -> bool {
+        let location = location / tank_scale;
+        match self {
+            FillRegion::Box { min, max } => location.cmpge( *min ).all() && location.cmple( *max ).all(),
+            FillRegion::Sphere { center, radius } => location.distance_squared( *center ) <= radius * radius,
+            FillRegion::HalfSpace { normal, offset } => location.dot( normal.normalize_or_zero() ) <= *offset,
+        }
+    }
+}
+
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SurfaceExportFormat {
+    #[default]
+    Csv,
+    /// 8bit grayscale; heights in [-range, range] map to black..white
+    Png,
+}
+
+/// periodic dump of the surface height field for offline wave analysis
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct SurfaceExportConf {
+    pub directory: String,
+    /// export every n-th frame
+    pub interval: u32,
+    pub format: SurfaceExportFormat,
+    /// height that maps to white in the png export
+    pub range: f32,
+}
+
+impl Default for SurfaceExportConf {
+    fn default() -> Self {
+        SurfaceExportConf {
+            directory: String::from("surface_export"),
+            interval: 10,
+            format: SurfaceExportFormat::Csv,
+            range: 1.0,
+        }
+    }
+}
+
+/// top-down heatmap of the water mass per grid column (colored with DEBUG_COLORMAP)
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct DensityHeatmapConf {
+    /// column mass that gets the top color; the heaviest column if not set
+    pub max_mass: Option<f32>,
+    pub opacity: f32,
+}
+
+impl Default for DensityHeatmapConf {
+    fn default() -> Self {
+        DensityHeatmapConf {
+            max_mass: None,
+            opacity: 0.7,
+        }
+    }
+}
+
+
+/// color gradient of the per-cell debug scalar
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Colormap {
+    /// black to white
+    Grayscale,
+    /// blue over white to red; for signed values like the divergence
+    BlueWhiteRed,
+    /// blue, cyan, green, yellow, red
+    #[default]
+    Rainbow,
+}
+
+
+/// how MAX_GRID_CELLS gets turned into grid dimensions
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum GridAllocation {
+    /// uniform scale (MAX_GRID_CELLS / volume)^(1/3); the tank may not span whole cells
+    #[default]
+    CubeRoot,
+    /// cell counts allocated per axis; the tank snaps to whole cells that stay roughly cubic
+    PerAxis,
+}
+
+
+/// what happens to the velocity at walls and colliders
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum BoundaryMode {
+    /// the normal component is cancelled, fluid slides along the wall
+    Slip,
+    /// the full velocity is cancelled
+    NoSlip,
+    /// only motion into the wall is blocked, fluid can move away from it
+    #[default]
+    Separate,
+}
+
+impl BoundaryMode {
+    /// velocity after the boundary condition of a wall with unit `normal` (pointing into the fluid)
+    pub fn apply(&self, velocity: Vec3A, normal: Vec3A) -> Vec3A {
+        let normal_speed = velocity.dot( normal );
+        match self {
+            BoundaryMode::Slip => velocity - normal * normal_speed,
+            BoundaryMode::NoSlip => Vec3A::ZERO,
+            BoundaryMode::Separate if normal_speed < 0.0 => velocity - normal * normal_speed,
+            BoundaryMode::Separate => velocity,
+        }
+    }
+}
+
+
+/// how visible particles get drawn
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ParticleRenderer {
+    /// one sphere entity per visible particle (DEBUG_FLUID_PARTICLES); fine for small counts
+    #[default]
+    Spheres,
+    /// one quad per particle, drawn GPU-instanced in a single draw call; for large counts
+    PointCloud,
+    /// smooth surface from the blurred particle depth (SCREEN_SPACE_FLUID)
+    ScreenSpace,
+}
+
+/// screen space fluid rendering: particle depth gets blurred into a continuous surface
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct ScreenSpaceFluidConf {
+    /// radius of a particle sphere (world units); should overlap the neighbors
+    pub particle_radius: f32,
+    /// radius of the bilateral blur in pixels
+    pub blur_radius: u32,
+    /// how fast depth differences stop blending (1/world units); keeps separate bodies of fluid apart
+    pub depth_falloff: f32,
+    pub color: Vec3,
+    pub alpha: f32,
+}
+
+impl Default for ScreenSpaceFluidConf {
+    fn default() -> Self {
+        ScreenSpaceFluidConf {
+            particle_radius: 0.3,
+            blur_radius: 6,
+            depth_falloff: 2.0,
+            color: Vec3::new( 0.1, 0.35, 0.8 ),
+            alpha: 0.8,
+        }
+    }
+}
+
+
+/// fraction of heavier (sediment) particles seeded into the initial fill
+#[derive(Resource, Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct SedimentConf {
+    /// share of particles in [0..1] that get the heavier mass
+    pub fraction: f32,
+    /// mass of sediment particles relative to DEFAULT_PARTICLE_MASS
+    pub mass_multiplier: f32,
+}
+
+impl Default for SedimentConf {
+    fn default() -> Self {
+        SedimentConf {
+            fraction: 0.0,
+            mass_multiplier: 2.5,
+        }
+    }
+}
+
+impl SedimentConf {
+    /// mass of a particle given a uniform random roll in [0..1)
+    pub fn particle_mass(&self, base_mass: f32, roll: f32) -> f32 {
+        if roll < self.fraction {
+            base_mass * self.mass_multiplier
+        } else {
+            base_mass
+        }
+    }
+}
+
+
+/// optional second immiscible fluid phase (e.g. oil on water or brackish layers)
+///   phase 0 is always FLUID_MODEL; particles of this phase are phase 1
+#[derive(Resource, Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct FluidPhaseConf {
+    pub model: FluidModel,
+    /// vertical band of the initial fill [from..to] as fraction of the fill height
+    pub layer: Vec2,
+    /// share of particles in [0..1] inside the layer band that get seeded as this phase
+    pub fraction: f32,
+}
+
+impl Default for FluidPhaseConf {
+    fn default() -> Self {
+        FluidPhaseConf {
+            model: FluidModel {
+                rest_density: 0.8,
+                dynamic_viscosity: 0.01,
+                eos_stiffness: 10.,
+                eos_power: 4.,
+            },
+            layer: Vec2::new( 0.8, 1.0 ),
+            fraction: 1.0,
+        }
+    }
+}
+
+impl FluidPhaseConf {
+    /// phase of a particle seeded at `relative_height` of the fill, given a uniform random roll in [0..1)
+    pub fn phase_at(&self, relative_height: f32, roll: f32) -> u8 {
+        if relative_height >= self.layer.x && relative_height <= self.layer.y && roll < self.fraction {
+            1
+        } else {
+            0
+        }
+    }
+}
+
+
+/// how the per-vertex color of the water surface gets computed
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SurfaceColorMode {
+    /// base tint with the green channel driven by vertical velocity
+    #[default]
+    VerticalVelocity,
+    /// gradient from deep to shallow color based on surface height
+    Height,
+    /// gradient from deep to shallow color based on horizontal speed
+    Speed,
+}
+
+#[derive(Resource, Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct SurfaceColorConf {
+    pub mode: SurfaceColorMode,
+    /// base color for VerticalVelocity mode (green gets replaced)
+    pub tint: Vec3,
+    pub alpha: f32,
+    /// gradient end points for Height and Speed modes
+    pub deep: Vec3,
+    pub shallow: Vec3,
+    /// value that maps to the full gradient range
+    pub range: f32,
+}
+
+impl Default for SurfaceColorConf {
+    fn default() -> Self {
+        SurfaceColorConf {
+            mode: SurfaceColorMode::VerticalVelocity,
+            tint: Vec3::new(0.0, 0.0, 0.8),
+            alpha: 0.7,
+            deep: Vec3::new(0.0, 0.05, 0.3),
+            shallow: Vec3::new(0.4, 0.8, 1.0),
+            range: 1.0,
+        }
+    }
+}
+
+
+/// overlay of arrows showing the horizontal flow at the surface level
+#[derive(Resource, Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct SurfaceFlowConf {
+    /// show the overlay at startup (toggle with F)
+    pub enabled: bool,
+    /// distance between arrows in grid cells
+    pub spacing: u32,
+    /// arrow length per unit of velocity
+    pub scale: f32,
+}
+
+impl Default for SurfaceFlowConf {
+    fn default() -> Self {
+        SurfaceFlowConf {
+            enabled: false,
+            spacing: 4,
+            scale: 0.5,
+        }
+    }
+}
+
+
+/// background image that shows through the water surface, shifted by the surface normals
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct SurfaceRefractionConf {
+    /// asset path of the image, sampled in screen space
+    pub texture: String,
+    /// screen space offset per unit of horizontal normal
+    pub strength: f32,
+}
+
+impl Default for SurfaceRefractionConf {
+    fn default() -> Self {
+        SurfaceRefractionConf {
+            texture: String::from("textures/flower_background.png"),
+            strength: 0.05,
+        }
+    }
+}
+
+
+/// shorter steps while particles move fast (CFL condition); WORLD_DT stays the upper bound
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+pub struct AdaptiveDtConf {
+    /// max fraction of a cell the fastest particle moves per step
+    pub cfl: f32,
+    pub dt_min: f32,
+}
+
+
+/// box (in cm) that gets filled with elastic solid particles
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+pub struct ElasticBlobConf {
+    pub min: Vec3,
+    pub max: Vec3,
+    pub particles_per_cell: u32,
+}
+
+
+/// update the surface mesh only every `interval` frames once the camera is at least `distance` away
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct SurfaceLodBand {
+    /// camera distance to the tank center in world units
+    pub distance: f32,
+    pub interval: u32,
+}
+
+
+/// closed loop control of the water level by injecting (inlet) or draining (surface) particles
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+#[serde(default)]
+pub struct LevelHoldConf {
+    /// level to hold; same semantics as FILL_LEVEL
+    pub target: FillSpec,
+    /// PID gains; error in grid cells, output as level change in grid cells per second
+    pub kp: f32,
+    pub ki: f32,
+    pub kd: f32,
+    /// limit of the level change rate (grid cells per second)
+    pub max_rate: f32,
+}
+
+impl Default for LevelHoldConf {
+    fn default() -> Self {
+        LevelHoldConf {
+            target: FillSpec::Fraction( 0.95 ),
+            kp: 0.5,
+            ki: 0.05,
+            kd: 0.0,
+            max_rate: 1.0,
+        }
+    }
+}
+
+/// adjusts the number of shown particles every few seconds to hold a target frame rate
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+#[serde(default)]
+pub struct ParticleBudgetConf {
+    pub target_fps: f32,
+    /// bounds of the visible particle count
+    pub min_visible: usize,
+    pub max_visible: usize,
+    /// seconds between adjustments
+    pub interval: f32,
+    /// relative change of the visible count per adjustment
+    pub step: f32,
+    /// relative deviation from the target frame time that is left alone
+    pub tolerance: f32,
+}
+
+impl Default for ParticleBudgetConf {
+    fn default() -> Self {
+        ParticleBudgetConf {
+            target_fps: 60.0,
+            min_visible: 10,
+            max_visible: 10000,
+            interval: 2.0,
+            step: 0.2,
+            tolerance: 0.1,
+        }
+    }
+}
+
+
+fn default_point_cloud_radius() -> f32 {
+    0.1
+}
+
+fn default_max_sim_advance_per_frame() -> f32 {
+    0.2
+}
+
+fn default_air_layers() -> u32 {
+    1
+}
+
+fn default_escape_margin() -> f32 {
+    2.0
+}
+
+fn default_water_temperature() -> f32 {
+    25.0
+}
+
+fn default_thermal_expansion() -> f32 {
+    2.6e-4
+}
+
+fn default_camera_responsiveness() -> f32 {
+    12.0
+}
+
+fn default_particle_export_dir() -> String {
+    String::from("particle_export")
+}
+
+fn default_show_debug_background() -> bool {
+    true
+}
+
+fn default_kernel_stretch() -> f32 {
+    1.0
+}
+
+fn default_seed_jitter() -> f32 {
+    1.0
+}
+
+fn default_advection_order() -> u32 {
+    1
+}
+
+fn default_boundary_lookahead() -> f32 {
+    0.1
+}
+
+fn default_boundary_stiffness() -> f32 {
+    1.0
+}
+
+
 #[allow(non_snake_case)] // allow those constants to be uppercase var names
 #[derive(Resource, Serialize, Deserialize, Debug)]
 pub struct Constants {
     pub MAX_GRID_CELLS: usize,
+    /// optional fixed size of a grid cell in tank units; overrides MAX_GRID_CELLS
+    #[serde(default)]
+    pub CELL_SIZE: Option<f32>,
+    /// how the MAX_GRID_CELLS budget gets distributed; ignored if CELL_SIZE is set
+    #[serde(default)]
+    pub GRID_ALLOCATION: GridAllocation,
+    /// optional fraction of a cell that has to be inside a decoration to make it solid (sampled on 3x3x3 points);
+    ///   without it a cell is solid as soon as a collider is within half a cell of its center
+    #[serde(default)]
+    pub SOLID_COVERAGE: Option<f32>,
     pub WORLD_DT: f32,
+    /// max simulated time per rendered frame (seconds); a stalled frame doesn't make the fluid catch up
+    #[serde(default = "default_max_sim_advance_per_frame")]
+    pub MAX_SIM_ADVANCE_PER_FRAME: f32,
+    /// optional adaptive time step (see water::sim_time)
+    #[serde(default)]
+    pub ADAPTIVE_DT: Option<AdaptiveDtConf>,
     pub DEFAULT_GRAVITY: f32,
+    /// optional gravity vector (e.g. a tilted tank); replaces DEFAULT_GRAVITY along y
+    #[serde(default)]
+    pub GRAVITY: Option<Vec3>,
+    /// number of simulation steps over which gravity ramps up from 0 at startup; 0 applies it at once
+    #[serde(default)]
+    pub GRAVITY_RAMP_FRAMES: u32,
+    /// number of Air layers at the top of the grid (headroom for splashes)
+    #[serde(default = "default_air_layers")]
+    pub AIR_LAYERS: u32,
+    /// the air layers become a solid lid so that no fluid can splash out
+    #[serde(default)]
+    pub CLOSED_TOP: bool,
 
     pub DEFAULT_DENSITY: Vec2,
+    /// mass of a particle; derived from the rest density and DEFAULT_PPC (see derived_particle_mass)
+    #[serde(skip)]
     pub DEFAULT_PARTICLE_MASS: f32,
+    /// optional fixed particle mass instead of the derived one (the density may then contradict the EOS rest density)
+    #[serde(default)]
+    pub PARTICLE_MASS: Option<f32>,
     pub DEFAULT_FILL_HEIGHT: f32,
+    /// optional water level that replaces the DEFAULT_FILL_HEIGHT fraction
+    #[serde(default)]
+    pub FILL_LEVEL: Option<FillSpec>,
+    /// optional shape of the initial fill (e.g. a dam break); replaces the fill level
+    #[serde(default)]
+    pub FILL_REGION: Option<FillRegion>,
+    /// random offset of the seeded particles around the cell center; 0 = on the lattice, 1 = anywhere in the cell
+    #[serde(default = "default_seed_jitter")]
+    pub SEED_JITTER: f32,
     pub DEFAULT_DAMPENING: f32,
 
     pub MAX_PARTICLES: usize,
     pub VISIBLE_PARTICLES: usize,
+    /// optional auto-tuning of the shown particles from the measured frame time
+    #[serde(default)]
+    pub PARTICLE_BUDGET: Option<ParticleBudgetConf>,
+
+    /// optional controller that holds the water level during recirculation
+    #[serde(default)]
+    pub LEVEL_HOLD: Option<LevelHoldConf>,
+
+    /// refuse to start if the estimated particle and grid memory exceeds this
+    #[serde(default)]
+    pub MEMORY_BUDGET_MB: Option<f32>,
+
+    /// predictive wall enforcement: lookahead as fraction of WORLD_DT and gain of the velocity correction
+    #[serde(default = "default_boundary_lookahead")]
+    pub BOUNDARY_LOOKAHEAD: f32,
+    #[serde(default = "default_boundary_stiffness")]
+    pub BOUNDARY_STIFFNESS: f32,
+    /// velocity condition at the tank walls and colliders
+    #[serde(default)]
+    pub BOUNDARY_MODE: BoundaryMode,
+
+    /// particles further outside the grid than this (in cells) or with non-finite positions get despawned
+    #[serde(default = "default_escape_margin")]
+    pub ESCAPE_MARGIN: f32,
+
+    /// strength (epsilon) of the vorticity confinement force; 0 disables it
+    #[serde(default)]
+    pub VORTICITY_CONFINEMENT: f32,
+
+    /// relaxation passes that reduce the grid velocity divergence (cheap approximate pressure solve); 0 disables it
+    #[serde(default)]
+    pub RELAX_ITERS: usize,
 
+    /// particle advection: 1 = forward Euler, 2 = RK2 midpoint
+    #[serde(default = "default_advection_order")]
+    pub ADVECTION_ORDER: u32,
+
+    /// particle velocity update: 0 = PIC (rebuilt from the grid), 1 = FLIP (adds the grid velocity change)
+    #[serde(default)]
+    pub FLIP_RATIO: f32,
+
+    /// kernel stretch along the particle velocity for thin, fast streams; 1 = isotropic
+    #[serde(default = "default_kernel_stretch")]
+    pub KERNEL_STRETCH: f32,
+
+    /// constitutive model of the water; the rest density follows DEFAULT_DENSITY
     #[serde(default)]
     pub FLUID_MODEL: FluidModel,
+    /// optional second fluid phase; single-phase if not set
+    #[serde(default)]
+    pub SECOND_PHASE: Option<FluidPhaseConf>,
     #[serde(default)]
     pub ELASTIC_MODEL: NeoHookeanHyperElasticModel,
 
+    /// optional block of elastic solid particles (Neo-Hookean, see ELASTIC_MODEL)
+    #[serde(default)]
+    pub ELASTIC_BLOB: Option<ElasticBlobConf>,
+
     #[serde(default)]
     pub DEBUG_FLUID_PARTICLES: ParticleVisibilityConf,
 
+    #[serde(default)]
+    pub PARTICLE_RENDERER: ParticleRenderer,
+    /// size of a particle in the point cloud (world units)
+    #[serde(default = "default_point_cloud_radius")]
+    pub POINT_CLOUD_RADIUS: f32,
+    #[serde(default)]
+    pub SCREEN_SPACE_FLUID: ScreenSpaceFluidConf,
+
     #[serde(default)]
     pub DEFAULT_PPC: u32,
+
+    #[serde(default)]
+    pub SURFACE_COLOR: SurfaceColorConf,
+
+    /// optional export of the surface height field to a csv/png sequence
+    #[serde(default)]
+    pub SURFACE_EXPORT: Option<SurfaceExportConf>,
+
+    /// directory of the particle snapshots written with E
+    #[serde(default = "default_particle_export_dir")]
+    pub PARTICLE_EXPORT_DIR: String,
+
+    #[serde(default)]
+    pub SURFACE_FLOW: SurfaceFlowConf,
+
+    #[serde(default)]
+    pub SURFACE_REFRACTION: SurfaceRefractionConf,
+
+    /// distance bands that update the surface mesh less often for far away views; empty updates every frame
+    #[serde(default)]
+    pub SURFACE_LOD: Vec<SurfaceLodBand>,
+
+    /// color the visible particles by speed, blue at the min to red at the max (grid units per second)
+    #[serde(default)]
+    pub COLOR_VEL_RANGE: Option<Vec2>,
+
+    /// colors of the per-cell debug scalar (cycle the scalar with G)
+    #[serde(default)]
+    pub DEBUG_COLORMAP: Colormap,
+
+    /// optional heatmap of the water mass per column above the tank
+    #[serde(default)]
+    pub DENSITY_HEATMAP: Option<DensityHeatmapConf>,
+
+    #[serde(default)]
+    pub SEDIMENT: SedimentConf,
+
+    /// temperature (°C) of the initial fill and of injected water if the pump sets none
+    #[serde(default = "default_water_temperature")]
+    pub WATER_TEMPERATURE: f32,
+
+    /// volumetric thermal expansion of the water (1/°C): particles warmer than WATER_TEMPERATURE
+    /// are lighter (Boussinesq approximation); 0 turns the buoyancy of warm water off
+    #[serde(default = "default_thermal_expansion")]
+    pub THERMAL_EXPANSION: f32,
+
+    /// optional csv file to log the per-frame pump throughput to
+    #[serde(default)]
+    pub PUMP_STATS_CSV: Option<String>,
+
+    /// color of the main (directional) light
+    #[serde(default)]
+    pub LIGHT_COLOR: LightColor,
+
+    /// illuminance (lux) of a dimmer fill light from the opposite side to soften shadows; 0 turns it off
+    #[serde(default)]
+    pub FILL_LIGHT: f32,
+
+    /// brightness of the ambient light; the bevy default if not set
+    #[serde(default)]
+    pub AMBIENT_LIGHT: Option<f32>,
+
+    /// spawn the textured background plane and the floor below the tank (debugging aids)
+    #[serde(default = "default_show_debug_background")]
+    pub SHOW_DEBUG_BACKGROUND: bool,
+
+    /// how fast the camera follows the mouse input (1/s); 0 applies input instantly
+    #[serde(default = "default_camera_responsiveness")]
+    pub CAMERA_RESPONSIVENESS: f32,
 }
 
-impl FromWorld for Constants {
-    fn from_world( _world: &mut World ) -> Self {
-        let mut aqs_constants: Constants = cfg::read_json::<Constants>(String::from("assets/constants.json")).unwrap();
+impl Constants {
+    /// the configured water level; DEFAULT_FILL_HEIGHT as fraction unless FILL_LEVEL is set
+    pub fn fill_spec(&self) -> FillSpec {
+        self.FILL_LEVEL.unwrap_or( FillSpec::Fraction( self.DEFAULT_FILL_HEIGHT ) )
+    }
 
-        let fluid_model = FluidModel {
-            rest_density: aqs_constants.DEFAULT_DENSITY.y,
-            dynamic_viscosity: 0.001,
-            eos_stiffness: 10.,
-            eos_power: 4.,
-        };
+    /// the configured gravity vector; (0, DEFAULT_GRAVITY, 0) unless GRAVITY is set
+    pub fn gravity(&self) -> Vec3 {
+        self.GRAVITY.unwrap_or( Vec3::Y * self.DEFAULT_GRAVITY )
+    }
+
+    /// scale gravity to the given strength and keep its direction (straight down for a zero vector)
+    pub fn set_gravity_magnitude(&mut self, magnitude: f32) {
+        match self.GRAVITY {
+            Some( gravity ) => self.GRAVITY = Some( gravity.normalize_or( Vec3::NEG_Y ) * magnitude ),
+            None => self.DEFAULT_GRAVITY = -magnitude,
+        }
+    }
+
+    /// constitutive model of the given fluid phase; unknown phases fall back to FLUID_MODEL
+    pub fn fluid_model(&self, phase: u8) -> &FluidModel {
+        match (phase, &self.SECOND_PHASE) {
+            (1, Some( second )) => &second.model,
+            _ => &self.FLUID_MODEL,
+        }
+    }
+
+    /// particle mass of the given phase; scaled by the rest density relative to phase 0
+    pub fn phase_particle_mass(&self, phase: u8) -> f32 {
+        let base = self.FLUID_MODEL.rest_density;
+        if base <= 0.0 {
+            return self.DEFAULT_PARTICLE_MASS;
+        }
+        self.DEFAULT_PARTICLE_MASS * self.fluid_model( phase ).rest_density / base
+    }
+}
+
+/// particle mass that makes PPC particles fill a cell with the rest density
+pub fn derived_particle_mass(rest_density: f32, cell_volume: f32, ppc: u32) -> f32 {
+    rest_density * cell_volume / ppc.max( 1 ) as f32
+}
+
+impl Constants {
+    /// derive the values that follow from the configured ones (rest density, particle count and mass)
+    pub fn finalize(mut self) -> Self {
         let elastic_model = NeoHookeanHyperElasticModel {
-            deformation_gradient: Default::default(),
+            deformation_gradient: Mat3A::IDENTITY,
             elastic_lambda: 180. * 1000.,
             elastic_mu: 78. * 1000.,
         };
-        aqs_constants.FLUID_MODEL = fluid_model;
-        aqs_constants.ELASTIC_MODEL = elastic_model;
-        aqs_constants.DEFAULT_PPC = aqs_constants.DEFAULT_DENSITY.x as u32;
+        // viscosity and equation of state come from the file, the density from DEFAULT_DENSITY
+        self.FLUID_MODEL.rest_density = self.DEFAULT_DENSITY.y;
+        self.ELASTIC_MODEL = elastic_model;
+        self.DEFAULT_PPC = self.DEFAULT_DENSITY.x as u32;
+        // grid cells have unit volume
+        self.DEFAULT_PARTICLE_MASS = self.PARTICLE_MASS.unwrap_or(
+            derived_particle_mass( self.FLUID_MODEL.rest_density, 1.0, self.DEFAULT_PPC ) );
+        self
+    }
+
+    /// re-read the config file for tuning at runtime; the values that only apply to a fresh grid
+    ///   (and particle set) are kept and the names of those that changed in the file get returned
+    pub fn reload(&self, file: &str) -> Result<(Constants, Vec<&'static str>), cfg::ConfigError> {
+        let mut reloaded = cfg::read_json::<Constants>( file.to_string() )?;
+        let mut ignored = vec![];
+        macro_rules! keep {
+            ( $( $field:ident ),* ) => {
+                $(
+                    if reloaded.$field != self.$field {
+                        ignored.push( stringify!( $field ) );
+                        reloaded.$field = self.$field;
+                    }
+                )*
+            };
+        }
+        keep!( MAX_GRID_CELLS, CELL_SIZE, GRID_ALLOCATION, AIR_LAYERS, CLOSED_TOP, DEFAULT_DENSITY, MAX_PARTICLES );
+        // re-derives the rest density, the particle mass and the elastic model
+        Ok( (reloaded.finalize(), ignored) )
+    }
+}
+
+impl FromWorld for Constants {
+    fn from_world( world: &mut World ) -> Self {
+        // a tank template replaces the config file
+        if let Some( template ) = world.get_resource::<SelectedTemplate>() {
+            return template.0.constants();
+        }
+        let aqs_constants: Constants = cfg::read_json::<Constants>(String::from("assets/constants.json")).unwrap_or_else(|e| panic!("{}", e));
+        aqs_constants.finalize()
+    }
+}
+
+
+
+#[cfg(test)]
+mod test
+{
+    use super::*;
+
+    #[test]
+    fn test_derived_particle_mass() {
+        let mass = derived_particle_mass( 1.0, 1.0, 4 );
+        assert_eq!( mass, 0.25 );
+
+        // doubling the particles per cell halves the mass and keeps the cell mass
+        let doubled = derived_particle_mass( 1.0, 1.0, 8 );
+        assert_eq!( doubled, mass / 2.0 );
+        assert_eq!( doubled * 8.0, mass * 4.0 );
+
+        assert_eq!( derived_particle_mass( 2.0, 1.0, 4 ), 0.5 );
+    }
+
+    #[test]
+    fn test_boundary_modes() {
+        // floor below the fluid
+        let normal = Vec3A::Y;
+        let into_wall = Vec3A::new( 2.0, -3.0, 1.0 );
+        let away = Vec3A::new( 2.0, 3.0, 1.0 );
+
+        assert_eq!( BoundaryMode::Slip.apply( into_wall, normal ), Vec3A::new( 2.0, 0.0, 1.0 ) );
+        assert_eq!( BoundaryMode::Slip.apply( away, normal ), Vec3A::new( 2.0, 0.0, 1.0 ) );
+
+        assert_eq!( BoundaryMode::NoSlip.apply( into_wall, normal ), Vec3A::ZERO );
+        assert_eq!( BoundaryMode::NoSlip.apply( away, normal ), Vec3A::ZERO );
+
+        assert_eq!( BoundaryMode::Separate.apply( into_wall, normal ), Vec3A::new( 2.0, 0.0, 1.0 ) );
+        assert_eq!( BoundaryMode::Separate.apply( away, normal ), away );
+
+        assert_eq!( serde_json::from_str::<BoundaryMode>( r#""NoSlip""# ).unwrap(), BoundaryMode::NoSlip );
+    }
+
+    #[test]
+    fn test_gravity_magnitude() {
+        let mut constants = crate::aqs_utils::config::read_json::<Constants>(String::from("assets/constants.json")).unwrap();
+        constants.GRAVITY = None;
+        constants.set_gravity_magnitude( 5.0 );
+        assert_eq!( constants.gravity(), Vec3::new( 0.0, -5.0, 0.0 ) );
 
-        aqs_constants
+        // a configured vector keeps its direction
+        constants.GRAVITY = Some( Vec3::new( 3.0, -4.0, 0.0 ) );
+        constants.set_gravity_magnitude( 10.0 );
+        assert!( (constants.gravity() - Vec3::new( 6.0, -8.0, 0.0 )).length() < 1e-5 );
+        assert_eq!( constants.DEFAULT_GRAVITY, -5.0 );
     }
 }
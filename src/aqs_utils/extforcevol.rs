@@ -22,6 +22,10 @@ pub enum ForceVolumeDirection {
     Inward(f32),
     Outward(f32),
     Parallel(Vec3),
+    /// wavemaker: base + amplitude * cos(2 pi t / period), period in seconds of simulation time
+    Oscillating { base: Vec3, amplitude: Vec3, period: f32 },
+    /// whirlpool: tangential force of the given strength around the axis through the location
+    Vortex { axis: Vec3, strength: f32 },
 }
 
 #[allow(dead_code)]
@@ -37,6 +41,51 @@ impl ForceVolumeDirection {
     pub fn from_outward(speed: f32) -> Self {
         ForceVolumeDirection::Outward(speed)
     }
+
+    pub fn from_oscillating(base: Vec3, amplitude: Vec3, period: f32) -> Self {
+        ForceVolumeDirection::Oscillating { base, amplitude, period }
+    }
+
+    pub fn from_vortex(axis: Vec3, strength: f32) -> Self {
+        ForceVolumeDirection::Vortex { axis, strength }
+    }
+
+    /// true if the force changes with the simulation time
+    pub fn is_time_varying(&self) -> bool {
+        matches!(self, ForceVolumeDirection::Oscillating { .. })
+    }
+}
+
+
+/// Region of a force volume around its location
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+pub enum VolumeShape {
+    /// axis aligned box with the half-extent
+    #[default]
+    Box,
+    /// radius extent.x
+    Sphere,
+    /// radius extent.x; axis points from the location to one of the caps (direction and half length)
+    Cylinder { axis: Vec3 },
+}
+
+impl VolumeShape {
+    /// true if the offset from the location of the volume is inside the shape
+    pub fn contains(&self, offset: Vec3, extent: Vec3) -> bool {
+        match *self {
+            VolumeShape::Box => offset.abs().cmplt( extent ).all(),
+            VolumeShape::Sphere => offset.length() < extent.x,
+            VolumeShape::Cylinder { axis } => {
+                let half_length = axis.length();
+                if half_length <= 0.0 {
+                    return false;
+                }
+                let along = offset.dot( axis ) / half_length;
+                let radial = offset - axis * (along / half_length);
+                along.abs() < half_length && radial.length() < extent.x
+            },
+        }
+    }
 }
 
 
@@ -48,6 +97,8 @@ pub struct ExternalForceVolume {
     pub extent: Vec3,
     pub direction: ForceVolumeDirection,
     pub name: Option<String>,
+    #[serde(default)]
+    pub shape: VolumeShape,
 }
 
 impl Default for ExternalForceVolume {
@@ -58,6 +109,7 @@ impl Default for ExternalForceVolume {
             extent: Vec3::ONE,
             direction: ForceVolumeDirection::Parallel(Vec3::ONE),
             name: None,
+            shape: VolumeShape::Box,
         }
     }
 }
@@ -75,23 +127,36 @@ impl ExternalForceVolume {
             extent,
             direction,
             name,
+            shape: VolumeShape::Box,
         }
     }
 
+    #[cfg(test)]
+    pub fn with_shape(mut self, shape: VolumeShape) -> Self {
+        self.shape = shape;
+        self
+    }
+
     // pub fn relative_distance(&self, refpoint: Vec3) -> (Vec3, f32) {
     //     let refpoint_distance = refpoint - self.location;
     //     let min_extent = self.extent.min_element().powi(2);
     //     (refpoint_distance, refpoint_distance.length_squared()/min_extent)
     // }
 
-    pub fn get_force_for_position(&self, refpoint: Vec3) -> Vec3 {
-        let floc = (refpoint - self.location).abs();
-        let fextent_mask = floc.cmplt( self.extent ).all();
+    /// force at refpoint at the simulation time (seconds); only oscillating volumes depend on the time
+    pub fn get_force_for_position(&self, refpoint: Vec3, time: f32) -> Vec3 {
+        let fextent_mask = self.shape.contains( refpoint - self.location, self.extent );
         let outward_norm = (refpoint - self.location).normalize_or_zero();
         let force = match self.direction {
             ForceVolumeDirection::Inward(speed) => -outward_norm * speed,
             ForceVolumeDirection::Outward(speed) => outward_norm * speed,
             ForceVolumeDirection::Parallel(dir) => dir,
+            ForceVolumeDirection::Oscillating { base, amplitude, period } => {
+                let phase = if period > 0.0 { std::f32::consts::TAU * time / period } else { 0.0 };
+                base + amplitude * phase.cos()
+            },
+            ForceVolumeDirection::Vortex { axis, strength } =>
+                axis.cross( refpoint - self.location ).normalize_or_zero() * strength,
         };
         force * (fextent_mask as u32) as f32
     }
@@ -99,11 +164,101 @@ impl ExternalForceVolume {
     pub fn scale(&mut self, scale: f32) {
         self.location *= scale;
         self.extent *= scale;
+        if let VolumeShape::Cylinder { axis } = self.shape {
+            self.shape = VolumeShape::Cylinder { axis: axis * scale };
+        }
         // direction is not scaled
         self.direction = match self.direction {
             ForceVolumeDirection::Inward(speed) => ForceVolumeDirection::Inward( speed * scale ),
             ForceVolumeDirection::Outward(speed) => ForceVolumeDirection::Outward( speed * scale ),
             ForceVolumeDirection::Parallel(dir) => ForceVolumeDirection::Parallel( dir * scale ),
+            ForceVolumeDirection::Oscillating { base, amplitude, period } =>
+                ForceVolumeDirection::Oscillating { base: base * scale, amplitude: amplitude * scale, period },
+            ForceVolumeDirection::Vortex { axis, strength } =>
+                ForceVolumeDirection::Vortex { axis, strength: strength * scale },
         };
     }
 }
+
+
+#[cfg(test)]
+mod test
+{
+    use super::*;
+
+    #[test]
+    fn test_volume_shapes() {
+        let location = Vec3::new( 10.0, 5.0, 5.0 );
+        let extent = Vec3::new( 2.0, 1.0, 1.0 );
+        let force = | shape | ExternalForceVolume::new( location, extent, ForceVolumeDirection::from_parallel( Vec3::X ), None )
+            .with_shape( shape );
+        let inside = | volume: &ExternalForceVolume, offset: Vec3 | volume.get_force_for_position( location + offset, 0.0 ) != Vec3::ZERO;
+
+        // box: the corner region counts
+        let cube = force( VolumeShape::Box );
+        assert!( inside( &cube, Vec3::new( 1.99, 0.99, 0.99 ) ) );
+        assert!( !inside( &cube, Vec3::new( 2.01, 0.0, 0.0 ) ) );
+
+        // sphere: radius extent.x in every direction, no corners
+        let sphere = force( VolumeShape::Sphere );
+        assert!( inside( &sphere, Vec3::new( 0.0, 1.99, 0.0 ) ) );
+        assert!( !inside( &sphere, Vec3::new( 0.0, 2.01, 0.0 ) ) );
+        assert!( !inside( &sphere, Vec3::new( 1.5, 1.5, 0.0 ) ) );
+
+        // cylinder along z with half length 3
+        let mut cylinder = force( VolumeShape::Cylinder { axis: Vec3::Z * 3.0 } );
+        assert!( inside( &cylinder, Vec3::new( 1.99, 0.0, 2.99 ) ) );
+        assert!( inside( &cylinder, Vec3::new( 1.4, -1.4, -2.99 ) ) );
+        assert!( !inside( &cylinder, Vec3::new( 0.0, 0.0, 3.01 ) ) );
+        assert!( !inside( &cylinder, Vec3::new( 1.5, 1.5, 0.0 ) ) );
+
+        // scaling grows the axis along with the radius
+        cylinder.scale( 2.0 );
+        assert_eq!( cylinder.shape, VolumeShape::Cylinder { axis: Vec3::Z * 6.0 } );
+        let inside_scaled = | offset: Vec3 | cylinder.get_force_for_position( cylinder.location + offset, 0.0 ) != Vec3::ZERO;
+        assert!( inside_scaled( Vec3::new( 3.99, 0.0, 5.99 ) ) );
+        assert!( !inside_scaled( Vec3::new( 0.0, 4.01, 0.0 ) ) );
+        assert!( !inside_scaled( Vec3::new( 0.0, 0.0, 6.01 ) ) );
+    }
+
+    #[test]
+    fn test_vortex_is_tangential() {
+        let axis = Vec3::new( 0.0, 1.0, 0.2 ).normalize();
+        let location = Vec3::new( 20.0, 10.0, 10.0 );
+        let mut vortex = ExternalForceVolume::new( location, Vec3::splat( 8.0 ), ForceVolumeDirection::from_vortex( axis, 2.0 ), None );
+        for offset in [ Vec3::X, Vec3::new( -3.0, 1.0, 2.0 ), Vec3::new( 0.5, -4.0, -6.0 ), Vec3::new( 7.0, 7.0, 0.0 ) ] {
+            let force = vortex.get_force_for_position( location + offset, 0.0 );
+            assert!( (force.length() - 2.0).abs() < 1e-5 );
+            assert!( force.dot( axis ).abs() < 1e-5 );
+            assert!( force.dot( offset ).abs() < 1e-4 );
+        }
+        // right handed rotation around the axis; nothing on the axis or outside of the extent
+        let up = ExternalForceVolume::new( Vec3::ZERO, Vec3::splat( 8.0 ), ForceVolumeDirection::from_vortex( Vec3::Y, 1.0 ), None );
+        assert!( up.get_force_for_position( Vec3::X, 0.0 ).abs_diff_eq( -Vec3::Z, 1e-6 ) );
+        assert_eq!( vortex.get_force_for_position( location + axis, 0.0 ), Vec3::ZERO );
+        assert_eq!( vortex.get_force_for_position( location + Vec3::X * 9.0, 0.0 ), Vec3::ZERO );
+
+        // the strength scales, the axis doesn't
+        vortex.scale( 3.0 );
+        assert!( matches!( vortex.direction, ForceVolumeDirection::Vortex { axis: a, strength } if a == axis && strength == 6.0 ) );
+    }
+
+    #[test]
+    fn test_oscillating_phase() {
+        let volume = ExternalForceVolume::new( Vec3::ZERO, Vec3::splat(2.0),
+                                               ForceVolumeDirection::from_oscillating( Vec3::X, Vec3::new(3.0, 0.0, 1.0), 4.0 ),
+                                               None );
+        assert!( volume.direction.is_time_varying() );
+        // full swing at the start, reversed after half a period, back after a full one
+        assert!( volume.get_force_for_position( Vec3::ONE, 0.0 ).abs_diff_eq( Vec3::new(4.0, 0.0, 1.0), 1e-5 ) );
+        assert!( volume.get_force_for_position( Vec3::ONE, 2.0 ).abs_diff_eq( Vec3::new(-2.0, 0.0, -1.0), 1e-5 ) );
+        assert!( volume.get_force_for_position( Vec3::ONE, 1.0 ).abs_diff_eq( Vec3::X, 1e-5 ) );
+        assert!( volume.get_force_for_position( Vec3::ONE, 4.0 ).abs_diff_eq( Vec3::new(4.0, 0.0, 1.0), 1e-4 ) );
+        // nothing outside of the volume
+        assert_eq!( volume.get_force_for_position( Vec3::splat(3.0), 0.0 ), Vec3::ZERO );
+
+        let steady = ExternalForceVolume::new( Vec3::ZERO, Vec3::ONE, ForceVolumeDirection::from_parallel( Vec3::Y ), None );
+        assert!( !steady.direction.is_time_varying() );
+        assert_eq!( steady.get_force_for_position( Vec3::ZERO, 0.0 ), steady.get_force_for_position( Vec3::ZERO, 2.0 ) );
+    }
+}
@@ -16,6 +16,7 @@
 
 use bevy::{
     prelude::*,
+    image::{CompressedImageFormats, ImageSampler, ImageType},
     render::{
         mesh::Indices, render_asset::RenderAssetUsages, render_resource::PrimitiveTopology
     },
@@ -23,9 +24,11 @@ use bevy::{
 use rand::Rng;
 use itertools::Itertools;
 
+use crate::aqs_utils::noise::value_noise;
+
 
 pub struct MeshOfSquares {
-    indices: Vec<u16>,
+    indices: Vec<u32>,
     normals: Vec<Vec3>,
     locations: Vec<Vec3>,
     uvs: Vec<Vec2>,
@@ -59,8 +62,8 @@ impl MeshOfSquares {
          12  13  14  15  16  17
  */
     pub fn new( area_size: UVec2, pos_scale: Vec2, uv_scale: Vec2 ) -> MeshOfSquares {
-        // indices use u16, i.e. area cannot have more than 16k vertices
-        assert!( area_size.x * area_size.y < 16384 );
+        // indices use u32, i.e. the area is only limited by the u32 vertex range
+        assert!( (area_size.x as u64) * (area_size.y as u64) <= u32::MAX as u64 );
         let space = (area_size.x * (area_size.y + 1)) as usize;
 
         let mut locations = Vec::with_capacity(space);
@@ -81,9 +84,8 @@ impl MeshOfSquares {
 
         // generate index list
 
-        let triangle_count = (area_size.x-1)*(area_size.y-1)*2 + 2;
-        let mut indices = Vec::with_capacity( triangle_count as usize);
-        println!("Even number of rows: {:?}, space:{}|{}", area_size, space, locations.len());
+        let mut indices = Vec::with_capacity( strip_index_count( area_size ) );
+        debug!("even number of rows: {:?}, space:{}|{}", area_size, space, locations.len());
         (0..area_size.y-1).cartesian_product(0..area_size.x)
             .for_each(|(y,x)| {
                 let top_offset = y * area_size.x;
@@ -96,11 +98,11 @@ impl MeshOfSquares {
 
                 // triangle definition ordering matters for which face is
                 if direction == 0 { // even numbered rows
-                    indices.push( (top_offset + x_idx) as u16 );
-                    indices.push( (bot_offset + x_idx) as u16 );
+                    indices.push( top_offset + x_idx );
+                    indices.push( bot_offset + x_idx );
                 } else { // odd numbered rows
-                    indices.push( (bot_offset + x_idx) as u16 );
-                    indices.push( (top_offset + x_idx) as u16 );
+                    indices.push( bot_offset + x_idx );
+                    indices.push( top_offset + x_idx );
                 }
             });
         MeshOfSquares {
@@ -113,17 +115,76 @@ impl MeshOfSquares {
     }
 
     /// randomize the y-coordinate of the mesh surface
+    ///   the random offset is added to the current height
     #[allow(dead_code)]
     pub fn randomize_position(mut self, range: (f32, f32)) -> MeshOfSquares {
         let granularity = 200.;
         let mut rng = rand::thread_rng();
         for vertex in self.locations.iter_mut() {
             let new_y = rng.gen_range(range.0 * granularity..range.1 * granularity) / (2.*granularity);
-            vertex.y = new_y;
+            vertex.y += new_y;
+        }
+        self
+    }
+
+    /// offset the y-coordinate by a height function of the (x, z) position of the vertex
+    pub fn apply_heightmap(mut self, height: impl Fn(Vec2) -> f32) -> MeshOfSquares {
+        for vertex in self.locations.iter_mut() {
+            vertex.y += height( Vec2::new( vertex.x, vertex.z ) );
+        }
+        self
+    }
+
+    /// offset the y-coordinate by seeded value noise in [0..amplitude]
+    ///   unlike randomize_position, the result is smooth and identical for the same seed
+    pub fn noise_position(self, seed: u32, frequency: f32, amplitude: f32) -> MeshOfSquares {
+        self.apply_heightmap(| p | value_noise( seed, p.x * frequency, p.y * frequency ) * amplitude )
+    }
+
+    /// offset the y-coordinate by the heightmap value scaled with height_scale
+    ///   the map gets stretched across the full x and z extent of the mesh (bilinear resampling)
+    pub fn heightmap_position(mut self, map: &Heightmap, height_scale: f32) -> MeshOfSquares {
+        let extent = self.locations.iter()
+            .fold(Vec2::ZERO, |acc, v| acc.max(Vec2::new(v.x, v.z)))
+            .max(Vec2::splat(f32::EPSILON));
+        for vertex in self.locations.iter_mut() {
+            vertex.y += map.sample( vertex.x / extent.x, vertex.z / extent.y ) * height_scale;
+        }
+        self
+    }
+
+    /// raise the mesh surface by base and tilt it linearly
+    ///   gradient is the total rise across the full x and z extent of the mesh
+    pub fn slope(mut self, base: f32, gradient: Vec2) -> MeshOfSquares {
+        let extent = self.locations.iter()
+            .fold(Vec2::ZERO, |acc, v| acc.max(Vec2::new(v.x, v.z)))
+            .max(Vec2::splat(f32::EPSILON));
+        for vertex in self.locations.iter_mut() {
+            vertex.y += base + gradient.x * vertex.x / extent.x + gradient.y * vertex.z / extent.y;
         }
         self
     }
 
+    /// smooth normals from the current heights: the average of the face normals of all strip triangles at a vertex
+    ///   the strip alternates the winding, so every face normal gets oriented upwards (the mesh is a height field);
+    ///   the vertical triangles at the row changes have no area in the xz-plane and don't count
+    pub fn recompute_normals(mut self) -> MeshOfSquares {
+        let mut sums = vec![ Vec3::ZERO; self.locations.len() ];
+        self.indices.windows(3).for_each(| triangle | {
+            let [a, b, c] = [ triangle[0], triangle[1], triangle[2] ].map(| idx | self.locations[ idx as usize ]);
+            let face = (b - a).cross( c - a );
+            if face.y.abs() <= f32::EPSILON {
+                return;
+            }
+            let face = if face.y < 0.0 { -face } else { face };
+            triangle.iter().for_each(| &idx | sums[ idx as usize ] += face );
+        });
+        self.normals = sums.into_iter()
+            .map(| sum | sum.try_normalize().unwrap_or( Vec3::Y ))
+            .collect();
+        self
+    }
+
     /// randomize the y-coordinate of the mesh surface
     #[allow(dead_code)]
     pub fn randomize_normals(mut self, noise_level: f32) -> MeshOfSquares {
@@ -140,7 +201,7 @@ impl MeshOfSquares {
     #[allow(dead_code)]
     pub fn into_mesh(self) -> Mesh {
         let mut mesh = Mesh::new(PrimitiveTopology::TriangleStrip, RenderAssetUsages::default());
-        mesh.insert_indices(Indices::U16(self.indices));
+        mesh.insert_indices(Indices::U32(self.indices));
         mesh.insert_attribute(Mesh::ATTRIBUTE_NORMAL, self.normals);
         mesh.insert_attribute(Mesh::ATTRIBUTE_POSITION, self.locations);
         mesh.insert_attribute(Mesh::ATTRIBUTE_UV_0, self.uvs);
@@ -148,3 +209,197 @@ impl MeshOfSquares {
         mesh
     }
 }
+
+
+/// number of strip indices of an area: 2 per vertex for each pair of neighboring rows
+///   covers the (x-1)*(y-1)*2 triangles plus 2 degenerated ones per row change (strip length = triangles + 2)
+pub fn strip_index_count( area_size: UVec2 ) -> usize {
+    let triangles = (area_size.x - 1) * (area_size.y - 1) * 2;
+    let degenerate = 2 * area_size.y.saturating_sub( 2 );
+    (triangles + degenerate + 2) as usize
+}
+
+
+/// grayscale height values in [0..1] (e.g. a measured substrate contour)
+pub struct Heightmap {
+    size: UVec2,
+    values: Vec<f32>,
+}
+
+impl Heightmap {
+    /// row-major values with size.x values per row
+    pub fn new(size: UVec2, values: Vec<f32>) -> Heightmap {
+        assert_eq!( values.len(), (size.x * size.y) as usize );
+        Heightmap { size, values }
+    }
+
+    /// luminance of every pixel of the image
+    pub fn from_image(image: &Image) -> Result<Heightmap, String> {
+        let size = image.size();
+        let mut values = Vec::with_capacity( (size.x * size.y) as usize );
+        for (y, x) in (0..size.y).cartesian_product(0..size.x) {
+            let color = image.get_color_at( x, y )
+                .map_err(| e | format!("unsupported heightmap pixel format: {:?}", e))?
+                .to_linear();
+            values.push( 0.2126 * color.red + 0.7152 * color.green + 0.0722 * color.blue );
+        }
+        Ok( Heightmap::new( size, values ) )
+    }
+
+    /// read a heightmap from an image file (png, unless the extension says otherwise)
+    pub fn load(path: &str) -> Result<Heightmap, String> {
+        let buffer = std::fs::read( path ).map_err(| e | format!("{}: {}", path, e))?;
+        let extension = std::path::Path::new( path ).extension()
+            .and_then(| ext | ext.to_str())
+            .unwrap_or("png");
+        let image = Image::from_buffer(
+            &buffer,
+            ImageType::Extension( extension ),
+            CompressedImageFormats::NONE,
+            false,  // raw pixel values, no sRGB conversion
+            ImageSampler::Default,
+            RenderAssetUsages::default(),
+        ).map_err(| e | format!("{}: {}", path, e))?;
+        Heightmap::from_image( &image )
+    }
+
+    fn value(&self, x: u32, y: u32) -> f32 {
+        self.values[ (y.min(self.size.y - 1) * self.size.x + x.min(self.size.x - 1)) as usize ]
+    }
+
+    /// bilinear sample at the relative location u, v in [0..1]; pixel centers of the corners map to 0 and 1
+    pub fn sample(&self, u: f32, v: f32) -> f32 {
+        let px = u.clamp(0.0, 1.0) * (self.size.x - 1) as f32;
+        let py = v.clamp(0.0, 1.0) * (self.size.y - 1) as f32;
+        let (x0, y0) = (px.floor() as u32, py.floor() as u32);
+        let (tx, ty) = (px - x0 as f32, py - y0 as f32);
+
+        let top = self.value( x0, y0 ) * (1.0 - tx) + self.value( x0 + 1, y0 ) * tx;
+        let bot = self.value( x0, y0 + 1 ) * (1.0 - tx) + self.value( x0 + 1, y0 + 1 ) * tx;
+        top * (1.0 - ty) + bot * ty
+    }
+}
+
+
+#[cfg(test)]
+mod test
+{
+    use super::*;
+    use crate::aqs_utils::noise::NoiseField;
+
+    #[test]
+    fn test_large_area_indices() {
+        // beyond the former u16 limit of 16k vertices
+        let size = UVec2::new(200, 200);
+        let meshy = MeshOfSquares::new(size, Vec2::ONE, Vec2::ONE);
+
+        assert_eq!(meshy.indices.len(), (199 * 199 * 2 + 2 * 198 + 2) as usize);
+        assert_eq!(meshy.indices.len(), strip_index_count(size));
+        assert_eq!(meshy.indices.iter().max(), Some(&(200 * 200 - 1)));
+        assert!(meshy.indices.iter().all(|&idx| (idx as usize) < meshy.locations.len()));
+
+        let mesh = meshy.into_mesh();
+        assert!(matches!(mesh.indices(), Some(Indices::U32(_))));
+    }
+
+    #[test]
+    fn test_slope() {
+        let meshy = MeshOfSquares::new(UVec2::new(5, 3), Vec2::splat(2.), Vec2::ONE)
+            .slope(1.0, Vec2::new(0.0, -1.0));
+
+        // back row (z=0) at base height, front row (z=max) one unit lower
+        assert_eq!(meshy.locations[0].y, 1.0);
+        assert_eq!(meshy.locations.last().unwrap().y, 0.0);
+        assert!(meshy.locations.iter().all(|v| v.y >= 0.0 && v.y <= 1.0));
+    }
+
+    #[test]
+    fn test_noise_is_seeded() {
+        let ground = | seed | MeshOfSquares::new(UVec2::new(20, 10), Vec2::ONE, Vec2::ONE)
+            .noise_position(seed, 0.2, 3.0)
+            .locations;
+
+        assert_eq!(ground(7), ground(7));
+        assert_ne!(ground(7), ground(8));
+        assert!(ground(7).iter().all(|v| v.y >= 0.0 && v.y < 3.0));
+
+        // coherent: neighboring vertices only differ by a fraction of the amplitude
+        let heights = ground(7);
+        assert!(heights.windows(2).filter(|w| w[0].z == w[1].z).all(|w| (w[0].y - w[1].y).abs() < 1.5));
+    }
+
+    #[test]
+    fn test_recompute_normals() {
+        // y = 1 + 2 * x/4 - z/3
+        let meshy = MeshOfSquares::new(UVec2::new(5, 4), Vec2::ONE, Vec2::ONE)
+            .slope(1.0, Vec2::new(2.0, -1.0))
+            .recompute_normals();
+        let expected = Vec3::new(-0.5, 1.0, 1.0 / 3.0).normalize();
+        assert_eq!(meshy.normals.len(), meshy.locations.len());
+        assert!(meshy.normals.iter().all(|n| n.abs_diff_eq(expected, 1e-5)), "{:?}", meshy.normals);
+
+        // flat stays up
+        let flat = MeshOfSquares::new(UVec2::new(3, 3), Vec2::splat(2.0), Vec2::ONE).recompute_normals();
+        assert!(flat.normals.iter().all(|n| n.abs_diff_eq(Vec3::Y, 1e-6)));
+    }
+
+    /// largest height difference between neighbors along x and z (unit spacing)
+    fn max_neighbor_step(field: &NoiseField, size: UVec2) -> f32 {
+        let ground = MeshOfSquares::new(size, Vec2::ONE, Vec2::ONE)
+            .apply_heightmap(|p| field.height(p))
+            .locations;
+        assert!(ground.iter().all(|v| v.y.abs() <= field.amplitude));
+        assert!(ground.iter().any(|v| v.y.abs() > 0.1 * field.amplitude));
+
+        let height = |x: u32, z: u32| ground[(z * size.x + x) as usize].y;
+        let mut step: f32 = 0.0;
+        for z in 0..size.y {
+            for x in 0..size.x {
+                assert_eq!(ground[(z * size.x + x) as usize].x, x as f32);
+                if x + 1 < size.x {
+                    step = step.max((height(x, z) - height(x + 1, z)).abs());
+                }
+                if z + 1 < size.y {
+                    step = step.max((height(x, z) - height(x, z + 1)).abs());
+                }
+            }
+        }
+        step
+    }
+
+    #[test]
+    fn test_noise_field_is_smooth() {
+        // (field, configured maximum slope)
+        let cases = [
+            (NoiseField { seed: 5, frequency: 0.15, amplitude: 2.0, octaves: 3 }, 1.0),
+            (NoiseField { seed: 9, frequency: 0.05, amplitude: 1.0, octaves: 2 }, 0.25),
+            (NoiseField { seed: 2, frequency: 0.3, amplitude: 0.5, octaves: 1 }, 0.5),
+        ];
+        for (field, max_slope) in cases {
+            let step = max_neighbor_step(&field, UVec2::new(40, 30));
+            assert!(step <= max_slope, "{:?}: {} > {}", field, step, max_slope);
+            // the computed bound holds as well
+            assert!(step <= field.lipschitz_bound(), "{:?}: {} > {}", field, step, field.lipschitz_bound());
+        }
+    }
+
+    #[test]
+    fn test_heightmap() {
+        let pixels: Vec<f32> = (0..16).map(|i| i as f32 / 15.0).collect();
+        let map = Heightmap::new(UVec2::new(4, 4), pixels.clone());
+
+        // same resolution: every vertex gets its pixel value
+        let ground = MeshOfSquares::new(UVec2::new(4, 4), Vec2::splat(2.), Vec2::ONE)
+            .heightmap_position(&map, 5.0);
+        for (vertex, pixel) in ground.locations.iter().zip(pixels.iter()) {
+            assert!((vertex.y - pixel * 5.0).abs() < 1e-5, "{} != {}", vertex.y, pixel * 5.0);
+        }
+
+        // finer mesh: corners match, values in between get interpolated
+        let ground = MeshOfSquares::new(UVec2::new(7, 7), Vec2::ONE, Vec2::ONE)
+            .heightmap_position(&map, 1.0);
+        assert!((ground.locations[0].y - pixels[0]).abs() < 1e-5);
+        assert!((ground.locations[48].y - pixels[15]).abs() < 1e-5);
+        assert!((ground.locations[1].y - 0.5 * (pixels[0] + pixels[1])).abs() < 1e-5);
+    }
+}
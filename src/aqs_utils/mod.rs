@@ -19,4 +19,7 @@ pub mod constants;
 pub mod extforcevol;
 pub mod scale;
 pub mod coneshape;
+pub mod billboard;
 pub mod mesh_of_squares;
+pub mod color;
+pub mod noise;
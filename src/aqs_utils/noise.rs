@@ -0,0 +1,132 @@
+/*
+    Copyright 2023 github.com/programaquatic
+
+   Licensed under the Apache License, Version 2.0 (the "License");
+   you may not use this file except in compliance with the License.
+   You may obtain a copy of the License at
+
+       http://www.apache.org/licenses/LICENSE-2.0
+
+   Unless required by applicable law or agreed to in writing, software
+   distributed under the License is distributed on an "AS IS" BASIS,
+   WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+   See the License for the specific language governing permissions and
+   limitations under the License.
+*/
+
+use bevy::prelude::*;
+
+
+/// upper bound of the slope of perlin_noise per unit of its input (sampled maximum is ~2.8)
+pub const PERLIN_MAX_SLOPE: f32 = 4.0;
+
+const GRADIENTS: [Vec2; 8] = [
+    Vec2::X, Vec2::NEG_X, Vec2::Y, Vec2::NEG_Y,
+    Vec2::new( std::f32::consts::FRAC_1_SQRT_2, std::f32::consts::FRAC_1_SQRT_2 ),
+    Vec2::new( -std::f32::consts::FRAC_1_SQRT_2, std::f32::consts::FRAC_1_SQRT_2 ),
+    Vec2::new( std::f32::consts::FRAC_1_SQRT_2, -std::f32::consts::FRAC_1_SQRT_2 ),
+    Vec2::new( -std::f32::consts::FRAC_1_SQRT_2, -std::f32::consts::FRAC_1_SQRT_2 ),
+];
+
+
+/// hash of an integer lattice point
+fn lattice_hash(seed: u32, x: i32, z: i32) -> u32 {
+    let mut h = seed
+        .wrapping_mul(0x9E37_79B9)
+        ^ (x as u32).wrapping_mul(0x85EB_CA6B)
+        ^ (z as u32).wrapping_mul(0xC2B2_AE35);
+    h ^= h >> 16;
+    h = h.wrapping_mul(0x7FEB_352D);
+    h ^= h >> 15;
+    h = h.wrapping_mul(0x846C_A68B);
+    h ^= h >> 16;
+    h
+}
+
+/// hash of an integer lattice point into [0..1)
+fn lattice_value(seed: u32, x: i32, z: i32) -> f32 {
+    (lattice_hash( seed, x, z ) >> 8) as f32 / (1u32 << 24) as f32
+}
+
+/// 2D value noise: smoothly interpolated lattice values in [0..1)
+pub fn value_noise(seed: u32, x: f32, z: f32) -> f32 {
+    let (x0, z0) = (x.floor(), z.floor());
+    let smooth = | t: f32 | t * t * (3.0 - 2.0 * t);
+    let (tx, tz) = (smooth( x - x0 ), smooth( z - z0 ));
+    let (ix, iz) = (x0 as i32, z0 as i32);
+
+    let top = lattice_value( seed, ix, iz ) * (1.0 - tx) + lattice_value( seed, ix + 1, iz ) * tx;
+    let bot = lattice_value( seed, ix, iz + 1 ) * (1.0 - tx) + lattice_value( seed, ix + 1, iz + 1 ) * tx;
+    top * (1.0 - tz) + bot * tz
+}
+
+/// 2D gradient (perlin) noise in [-1..1]; zero at the lattice points, which avoids the blocky look of value noise
+pub fn perlin_noise(seed: u32, p: Vec2) -> f32 {
+    let p0 = p.floor();
+    let f = p - p0;
+    let fade = | t: f32 | t * t * t * (t * (t * 6.0 - 15.0) + 10.0);
+    let (ix, iz) = (p0.x as i32, p0.y as i32);
+    let corner = | dx: i32, dz: i32 | {
+        let gradient = GRADIENTS[ (lattice_hash( seed, ix + dx, iz + dz ) & 7) as usize ];
+        gradient.dot( f - Vec2::new( dx as f32, dz as f32 ) )
+    };
+
+    let (u, v) = (fade( f.x ), fade( f.y ));
+    let top = corner( 0, 0 ) * (1.0 - u) + corner( 1, 0 ) * u;
+    let bot = corner( 0, 1 ) * (1.0 - u) + corner( 1, 1 ) * u;
+    // unit gradients give at most sqrt(0.5)
+    (top * (1.0 - v) + bot * v) * std::f32::consts::SQRT_2
+}
+
+
+/// Layered perlin noise as a height function of (x, z)
+///   every octave doubles the frequency and halves the amplitude of the previous one
+#[derive(Debug, Clone, Copy)]
+pub struct NoiseField {
+    pub seed: u32,
+    /// features per unit of the input
+    pub frequency: f32,
+    /// maximum height offset in either direction
+    pub amplitude: f32,
+    pub octaves: u32,
+}
+
+impl NoiseField {
+    fn weight_sum(&self) -> f32 {
+        (0..self.octaves.max( 1 )).map(| octave | 0.5f32.powi( octave as i32 )).sum()
+    }
+
+    /// height in [-amplitude..amplitude]
+    pub fn height(&self, p: Vec2) -> f32 {
+        let sum: f32 = (0..self.octaves.max( 1 )).map(| octave | {
+            let scale = 2.0f32.powi( octave as i32 );
+            perlin_noise( self.seed.wrapping_add( octave ), p * self.frequency * scale ) / scale
+        }).sum();
+        self.amplitude * sum / self.weight_sum()
+    }
+
+    /// upper bound of the height difference per unit distance
+    ///   each octave contributes the same slope: its amplitude halves while its frequency doubles
+    #[allow(dead_code)]
+    pub fn lipschitz_bound(&self) -> f32 {
+        self.amplitude.abs() * self.frequency.abs() * PERLIN_MAX_SLOPE * self.octaves.max( 1 ) as f32 / self.weight_sum()
+    }
+}
+
+
+
+#[cfg(test)]
+mod test
+{
+    use super::*;
+
+    #[test]
+    fn test_perlin_noise() {
+        // zero on the lattice, bounded and seeded in between
+        assert_eq!( perlin_noise( 3, Vec2::new( 4.0, -2.0 ) ), 0.0 );
+        let samples: Vec<f32> = (0..1000).map(| i | perlin_noise( 3, Vec2::new( i as f32 * 0.37, i as f32 * 0.11 ) )).collect();
+        assert!( samples.iter().all(| v | (-1.0..=1.0).contains( v )) );
+        assert!( samples.iter().any(| v | v.abs() > 0.3) );
+        assert_ne!( perlin_noise( 3, Vec2::splat( 0.5 ) ), perlin_noise( 4, Vec2::splat( 0.5 ) ) );
+    }
+}
@@ -15,10 +15,13 @@
 */
 
 use bevy::{
+    log::debug,
     math::f32::Vec3,
 };
 
 
+/// per-axis scaling between two coordinate systems (e.g. unit-grid and world)
+#[derive(Debug, Clone, Copy)]
 pub struct Scaler{
     to_scale: Vec3,
     from_scale: Vec3,  // is just 1./to_scale; but put it here to avoid divisions
@@ -38,7 +41,7 @@ impl Scaler {
     #[allow(dead_code)]
     pub fn from_vecs( origin: Vec3, target: Vec3 ) -> Self {
         let scale = target/origin;
-        println!("NewScaler: {}", scale );
+        debug!("new scaler: {}", scale );
         Self {
             to_scale: scale,
             from_scale: 1./scale,
@@ -62,6 +65,11 @@ impl Scaler {
         input * self.from_scale
     }
 
+    /// per-axis factors applied by `to`
+    pub fn get_scale( &self ) -> Vec3 {
+        self.to_scale
+    }
+
     #[allow(dead_code)]
     pub fn is_isometric( &self ) -> bool {
         self.to_scale.x == self.to_scale.y && self.to_scale.x == self.to_scale.z
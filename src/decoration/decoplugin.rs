@@ -23,8 +23,9 @@ use bevy_rapier3d::prelude::*;
 use crate::{
     tech::tank::Tank,
     decoration::{
-        types::DecorationTag,
+        types::{DecorationTag, DecorationShape, FloatingDecoration},
         ground,
+        materials,
     },
 };
 
@@ -41,32 +42,39 @@ impl Plugin for DecorationPlugin {
 }
 
 
-// Temporary function to place some 'obstacles' into the tank for debugging flow
+// place the configured decorations (spheres for now) into the tank
 fn initialize(
     tank_cfg: Res<Tank>,
     mut materials: ResMut<Assets<StandardMaterial>>,
     mut meshes: ResMut<Assets<Mesh>>,
     mut commands: Commands,
 ) {
-    let decoration_material_hdl = materials.add(StandardMaterial {
-        base_color: Color::linear_rgba(0.7, 0.8, 0.7, 1.0),
-        alpha_mode: AlphaMode::Opaque,
-        ..default()
-    });
-
-    // experimental 'rock'-sphere to see how the flow goes around the obstacle
-    let rock_mesh = Sphere::new(15.0 * tank_cfg.scale).mesh().ico(16).unwrap();
-    let collider = Collider::from_bevy_mesh( &rock_mesh, &ComputedColliderShape::TriMesh(TriMeshFlags::all()) ).unwrap();
-    let rock = commands.spawn((
-        Mesh3d(meshes.add(rock_mesh)),
-        MeshMaterial3d(decoration_material_hdl),
-        Transform::from_translation( Vec3::new( 80., 0.0, 35. ) * tank_cfg.scale ),
-    ))
-        .insert( collider )
-        .insert( RigidBody::Fixed )
-        .insert( DecorationTag )
-        .id();
-    commands.entity(tank_cfg.get_tank_parent()).add_child( rock );
+    for definition in tank_cfg.decorations.iter() {
+        let decoration_material_hdl = materials.add( materials::decoration_material( definition.material.as_deref() ) );
+
+        let radius = definition.radius * tank_cfg.scale;
+        let rock_mesh = Sphere::new(radius).mesh().ico(16).unwrap();
+        // a dynamic body needs a solid shape for its mass properties
+        let collider = if definition.floating {
+            Collider::ball( radius )
+        } else {
+            Collider::from_bevy_mesh( &rock_mesh, &ComputedColliderShape::TriMesh(TriMeshFlags::all()) ).unwrap()
+        };
+        let rock = commands.spawn((
+            Mesh3d(meshes.add(rock_mesh)),
+            MeshMaterial3d(decoration_material_hdl),
+            Transform::from_translation( definition.location * tank_cfg.scale ),
+        ))
+            .insert( collider )
+            .insert( DecorationTag )
+            .id();
+        if definition.floating {
+            commands.entity( rock ).insert(( RigidBody::Dynamic, ExternalForce::default(), FloatingDecoration ));
+        } else {
+            commands.entity( rock ).insert( RigidBody::Fixed );
+        }
+        commands.entity(tank_cfg.get_tank_parent()).add_child( rock );
+    }
 }
 
 
@@ -74,11 +82,15 @@ fn initialize(
 
 
 // get rid of decoration colliders because they're only needed during initialization for fluid grid cells to become solid
+//   the shape is kept to re-voxelize the grid when decorations get edited at runtime
+//   floating decorations keep their collider for the physics
 fn remove_colliders(
     mut commands: Commands,
-    colliders: Query<(Entity, &Collider), With<DecorationTag>>,
+    colliders: Query<(Entity, &Collider), (With<DecorationTag>, Without<FloatingDecoration>)>,
 ) {
-    colliders.iter().for_each( | (item, _) | {
-        commands.entity( item ).remove::<Collider>();
+    colliders.iter().for_each( | (item, collider) | {
+        commands.entity( item )
+            .insert( DecorationShape( collider.clone() ) )
+            .remove::<Collider>();
     })
 }
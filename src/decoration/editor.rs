@@ -0,0 +1,60 @@
+/*
+    Copyright 2023 github.com/programaquatic
+
+   Licensed under the Apache License, Version 2.0 (the "License");
+   you may not use this file except in compliance with the License.
+   You may obtain a copy of the License at
+
+       http://www.apache.org/licenses/LICENSE-2.0
+
+   Unless required by applicable law or agreed to in writing, software
+   distributed under the License is distributed on an "AS IS" BASIS,
+   WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+   See the License for the specific language governing permissions and
+   limitations under the License.
+*/
+
+use bevy::{
+    prelude::*,
+    ecs::system::SystemParam,
+};
+
+use crate::decoration::types::DecorationTag;
+
+
+/// Runtime access to the decorations of the tank (e.g. for an editor)
+///   moving or removing a decoration makes re_voxelize_decorations update the solid grid cells
+#[derive(SystemParam)]
+pub struct Decorations<'w, 's> {
+    decorations: Query<'w, 's, (Entity, &'static mut Transform), With<DecorationTag>>,
+    commands: Commands<'w, 's>,
+}
+
+impl Decorations<'_, '_> {
+    /// all decorations with their transform (relative to the tank)
+    pub fn list(&self) -> Vec<(Entity, Transform)> {
+        self.decorations.iter()
+            .map(| (entity, transform) | (entity, *transform))
+            .collect()
+    }
+
+    /// returns false if the entity is not a decoration
+    pub fn set_transform(&mut self, entity: Entity, transform: Transform) -> bool {
+        match self.decorations.get_mut( entity ) {
+            Ok( (_, mut current) ) => {
+                *current = transform;
+                true
+            },
+            Err( _ ) => false,
+        }
+    }
+
+    /// returns false if the entity is not a decoration
+    pub fn remove(&mut self, entity: Entity) -> bool {
+        if !self.decorations.contains( entity ) {
+            return false;
+        }
+        self.commands.entity( entity ).despawn_recursive();
+        true
+    }
+}
@@ -19,10 +19,17 @@ use bevy_rapier3d::prelude::*;
 
 use crate::{
     tech::tank::Tank,
-    aqs_utils::mesh_of_squares::MeshOfSquares,
+    aqs_utils::{
+        mesh_of_squares::{MeshOfSquares, Heightmap},
+        noise::NoiseField,
+    },
     decoration::types::DecorationTag,
 };
 
+/// dunes of the substrate without explicit noise or heightmap: a few features across the tank
+const DUNE_FREQUENCY: f32 = 0.1;
+const DUNE_OCTAVES: u32 = 3;
+
 
 pub fn ground(
     tank_cfg: Res<Tank>,
@@ -34,14 +41,54 @@ pub fn ground(
     let sscale = Vec3::new( tank_cfg.get_size().x / sgrid_size.x as f32,
                             1.0,
                             tank_cfg.get_size().z / sgrid_size.y as f32 );
-    dbg!(tank_cfg.get_size());
+    debug!("substrate for tank size {}", tank_cfg.get_size());
     let sgrid_scale = Vec2::splat( 1.0 );
     let sgrid_uv_scale = Vec2::new(1. / sgrid_size.x as f32, 1. / sgrid_size.y as f32);
     // // let sgrid_uv_scale = Vec2::splat(1.0);
-    let ground_mesh = MeshOfSquares::new(sgrid_size + 1, sgrid_scale, sgrid_uv_scale)
-        .randomize_position((-0.2, 0.5))  // roughness of surface
-        .randomize_normals(0.002)         // bumpiness via normals
-        .into_mesh();
+    let ground = MeshOfSquares::new(sgrid_size + 1, sgrid_scale, sgrid_uv_scale);
+    let (ground, ground_offset) = match &tank_cfg.substrate {
+        Some( substrate ) => {
+            // mesh z grows towards the front, so the rise towards the back is a negative gradient
+            let ground = ground
+                .slope( substrate.depth + substrate.rise.y, Vec2::new( substrate.rise.x, -substrate.rise.y ) );
+            let heightmap = substrate.heightmap.as_ref().and_then(| heightmap | {
+                Heightmap::load( &heightmap.path )
+                    .map(| map | (map, heightmap.height))
+                    .map_err(| e | warn!("substrate heightmap not used: {}", e))
+                    .ok()
+            });
+            match (heightmap, &substrate.noise) {
+                (Some( (map, height) ), _) => (ground.heightmap_position( &map, height ).recompute_normals(), 0.0),
+                (None, Some( noise )) => (ground
+                         .noise_position( noise.seed, noise.frequency, noise.amplitude )
+                         .recompute_normals(),
+                     0.0),
+                (None, None) => {
+                    let dunes = NoiseField {
+                        seed: 0,
+                        frequency: DUNE_FREQUENCY,
+                        amplitude: substrate.roughness,
+                        octaves: DUNE_OCTAVES,
+                    };
+                    (ground
+                         .apply_heightmap(| p | dunes.height( p ))
+                         .recompute_normals()
+                         .randomize_normals(0.002),         // bumpiness via normals
+                     0.0)
+                },
+            }
+        },
+        None => {
+            // roughness of surface: -0.1..0.25 like the former random offsets
+            let dunes = NoiseField { seed: 0, frequency: DUNE_FREQUENCY, amplitude: 0.175, octaves: DUNE_OCTAVES };
+            (ground
+                 .apply_heightmap(| p | 0.075 + dunes.height( p ))
+                 .recompute_normals()
+                 .randomize_normals(0.002),
+             2.0)
+        },
+    };
+    let ground_mesh = ground.into_mesh();
     let gmesh_hdl = meshes.add(ground_mesh.clone());
 
 
@@ -57,11 +104,11 @@ pub fn ground(
     });
 
     let collider = Collider::from_bevy_mesh( &ground_mesh, &ComputedColliderShape::TriMesh(TriMeshFlags::all()) ).unwrap();
-    let _ground_surface = commands
+    let ground_surface = commands
         .spawn((
             Mesh3d(gmesh_hdl),
             MeshMaterial3d(mt_hdl),
-            Transform::from_translation(Vec3::Y * 2.0)
+            Transform::from_translation(Vec3::Y * ground_offset)
                 .with_scale(sscale),
             Visibility::default(),
         ))
@@ -69,4 +116,5 @@ pub fn ground(
         .insert( RigidBody::Fixed )
         .insert( DecorationTag )
         .id();
+    commands.entity(tank_cfg.get_tank_parent()).add_child( ground_surface );
 }
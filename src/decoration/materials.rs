@@ -0,0 +1,112 @@
+/*
+    Copyright 2023 github.com/programaquatic
+
+   Licensed under the Apache License, Version 2.0 (the "License");
+   you may not use this file except in compliance with the License.
+   You may obtain a copy of the License at
+
+       http://www.apache.org/licenses/LICENSE-2.0
+
+   Unless required by applicable law or agreed to in writing, software
+   distributed under the License is distributed on an "AS IS" BASIS,
+   WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+   See the License for the specific language governing permissions and
+   limitations under the License.
+*/
+
+use bevy::{
+    prelude::*,
+    render::render_resource::Face,
+};
+
+
+/// Look of a decoration material, referenced by name from the decoration config
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MaterialPreset {
+    pub color: [f32; 4],
+    pub roughness: f32,
+    pub metallic: f32,
+    pub reflectance: f32,
+}
+
+/// material of decorations without a (known) preset
+pub const DEFAULT_PRESET: MaterialPreset = MaterialPreset {
+    color: [ 0.7, 0.8, 0.7, 1.0 ],
+    roughness: 0.5,
+    metallic: 0.0,
+    reflectance: 0.5,
+};
+
+const PRESETS: [(&str, MaterialPreset); 4] = [
+    ("rock", MaterialPreset { color: [ 0.45, 0.45, 0.43, 1.0 ], roughness: 0.95, metallic: 0.0, reflectance: 0.3 }),
+    ("wood", MaterialPreset { color: [ 0.35, 0.2, 0.1, 1.0 ], roughness: 0.9, metallic: 0.0, reflectance: 0.1 }),
+    ("sand", MaterialPreset { color: [ 0.8, 0.7, 0.5, 1.0 ], roughness: 1.0, metallic: 0.0, reflectance: 0.2 }),
+    ("plant", MaterialPreset { color: [ 0.15, 0.55, 0.15, 0.7 ], roughness: 0.6, metallic: 0.0, reflectance: 0.4 }),
+];
+
+
+/// the preset with the given name; None for unknown names
+pub fn find_preset(name: &str) -> Option<MaterialPreset> {
+    PRESETS.iter()
+        .find(| (preset, _) | *preset == name)
+        .map(| (_, preset) | *preset)
+}
+
+impl MaterialPreset {
+    pub fn to_material(&self) -> StandardMaterial {
+        let [r, g, b, a] = self.color;
+        StandardMaterial {
+            base_color: Color::linear_rgba( r, g, b, a ),
+            perceptual_roughness: self.roughness,
+            metallic: self.metallic,
+            reflectance: self.reflectance,
+            alpha_mode: if a < 1.0 { AlphaMode::Blend } else { AlphaMode::Opaque },
+            // translucent leaves are visible from both sides
+            double_sided: a < 1.0,
+            cull_mode: if a < 1.0 { None } else { Some( Face::Back ) },
+            ..default()
+        }
+    }
+}
+
+/// material for a decoration; unknown or missing preset names fall back to DEFAULT_PRESET
+pub fn decoration_material(name: Option<&str>) -> StandardMaterial {
+    let preset = match name {
+        Some( name ) => find_preset( name ).unwrap_or_else(|| {
+            warn!("unknown decoration material {}; using the default", name);
+            DEFAULT_PRESET
+        }),
+        None => DEFAULT_PRESET,
+    };
+    preset.to_material()
+}
+
+
+
+#[cfg(test)]
+mod test
+{
+    use super::*;
+
+    #[test]
+    fn test_material_presets() {
+        let wood = decoration_material( Some( "wood" ) );
+        let brown = wood.base_color.to_linear();
+        assert!( brown.red > brown.green && brown.green > brown.blue );
+        // matte
+        assert!( wood.perceptual_roughness >= 0.8 && wood.reflectance <= 0.2 && wood.metallic == 0.0 );
+        assert_eq!( wood.alpha_mode, AlphaMode::Opaque );
+
+        let rock = decoration_material( Some( "rock" ) );
+        let gray = rock.base_color.to_linear();
+        assert!( (gray.red - gray.green).abs() < 0.05 && (gray.green - gray.blue).abs() < 0.05 );
+        assert!( rock.perceptual_roughness >= 0.9 );
+
+        let plant = decoration_material( Some( "plant" ) );
+        assert_eq!( plant.alpha_mode, AlphaMode::Blend );
+
+        let fallback = decoration_material( Some( "marble" ) );
+        assert_eq!( fallback.base_color, DEFAULT_PRESET.to_material().base_color );
+        assert_eq!( decoration_material( None ).base_color, fallback.base_color );
+    }
+}
@@ -17,3 +17,5 @@
 pub mod types;
 pub mod decoplugin;
 pub mod ground;
+pub mod editor;
+pub mod materials;
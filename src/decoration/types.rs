@@ -15,6 +15,15 @@
 */
 
 use bevy::prelude::*;
+use bevy_rapier3d::prelude::Collider;
 
 #[derive(Component)]
 pub struct DecorationTag;
+
+/// Decoration that keeps its collider and moves with the fluid (RigidBody::Dynamic)
+#[derive(Component)]
+pub struct FloatingDecoration;
+
+/// Shape of a decoration kept after its physics collider got removed (for re-voxelizing the grid)
+#[derive(Component)]
+pub struct DecorationShape(pub Collider);
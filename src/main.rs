@@ -35,41 +35,48 @@ mod water;
 
 /// set up a simple 3D scene
 fn setup(
+    constants: Res<aqs_utils::constants::Constants>,
     mut commands: Commands,
     mut meshes: ResMut<Assets<Mesh>>,
     mut materials: ResMut<Assets<StandardMaterial>>,
     asset_server: Res<AssetServer>,
 ) {
-    // create a textured background so that any potential reflections and/or water surface vectors become more visible
-    let text_hdl = Some(asset_server.load("textures/flower_background.png"));
-    commands.spawn((
-        Mesh3d(meshes.add(Plane3d::default().mesh().size(500., 500.))),
-        MeshMaterial3d(materials.add(StandardMaterial {
-            base_color: Color::linear_rgba(0.8, 0.7, 0.1, 1.0),
-            base_color_texture: text_hdl.clone(),
-            // emissive: (),
-            emissive_texture: text_hdl,
-            // perceptual_roughness: (),
-            // metallic: (),
-            // metallic_roughness_texture: (),
-            // reflectance: (),
-            // normal_map_texture: (),
-            // flip_normal_map_y: (),
-            // occlusion_texture: (),
-            // double_sided: (),
-            // cull_mode: (),
-            // unlit: (),
-            // alpha_mode: (),
-            // depth_bias: ()
-            ..default()
-        })),
-        Transform::from_xyz(0.0, 50., -50.)
-            .with_rotation( Quat::from_rotation_x( std::f32::consts::PI / 2.) ),
-    ));
+    if constants.SHOW_DEBUG_BACKGROUND {
+        // create a textured background so that any potential reflections and/or water surface vectors become more visible
+        let text_hdl = Some(asset_server.load("textures/flower_background.png"));
+        commands.spawn((
+            Mesh3d(meshes.add(Plane3d::default().mesh().size(500., 500.))),
+            MeshMaterial3d(materials.add(StandardMaterial {
+                base_color: Color::linear_rgba(0.8, 0.7, 0.1, 1.0),
+                base_color_texture: text_hdl.clone(),
+                // emissive: (),
+                emissive_texture: text_hdl,
+                // perceptual_roughness: (),
+                // metallic: (),
+                // metallic_roughness_texture: (),
+                // reflectance: (),
+                // normal_map_texture: (),
+                // flip_normal_map_y: (),
+                // occlusion_texture: (),
+                // double_sided: (),
+                // cull_mode: (),
+                // unlit: (),
+                // alpha_mode: (),
+                // depth_bias: ()
+                ..default()
+            })),
+            Transform::from_xyz(0.0, 50., -50.)
+                .with_rotation( Quat::from_rotation_x( std::f32::consts::PI / 2.) ),
+        ));
+    } else {
+        // neutral backdrop instead of the textured plane
+        commands.insert_resource(ClearColor(Color::srgb(0.35, 0.38, 0.42)));
+    }
 
 
     commands.spawn((
         DirectionalLight {
+            color: constants.LIGHT_COLOR.to_color(),
             illuminance: light_consts::lux::OVERCAST_DAY,
             shadows_enabled: true,
             ..default()
@@ -89,17 +96,93 @@ fn setup(
         }
         .build(),
         ));
+
+    if constants.FILL_LIGHT > 0.0 {
+        // dimmer light from the front/below without shadows so the substrate and back corners don't end up black
+        commands.spawn((
+            Name::new("Fill_Light"),
+            DirectionalLight {
+                color: constants.LIGHT_COLOR.to_color(),
+                illuminance: constants.FILL_LIGHT,
+                shadows_enabled: false,
+                ..default()
+            },
+            Transform::from_xyz(-60., 40.0, 120.0).looking_at(Vec3::ZERO, Vec3::Y),
+        ));
+    }
+    if let Some( brightness ) = constants.AMBIENT_LIGHT {
+        commands.insert_resource(AmbientLight {
+            color: constants.LIGHT_COLOR.to_color(),
+            brightness,
+        });
+    }
+}
+
+
+/// value of a command line option like --template <name>
+fn arg_value(option: &str) -> Option<String> {
+    let args: Vec<String> = std::env::args().collect();
+    args.iter().position(|arg| arg == option).and_then(|i| args.get(i + 1)).cloned()
 }
 
+/// the built-in tank selected with --template <name> (instead of the config files)
+fn selected_template() -> Option<tech::templates::SelectedTemplate> {
+    let name = arg_value("--template")?;
+    match tech::templates::TankTemplate::from_name(&name) {
+        Some( template ) => Some( tech::templates::SelectedTemplate(template) ),
+        None => {
+            let names: Vec<&str> = tech::templates::TankTemplate::ALL.iter().map(|t| t.name()).collect();
+            println!("WARNING: unknown template {}; available: {}", name, names.join(", "));
+            std::process::exit(1);
+        }
+    }
+}
+
+/// the seed for reproducible particle initialization (--seed <n>)
+fn selected_seed() -> Option<water::resources::SimSeed> {
+    let seed = arg_value("--seed")?;
+    match seed.parse::<u64>() {
+        Ok( seed ) => Some( water::resources::SimSeed(seed) ),
+        Err( _ ) => {
+            println!("WARNING: --seed expects a number, got {}", seed);
+            std::process::exit(1);
+        }
+    }
+}
 
 fn main() {
-    App::new()
+    let template = selected_template();
+    let seed = selected_seed();
+
+    // quick headless check whether the config produces a stable simulation
+    //   --bench <steps> runs longer; --report <path> writes a JSON summary of the run
+    let bench_steps = arg_value("--bench").map(|steps| steps.parse::<usize>().unwrap_or_else(|_| {
+        println!("WARNING: --bench expects a number of steps, got {}", steps);
+        std::process::exit(1);
+    }));
+    if bench_steps.is_some() || std::env::args().any(|arg| arg == "--validate") {
+        let steps = bench_steps.unwrap_or(water::validate::DEFAULT_VALIDATION_STEPS);
+        let report = arg_value("--report").map(std::path::PathBuf::from);
+        let exit = water::validate::run(steps, template, seed, report, bench_steps.is_some());
+        std::process::exit( if exit.is_success() { 0 } else { 1 } );
+    }
+
+    let mut app = App::new();
+    // has to be in place before the plugins load the tank and the constants
+    if let Some( template ) = template {
+        app.insert_resource(template);
+    }
+    if let Some( seed ) = seed {
+        app.insert_resource(seed);
+    }
+    app
         .add_plugins(DefaultPlugins) //.set(CorePlugin { task_pool_options: TaskPoolOptions::with_num_threads(8), }))
         .add_systems(Startup, setup)
 
         // Diagnostics and Inspectors
         // .add_plugins(LogDiagnosticsPlugin::default())
-        // .add_plugins(FrameTimeDiagnosticsPlugin)
+        // frame times for the particle budget (PARTICLE_BUDGET)
+        .add_plugins(FrameTimeDiagnosticsPlugin)
         // .add_plugins(WorldInspectorPlugin::new())
 
         // old Rapier/Physics experiments
@@ -110,7 +193,10 @@ fn main() {
         .add_plugins(tech::tank::TankPlugin)
         .add_plugins(tech::cam::AquaSimCamPlugin)
         .add_plugins(decoration::decoplugin::DecorationPlugin)
-        .add_plugins(water::fluid::FluidPlugin)
+        .add_plugins(water::fluid::FluidPlugin);
+
+    #[cfg(feature = "egui")]
+    app.add_plugins(tech::config_panel::ConfigPanelPlugin);
 
-        .run();
+    app.run();
 }
@@ -19,11 +19,15 @@ use bevy::{
     input::mouse::{
         MouseMotion,
         MouseWheel
-    }
+    },
+    render::camera::ScalingMode,
 };
 use serde::{Serialize, Deserialize};
 
-use crate::tech::tank::Tank;
+use crate::{
+    tech::tank::Tank,
+    aqs_utils::constants::Constants,
+};
 
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -42,6 +46,119 @@ pub struct AquaSimCamElement(CameraElement);
 // Camera Scroll Factor
 const CSFACTOR: f32 = 0.5;
 const CCLOSEST: f32 = 2.0;
+// initial distance of the camera to the panning point
+const CINITIAL_DISTANCE: f32 = 80.0;
+// panning per pixel of mouse motion and unit of camera distance (0.025 at the initial distance)
+const CPAN_FACTOR: f32 = 0.25 * (CSFACTOR / 5.0) / CINITIAL_DISTANCE;
+// duration of the transition to a preset view in seconds
+const PRESET_TRANSITION_SECS: f32 = 0.3;
+
+
+/// Predefined camera angles, selected with the number keys 1-4
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CameraPreset {
+    Front,
+    Top,
+    Side,
+    Hero,
+}
+
+/// transforms of the panning point, orbit handle and camera that make up one view
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CameraPose {
+    pub pan: Vec3,
+    pub orbit: Quat,
+    pub cam: Transform,
+}
+
+impl CameraPose {
+    /// view of the tank centered at `center` from the preset angle at a distance that fits the whole tank
+    pub fn from_preset(preset: CameraPreset, center: Vec3, tank_size: Vec3) -> Self {
+        let orbit = match preset {
+            CameraPreset::Front => Quat::IDENTITY,
+            CameraPreset::Top => Quat::from_rotation_x(-std::f32::consts::FRAC_PI_2),
+            CameraPreset::Side => Quat::from_rotation_y(std::f32::consts::FRAC_PI_2),
+            CameraPreset::Hero => Quat::from_rotation_y(std::f32::consts::FRAC_PI_4)
+                * Quat::from_rotation_x(-std::f32::consts::FRAC_PI_6),
+        };
+        CameraPose {
+            pan: center,
+            orbit,
+            cam: Transform::from_translation(Vec3::Z * framing_distance(tank_size))
+                .looking_at(Vec3::ZERO, Vec3::Y),
+        }
+    }
+
+    /// eased interpolation between two poses for t in [0..1]
+    pub fn interpolate(&self, other: &CameraPose, t: f32) -> Self {
+        let t = t.clamp(0.0, 1.0);
+        let t = t * t * (3.0 - 2.0 * t);
+        CameraPose {
+            pan: self.pan.lerp(other.pan, t),
+            orbit: self.orbit.slerp(other.orbit, t),
+            cam: Transform {
+                translation: self.cam.translation.lerp(other.cam.translation, t),
+                rotation: self.cam.rotation.slerp(other.cam.rotation, t),
+                scale: self.cam.scale.lerp(other.cam.scale, t),
+            },
+        }
+    }
+}
+
+/// camera distance at which the bounding sphere of the tank fits into the default field of view
+pub fn framing_distance(tank_size: Vec3) -> f32 {
+    let fov = PerspectiveProjection::default().fov;
+    f32::max(CCLOSEST, 0.5 * tank_size.length() / (0.5 * fov).tan())
+}
+
+/// world space translation of the panning point for a mouse motion
+///   moves along the image plane of the camera at `cam` (relative to the orbit handle) looking at the panning point;
+///   the further away the camera, the larger the step
+pub fn pan_vector(delta: Vec2, orbit: Quat, cam: Vec3) -> Vec3 {
+    let view = orbit * Transform::from_translation(cam).looking_at(Vec3::ZERO, Vec3::Y).rotation;
+    let right = view * Vec3::X * -delta.x;
+    let up = view * Vec3::Y * delta.y;
+    (right + up) * CPAN_FACTOR * cam.length()
+}
+
+/// visible height of the orthographic view that fits the whole tank (from any angle)
+pub fn ortho_scale(tank_size: Vec3) -> f32 {
+    f32::max(CCLOSEST, tank_size.length())
+}
+
+/// orthographic projection that shows `scale` world units vertically
+fn orthographic(scale: f32) -> Projection {
+    Projection::Orthographic(OrthographicProjection {
+        scaling_mode: ScalingMode::FixedVertical { viewport_height: 1.0 },
+        scale,
+        ..OrthographicProjection::default_3d()
+    })
+}
+
+/// Where the mouse input wants the camera elements to be; the actual transforms ease towards it
+#[derive(Resource, Debug, Clone, Copy)]
+pub struct CameraTargets {
+    pub pan: Vec3,
+    pub orbit: Quat,
+    pub cam: Vec3,
+}
+
+/// fraction of the remaining distance to the target covered in `dt` (exponential smoothing)
+///   frame rate independent; responsiveness <= 0 disables the smoothing
+pub fn smoothing_factor(responsiveness: f32, dt: f32) -> f32 {
+    if responsiveness <= 0.0 {
+        return 1.0;
+    }
+    1.0 - (-responsiveness * dt).exp()
+}
+
+/// Ongoing transition of the camera towards a preset view
+#[derive(Resource, Default)]
+pub struct CameraTransition {
+    start: Option<CameraPose>,
+    target: Option<CameraPose>,
+    elapsed: f32,
+}
 
 
 pub struct AquaSimCamPlugin;
@@ -49,8 +166,13 @@ pub struct AquaSimCamPlugin;
 impl Plugin for AquaSimCamPlugin {
     fn build(&self, app: &mut App) {
         app
+            .init_resource::<CameraTransition>()
             .add_systems( Startup, initialize)
-            .add_systems( Update, move_cam);
+            .add_systems( Update, move_cam)
+            .add_systems( Update, toggle_projection)
+            .add_systems( Update, select_preset_view.before(animate_camera_transition))
+            .add_systems( Update, animate_camera_transition.after(move_cam))
+            .add_systems( Update, ease_camera.after(animate_camera_transition));
     }
 }
 
@@ -60,7 +182,7 @@ fn initialize(
     mut meshes: ResMut<Assets<Mesh>>,
     mut materials: ResMut<Assets<StandardMaterial>>,
 ) {
-    let initial_cam = Vec3::new(0.0, 50.0, 200.0).normalize() * 80.0;
+    let initial_cam = Vec3::new(0.0, 50.0, 200.0).normalize() * CINITIAL_DISTANCE;
 
     // cam_center is the transparent parent of the camera to simplify the cam-panning
     // all panning happens within this parent
@@ -73,7 +195,7 @@ fn initialize(
                 alpha_mode: AlphaMode::Opaque,
                 ..default()
             })),
-            Transform::from_translation( tank_cfg.get_center() ),
+            Transform::from_translation( tank_cfg.get_world_center() ),
         ))
             // TransformBundle::from_transform(
             //     Transform::from_translation(tank_cfg.get_center())
@@ -103,6 +225,12 @@ fn initialize(
 
     commands.entity(cam_center_parent).add_child(cam_holder);
     commands.entity(cam_holder).add_child(cam);
+
+    commands.insert_resource(CameraTargets {
+        pan: tank_cfg.get_world_center(),
+        orbit: Quat::IDENTITY,
+        cam: initial_cam,
+    });
 }
 
 
@@ -111,7 +239,8 @@ fn move_cam(
     mut ev_motion: EventReader<MouseMotion>,
     mut ev_scroll: EventReader<MouseWheel>,
     input_mouse: Res<ButtonInput<MouseButton>>,
-    mut camera_elements: Query<(&mut Transform, &AquaSimCamElement)>,
+    mut targets: ResMut<CameraTargets>,
+    mut projections: Query<&mut Projection, With<AquaSimCamElement>>,
 ) {
     let window = windows.single();
     let orbit = MouseButton::Right;
@@ -140,42 +269,243 @@ fn move_cam(
         }
     }
 
+    if move_pan.length_squared() > 0.0 {
+        let pan = pan_vector( move_pan, targets.orbit, targets.cam );
+        targets.pan += pan;
+    }
+    if move_orbit.length_squared() > 0.0 {
+        let window = get_primary_window_size(window);
+        let delta_x = move_orbit.x / window.x * std::f32::consts::PI * 2.0;
+        let delta_y = move_orbit.y / window.y * std::f32::consts::PI;
+
+        // rotational axis Y (horizontal rotation)
+        let decl = Quat::from_rotation_y(-delta_x);
+
+        // rotational axis from orthogonal vector in XZ plane
+        let asct = Quat::from_rotation_x(-delta_y);
+        targets.orbit = decl * targets.orbit * asct;
+    }
+    if scroll.abs() > 0.0 {
+        scroll *= CSFACTOR;
+        // the distance doesn't change the size of an orthographic view, zoom by its scale instead
+        if let Ok( mut projection ) = projections.get_single_mut() {
+            if let Projection::Orthographic( ortho ) = projection.as_mut() {
+                ortho.scale = (ortho.scale + scroll).clamp(CCLOSEST, 1000.0);
+                return;
+            }
+        }
+        targets.cam = (targets.cam + (targets.cam.normalize() * scroll )).clamp_length(CCLOSEST, 1000.0);
+    }
+}
+
+/// O switches between perspective and orthographic projection; the camera transforms stay as they are
+fn toggle_projection(
+    keys: Res<ButtonInput<KeyCode>>,
+    tank_cfg: Res<Tank>,
+    mut projections: Query<&mut Projection, With<AquaSimCamElement>>,
+) {
+    if !keys.just_pressed(KeyCode::KeyO) {
+        return;
+    }
+    for mut projection in projections.iter_mut() {
+        *projection = match *projection {
+            Projection::Orthographic( _ ) => Projection::Perspective( PerspectiveProjection::default() ),
+            _ => orthographic( ortho_scale( tank_cfg.get_size() ) ),
+        };
+    }
+}
+
+/// move the camera elements a frame rate independent step towards their targets
+fn ease_camera(
+    time: Res<Time>,
+    constants: Res<Constants>,
+    targets: Res<CameraTargets>,
+    mut camera_elements: Query<(&mut Transform, &AquaSimCamElement)>,
+) {
+    let factor = smoothing_factor( constants.CAMERA_RESPONSIVENESS, time.delta_secs() );
     for (mut transform, element) in camera_elements.iter_mut() {
         match element.0 {
-            // TODO: panning needs to be orthogonal to the current angle of the orbit
-            CameraElement::PanningPoint => {
-                if move_pan.length_squared() > 0.0 {
-                    let right = Vec3::X * -move_pan.x * 0.25;
-                    let up = Vec3::Y * move_pan.y * 0.25;
-                    transform.translation += (right + up) * (CSFACTOR/5.0);
-                }
-            }
-            CameraElement::OrbitHandle => {
-                if move_orbit.length_squared() > 0.0 {
-                    let window = get_primary_window_size(window);
-                    let delta_x = move_orbit.x / window.x * std::f32::consts::PI * 2.0;
-                    let delta_y = move_orbit.y / window.y * std::f32::consts::PI;
-
-                    // rotational axis Y (horizontal rotation)
-                    let decl = Quat::from_rotation_y(-delta_x);
-
-                    // rotational axis from orthogonal vector in XZ plane
-                    let asct = Quat::from_rotation_x(-delta_y);
-                    transform.rotation = decl * transform.rotation * asct;
-                }
-            }
-            CameraElement::Camera => {
-                if scroll.abs() > 0.0 {
-                    scroll *= CSFACTOR;
-                    transform.translation = (transform.translation + (transform.translation.normalize() * scroll )).clamp_length(CCLOSEST, 1000.0);
-                }
-            }
+            CameraElement::PanningPoint => transform.translation = transform.translation.lerp(targets.pan, factor),
+            CameraElement::OrbitHandle => transform.rotation = transform.rotation.slerp(targets.orbit, factor),
+            CameraElement::Camera => transform.translation = transform.translation.lerp(targets.cam, factor),
         };
     }
 }
 
+fn select_preset_view(
+    keys: Res<ButtonInput<KeyCode>>,
+    tank_cfg: Res<Tank>,
+    mut transition: ResMut<CameraTransition>,
+) {
+    let preset = if keys.just_pressed(KeyCode::Digit1) {
+        CameraPreset::Front
+    } else if keys.just_pressed(KeyCode::Digit2) {
+        CameraPreset::Top
+    } else if keys.just_pressed(KeyCode::Digit3) {
+        CameraPreset::Side
+    } else if keys.just_pressed(KeyCode::Digit4) {
+        CameraPreset::Hero
+    } else {
+        return;
+    };
+    transition.target = Some( CameraPose::from_preset( preset, tank_cfg.get_world_center(), tank_cfg.get_size() ) );
+    // the start pose gets captured from the current transforms on the next update
+    transition.start = None;
+    transition.elapsed = 0.0;
+}
+
+fn animate_camera_transition(
+    time: Res<Time>,
+    mut transition: ResMut<CameraTransition>,
+    mut targets: ResMut<CameraTargets>,
+    mut camera_elements: Query<(&mut Transform, &AquaSimCamElement)>,
+) {
+    let Some( target ) = transition.target else {
+        return;
+    };
+    if transition.start.is_none() {
+        let mut start = target;
+        for (transform, element) in camera_elements.iter() {
+            match element.0 {
+                CameraElement::PanningPoint => start.pan = transform.translation,
+                CameraElement::OrbitHandle => start.orbit = transform.rotation,
+                CameraElement::Camera => start.cam = *transform,
+            }
+        }
+        transition.start = Some( start );
+    }
+
+    transition.elapsed += time.delta_secs();
+    let t = transition.elapsed / PRESET_TRANSITION_SECS;
+    let pose = transition.start.unwrap().interpolate( &target, t );
+    // keep the targets in sync so the smoothing doesn't pull the camera back
+    *targets = CameraTargets { pan: pose.pan, orbit: pose.orbit, cam: pose.cam.translation };
+    for (mut transform, element) in camera_elements.iter_mut() {
+        match element.0 {
+            CameraElement::PanningPoint => transform.translation = pose.pan,
+            CameraElement::OrbitHandle => transform.rotation = pose.orbit,
+            CameraElement::Camera => *transform = pose.cam,
+        }
+    }
+    if t >= 1.0 {
+        *transition = CameraTransition::default();
+    }
+}
+
 
 fn get_primary_window_size(windows: &Window) -> Vec2 {
     let window = windows;
     Vec2::new(window.width(), window.height())
 }
+
+
+
+#[cfg(test)]
+mod test
+{
+    use super::*;
+
+    #[test]
+    fn test_presets_frame_tank() {
+        let tank_size = Vec3::new(80.0, 40.0, 30.0);
+        let center = Vec3::new(40.0, 20.0, 15.0);
+        let half_fov = 0.5 * PerspectiveProjection::default().fov;
+        for preset in [CameraPreset::Front, CameraPreset::Top, CameraPreset::Side, CameraPreset::Hero] {
+            let pose = CameraPose::from_preset(preset, center, tank_size);
+            // world position of the camera and its distance to the tank center
+            let cam_pos = pose.pan + pose.orbit * pose.cam.translation;
+            let distance = cam_pos.distance(center);
+            // the bounding sphere of the tank fits into the view cone
+            assert!(distance * half_fov.tan() >= 0.5 * tank_size.length() - 1e-3);
+            // the camera looks at the center
+            let forward = pose.orbit * pose.cam.forward();
+            assert!(forward.dot((center - cam_pos).normalize()) > 0.999);
+        }
+    }
+
+    #[test]
+    fn test_pan_follows_orbit() {
+        let cam = Vec3::Z * CINITIAL_DISTANCE;
+        // unrotated: dragging left moves the panning point along world x, dragging down along world y
+        let pan = pan_vector(Vec2::new(-4.0, 0.0), Quat::IDENTITY, cam);
+        assert!(pan.normalize().abs_diff_eq(Vec3::X, 1e-5));
+        assert!((pan.length() - 4.0 * 0.025).abs() < 1e-5);
+        assert!(pan_vector(Vec2::new(0.0, 4.0), Quat::IDENTITY, cam).normalize().abs_diff_eq(Vec3::Y, 1e-5));
+
+        // orbited by 90 degrees around y: the same motion moves along the rotated right vector
+        let orbit = Quat::from_rotation_y(std::f32::consts::FRAC_PI_2);
+        let pan = pan_vector(Vec2::new(-4.0, 0.0), orbit, cam);
+        assert!(pan.normalize().abs_diff_eq(orbit * Vec3::X, 1e-5));
+        // always within the image plane
+        let forward = orbit * -cam.normalize();
+        assert!(pan_vector(Vec2::new(3.0, -7.0), orbit, cam).dot(forward).abs() < 1e-4);
+
+        // twice the distance, twice the step
+        let near = pan_vector(Vec2::new(5.0, 2.0), orbit, cam).length();
+        let far = pan_vector(Vec2::new(5.0, 2.0), orbit, cam * 2.0).length();
+        assert!((far - 2.0 * near).abs() < 1e-4);
+    }
+
+    #[test]
+    fn test_ortho_scale() {
+        let tank_size = Vec3::new(80.0, 40.0, 30.0);
+        let scale = ortho_scale(tank_size);
+        // the tank fits from every angle, the diagonal being the widest extent
+        assert!(scale >= tank_size.max_element());
+        assert!((scale - tank_size.length()).abs() < 1e-4);
+        // grows with the tank, but never collapses
+        assert!(ortho_scale(tank_size * 2.0) > scale);
+        assert_eq!(ortho_scale(Vec3::ZERO), CCLOSEST);
+
+        let Projection::Orthographic(ortho) = orthographic(scale) else {
+            panic!("expected an orthographic projection");
+        };
+        assert_eq!(ortho.scale, scale);
+    }
+
+    #[test]
+    fn test_pose_interpolation() {
+        let a = CameraPose::from_preset(CameraPreset::Front, Vec3::ZERO, Vec3::splat(10.0));
+        let b = CameraPose::from_preset(CameraPreset::Top, Vec3::X, Vec3::splat(20.0));
+        let start = a.interpolate(&b, 0.0);
+        assert_eq!(start.pan, a.pan);
+        assert!(start.orbit.abs_diff_eq(a.orbit, 1e-5));
+        assert!(start.cam.translation.abs_diff_eq(a.cam.translation, 1e-4));
+        assert_eq!(a.interpolate(&b, 1.0).pan, b.pan);
+        assert!(a.interpolate(&b, 1.0).orbit.abs_diff_eq(b.orbit, 1e-5));
+
+        // eased: slow start, half way at the midpoint
+        let quarter = a.interpolate(&b, 0.25).pan.x;
+        let half = a.interpolate(&b, 0.5).pan.x;
+        assert!(quarter < 0.25);
+        assert!((half - 0.5).abs() < 1e-5);
+    }
+
+    #[test]
+    fn test_smoothing_settles() {
+        assert_eq!(smoothing_factor(0.0, 0.016), 1.0);
+
+        // a flick moves the target far away; the camera follows without snapping and settles
+        let target = 10.0;
+        let mut position = 0.0;
+        let mut last_step = f32::MAX;
+        for frame in 0..40 {
+            let step = (target - position) * smoothing_factor(12.0, 1.0 / 60.0);
+            if frame == 0 {
+                assert!(step < 0.5 * target);
+            }
+            assert!(step > 0.0 && step < last_step);
+            last_step = step;
+            position += step;
+        }
+        assert!((target - position).abs() < 0.01);
+
+        // frame rate independent: two half steps equal one full step
+        let half = smoothing_factor(12.0, 0.01);
+        let full = smoothing_factor(12.0, 0.02);
+        assert!((1.0 - (1.0 - half) * (1.0 - half) - full).abs() < 1e-6);
+
+        // higher responsiveness covers more distance per frame
+        assert!(smoothing_factor(20.0, 0.016) > smoothing_factor(5.0, 0.016));
+    }
+}
@@ -0,0 +1,187 @@
+/*
+    Copyright 2023 github.com/programaquatic
+
+   Licensed under the Apache License, Version 2.0 (the "License");
+   you may not use this file except in compliance with the License.
+   You may obtain a copy of the License at
+
+       http://www.apache.org/licenses/LICENSE-2.0
+
+   Unless required by applicable law or agreed to in writing, software
+   distributed under the License is distributed on an "AS IS" BASIS,
+   WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+   See the License for the specific language governing permissions and
+   limitations under the License.
+*/
+
+use bevy::prelude::*;
+use bevy_inspector_egui::bevy_egui::{
+    egui,
+    EguiContexts,
+    EguiPlugin,
+};
+
+use std::ops::RangeInclusive;
+
+use crate::{
+    aqs_utils::constants::{Constants, FluidModel},
+    tech::{
+        tank::Tank,
+        pump::{Pump, MAX_FLOW_SCALE},
+    },
+    decoration::editor::Decorations,
+    water::diagnostics::SimDiagnostics,
+};
+
+const MAX_PUMP_SPEED: f32 = 100.0;
+
+// slider ranges of the fluid model (grid units); values outside don't give a usable fluid
+const REST_DENSITY_RANGE: RangeInclusive<f32> = 0.1..=10.0;
+const VISCOSITY_RANGE: RangeInclusive<f32> = 0.0..=1.0;
+const EOS_STIFFNESS_RANGE: RangeInclusive<f32> = 0.1..=100.0;
+const EOS_POWER_RANGE: RangeInclusive<f32> = 1.0..=10.0;
+const GRAVITY_RANGE: RangeInclusive<f32> = 0.0..=20.0;
+
+
+/// value limited to the range; non-finite values end up at the lower bound
+fn clamp_to_range(value: f32, range: &RangeInclusive<f32>) -> f32 {
+    if !value.is_finite() {
+        return *range.start();
+    }
+    value.clamp( *range.start(), *range.end() )
+}
+
+/// fluid model with all parameters within the slider ranges
+fn clamp_fluid_model(model: &FluidModel) -> FluidModel {
+    FluidModel {
+        rest_density: clamp_to_range( model.rest_density, &REST_DENSITY_RANGE ),
+        dynamic_viscosity: clamp_to_range( model.dynamic_viscosity, &VISCOSITY_RANGE ),
+        eos_stiffness: clamp_to_range( model.eos_stiffness, &EOS_STIFFNESS_RANGE ),
+        eos_power: clamp_to_range( model.eos_power, &EOS_POWER_RANGE ),
+    }
+}
+
+
+/// egui panel showing the tank config and allowing to tune the fluid model, gravity and pump speed at runtime
+pub struct ConfigPanelPlugin;
+
+impl Plugin for ConfigPanelPlugin {
+    fn build(&self, app: &mut App) {
+        if !app.is_plugin_added::<EguiPlugin>() {
+            app.add_plugins(EguiPlugin);
+        }
+        app
+            .add_systems( Update, config_panel );
+    }
+}
+
+
+fn config_panel(
+    mut contexts: EguiContexts,
+    tank_cfg: Res<Tank>,
+    mut constants: ResMut<Constants>,
+    mut pumps: Query<&mut Pump>,
+    mut decorations: Decorations,
+    sim: Res<SimDiagnostics>,
+) {
+    egui::Window::new("Config").show(contexts.ctx_mut(), |ui| {
+        ui.heading("Tank");
+        let size = tank_cfg.get_size();
+        ui.label(format!("Size (grid): {:.1} x {:.1} x {:.1}", size.x, size.y, size.z));
+        ui.label(format!("Volume: {:.0} L", tank_cfg.get_volume_liters()));
+        ui.label(format!("Tank-to-Grid Scale: {:.3}", tank_cfg.scale));
+
+        ui.separator();
+        ui.heading("Simulation");
+        ui.label(format!("WORLD_DT: {}", constants.WORLD_DT));
+        ui.label(format!("MAX_GRID_CELLS: {}", constants.MAX_GRID_CELLS));
+        ui.label(format!("Particles: {}", sim.particle_count));
+        ui.label(format!("Kinetic energy: {:.3}", sim.kinetic_energy));
+        // strength of the (possibly configured) gravity vector; the direction stays
+        let mut gravity = constants.gravity().length();
+        if ui.add(egui::Slider::new(&mut gravity, GRAVITY_RANGE).text("gravity")).changed() {
+            constants.set_gravity_magnitude( clamp_to_range( gravity, &GRAVITY_RANGE ) );
+        }
+        let mut color_range = constants.SURFACE_COLOR.range;
+        if ui.add(egui::Slider::new(&mut color_range, 0.1..=10.0).text("surface color range")).changed() {
+            constants.SURFACE_COLOR.range = color_range;
+        }
+
+        ui.separator();
+        ui.heading("Fluid Model");
+        // picked up by p2g_stage2 in the next step
+        let mut model = constants.FLUID_MODEL.clone();
+        let changed = ui.add(egui::Slider::new(&mut model.rest_density, REST_DENSITY_RANGE).text("rest density")).changed()
+            | ui.add(egui::Slider::new(&mut model.dynamic_viscosity, VISCOSITY_RANGE).text("dynamic viscosity")).changed()
+            | ui.add(egui::Slider::new(&mut model.eos_stiffness, EOS_STIFFNESS_RANGE).text("eos stiffness")).changed()
+            | ui.add(egui::Slider::new(&mut model.eos_power, EOS_POWER_RANGE).text("eos power")).changed();
+        if changed {
+            constants.FLUID_MODEL = clamp_fluid_model( &model );
+        }
+
+        ui.separator();
+        ui.heading("Pumps");
+        for (i, mut pump) in pumps.iter_mut().enumerate() {
+            let mut speed = pump.target_velocity().length();
+            if ui.add(egui::Slider::new(&mut speed, 0.0..=MAX_PUMP_SPEED).text(format!("pump {} speed", i + 1))).changed() {
+                pump.set_target_speed( speed );
+            }
+            let mut flow_scale = pump.flow_scale();
+            if ui.add(egui::Slider::new(&mut flow_scale, 0.0..=MAX_FLOW_SCALE).text(format!("pump {} flow scale", i + 1))).changed() {
+                pump.set_flow_scale( flow_scale );
+            }
+            ui.label(format!("turnover: {:.0} L/hr", pump.turnover( tank_cfg.scale )));
+        }
+
+        ui.separator();
+        ui.heading("Decorations");
+        for (entity, transform) in decorations.list() {
+            ui.horizontal(|ui| {
+                let mut position = transform.translation;
+                ui.label(format!("{}", entity));
+                let changed = ui.add(egui::DragValue::new(&mut position.x).speed(0.5).prefix("x: ")).changed()
+                    | ui.add(egui::DragValue::new(&mut position.y).speed(0.5).prefix("y: ")).changed()
+                    | ui.add(egui::DragValue::new(&mut position.z).speed(0.5).prefix("z: ")).changed();
+                if changed {
+                    decorations.set_transform( entity, transform.with_translation( position ) );
+                }
+                if ui.button("remove").clicked() {
+                    decorations.remove( entity );
+                }
+            });
+        }
+
+        ui.separator();
+        ui.label("Tank dimensions and pump locations are applied on restart.");
+    });
+}
+
+
+
+#[cfg(test)]
+mod test
+{
+    use super::*;
+
+    #[test]
+    fn test_clamp_fluid_model() {
+        let model = FluidModel {
+            rest_density: 0.0,
+            dynamic_viscosity: -0.5,
+            eos_stiffness: 1000.0,
+            eos_power: f32::NAN,
+        };
+        let clamped = clamp_fluid_model( &model );
+        assert_eq!( clamped.rest_density, 0.1 );
+        assert_eq!( clamped.dynamic_viscosity, 0.0 );
+        assert_eq!( clamped.eos_stiffness, 100.0 );
+        assert_eq!( clamped.eos_power, 1.0 );
+
+        // sane values stay untouched
+        let model = FluidModel { rest_density: 1.0, dynamic_viscosity: 0.001, eos_stiffness: 10.0, eos_power: 4.0 };
+        let clamped = clamp_fluid_model( &model );
+        assert_eq!( clamped.rest_density, model.rest_density );
+        assert_eq!( clamped.eos_power, model.eos_power );
+        assert_eq!( clamp_to_range( 30.0, &GRAVITY_RANGE ), 20.0 );
+    }
+}
@@ -0,0 +1,135 @@
+/*
+    Copyright 2023 github.com/programaquatic
+
+   Licensed under the Apache License, Version 2.0 (the "License");
+   you may not use this file except in compliance with the License.
+   You may obtain a copy of the License at
+
+       http://www.apache.org/licenses/LICENSE-2.0
+
+   Unless required by applicable law or agreed to in writing, software
+   distributed under the License is distributed on an "AS IS" BASIS,
+   WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+   See the License for the specific language governing permissions and
+   limitations under the License.
+*/
+
+
+use std::time::SystemTime;
+
+use bevy::prelude::*;
+
+use crate::{
+    aqs_utils::constants::Constants,
+    tech::templates::SelectedTemplate,
+};
+
+pub const CONSTANTS_FILE: &str = "assets/constants.json";
+pub const TANK_FILE: &str = "assets/tank.json";
+/// seconds between two checks of the config files
+const RELOAD_CHECK_SECS: f32 = 1.0;
+
+
+/// modification time of a file; None if it can't be read
+fn modified(path: &str) -> Option<SystemTime> {
+    std::fs::metadata( path ).and_then(| meta | meta.modified()).ok()
+}
+
+/// true if the file got modified since the last call; the first call only records the time
+pub fn file_changed(path: &str, last: &mut Option<SystemTime>) -> bool {
+    let current = modified( path );
+    let changed = last.is_some() && current.is_some() && current != *last;
+    if current.is_some() {
+        *last = current;
+    }
+    changed
+}
+
+/// re-read constants.json once it changed on disk and replace the Constants in place
+///   tank.json only gets a warning since the tank, grid and particles would have to be rebuilt
+pub fn reload_config_files(
+    time: Res<Time>,
+    template: Option<Res<SelectedTemplate>>,
+    mut elapsed: Local<f32>,
+    mut constants_modified: Local<Option<SystemTime>>,
+    mut tank_modified: Local<Option<SystemTime>>,
+    mut constants: ResMut<Constants>,
+    mut fixed_time: ResMut<Time<Fixed>>,
+) {
+    // a tank template doesn't come from the files
+    if template.is_some() {
+        return;
+    }
+    *elapsed += time.delta_secs();
+    if *elapsed < RELOAD_CHECK_SECS {
+        return;
+    }
+    *elapsed = 0.0;
+
+    if file_changed( TANK_FILE, &mut tank_modified ) {
+        warn!("{} changed; the tank only gets rebuilt on restart", TANK_FILE);
+    }
+    if !file_changed( CONSTANTS_FILE, &mut constants_modified ) {
+        return;
+    }
+    match constants.reload( CONSTANTS_FILE ) {
+        Ok( (reloaded, ignored) ) => {
+            if !ignored.is_empty() {
+                warn!("{} reloaded; changes to {} need a restart", CONSTANTS_FILE, ignored.join(", "));
+            } else {
+                info!("{} reloaded", CONSTANTS_FILE);
+            }
+            if reloaded.WORLD_DT != constants.WORLD_DT {
+                fixed_time.set_timestep_seconds( reloaded.WORLD_DT as f64 );
+            }
+            *constants = reloaded;
+        },
+        // keep running with the current values while the file is being edited
+        Err( e ) => warn!("{} not reloaded: {}", CONSTANTS_FILE, e),
+    }
+}
+
+
+
+#[cfg(test)]
+mod test
+{
+    use super::*;
+
+    #[test]
+    fn test_reload_constants() {
+        let path = std::env::temp_dir().join( format!("lisal_reload_{}.json", std::process::id()) );
+        let path = path.to_str().unwrap();
+        let original = std::fs::read_to_string( CONSTANTS_FILE ).unwrap();
+        let current = crate::aqs_utils::config::read_json::<Constants>( CONSTANTS_FILE.to_string() ).unwrap().finalize();
+
+        let mut last = None;
+        std::fs::write( path, &original ).unwrap();
+        assert!( !file_changed( path, &mut last ) );
+        assert!( last.is_some() );
+
+        // tunable values get picked up, the grid size stays
+        let edited = original
+            .replace( "\"DEFAULT_GRAVITY\": -9.81", "\"DEFAULT_GRAVITY\": -4.5" )
+            .replace( "\"MAX_GRID_CELLS\": 50000", "\"MAX_GRID_CELLS\": 90000" )
+            .replace( "0.001, \"eos_stiffness\": 10.0", "0.001, \"eos_stiffness\": 25.0" );
+        std::fs::write( path, &edited ).unwrap();
+        let (reloaded, ignored) = current.reload( path ).unwrap();
+        assert_eq!( reloaded.DEFAULT_GRAVITY, -4.5 );
+        assert_eq!( reloaded.MAX_GRID_CELLS, current.MAX_GRID_CELLS );
+        assert_eq!( ignored, vec![ "MAX_GRID_CELLS" ] );
+        // derived values are in place again
+        assert_eq!( reloaded.DEFAULT_PARTICLE_MASS, current.DEFAULT_PARTICLE_MASS );
+        assert_eq!( reloaded.FLUID_MODEL.rest_density, current.FLUID_MODEL.rest_density );
+        // the fluid model gets tuned without a restart
+        assert_eq!( current.FLUID_MODEL.eos_stiffness, 10.0 );
+        assert_eq!( reloaded.FLUID_MODEL.eos_stiffness, 25.0 );
+        assert_eq!( reloaded.FLUID_MODEL.dynamic_viscosity, current.FLUID_MODEL.dynamic_viscosity );
+
+        // a broken file doesn't replace anything
+        std::fs::write( path, "{ \"DEFAULT_GRAVITY\": " ).unwrap();
+        assert!( current.reload( path ).is_err() );
+        std::fs::remove_file( path ).unwrap();
+        assert!( current.reload( path ).is_err() );
+    }
+}
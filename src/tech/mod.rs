@@ -17,3 +17,7 @@
 pub mod tank;
 pub mod cam;
 pub mod pump;
+#[cfg(feature = "egui")]
+pub mod config_panel;
+pub mod templates;
+pub mod hot_reload;
@@ -21,16 +21,32 @@ use bevy::{
         prelude::Sphere
     }
 };
+use std::{
+    fs::File,
+    io::Write,
+    sync::atomic::{AtomicUsize, Ordering},
+};
 
 
 use crate::{
-    aqs_utils::extforcevol::ExternalForceVolume,
+    aqs_utils::{
+        constants::Constants,
+        extforcevol::ExternalForceVolume,
+    },
     tech::tank::Tank,
+    water::sim_time::SimTime,
 };
 
 const EFFECTIVE_RADIUS: f32 = 1.0;
 
-#[derive(Component, Default)]
+/// seconds per hour and cm^3 per liter to convert flow into L/hr
+pub const LPH_PER_CM3_PER_SEC: f32 = 3600. / 1000.;
+/// change of the flow scale per key press of + or -
+const FLOW_SCALE_STEP: f32 = 0.1;
+/// upper limit of the runtime flow scale
+pub const MAX_FLOW_SCALE: f32 = 4.0;
+
+#[derive(Component)]
 pub struct Pump {
     /// the center source position (from where the particles get pulled)
     source: Vec3A,
@@ -38,6 +54,24 @@ pub struct Pump {
     target: Vec3A,
     /// the velocity+direction of particles at the target
     target_velocity: Vec3A,
+    /// cross-section of the inlet perpendicular to the target velocity (in world units^2)
+    inlet_area: f32,
+    /// id of the inlet that gets stamped onto pumped particles (0 means not pumped)
+    inlet_id: u8,
+    /// share of the incoming particle speed that gets added to the target velocity
+    momentum_blend: f32,
+    /// temperature of the water leaving the target; None keeps the particle temperature
+    inlet_temperature: Option<f32>,
+    /// particles at the source get removed instead of relocated to the target
+    drain: bool,
+    /// runtime multiplier of the target velocity (and the fill rate of the inlet), adjusted with +/-
+    flow_scale: f32,
+    /// the pump is switched on (toggled with U)
+    enabled: bool,
+    /// seconds to spin up to full flow and back down; 0 switches at once
+    ramp_seconds: f32,
+    /// current share [0..1] of the full flow while spinning up or down
+    ramp: f32,
     // /// the radius of the source and target locations
     // radius: f32,
 }
@@ -53,22 +87,173 @@ impl Pump {
             source: Vec3A::from(source),
             target: Vec3A::from(target),
             target_velocity: Vec3A::from(target_velocity),
+            inlet_area: 0.0,
+            inlet_id: 1,
+            momentum_blend: 0.0,
+            inlet_temperature: None,
+            drain: false,
+            flow_scale: 1.0,
+            enabled: true,
+            ramp_seconds: 0.0,
+            ramp: 1.0,
             // radius,
         }
     }
     pub fn from_extforcevolumes(src: &ExternalForceVolume, dst: &ExternalForceVolume) -> Self {
+        let target_velocity = Vec3A::from( dst.get_force_for_position(dst.location, 0.0) );
         Pump {
             source: Vec3A::from( src.location ),
             target: Vec3A::from( dst.location ),
-            target_velocity: Vec3A::from( dst.get_force_for_position(dst.location) ),
+            target_velocity,
+            inlet_area: cross_section( dst.extent, target_velocity ),
+            inlet_id: 1,
+            momentum_blend: 0.0,
+            inlet_temperature: None,
+            drain: false,
+            flow_scale: 1.0,
+            enabled: true,
+            ramp_seconds: 0.0,
+            ramp: 1.0,
             // radius: f32::min( src.extent.min_element(), dst.extent.min_element() ),  // using squared lengths
         }
     }
 
-    pub fn particle_pump(&self, refpoint: Vec3A) -> Option::<(Vec3A, Vec3A)> {
+    pub fn with_inlet_id(mut self, inlet_id: u8) -> Self {
+        self.inlet_id = inlet_id;
+        self
+    }
+
+    pub fn with_momentum_blend(mut self, blend: f32) -> Self {
+        self.momentum_blend = blend.clamp(0.0, 1.0);
+        self
+    }
+
+    pub fn with_inlet_temperature(mut self, temperature: Option<f32>) -> Self {
+        self.inlet_temperature = temperature;
+        self
+    }
+
+    pub fn with_drain(mut self, drain: bool) -> Self {
+        self.drain = drain;
+        self
+    }
+
+    pub fn with_ramp_seconds(mut self, seconds: f32) -> Self {
+        self.ramp_seconds = seconds.max( 0.0 );
+        self
+    }
+
+    pub fn is_drain(&self) -> bool {
+        self.drain
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    /// switch the pump on or off; the flow follows over ramp_seconds
+    pub fn toggle(&mut self) {
+        self.enabled = !self.enabled;
+    }
+
+    /// current share of the full flow
+    pub fn ramp(&self) -> f32 {
+        self.ramp
+    }
+
+    /// the pump moves water (switched on or still spinning down)
+    pub fn is_running(&self) -> bool {
+        self.ramp > 0.0
+    }
+
+    /// move the ramp toward full flow (enabled) or standstill (disabled) by dt seconds
+    pub fn advance_ramp(&mut self, dt: f32) {
+        let target = if self.enabled { 1.0 } else { 0.0 };
+        if self.ramp_seconds <= 0.0 {
+            self.ramp = target;
+            return;
+        }
+        let step = dt / self.ramp_seconds;
+        self.ramp = if target > self.ramp {
+            (self.ramp + step).min( target )
+        } else {
+            (self.ramp - step).max( target )
+        };
+    }
+
+    pub fn inlet_id(&self) -> u8 {
+        self.inlet_id
+    }
+
+    pub fn inlet_temperature(&self) -> Option<f32> {
+        self.inlet_temperature
+    }
+
+    /// temperature of water injected at the inlet
+    pub fn injection_temperature(&self, water_temperature: f32) -> f32 {
+        self.inlet_temperature.unwrap_or( water_temperature )
+    }
+
+    pub fn target(&self) -> Vec3A {
+        self.target
+    }
+
+    pub fn target_velocity(&self) -> Vec3A {
+        self.target_velocity
+    }
+
+    pub fn flow_scale(&self) -> f32 {
+        self.flow_scale
+    }
+
+    /// scale the flow at runtime; clamped to [0..MAX_FLOW_SCALE]
+    pub fn set_flow_scale(&mut self, scale: f32) {
+        self.flow_scale = scale.clamp( 0.0, MAX_FLOW_SCALE );
+    }
+
+    /// change the speed at the target while keeping the direction
+    pub fn set_target_speed(&mut self, speed: f32) {
+        self.target_velocity = self.target_velocity.normalize_or_zero() * speed;
+    }
+
+    /// speed of the particles leaving the target (world units per second)
+    pub fn target_speed(&self) -> f32 {
+        self.target_velocity.length()
+    }
+
+    /// the flow through the inlet in L/hr
+    ///   world_scale is the cm-to-world factor of the tank (see Tank::scale)
+    pub fn turnover(&self, world_scale: f32) -> f32 {
+        let flow_world = self.inlet_area * self.target_velocity.length() * self.flow_scale * self.ramp;
+        flow_world / world_scale.powi(3) * LPH_PER_CM3_PER_SEC
+    }
+
+    /// adjust the magnitude of the target velocity so that the inlet flow matches target_lph
+    ///   the direction of the target velocity is kept
+    pub fn set_turnover(&mut self, target_lph: f32, world_scale: f32) {
+        if self.inlet_area <= 0.0 {
+            warn!("pump inlet has no cross-section, cannot set turnover");
+            return;
+        }
+        let direction = self.target_velocity.normalize_or_zero();
+        let speed = target_lph / LPH_PER_CM3_PER_SEC * world_scale.powi(3) / self.inlet_area;
+        self.target_velocity = direction * speed;
+    }
+
+    /// velocity of a particle leaving the target
+    ///   blend 0 gives the pure target velocity, 1 adds the full incoming speed along the target direction
+    pub fn outgoing_velocity(&self, incoming: Vec3A) -> Vec3A {
+        let target_velocity = self.target_velocity * self.flow_scale * self.ramp;
+        target_velocity + target_velocity.normalize_or_zero() * incoming.length() * self.momentum_blend
+    }
+
+    pub fn particle_pump(&self, refpoint: Vec3A, velocity: Vec3A) -> Option::<(Vec3A, Vec3A)> {
+        if !self.is_running() {
+            return None;
+        }
         let (distance, relative) = self.relative_distance(refpoint);
         if  relative <= EFFECTIVE_RADIUS {
-            Some( (self.target + distance, self.target_velocity) )
+            Some( (self.target + distance, self.outgoing_velocity( velocity )) )
         } else {
             None
         }
@@ -80,53 +265,431 @@ impl Pump {
     }
 }
 
+/// relocate a particle through all pumps whose source it is in (draining pumps excluded)
+///   returns the last pump that moved the particle
+pub fn pump_particle<'a>(pumps: impl Iterator<Item = &'a Pump>, location: &mut Vec3A, velocity: &mut Vec3A) -> Option<&'a Pump> {
+    let mut pumped = None;
+    pumps.filter(| r | !r.is_drain()).for_each(| r | {
+        if let Some( ( new_loc, vel_diff) ) = r.particle_pump(*location, *velocity) {
+            *location = new_loc;
+            *velocity = vel_diff;
+            pumped = Some( r );
+        }
+    });
+    pumped
+}
+
+
+/// true if the location is within the shape of the volume
+pub fn in_volume(volume: &ExternalForceVolume, location: Vec3A) -> bool {
+    volume.shape.contains( Vec3::from( location ) - volume.location, volume.extent )
+}
+
+
+/// Per-frame count of particles relocated by the pumps
+///   the counter is atomic because pumping happens inside par_iter_mut
+#[derive(Resource, Default)]
+pub struct PumpStats {
+    frame: usize,
+    pumped: AtomicUsize,
+    /// the number of pumped particles of the last completed frame
+    pub last_pumped: usize,
+}
+
+impl PumpStats {
+    pub fn count_pumped(&self) {
+        self.pumped.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// close the current frame and return the pumped count
+    pub fn finish_frame(&mut self) -> usize {
+        self.frame += 1;
+        self.last_pumped = self.pumped.swap(0, Ordering::Relaxed);
+        self.last_pumped
+    }
+
+    /// estimated flow in L/hr from the number of pumped particles per step
+    ///   each particle represents 1/ppc of a grid cell
+    pub fn estimated_flow(&self, dt: f32, ppc: u32, world_scale: f32) -> f32 {
+        let volume_world = self.last_pumped as f32 / ppc.max(1) as f32;
+        volume_world / dt / world_scale.powi(3) * LPH_PER_CM3_PER_SEC
+    }
+}
+
+/// number of particles to spawn for a base count at the given flow scale
+pub fn scaled_spawn_count(base: usize, flow_scale: f32) -> usize {
+    (base as f32 * flow_scale.max( 0.0 )).round() as usize
+}
+
+/// + and - scale the flow of all (non-draining) pumps
+pub fn control_flow_scale(
+    keys: Res<ButtonInput<KeyCode>>,
+    tank_cfg: Res<Tank>,
+    mut pumps: Query<&mut Pump>,
+) {
+    let step = if keys.just_pressed( KeyCode::Equal ) || keys.just_pressed( KeyCode::NumpadAdd ) {
+        FLOW_SCALE_STEP
+    } else if keys.just_pressed( KeyCode::Minus ) || keys.just_pressed( KeyCode::NumpadSubtract ) {
+        -FLOW_SCALE_STEP
+    } else {
+        return;
+    };
+    pumps.iter_mut()
+        .filter(| pump | !pump.is_drain())
+        .for_each(| mut pump | {
+            let scale = pump.flow_scale() + step;
+            pump.set_flow_scale( scale );
+            info!("pump {} flow x{:.1}: {:.0} L/hr", pump.inlet_id(), pump.flow_scale(), pump.turnover( tank_cfg.scale ));
+        });
+}
+
+/// U switches all (non-draining) pumps on or off
+pub fn toggle_pumps(
+    keys: Res<ButtonInput<KeyCode>>,
+    mut pumps: Query<&mut Pump>,
+) {
+    if !keys.just_pressed( KeyCode::KeyU ) {
+        return;
+    }
+    pumps.iter_mut()
+        .filter(| pump | !pump.is_drain())
+        .for_each(| mut pump | {
+            pump.toggle();
+            info!("pump {} {}", pump.inlet_id(), if pump.is_enabled() { "on" } else { "off" });
+        });
+}
+
+/// spin the pumps up or down toward their switch state
+pub fn update_pump_ramps(
+    time: Res<Time>,
+    mut pumps: Query<&mut Pump>,
+) {
+    pumps.iter_mut().for_each(| mut pump | pump.advance_ramp( time.delta_secs() ));
+}
+
+/// log the pump throughput of the last frame to stdout (periodically) and to csv (if configured)
+pub fn log_pump_stats(
+    constants: Res<Constants>,
+    time: Res<SimTime>,
+    tank_cfg: Res<Tank>,
+    mut stats: ResMut<PumpStats>,
+    mut csv: Local<Option<File>>,
+) {
+    let pumped = stats.finish_frame();
+    let flow = stats.estimated_flow( time.dt, constants.DEFAULT_PPC, tank_cfg.scale );
+
+    if stats.frame % 100 == 0 {
+        info!("pump: frame {}, pumped {}, est. flow {:.0} L/hr", stats.frame, pumped, flow);
+    }
+
+    if let Some( path ) = &constants.PUMP_STATS_CSV {
+        if csv.is_none() {
+            match File::create( path ) {
+                Ok( mut file ) => {
+                    let _ = writeln!(file, "frame,pumped_count,estimated_flow");
+                    *csv = Some( file );
+                },
+                Err( e ) => warn!("cannot create pump stats file {}: {}", path, e),
+            }
+        }
+        if let Some( file ) = csv.as_mut() {
+            let _ = writeln!(file, "{},{},{}", stats.frame, pumped, flow);
+        }
+    }
+}
+
+
+/// area of the box with half-extent `extent` projected onto the plane perpendicular to `direction`
+pub fn cross_section(extent: Vec3, direction: Vec3A) -> f32 {
+    let dir = direction.normalize_or_zero().abs();
+    let full = extent * 2.0;
+    dir.x * full.y * full.z + dir.y * full.x * full.z + dir.z * full.x * full.y
+}
+
+/// one pump per definition of the (scaled) tank; the inlet ids count from 1 in config order
+pub fn pumps_from_definitions(tank_cfg: &Tank) -> Vec<Pump> {
+    tank_cfg.get_pump_definitions().iter()
+        .enumerate()
+        .map(| (idx, definition) | {
+            let mut pump = Pump::from_extforcevolumes( &definition.outlet, &definition.inlet );
+            if let Some( target_lph ) = definition.turnover_lph {
+                pump.set_turnover( target_lph, tank_cfg.scale );
+            }
+            pump.with_inlet_id( idx as u8 + 1 )
+                .with_momentum_blend( definition.momentum_blend )
+                .with_inlet_temperature( definition.inlet_temperature )
+                .with_drain( definition.drain )
+                .with_ramp_seconds( definition.ramp_seconds )
+        })
+        .collect()
+}
+
 pub fn initialize(
     tank_cfg: Res<Tank>,
     mut materials: ResMut<Assets<StandardMaterial>>,
     mut meshes: ResMut<Assets<Mesh>>,
     mut commands: Commands,
 ) {
-    dbg!("{}", tank_cfg.pump.outlet.clone());
-
-    let pump_efv = Pump::from_extforcevolumes(
-        &tank_cfg.pump.outlet,
-        &tank_cfg.pump.inlet,
-    );
+    let _span = info_span!("pump_initialize").entered();
 
     let water_material = materials.add(StandardMaterial {
         base_color: Color::linear_rgba(0.5, 0.5, 0.5, 0.1),
         alpha_mode: AlphaMode::Blend,
         ..default()
     });
+    let outlet_mesh = meshes.add(Sphere::new(1.0).mesh().ico(8).unwrap());
+
+    let pumps = pumps_from_definitions( &tank_cfg );
+    for (pump_efv, definition) in pumps.into_iter().zip( tank_cfg.get_pump_definitions() ) {
+        debug!("pump {} outlet: {:?}", pump_efv.inlet_id(), definition.outlet);
+        let turnover = pump_efv.turnover( tank_cfg.scale );
+        info!("pump {} turnover: {:.0} L/hr ({:.1}x tank volume per hour)",
+              pump_efv.inlet_id(), turnover, turnover / tank_cfg.get_volume_liters() );
+
+        let pump = commands
+            .spawn(pump_efv)
+            .id();
+        let inlet = commands
+            .spawn( definition.inlet.clone() )
+            .id();
+        let outlet = commands
+            .spawn( definition.outlet.clone() )
+            .insert((
+                Mesh3d(outlet_mesh.clone()),
+                MeshMaterial3d(water_material.clone()),
+                Transform::from_translation( definition.outlet.location )
+                    .with_scale( definition.outlet.extent * EFFECTIVE_RADIUS ),
+            ))
+            .id();
+
+        commands.entity(tank_cfg.get_tank_parent()).add_children(&[pump, inlet, outlet]);
+    }
+}
+
+
+
+#[cfg(test)]
+mod test
+{
+    use super::*;
+    use crate::aqs_utils::extforcevol::ForceVolumeDirection;
+
+    fn test_pump() -> Pump {
+        let outlet = ExternalForceVolume::new( Vec3::new(15., 10., 7.),
+                                               Vec3::new(10., 9., 6.),
+                                               ForceVolumeDirection::from_inward(-1.0),
+                                               None );
+        let inlet = ExternalForceVolume::new( Vec3::new(10., 70., 57.),
+                                              Vec3::new(5., 5., 10.),
+                                              ForceVolumeDirection::from_parallel( Vec3::new(30., 0., 0.) ),
+                                              None );
+        Pump::from_extforcevolumes( &outlet, &inlet )
+    }
+
+    #[test]
+    fn test_cross_section() {
+        // flow along x sees the y/z face of the box
+        assert_eq!( cross_section( Vec3::new(5., 5., 10.), Vec3A::X ), 200. );
+        assert_eq!( cross_section( Vec3::new(5., 5., 10.), Vec3A::ZERO ), 0. );
+    }
+
+    #[test]
+    fn test_turnover_scales_velocity() {
+        let scale = 0.5;
+        let mut pump = test_pump();
+
+        pump.set_turnover( 1000., scale );
+        let single = pump.target_velocity.length();
+        assert!( (pump.turnover( scale ) - 1000.).abs() < 0.01 );
 
-    let pump = commands
-        .spawn(pump_efv)
-        // .insert(PbrBundle {
-        //     mesh: meshes.add(Mesh::from(shape::UVSphere {
-        //         radius: 1.0,
-        //         sectors: 8,
-        //         stacks: 8,
-        //     })),
-        //     material: water_material.clone(),
-        //     transform: Transform::from_translation( tank_cfg.pump.outlet.location )
-        //         .with_scale( tank_cfg.pump.outlet.extent ),
-        //     ..default()
-        // })
-        .id();
-
-    let inlet = commands
-        .spawn( tank_cfg.pump.inlet.clone() )
-        .id();
-    let outlet = commands
-        .spawn( tank_cfg.pump.outlet.clone() )
-        .insert((
-            Mesh3d(meshes.add(Sphere::new(1.0).mesh().ico(8).unwrap())),
-            MeshMaterial3d(water_material),
-            Transform::from_translation( tank_cfg.pump.outlet.location )
-                .with_scale( tank_cfg.pump.outlet.extent * EFFECTIVE_RADIUS ),
-        ))
-        .id();
-
-    commands.entity(tank_cfg.get_tank_parent()).add_children(&[pump, inlet, outlet]);
-    println!("pump outlet location: {}", tank_cfg.pump.outlet.location );
+        pump.set_turnover( 2000., scale );
+        let double = pump.target_velocity.length();
+        assert!( (double / single - 2.0).abs() < 1e-4 );
+
+        // direction is preserved
+        assert!( pump.target_velocity.normalize().abs_diff_eq( Vec3A::X, 1e-6 ) );
+    }
+
+    #[test]
+    fn test_flow_scale() {
+        let mut pump = test_pump();
+        let inside = pump.source + Vec3A::new( 0.5, 0., 0. );
+        let (_, base) = pump.particle_pump( inside, Vec3A::ZERO ).unwrap();
+
+        pump.set_flow_scale( 2.5 );
+        let (_, scaled) = pump.particle_pump( inside, Vec3A::ZERO ).unwrap();
+        assert!( scaled.abs_diff_eq( base * 2.5, 1e-5 ), "{}", scaled );
+        assert!( pump.particle_pump( pump.source + Vec3A::new( 2., 0., 0. ), Vec3A::ZERO ).is_none() );
+
+        pump.set_flow_scale( 10. );
+        assert_eq!( pump.flow_scale(), MAX_FLOW_SCALE );
+        pump.set_flow_scale( -1. );
+        assert_eq!( pump.particle_pump( inside, Vec3A::ZERO ).unwrap().1, Vec3A::ZERO );
+
+        assert_eq!( scaled_spawn_count( 10, 1.0 ), 10 );
+        assert_eq!( scaled_spawn_count( 10, 0.25 ), 3 );
+        assert_eq!( scaled_spawn_count( 10, 0.0 ), 0 );
+    }
+
+    #[test]
+    fn test_pump_ramp() {
+        let mut pump = test_pump().with_ramp_seconds( 2.0 );
+        let inside = pump.source + Vec3A::new( 0.5, 0., 0. );
+        let (_, full) = pump.particle_pump( inside, Vec3A::ZERO ).unwrap();
+
+        // spin down: still pumping while the ramp runs out
+        pump.toggle();
+        pump.advance_ramp( 1.0 );
+        assert!( (pump.ramp() - 0.5).abs() < 1e-6 );
+        assert!( pump.particle_pump( inside, Vec3A::ZERO ).unwrap().1.abs_diff_eq( full * 0.5, 1e-5 ) );
+        pump.advance_ramp( 1.0 );
+        assert_eq!( pump.ramp(), 0.0 );
+        assert!( pump.particle_pump( inside, Vec3A::ZERO ).is_none() );
+
+        // spin up from 0 to full over ramp_seconds in steps of 0.25s
+        pump.toggle();
+        for step in 1..=8 {
+            pump.advance_ramp( 0.25 );
+            assert!( (pump.ramp() - step as f32 / 8.0).abs() < 1e-5, "{}", pump.ramp() );
+        }
+        pump.advance_ramp( 0.25 );
+        assert_eq!( pump.ramp(), 1.0 );
+        assert!( pump.particle_pump( inside, Vec3A::ZERO ).unwrap().1.abs_diff_eq( full, 1e-5 ) );
+
+        // without a ramp the pump switches at once
+        let mut instant = test_pump();
+        instant.toggle();
+        instant.advance_ramp( 0.01 );
+        assert!( !instant.is_running() );
+    }
+
+    #[test]
+    fn test_pumped_count() {
+        let pump = test_pump();
+        let mut stats = PumpStats::default();
+
+        // a cluster of 8 particles around the pump source and 4 particles far away
+        let mut particles: Vec<Vec3A> = (0..8)
+            .map(|i| pump.source + Vec3A::new( (i % 2) as f32, ((i / 2) % 2) as f32, (i / 4) as f32 ) * 0.5)
+            .chain( (0..4).map(|i| Vec3A::new( 100. + i as f32, 0., 0. )) )
+            .collect();
+        particles.iter_mut().for_each(| loc | {
+            let mut vel = Vec3A::ZERO;
+            if pump_particle( std::iter::once( &pump ), loc, &mut vel ).is_some() {
+                stats.count_pumped();
+            }
+        });
+
+        assert_eq!( stats.finish_frame(), 8 );
+        assert_eq!( stats.finish_frame(), 0 );
+    }
+
+    #[test]
+    fn test_inlet_source() {
+        let first = test_pump();
+        let mut second = test_pump().with_inlet_id( 2 );
+        second.source = Vec3A::new( 50., 10., 7. );
+        let pumps = [first, second];
+
+        let mut loc = Vec3A::new( 50., 10.5, 7. );
+        let mut vel = Vec3A::ZERO;
+        assert_eq!( pump_particle( pumps.iter(), &mut loc, &mut vel ).map( Pump::inlet_id ), Some( 2 ) );
+
+        let mut loc = Vec3A::new( 30., 30., 30. );
+        assert!( pump_particle( pumps.iter(), &mut loc, &mut vel ).is_none() );
+    }
+
+    #[test]
+    fn test_inlet_temperature() {
+        let pump = test_pump().with_inlet_temperature( Some( 28.0 ) );
+        let water_temperature = 25.0;
+
+        // pumped particles leave the inlet with its temperature, the others keep theirs
+        let mut particles = [ (pump.source, water_temperature), (Vec3A::new( 100., 0., 0. ), water_temperature) ];
+        particles.iter_mut().for_each(| (loc, temperature) | {
+            let mut vel = Vec3A::ZERO;
+            if let Some( temp ) = pump_particle( std::iter::once( &pump ), loc, &mut vel ).and_then( Pump::inlet_temperature ) {
+                *temperature = temp;
+            }
+        });
+        assert_eq!( particles[0].1, 28.0 );
+        assert_eq!( particles[1].1, water_temperature );
+
+        // particles injected at the inlet
+        let definition: crate::tech::tank::PumpDefinition = serde_json::from_str( r#"{
+            "inlet_temperature": 28.0,
+            "inlet": { "location": [10.0, 70.0, 57.0], "extent": [5.0, 5.0, 10.0], "direction": { "Parallel": [30.0, 1.0, 0.0] } },
+            "outlet": { "location": [15.0, 10.0, 7.0], "extent": [10.0, 9.0, 6.0], "direction": { "Inward": -1.0 } }
+        }"# ).unwrap();
+        let injecting = test_pump().with_inlet_temperature( definition.inlet_temperature );
+        assert_eq!( injecting.injection_temperature( water_temperature ), 28.0 );
+        assert_eq!( test_pump().injection_temperature( water_temperature ), water_temperature );
+    }
+
+    #[test]
+    fn test_momentum_blend() {
+        let incoming = Vec3A::new( 0., -4., 0. );
+        let target_velocity = test_pump().target_velocity();
+
+        let pure = test_pump();
+        assert_eq!(pure.outgoing_velocity( incoming ), target_velocity);
+
+        let full = test_pump().with_momentum_blend( 1.0 );
+        let out = full.outgoing_velocity( incoming );
+        assert!((out.length() - (target_velocity.length() + 4.0)).abs() < 1e-4);
+        assert!(out.normalize().abs_diff_eq( target_velocity.normalize(), 1e-5 ));
+
+        // relocation uses the blended velocity
+        let source = Vec3A::new(15., 10., 7.);
+        let (_, vel) = full.particle_pump( source, incoming ).unwrap();
+        assert_eq!(vel, out);
+    }
+
+    #[test]
+    fn test_multiple_pumps() {
+        let volumes = | inlet: &str, outlet: &str | format!( r#"{{
+            "inlet": {{ "location": {}, "extent": [5.0, 5.0, 10.0], "direction": {{ "Parallel": [30.0, 1.0, 0.0] }} }},
+            "outlet": {{ "location": {}, "extent": [10.0, 9.0, 6.0], "direction": {{ "Inward": -1.0 }} }}
+        }}"#, inlet, outlet );
+        let tank_json = | pumps: &str | format!( r#"{{
+            "tank": {{ "width": 160, "depth": 70, "height": 80, "glass": 12 }},
+            "overflow": {{ "drill": [], "shaft": [] }},
+            {}
+        }}"#, pumps );
+
+        let mut two: Tank = serde_json::from_str( &tank_json( &format!( r#""pumps": [ {}, {} ]"#,
+            volumes( "[10.0, 70.0, 57.0]", "[15.0, 10.0, 7.0]" ),
+            volumes( "[150.0, 70.0, 57.0]", "[145.0, 10.0, 7.0]" ) ) ) ).unwrap();
+        two.fold_pump();
+        let pumps = pumps_from_definitions( &two );
+        assert_eq!( pumps.len(), 2 );
+        assert_ne!( pumps[0].target(), pumps[1].target() );
+        assert_eq!( pumps[0].target(), Vec3A::new( 10., 70., 57. ) );
+        assert_eq!( (pumps[0].inlet_id(), pumps[1].inlet_id()), (1, 2) );
+
+        // a single pump of older configs still works
+        let mut single: Tank = serde_json::from_str( &tank_json( &format!( r#""pump": {}"#,
+            volumes( "[10.0, 70.0, 57.0]", "[15.0, 10.0, 7.0]" ) ) ) ).unwrap();
+        single.fold_pump();
+        assert_eq!( pumps_from_definitions( &single ).len(), 1 );
+    }
+
+    #[test]
+    fn test_drain() {
+        let outlet = ExternalForceVolume::new( Vec3::new(15., 10., 7.),
+                                               Vec3::new(10., 9., 6.),
+                                               ForceVolumeDirection::from_inward(-1.0),
+                                               None );
+        assert!( in_volume( &outlet, Vec3A::new( 20., 12., 5. ) ) );
+        assert!( !in_volume( &outlet, Vec3A::new( 30., 12., 5. ) ) );
+        // no inward force at the center, it's still inside
+        assert!( in_volume( &outlet, Vec3A::new( 15., 10., 7. ) ) );
+
+        // a draining pump leaves the particle for the drain system
+        let pumps = [ test_pump().with_drain( true ) ];
+        let mut location = Vec3A::new( 15.5, 10., 7. );
+        let mut velocity = Vec3A::new( 0., -1., 0. );
+        assert!( pump_particle( pumps.iter(), &mut location, &mut velocity ).is_none() );
+        assert_eq!( location, Vec3A::new( 15.5, 10., 7. ) );
+    }
 }
@@ -21,15 +21,25 @@ use bevy_rapier3d::prelude::*;
 
 use crate::{
     aqs_utils::{
-        constants::Constants,
+        constants::{Constants, GridAllocation},
         config,
         extforcevol::ExternalForceVolume,
     },
-    tech::pump,
+    tech::{
+        pump,
+        hot_reload,
+        templates::SelectedTemplate,
+    },
     decoration::types::DecorationTag,
+    water::flow_gate::FlowGate,
 };
 // use crate::water::surface as sf;
 
+/// grid cell count above which a fixed cell size is likely a config mistake
+pub const MAX_SANE_GRID_CELLS: usize = 1_000_000;
+/// deviation from whole grid cells that still counts as aligned (float rounding of the scale)
+const GRID_ALIGNMENT_TOLERANCE: f32 = 1e-3;
+
 #[derive(Serialize, Deserialize, Debug)]
 enum RelPosition {
     Right,
@@ -64,13 +74,244 @@ struct TankDimensions {
 #[derive(Serialize, Deserialize, Debug)]
 struct OverFlowData {
     drill: Vec<HoleAndLocation>,
-    shaft: Vec<Vec2>,
+    /// one polyline per overflow shaft; a single polyline is accepted for compatibility
+    #[serde(deserialize_with = "deserialize_shafts")]
+    shaft: Vec<Vec<Vec2>>,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum ShaftList {
+    Single(Vec<Vec2>),
+    Multiple(Vec<Vec<Vec2>>),
+}
+
+fn deserialize_shafts<'de, D>(deserializer: D) -> Result<Vec<Vec<Vec2>>, D::Error>
+    where D: serde::Deserializer<'de>
+{
+    Ok( match ShaftList::deserialize( deserializer )? {
+        ShaftList::Single( path ) if path.is_empty() => vec![],
+        ShaftList::Single( path ) => vec![ path ],
+        ShaftList::Multiple( paths ) => paths,
+    })
+}
+
+/// the (start, end) points of all shaft panes; consecutive points of each shaft polyline form one pane
+fn shaft_segments(shafts: &[Vec<Vec2>]) -> Vec<(Vec2, Vec2)> {
+    shafts.iter()
+        .flat_map(| path | path.windows(2).map(| w | (w[0], w[1])))
+        .collect()
+}
+
+/// xz bounding box (min, max) of every shaft polyline that has at least one pane
+fn shaft_areas(shafts: &[Vec<Vec2>]) -> Vec<(Vec2, Vec2)> {
+    shafts.iter()
+        .filter(| path | path.len() >= 2)
+        .map(| path | path.iter().fold( (Vec2::MAX, Vec2::MIN), | (min, max), &p | (min.min( p ), max.max( p )) ))
+        .collect()
+}
+
+/// Shape of the substrate (in cm like the tank dimensions)
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SubstrateDefinition {
+    /// height of the substrate at its lowest corner
+    pub depth: f32,
+    /// additional height towards the right side (x) and towards the back (y)
+    #[serde(default)]
+    pub rise: Vec2,
+    /// maximum height offset of the smooth dunes of the substrate surface
+    #[serde(default)]
+    pub roughness: f32,
+    /// optional seeded value noise; replaces the default dunes
+    #[serde(default)]
+    pub noise: Option<GroundNoise>,
+    /// optional grayscale image of the substrate contour; replaces noise and roughness
+    #[serde(default)]
+    pub heightmap: Option<HeightmapDefinition>,
+}
+
+/// substrate contour designed externally: pixel luminance maps to height
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct HeightmapDefinition {
+    /// image file (e.g. assets/textures/substrate.png); stretched across the tank floor
+    pub path: String,
+    /// height (cm) of a white pixel
+    pub height: f32,
+}
+
+/// coherent, reproducible substrate height variation
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct GroundNoise {
+    pub seed: u32,
+    /// noise features per ground mesh vertex spacing
+    pub frequency: f32,
+    /// maximum height offset (cm)
+    pub amplitude: f32,
+}
+
+/// A spherical decoration (in cm like the tank dimensions)
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct DecorationDefinition {
+    pub location: Vec3,
+    pub radius: f32,
+    /// material preset: rock, wood, sand or plant (see decoration::materials)
+    #[serde(default)]
+    pub material: Option<String>,
+    /// dynamic body that gets pushed around by the fluid (instead of a fixed solid)
+    #[serde(default)]
+    pub floating: bool,
+}
+
+/// the rock that used to be placed for debugging the flow around an obstacle
+fn default_decorations() -> Vec<DecorationDefinition> {
+    vec![ DecorationDefinition {
+        location: Vec3::new( 80., 0.0, 35. ),
+        radius: 15.0,
+        material: None,
+        floating: false,
+    } ]
 }
 
 #[derive(Serialize, Deserialize, Debug, Default)]
 pub struct PumpDefinition {
     pub inlet: ExternalForceVolume,
     pub outlet: ExternalForceVolume,
+    /// optional target flow rate in L/hr; pump velocity gets adjusted to match
+    #[serde(default)]
+    pub turnover_lph: Option<f32>,
+    /// share [0..1] of the incoming particle speed kept when being pumped to the inlet
+    #[serde(default)]
+    pub momentum_blend: f32,
+    /// temperature (°C) of the water leaving the inlet; None keeps the temperature of the pumped water
+    #[serde(default)]
+    pub inlet_temperature: Option<f32>,
+    /// remove the water at the outlet instead of returning it at the inlet (fill_tank tops up)
+    #[serde(default)]
+    pub drain: bool,
+    /// seconds the pump takes to spin up to full flow (and back down) when toggled; 0 switches at once
+    #[serde(default)]
+    pub ramp_seconds: f32,
+}
+
+/// Look of the glass panes
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct GlassMaterialDefinition {
+    pub tint: Vec3,
+    /// opacity for the simple (non-refractive) glass
+    pub alpha: f32,
+    /// index of refraction (1.5 for typical float glass)
+    pub ior: f32,
+    /// use transmission/refraction; disable on low-end GPUs to fall back to plain alpha blending
+    pub refraction: bool,
+}
+
+impl Default for GlassMaterialDefinition {
+    fn default() -> Self {
+        GlassMaterialDefinition {
+            tint: Vec3::new( 0.9, 1.0, 0.9 ),
+            alpha: 0.2,
+            ior: 1.5,
+            refraction: false,
+        }
+    }
+}
+
+impl GlassMaterialDefinition {
+    /// material of a glass pane with the given thickness (in world units)
+    pub fn to_material(&self, thickness: f32) -> StandardMaterial {
+        if self.refraction {
+            StandardMaterial {
+                base_color: Color::linear_rgb( self.tint.x, self.tint.y, self.tint.z ),
+                specular_transmission: 1.0,
+                thickness,
+                ior: self.ior,
+                perceptual_roughness: 0.05,
+                // thick glass gets its greenish tint from the edges
+                attenuation_color: Color::linear_rgb( self.tint.x, self.tint.y, self.tint.z ),
+                attenuation_distance: thickness * 10.0,
+                ..default()
+            }
+        } else {
+            StandardMaterial {
+                base_color: Color::linear_rgba( self.tint.x, self.tint.y, self.tint.z, self.alpha ),
+                alpha_mode: AlphaMode::Blend,
+                ..default()
+            }
+        }
+    }
+}
+
+/// A grid cell budget that divides the tank evenly along every axis
+#[derive(Debug, PartialEq)]
+pub struct GridSuggestion {
+    pub max_grid_cells: usize,
+    pub grid: UVec3,
+}
+
+fn gcd(a: u32, b: u32) -> u32 {
+    if b == 0 { a } else { gcd( b, a % b ) }
+}
+
+/// the aligned MAX_GRID_CELLS values next to `max_grid_cells` for a tank of `size_cm` (rounded to whole cm)
+pub fn suggest_grid_cells(size_cm: Vec3, max_grid_cells: usize) -> Vec<GridSuggestion> {
+    let dims = size_cm.round().as_uvec3().max( UVec3::ONE );
+    let base = dims / gcd( gcd( dims.x, dims.y ), dims.z );
+    let base_cells = (base.x * base.y * base.z) as f32;
+    let multiple = (max_grid_cells as f32 / base_cells).cbrt();
+    let mut multiples = vec![ multiple.floor().max( 1.0 ) as u32, multiple.ceil().max( 1.0 ) as u32 ];
+    multiples.dedup();
+    multiples.into_iter()
+        .map(| k | {
+            let grid = base * k;
+            GridSuggestion { max_grid_cells: (grid.x * grid.y * grid.z) as usize, grid }
+        })
+        .collect()
+}
+
+/// distribute the cell budget per axis: cell counts proportional to the tank dimensions,
+///   rounded such that the cells stay roughly cubic and the budget is used as far as possible
+pub fn allocate_cell_budget(size_cm: Vec3, max_grid_cells: usize) -> UVec3 {
+    let count = | cells: UVec3 | cells.x as usize * cells.y as usize * cells.z as usize;
+    let scale = (max_grid_cells as f32 / (size_cm.x * size_cm.y * size_cm.z)).cbrt();
+    let mut cells = (size_cm * scale).floor().as_uvec3().max( UVec3::ONE );
+    loop {
+        // grow the axis with the largest cells first as long as the budget allows
+        let cell_size = size_cm / cells.as_vec3();
+        let mut axes = [0, 1, 2];
+        axes.sort_by(| a, b | cell_size[ *b ].total_cmp( &cell_size[ *a ] ));
+        let grown = axes.iter()
+            .map(| &axis | {
+                let mut grown = cells;
+                grown[ axis ] += 1;
+                grown
+            })
+            .find(| grown | count( *grown ) <= max_grid_cells);
+        match grown {
+            Some( grown ) => cells = grown,
+            None => return cells,
+        }
+    }
+}
+
+/// warn with concrete MAX_GRID_CELLS values if the tank doesn't map onto whole grid cells
+fn report_grid_alignment(
+    constants: Res<Constants>,
+    tank_cfg: Res<Tank>,
+) {
+    if tank_cfg.is_grid_aligned() {
+        return;
+    }
+    let size = tank_cfg.get_size();
+    warn!("tank ({:.2} x {:.2} x {:.2} cells) does not divide evenly into grid cells with MAX_GRID_CELLS={}",
+          size.x, size.y, size.z, constants.MAX_GRID_CELLS);
+    if constants.CELL_SIZE.is_some() {
+        warn!("  CELL_SIZE is set and overrides MAX_GRID_CELLS; remove it to use the suggestions below");
+    }
+    for suggestion in suggest_grid_cells( tank_cfg.get_size_cm(), constants.MAX_GRID_CELLS ) {
+        warn!("  try MAX_GRID_CELLS={} for an even {}x{}x{} grid",
+              suggestion.max_grid_cells, suggestion.grid.x, suggestion.grid.y, suggestion.grid.z);
+    }
 }
 
 #[derive(Resource, Serialize, Deserialize, Debug)]
@@ -81,8 +322,25 @@ pub struct Tank {
     pub scale: f32,
     #[serde(default)]
     tank_id: Option<Entity>,
+    /// return pumps; see fold_pump for the single `pump` of older configs
+    #[serde(default)]
+    pub pumps: Vec<PumpDefinition>,
+    #[serde(default, skip_serializing)]
+    pump: Option<PumpDefinition>,
+    /// translation of the whole tank in world units (not scaled with the tank)
+    #[serde(default)]
+    pub world_offset: Vec3,
+    /// optional substrate shape; a slightly random flat ground if not set
+    #[serde(default)]
+    pub substrate: Option<SubstrateDefinition>,
+    #[serde(default)]
+    pub glass_material: GlassMaterialDefinition,
+    /// decorations in cm (not scaled with the tank); a single debug rock if not set
+    #[serde(default = "default_decorations")]
+    pub decorations: Vec<DecorationDefinition>,
+    /// measurement boxes that report the flow through their cross-section (in cm)
     #[serde(default)]
-    pub pump: PumpDefinition,
+    pub flow_gates: Vec<FlowGate>,
 }
 
 
@@ -96,20 +354,27 @@ impl fmt::Display for Tank {
 
 impl FromWorld for Tank {
     fn from_world( _world: &mut World ) -> Self {
-        let mut tank_cfg: Tank = config::read_json::<Tank>(String::from("assets/tank.json")).unwrap();
-        println!("{:?}", tank_cfg);
-        let aqs_constants: Constants = config::read_json::<Constants>(String::from("assets/constants.json")).unwrap();
+        // a tank template replaces the config files
+        let (mut tank_cfg, aqs_constants) = match _world.get_resource::<SelectedTemplate>() {
+            Some( template ) => (template.0.tank(), template.0.constants()),
+            None => (config::read_json::<Tank>(String::from("assets/tank.json")).unwrap_or_else(|e| panic!("{}", e)),
+                     config::read_json::<Constants>(String::from("assets/constants.json")).unwrap_or_else(|e| panic!("{}", e))),
+        };
+        debug!("tank config: {:?}", tank_cfg);
 
-        tank_cfg.pump.outlet.name = Some("OUT".to_string());
+        tank_cfg.fold_pump();
+        for (idx, pump) in tank_cfg.pumps.iter_mut().enumerate() {
+            pump.outlet.name = Some( if idx == 0 { "OUT".to_string() } else { format!("OUT{}", idx + 1) } );
+        }
         // adjust tank config for config parameters
-        tank_cfg.update( aqs_constants.MAX_GRID_CELLS );
+        tank_cfg.fit_to_grid( &aqs_constants );
 
         // this is the meshless parent entity for the tank to allow for a global offset,
         // it's a SpatialBundle to assure Transform- and Visibility Propagation
         // which require Visibility, ComputedVisibility, Transform and GlobalTransform to be set up
         let ptank = _world.spawn((
             // transform: Transform::from_translation(-tank_cfg.get_center()),
-            Transform::from_translation(Vec3::ZERO),
+            Transform::from_translation(tank_cfg.world_offset),
             Visibility::default(),
         ))
             .insert(Name::new("Core-Tank-box"))
@@ -122,6 +387,23 @@ impl FromWorld for Tank {
 }
 
 impl Tank {
+    /// a plain tank without overflow; size and pump in cm (see tank.json)
+    pub fn new(size_cm: Vec3, glass: f32, pump: PumpDefinition) -> Tank {
+        Tank {
+            tank: TankDimensions { width: size_cm.x, depth: size_cm.z, height: size_cm.y, glass },
+            overflow: OverFlowData { drill: vec![], shaft: vec![] },
+            scale: 0.0,
+            tank_id: None,
+            pumps: vec![ pump ],
+            pump: None,
+            world_offset: Vec3::ZERO,
+            substrate: None,
+            glass_material: GlassMaterialDefinition::default(),
+            decorations: vec![],
+            flow_gates: vec![],
+        }
+    }
+
     pub fn get_size(&self) -> Vec3 {
         Vec3::new(self.tank.width,
                   self.tank.height,
@@ -131,34 +413,142 @@ impl Tank {
         self.get_size() / 2.0
     }
 
+    /// center of the tank including the world offset
+    pub fn get_world_center(&self) -> Vec3 {
+        self.get_center() + self.world_offset
+    }
+
+    /// tank volume in liters based on the configured (unscaled) dimensions in cm
+    pub fn get_volume_liters(&self) -> f32 {
+        let size_cm = self.get_size() / self.scale;
+        size_cm.x * size_cm.y * size_cm.z / 1000.
+    }
+
     // CALL ONLY WHEN SURE TANK HAS BEEN INITIALIZED
     pub fn get_tank_parent(&self) -> Entity {
         self.tank_id.unwrap()
     }
 
+    /// xz bounding boxes (min, max) of the overflow shafts
+    pub fn shaft_areas(&self) -> Vec<(Vec2, Vec2)> {
+        shaft_areas( &self.overflow.shaft )
+    }
+
+    pub fn get_pump_definitions(&self) -> &[PumpDefinition] {
+        &self.pumps
+    }
+
+    /// the pump whose inlet fills the tank
     pub fn get_pump_definition(&self) -> &PumpDefinition {
-        &self.pump
+        &self.pumps[0]
+    }
+
+    /// move the single `pump` of older configs into `pumps`; a tank without any pump gets the default one
+    pub fn fold_pump(&mut self) {
+        if let Some( pump ) = self.pump.take() {
+            self.pumps.insert( 0, pump );
+        }
+        if self.pumps.is_empty() {
+            self.pumps.push( PumpDefinition::default() );
+        }
     }
 
-    pub fn update(&mut self, grid_cells: usize) -> f32 {
+    /// the factor that scales the current tank dimensions into grid units
+    fn grid_scale_factor(&self, grid_cells: usize, cell_size: Option<f32>) -> f32 {
         let cell_count = self.tank.width * self.tank.depth * self.tank.height;
-        let cell_scale_factor = f32::powf( grid_cells as f32 / cell_count, 1./3. );
+        match cell_size {
+            Some( size ) => {
+                let scale = 1. / size;
+                let resulting_cells = cell_count * scale.powi(3);
+                debug!("fixed cell size: {} -> {} grid cells", size, resulting_cells as usize );
+                if resulting_cells > MAX_SANE_GRID_CELLS as f32 {
+                    warn!("cell size {} results in {} grid cells (more than {})",
+                          size, resulting_cells as usize, MAX_SANE_GRID_CELLS);
+                }
+                scale
+            },
+            None => f32::powf( grid_cells as f32 / cell_count, 1./3. ),
+        }
+    }
+
+    /// tank dimensions in cm, undoing the grid scaling
+    pub fn get_size_cm(&self) -> Vec3 {
+        self.get_size() / self.scale
+    }
+
+    /// true if the scaled tank spans a whole number of grid cells along every axis
+    pub fn is_grid_aligned(&self) -> bool {
+        let size = self.get_size();
+        (size - size.round()).abs().max_element() < GRID_ALIGNMENT_TOLERANCE
+    }
+
+    /// scale the tank into grid units as configured by GRID_ALLOCATION, MAX_GRID_CELLS and CELL_SIZE
+    pub fn fit_to_grid(&mut self, constants: &Constants) {
+        match (constants.GRID_ALLOCATION, constants.CELL_SIZE) {
+            (GridAllocation::PerAxis, None) => { self.update_per_axis( constants.MAX_GRID_CELLS ); },
+            _ => { self.update( constants.MAX_GRID_CELLS, constants.CELL_SIZE ); },
+        }
+    }
+
+    /// scale the tank into grid units
+    ///   either from a budget of grid cells or, if given, from a fixed cell size (tank units per cell)
+    pub fn update(&mut self, grid_cells: usize, cell_size: Option<f32>) -> f32 {
+        let cell_scale_factor = self.grid_scale_factor( grid_cells, cell_size );
+        self.apply_scale( cell_scale_factor )
+    }
+
+    /// scale the tank into grid units with the cell budget allocated per axis (see allocate_cell_budget)
+    ///   the tank dimensions snap to whole cells; everything else uses the volume-preserving mean scale
+    ///   returns the per-axis scale
+    pub fn update_per_axis(&mut self, grid_cells: usize) -> Vec3 {
+        let size_cm = self.get_size();
+        let cells = allocate_cell_budget( size_cm, grid_cells );
+        let axis_scale = cells.as_vec3() / size_cm;
+        self.apply_scale( (axis_scale.x * axis_scale.y * axis_scale.z).cbrt() );
+
+        self.tank.width = cells.x as f32;
+        self.tank.height = cells.y as f32;
+        self.tank.depth = cells.z as f32;
+        let cell_size = size_cm / cells.as_vec3();
+        info!("per-axis grid {}x{}x{} = {} cells; scale ({:.3}, {:.3}, {:.3}); cell size ({:.2}, {:.2}, {:.2}) cm",
+              cells.x, cells.y, cells.z, cells.x * cells.y * cells.z,
+              axis_scale.x, axis_scale.y, axis_scale.z,
+              cell_size.x, cell_size.y, cell_size.z);
+        axis_scale
+    }
+
+    fn apply_scale(&mut self, cell_scale_factor: f32) -> f32 {
         self.scale = cell_scale_factor;
-        println!("Tank-to-Grid Scale: {}", cell_scale_factor );
+        info!("tank-to-grid scale: {}", cell_scale_factor );
 
         self.tank.width *= cell_scale_factor;
         self.tank.depth *= cell_scale_factor;
         self.tank.height *= cell_scale_factor;
         self.tank.glass *= cell_scale_factor;
 
-        self.pump.inlet.scale( cell_scale_factor );
-        self.pump.outlet.scale( cell_scale_factor );
+        for pump in self.pumps.iter_mut() {
+            pump.inlet.scale( cell_scale_factor );
+            pump.outlet.scale( cell_scale_factor );
+        }
+        self.flow_gates.iter_mut().for_each(| gate | gate.scale( cell_scale_factor ));
 
-        for s in self.overflow.shaft.iter_mut() {
+        for s in self.overflow.shaft.iter_mut().flatten() {
             s.x *= cell_scale_factor;
             s.y *= cell_scale_factor;
         }
-        println!("TANK_AFTER CONVERSION: {:?}", self);
+
+        if let Some( substrate ) = self.substrate.as_mut() {
+            substrate.depth *= cell_scale_factor;
+            substrate.rise *= cell_scale_factor;
+            substrate.roughness *= cell_scale_factor;
+            if let Some( heightmap ) = substrate.heightmap.as_mut() {
+                heightmap.height *= cell_scale_factor;
+            }
+            if let Some( noise ) = substrate.noise.as_mut() {
+                noise.amplitude *= cell_scale_factor;
+            }
+        }
+        debug!("tank after conversion: {:?}", self);
         cell_scale_factor
     }
 
@@ -178,7 +568,9 @@ impl Plugin for TankPlugin {
             .init_resource::<Constants>()
             .init_resource::<Tank>()
             .add_systems( PreStartup, initialize)
-            .add_systems( PreStartup, pump::initialize );
+            .add_systems( PreStartup, pump::initialize )
+            .add_systems( Startup, report_grid_alignment )
+            .add_systems( Update, hot_reload::reload_config_files );
     }
 }
 
@@ -213,6 +605,7 @@ impl Default for GlassPaneDefinition {
 pub struct ParentTankTag;
 
 fn initialize(
+    constants: Res<Constants>,
     mut commands: Commands,
     tank_cfg: ResMut<Tank>,
     mut meshes: ResMut<Assets<Mesh>>,
@@ -223,33 +616,34 @@ fn initialize(
 
     // let mut tank_srf = sf::Surface::default();
 
-    let glass_material_hdl = materials.add(StandardMaterial {
-        base_color: Color::linear_rgba(0.9, 1.0, 0.9, 0.2),
-        alpha_mode: AlphaMode::Blend,
-        ..default()
-    });
+    // create dimensions and center from tank configuration
+    let dim: Vec3 = tank_cfg.get_size();
+    let dim_center: Vec3 = tank_cfg.get_center();
+
+    let glass_thick = tank_cfg.tank.glass / 10.0;
+
+    let glass_material_hdl = materials.add( tank_cfg.glass_material.to_material( glass_thick ) );
     let black_glass_material_hdl = materials.add(StandardMaterial {
         base_color: Color::linear_rgba(1., 1., 1., 1.0),
         alpha_mode: AlphaMode::Opaque,
         ..default()
     });
 
-    // create dimensions and center from tank configuration
-    let dim: Vec3 = tank_cfg.get_size();
-    let dim_center: Vec3 = tank_cfg.get_center();
-
-    let glass_thick = tank_cfg.tank.glass / 10.0;
+    let ptank = tank_cfg.get_tank_parent();
 
     // temp extra plane as artificial bottom (for now)
-    commands.spawn((
-        Mesh3d(meshes.add(Plane3d::default().mesh().size( 200.0, 200.0 ))),
-                   MeshMaterial3d(materials.add(StandardMaterial {
-                       base_color: Color::linear_rgb(0.3, 0.3, 0.3),
-                       alpha_mode: AlphaMode::Opaque,
-                       ..default()
-                   })),
-        Transform::from_xyz( 0.0, -dim_center[1]-glass_thick, 0.0))
-    );
+    if constants.SHOW_DEBUG_BACKGROUND {
+        let floor = commands.spawn((
+            Mesh3d(meshes.add(Plane3d::default().mesh().size( 200.0, 200.0 ))),
+                       MeshMaterial3d(materials.add(StandardMaterial {
+                           base_color: Color::linear_rgb(0.3, 0.3, 0.3),
+                           alpha_mode: AlphaMode::Opaque,
+                           ..default()
+                       })),
+            Transform::from_xyz( 0.0, -dim_center[1]-glass_thick, 0.0))
+        ).id();
+        commands.entity(ptank).add_child(floor);
+    }
 
     // pre-define the glas panes as mesh and handles for re-use in Rapier colliders
     // pre-define side panes
@@ -267,8 +661,6 @@ fn initialize(
                                                            Vec3{ x: 0.0, y: 0.0, z: 0.0 }));
     let bottom_pane = meshes.add(bottom_pane_mesh);
 
-    let ptank = tank_cfg.get_tank_parent();
-
     // generate array of glass pane definitions/locations
     //  note: no .clone() if this is the 'last use' of the variable
     let mut glass_panes = vec![
@@ -310,8 +702,8 @@ fn initialize(
     ];
 
     // build the list of shaft panes
-    let path_len = tank_cfg.overflow.shaft.len();
-    if path_len > 0 {
+    let shaft_segments = shaft_segments( &tank_cfg.overflow.shaft );
+    if !shaft_segments.is_empty() {
         // define a base-pane mesh where x is the length of 1.0 and y and z are defaults (height and glass thickness)
         // then when inserting, stretch the pane in x-direction to match the config
         let spane_base_mesh = Mesh::from(Cuboid::from_corners(
@@ -327,8 +719,8 @@ fn initialize(
 
         let spane_base = meshes.add( spane_base_mesh );
 
-        // zip the shaft-path definitions such that we get a tuple of (i+1, i), i.e. the endpoint and the current point
-        for (i, (b, a)) in tank_cfg.overflow.shaft.iter().skip(1).zip( tank_cfg.overflow.shaft.iter().take( path_len ) ).enumerate() {
+        // each segment is the current point and the endpoint of a pane, across all shafts
+        for (i, (a, b)) in shaft_segments.iter().enumerate() {
             let (xd, zd) = ( b.x - a.x, b.y - a.y );
 
             let plen = f32::sqrt( xd*xd + zd*zd );
@@ -337,7 +729,7 @@ fn initialize(
                              z: 0.0 };
 
             let xangle = get_angle( xd, zd);
-            println!("{},{}", plen, xangle);
+            debug!("shaft pane length {}, angle {}", plen, xangle);
             glass_panes.push( GlassPaneDefinition {
                 name: Name::new(i.to_string() + "Shaft-Pane"),
                 mesh_hdl: spane_base.clone(),
@@ -386,7 +778,7 @@ fn initialize(
                 .insert( DecorationTag );
         }
         panes_list.push( pane );
-        println!("Glass: {} -> id{}", glass.name, pane.index() );
+        debug!("glass pane: {} -> id{}", glass.name, pane.index() );
     }
     commands.entity(ptank).add_children(&panes_list);
 }
@@ -403,9 +795,8 @@ mod test
     use super::*;
     use crate::aqs_utils::extforcevol::ForceVolumeDirection;
 
-    #[test]
-    fn test_serial_out() {
-        let tank = Tank {
+    fn test_tank() -> Tank {
+        Tank {
             tank: TankDimensions {
                 width: 160.,
                 depth: 60.,
@@ -414,11 +805,11 @@ mod test
             },
             overflow: OverFlowData {
                 drill: vec![],
-                shaft: vec![ Vec2::new( 40., 0.), Vec2::new( 40., 15.), Vec2::new( 0., 15.) ],
+                shaft: vec![ vec![ Vec2::new( 40., 0.), Vec2::new( 40., 15.), Vec2::new( 0., 15.) ] ],
             },
             scale: 1.0,
             tank_id: None,
-            pump: PumpDefinition {
+            pumps: vec![ PumpDefinition {
                 inlet: ExternalForceVolume::new( Vec3::new(10.,60.,25.),
                                                  Vec3::new(2.,2.,10.),
                                                  ForceVolumeDirection::from_parallel(
@@ -429,9 +820,110 @@ mod test
                                                   ForceVolumeDirection::from_parallel(
                                                     Vec3::new(20.,1.0,0.0)),
                                                   Some("OUT".to_string())),
-            },
-        };
+                turnover_lph: None,
+                momentum_blend: 0.0,
+                inlet_temperature: None,
+                drain: false,
+            } ],
+            pump: None,
+            world_offset: Vec3::ZERO,
+            substrate: None,
+            glass_material: GlassMaterialDefinition::default(),
+            decorations: vec![],
+            flow_gates: vec![],
+        }
+    }
+
+    #[test]
+    fn test_grid_suggestion() {
+        let mut tank = test_tank();
+        tank.update( 50000, None );
+        assert!( !tank.is_grid_aligned() );
+
+        // 160 x 70 x 60 cm has a common divisor of 10
+        let suggestions = suggest_grid_cells( tank.get_size_cm(), 50000 );
+        assert_eq!( suggestions, vec![
+            GridSuggestion { max_grid_cells: 43008, grid: UVec3::new( 64, 28, 24 ) },
+            GridSuggestion { max_grid_cells: 84000, grid: UVec3::new( 80, 35, 30 ) },
+        ]);
+        for suggestion in suggestions {
+            let mut aligned = test_tank();
+            aligned.update( suggestion.max_grid_cells, None );
+            assert!( aligned.is_grid_aligned() );
+            assert!( aligned.get_size().round().as_uvec3() == suggestion.grid );
+        }
+    }
+
+    #[test]
+    fn test_per_axis_cell_budget() {
+        let size = Vec3::new( 200., 30., 60. );
+        let cells = allocate_cell_budget( size, 50000 );
+        assert!( cells.x * cells.y * cells.z <= 50000 );
+        assert!( cells.x * cells.y * cells.z > 49000 );
+
+        // proportional to the tank dimensions within rounding
+        let ideal = size * (50000. / (size.x * size.y * size.z)).cbrt();
+        assert!( (cells.as_vec3() - ideal).abs().max_element() <= 1.0, "{:?} vs {:?}", cells, ideal );
+
+        let mut tank = test_tank();
+        tank.tank = TankDimensions { width: 200., depth: 60., height: 30., glass: 1. };
+        let axis_scale = tank.update_per_axis( 50000 );
+        assert_eq!( tank.get_size().as_uvec3(), cells );
+        assert!( tank.is_grid_aligned() );
+        // cells stay roughly cubic
+        assert!( axis_scale.max_element() / axis_scale.min_element() < 1.1 );
+    }
+
+    #[test]
+    fn test_multiple_shafts() {
+        let single: OverFlowData = serde_json::from_str( r#"{ "drill": [], "shaft": [ [0, 15], [25, 15], [35, 0] ] }"# ).unwrap();
+        assert_eq!( single.shaft.len(), 1 );
+        assert_eq!( shaft_segments( &single.shaft ).len(), 2 );
+
+        let two: OverFlowData = serde_json::from_str(
+            r#"{ "drill": [], "shaft": [ [ [0, 15], [25, 15], [35, 0] ], [ [120, 0], [120, 10], [160, 10] ] ] }"# ).unwrap();
+        assert_eq!( two.shaft.len(), 2 );
+        let segments = shaft_segments( &two.shaft );
+        assert_eq!( segments.len(), 4 );
+        // no pane connects the end of the first shaft to the start of the second
+        assert!( !segments.contains( &(Vec2::new( 35., 0. ), Vec2::new( 120., 0. )) ) );
+
+        let none: OverFlowData = serde_json::from_str( r#"{ "drill": [], "shaft": [] }"# ).unwrap();
+        assert!( shaft_segments( &none.shaft ).is_empty() );
+
+        let areas = shaft_areas( &two.shaft );
+        assert_eq!( areas, vec![ ( Vec2::new( 0., 0. ), Vec2::new( 35., 15. ) ), ( Vec2::new( 120., 0. ), Vec2::new( 160., 10. ) ) ] );
+        assert!( shaft_areas( &none.shaft ).is_empty() );
+    }
+
+    #[test]
+    fn test_glass_material() {
+        let simple = GlassMaterialDefinition::default().to_material( 0.8 );
+        assert_eq!( simple.alpha_mode, AlphaMode::Blend );
+        assert_eq!( simple.specular_transmission, 0.0 );
+
+        let glass = GlassMaterialDefinition { refraction: true, ior: 1.52, ..default() };
+        let refractive = glass.to_material( 0.8 );
+        assert_eq!( refractive.specular_transmission, 1.0 );
+        assert_eq!( refractive.thickness, 0.8 );
+        assert_eq!( refractive.ior, 1.52 );
+    }
+
+    #[test]
+    fn test_serial_out() {
+        let tank = test_tank();
         let ostr = serde_json::to_string_pretty(&tank).unwrap();
         println!("{}",ostr);
     }
+
+    #[test]
+    fn test_fixed_cell_size() {
+        let mut tank = test_tank();
+        let scale = tank.update( 50000, Some( 2.0 ) );
+        assert_eq!( scale, 0.5 );
+        assert_eq!( tank.get_size(), Vec3::new( 80., 35., 30. ) );
+
+        let grid = crate::water::grid::Grid::new( tank.get_size().as_uvec3(), 1.0 );
+        assert_eq!( *grid.grid_size(), UVec3::new( 82, 39, 32 ) );
+    }
 }
@@ -0,0 +1,187 @@
+/*
+    Copyright 2023 github.com/programaquatic
+
+   Licensed under the Apache License, Version 2.0 (the "License");
+   you may not use this file except in compliance with the License.
+   You may obtain a copy of the License at
+
+       http://www.apache.org/licenses/LICENSE-2.0
+
+   Unless required by applicable law or agreed to in writing, software
+   distributed under the License is distributed on an "AS IS" BASIS,
+   WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+   See the License for the specific language governing permissions and
+   limitations under the License.
+*/
+
+use bevy::prelude::*;
+use serde_json::json;
+
+use crate::{
+    aqs_utils::{
+        constants::Constants,
+        extforcevol::{ExternalForceVolume, ForceVolumeDirection},
+    },
+    tech::{
+        pump,
+        tank::{PumpDefinition, Tank, MAX_SANE_GRID_CELLS},
+    },
+};
+
+/// smallest number of cells along an axis that still resolves any flow
+const MIN_CELLS_PER_AXIS: f32 = 8.0;
+/// sensible range of tank volumes turned over per hour
+const TURNOVER_RANGE: (f32, f32) = (3.0, 30.0);
+
+
+/// Built-in tank setups for a quick start without writing config files (--template <name>)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TankTemplate {
+    /// 20 L nano cube
+    Nano20,
+    /// 40 gal breeder
+    Breeder40,
+    /// 75 gal reef tank
+    Reef75,
+    /// 120 L planted tank with a gentle flow
+    Planted120,
+}
+
+/// the template that replaces assets/tank.json and assets/constants.json
+#[derive(Resource, Debug, Clone, Copy)]
+pub struct SelectedTemplate(pub TankTemplate);
+
+
+impl TankTemplate {
+    pub const ALL: [TankTemplate; 4] = [
+        TankTemplate::Nano20,
+        TankTemplate::Breeder40,
+        TankTemplate::Reef75,
+        TankTemplate::Planted120,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            TankTemplate::Nano20 => "nano20",
+            TankTemplate::Breeder40 => "breeder40",
+            TankTemplate::Reef75 => "reef75",
+            TankTemplate::Planted120 => "planted120",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<TankTemplate> {
+        TankTemplate::ALL.into_iter().find(| template | template.name() == name)
+    }
+
+    /// tank size (cm), glass thickness (mm), pump turnover (L/hr) and grid cell budget
+    fn spec(self) -> (Vec3, f32, f32, usize) {
+        match self {
+            TankTemplate::Nano20 => (Vec3::new( 36.0, 25.0, 25.0 ), 5.0, 250.0, 20000),
+            TankTemplate::Breeder40 => (Vec3::new( 91.0, 41.0, 46.0 ), 8.0, 1500.0, 40000),
+            TankTemplate::Reef75 => (Vec3::new( 122.0, 53.0, 46.0 ), 10.0, 3000.0, 50000),
+            TankTemplate::Planted120 => (Vec3::new( 80.0, 45.0, 35.0 ), 6.0, 600.0, 40000),
+        }
+    }
+
+    /// tank config in cm like tank.json: return nozzle at the top left back, intake low at the left front
+    pub fn tank(self) -> Tank {
+        let (size, glass, turnover, _) = self.spec();
+        let nozzle = (size * 0.08).clamp( Vec3::splat( 2.0 ), Vec3::splat( 5.0 ) );
+        let intake = (size * 0.12).clamp( Vec3::splat( 3.0 ), Vec3::splat( 10.0 ) );
+        let pump = PumpDefinition {
+            inlet: ExternalForceVolume::new(
+                Vec3::new( nozzle.x * 2.0, size.y * 0.85, size.z * 0.8 ),
+                nozzle,
+                ForceVolumeDirection::Parallel( Vec3::X ),
+                None,
+            ),
+            outlet: ExternalForceVolume::new(
+                Vec3::new( intake.x * 1.5, intake.y * 1.5, intake.z * 1.5 ),
+                intake,
+                ForceVolumeDirection::Inward( -1.0 ),
+                None,
+            ),
+            turnover_lph: Some( turnover ),
+            momentum_blend: 0.0,
+            inlet_temperature: None,
+            drain: false,
+        };
+        Tank::new( size, glass, pump )
+    }
+
+    pub fn constants(self) -> Constants {
+        let (_, _, _, max_grid_cells) = self.spec();
+        let constants: Constants = serde_json::from_value( json!({
+            "MAX_GRID_CELLS": max_grid_cells,
+            "GRID_ALLOCATION": "PerAxis",
+            "WORLD_DT": 0.04,
+            "DEFAULT_GRAVITY": -9.81,
+            "DEFAULT_DENSITY": [4.0, 1.0],
+            "DEFAULT_FILL_HEIGHT": 0.9,
+            "DEFAULT_DAMPENING": 0.9999,
+            "MAX_PARTICLES": 1000,
+            "VISIBLE_PARTICLES": 50,
+            "SHOW_DEBUG_BACKGROUND": false,
+        })).expect( "invalid tank template constants" );
+        constants.finalize()
+    }
+}
+
+
+/// sanity check of a tank/constants combination before running it
+///   the tank gets scaled into grid units like at startup
+pub fn validate(mut tank: Tank, constants: &Constants) -> Result<(), String> {
+    tank.fit_to_grid( constants );
+    let size = tank.get_size();
+    let cells = size.x * size.y * size.z;
+    if cells > MAX_SANE_GRID_CELLS as f32 {
+        return Err( format!("{} grid cells exceed {}", cells as usize, MAX_SANE_GRID_CELLS) );
+    }
+    if size.min_element() < MIN_CELLS_PER_AXIS {
+        return Err( format!("grid {:?} has less than {} cells along an axis", size, MIN_CELLS_PER_AXIS) );
+    }
+    if constants.DEFAULT_PPC == 0 || constants.DEFAULT_PARTICLE_MASS <= 0.0 {
+        return Err( "no particles per cell".to_string() );
+    }
+
+    for definition in tank.get_pump_definitions() {
+        for volume in [ &definition.inlet, &definition.outlet ] {
+            if volume.location.cmplt( Vec3::ZERO ).any() || volume.location.cmpgt( size ).any() {
+                return Err( format!("pump volume at {} is outside of the tank", volume.location) );
+            }
+        }
+    }
+    let pumps = pump::pumps_from_definitions( &tank );
+    let turnover = pumps.iter().map(| pump | pump.turnover( tank.scale )).sum::<f32>() / tank.get_volume_liters();
+    if turnover < TURNOVER_RANGE.0 || turnover > TURNOVER_RANGE.1 {
+        return Err( format!("pumps turn the tank over {:.1}x per hour", turnover) );
+    }
+    // the jets must not skip cells within one step
+    for pump in pumps.iter() {
+        let cells_per_step = pump.target_speed() * constants.WORLD_DT;
+        if cells_per_step >= 1.0 {
+            return Err( format!("pump jet moves {:.2} cells per step", cells_per_step) );
+        }
+    }
+    Ok(())
+}
+
+
+
+#[cfg(test)]
+mod test
+{
+    use super::*;
+
+    #[test]
+    fn test_templates_validate() {
+        for template in TankTemplate::ALL {
+            assert_eq!( TankTemplate::from_name( template.name() ), Some( template ) );
+            let constants = template.constants();
+            if let Err( e ) = validate( template.tank(), &constants ) {
+                panic!("template {}: {}", template.name(), e);
+            }
+        }
+        assert_eq!( TankTemplate::from_name( "ocean" ), None );
+    }
+}
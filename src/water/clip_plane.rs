@@ -0,0 +1,141 @@
+/*
+    Copyright 2023 github.com/programaquatic
+
+   Licensed under the Apache License, Version 2.0 (the "License");
+   you may not use this file except in compliance with the License.
+   You may obtain a copy of the License at
+
+       http://www.apache.org/licenses/LICENSE-2.0
+
+   Unless required by applicable law or agreed to in writing, software
+   distributed under the License is distributed on an "AS IS" BASIS,
+   WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+   See the License for the specific language governing permissions and
+   limitations under the License.
+*/
+
+use bevy::{
+    prelude::*,
+    math::Vec3A,
+};
+
+use crate::water::{
+    grid::Grid,
+    surface::WaveGridFrameTag,
+};
+
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ClipAxis {
+    #[default]
+    X,
+    Y,
+    Z,
+}
+
+impl ClipAxis {
+    pub fn next(self) -> Self {
+        match self {
+            ClipAxis::X => ClipAxis::Y,
+            ClipAxis::Y => ClipAxis::Z,
+            ClipAxis::Z => ClipAxis::X,
+        }
+    }
+
+    pub fn component(self, v: Vec3A) -> f32 {
+        match self {
+            ClipAxis::X => v.x,
+            ClipAxis::Y => v.y,
+            ClipAxis::Z => v.z,
+        }
+    }
+}
+
+
+/// Axis-aligned plane that hides everything on its far side to reveal a cross-section of the fluid
+///   C toggles, X cycles the axis, [ and ] move the plane by one grid cell
+#[derive(Resource, Debug, Default)]
+pub struct ClipPlane {
+    pub enabled: bool,
+    pub axis: ClipAxis,
+    /// location of the plane along the axis in grid units
+    pub position: f32,
+}
+
+impl ClipPlane {
+    /// a location gets hidden if it's beyond the plane
+    pub fn is_clipped(&self, location: Vec3A) -> bool {
+        self.enabled && self.axis.component( location ) > self.position
+    }
+}
+
+
+pub fn control_clip_plane(
+    keys: Res<ButtonInput<KeyCode>>,
+    grid: Res<Grid>,
+    mut clip: ResMut<ClipPlane>,
+) {
+    let limit = clip.axis.component( grid.grid_size().as_vec3a() );
+    if keys.just_pressed( KeyCode::KeyC ) {
+        clip.enabled = !clip.enabled;
+        if clip.enabled {
+            clip.position = 0.5 * limit;
+        }
+    }
+    if !clip.enabled {
+        return;
+    }
+    if keys.just_pressed( KeyCode::KeyX ) {
+        clip.axis = clip.axis.next();
+        clip.position = 0.5 * clip.axis.component( grid.grid_size().as_vec3a() );
+    }
+    if keys.just_pressed( KeyCode::BracketLeft ) {
+        clip.position = (clip.position - 1.0).max( 0.0 );
+    }
+    if keys.just_pressed( KeyCode::BracketRight ) {
+        clip.position = (clip.position + 1.0).min( limit );
+    }
+}
+
+
+/// the surface mesh can't be cut on the CPU side, so it's hidden entirely while clipping
+pub fn clip_surface(
+    clip: Res<ClipPlane>,
+    mut surface: Query<&mut Visibility, With<WaveGridFrameTag>>,
+) {
+    if !clip.is_changed() {
+        return;
+    }
+    surface.iter_mut().for_each(| mut visibility | {
+        *visibility = if clip.enabled { Visibility::Hidden } else { Visibility::Inherited };
+    });
+}
+
+
+
+#[cfg(test)]
+mod test
+{
+    use super::*;
+
+    #[test]
+    fn test_clip_plane() {
+        let mut clip = ClipPlane { enabled: false, axis: ClipAxis::X, position: 5.0 };
+        let inside = Vec3A::new( 4.0, 9.0, 9.0 );
+        let beyond = Vec3A::new( 6.0, 1.0, 1.0 );
+        assert!( !clip.is_clipped( beyond ) );
+
+        clip.enabled = true;
+        assert!( !clip.is_clipped( inside ) );
+        assert!( clip.is_clipped( beyond ) );
+
+        // sliding the plane reveals the next layer
+        clip.position = 7.0;
+        assert!( !clip.is_clipped( beyond ) );
+
+        clip.axis = clip.axis.next();
+        assert_eq!( clip.axis, ClipAxis::Y );
+        assert!( clip.is_clipped( inside ) );
+        assert_eq!( ClipAxis::Z.next(), ClipAxis::X );
+    }
+}
@@ -0,0 +1,240 @@
+/*
+    Copyright 2023 github.com/programaquatic
+
+   Licensed under the Apache License, Version 2.0 (the "License");
+   you may not use this file except in compliance with the License.
+   You may obtain a copy of the License at
+
+       http://www.apache.org/licenses/LICENSE-2.0
+
+   Unless required by applicable law or agreed to in writing, software
+   distributed under the License is distributed on an "AS IS" BASIS,
+   WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+   See the License for the specific language governing permissions and
+   limitations under the License.
+*/
+
+use bevy::{
+    prelude::*,
+    math::Vec3A,
+};
+
+use crate::{
+    aqs_utils::{
+        constants::{Colormap, Constants},
+        coneshape::ZCone,
+    },
+    water::grid::{self, Grid, GridCellIndex, GridCellType},
+};
+
+/// number of materials the value range gets quantized into
+const PALETTE_SIZE: usize = 32;
+
+
+/// Per-cell quantity that colors the fluid grid cells (G cycles through them)
+#[derive(Resource, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DebugScalar {
+    #[default]
+    Off,
+    Mass,
+    Speed,
+    Divergence,
+    /// magnitude of the curl
+    Vorticity,
+    /// high where the water barely moves
+    Stagnation,
+}
+
+impl DebugScalar {
+    pub fn next(self) -> Self {
+        match self {
+            DebugScalar::Off => DebugScalar::Mass,
+            DebugScalar::Mass => DebugScalar::Speed,
+            DebugScalar::Speed => DebugScalar::Divergence,
+            DebugScalar::Divergence => DebugScalar::Vorticity,
+            DebugScalar::Vorticity => DebugScalar::Stagnation,
+            DebugScalar::Stagnation => DebugScalar::Off,
+        }
+    }
+
+    /// signed quantities get a range symmetric around 0
+    pub fn is_signed(self) -> bool {
+        self == DebugScalar::Divergence
+    }
+
+    /// value per cell index from the cell masses and velocities
+    pub fn values(self, grid: &Grid, mass: &[f32], velo: &[Vec3A]) -> Vec<f32> {
+        match self {
+            DebugScalar::Off => vec![ 0.0; mass.len() ],
+            DebugScalar::Mass => mass.to_vec(),
+            DebugScalar::Speed => velo.iter().map(| v | v.length()).collect(),
+            DebugScalar::Divergence => grid::divergence( grid, velo ),
+            DebugScalar::Vorticity => grid::vorticity( grid, velo ).iter().map(| c | c.length()).collect(),
+            DebugScalar::Stagnation => velo.iter().map(| v | 1.0 / (1.0 + v.length())).collect(),
+        }
+    }
+}
+
+
+/// color of t in [0, 1] (clamped)
+pub fn colormap_color(map: Colormap, t: f32) -> Vec3 {
+    let t = t.clamp( 0.0, 1.0 );
+    match map {
+        Colormap::Grayscale => Vec3::splat( t ),
+        Colormap::BlueWhiteRed => if t < 0.5 {
+            Vec3::new( 0.0, 0.0, 1.0 ).lerp( Vec3::ONE, t * 2.0 )
+        } else {
+            Vec3::ONE.lerp( Vec3::new( 1.0, 0.0, 0.0 ), t * 2.0 - 1.0 )
+        },
+        Colormap::Rainbow => {
+            let stops = [
+                Vec3::new( 0.0, 0.0, 1.0 ),
+                Vec3::new( 0.0, 1.0, 1.0 ),
+                Vec3::new( 0.0, 1.0, 0.0 ),
+                Vec3::new( 1.0, 1.0, 0.0 ),
+                Vec3::new( 1.0, 0.0, 0.0 ),
+            ];
+            let scaled = t * (stops.len() - 1) as f32;
+            let i = (scaled.floor() as usize).min( stops.len() - 2 );
+            stops[ i ].lerp( stops[ i + 1 ], scaled - i as f32 )
+        },
+    }
+}
+
+/// position of value within [min, max] as t in [0, 1]
+pub fn normalize_scalar(value: f32, min: f32, max: f32) -> f32 {
+    if max <= min {
+        return 0.5;
+    }
+    ((value - min) / (max - min)).clamp( 0.0, 1.0 )
+}
+
+/// value range of the given values; symmetric around 0 for signed quantities
+pub fn scalar_range(values: impl Iterator<Item = f32>, signed: bool) -> (f32, f32) {
+    let (min, max) = values.fold( (f32::MAX, f32::MIN), | (min, max), v | (min.min( v ), max.max( v )) );
+    if min > max {
+        return (0.0, 0.0);
+    }
+    if signed {
+        let extent = min.abs().max( max.abs() );
+        return (-extent, extent);
+    }
+    (min, max)
+}
+
+
+/// cone mesh and the quantized colormap materials
+pub struct DebugScalarAssets {
+    mesh: Handle<Mesh>,
+    palette: Vec<Handle<StandardMaterial>>,
+    colormap: Colormap,
+}
+
+
+pub fn cycle_debug_scalar(
+    keys: Res<ButtonInput<KeyCode>>,
+    mut scalar: ResMut<DebugScalar>,
+) {
+    if keys.just_pressed( KeyCode::KeyG ) {
+        *scalar = scalar.next();
+        info!("debug scalar: {:?}", *scalar);
+    }
+}
+
+/// color the fluid cells by the selected scalar; the cells get a cone mesh while the scalar is on
+pub fn update_debug_scalar(
+    constants: Res<Constants>,
+    scalar: Res<DebugScalar>,
+    grid: Res<Grid>,
+    mut assets: Local<Option<DebugScalarAssets>>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    mut cells: Query<(Entity, &GridCellType, &GridCellIndex, Option<&mut MeshMaterial3d<StandardMaterial>>, &mut Visibility)>,
+    mut commands: Commands,
+) {
+    if *scalar == DebugScalar::Off {
+        if scalar.is_changed() {
+            cells.iter_mut().for_each(| (cell, _, _, _, mut visibility) | {
+                commands.entity( cell ).remove::<(Mesh3d, MeshMaterial3d<StandardMaterial>)>();
+                *visibility = Visibility::Inherited;
+            });
+        }
+        return;
+    }
+
+    if assets.as_ref().is_none_or(| a | a.colormap != constants.DEBUG_COLORMAP) {
+        let colormap = constants.DEBUG_COLORMAP;
+        *assets = Some( DebugScalarAssets {
+            mesh: meshes.add( Mesh::from( ZCone { radius: 0.2, height: 0.4, subdivisions: 5 } ) ),
+            palette: (0..PALETTE_SIZE)
+                .map(| i | {
+                    let c = colormap_color( colormap, i as f32 / (PALETTE_SIZE - 1) as f32 );
+                    materials.add( StandardMaterial { base_color: Color::linear_rgb( c.x, c.y, c.z ), unlit: true, ..default() } )
+                })
+                .collect(),
+            colormap,
+        });
+    }
+    let Some( assets ) = assets.as_ref() else {
+        return;
+    };
+
+    let mass = grid.get_tmp_mass();
+    let values = scalar.values( &grid, mass, grid.get_tmp_velo() );
+    let shown = | gct: &GridCellType, idx: usize | *gct == GridCellType::Fluid && mass[ idx ] > 0.0;
+    let (min, max) = scalar_range(
+        cells.iter().filter(| (_, gct, idx, ..) | shown( gct, idx.0 )).map(| (_, _, idx, ..) | values[ idx.0 ]),
+        scalar.is_signed() );
+
+    cells.iter_mut().for_each(| (cell, gct, idx, material, mut visibility) | {
+        let bucket = (normalize_scalar( values[ idx.0 ], min, max ) * (PALETTE_SIZE - 1) as f32).round() as usize;
+        let handle = assets.palette[ bucket ].clone();
+        let wanted = if shown( gct, idx.0 ) { Visibility::Inherited } else { Visibility::Hidden };
+        visibility.set_if_neq( wanted );
+        match material {
+            Some( mut material ) => {
+                if material.0 != handle {
+                    material.0 = handle;
+                }
+            },
+            None => {
+                commands.entity( cell ).insert(( Mesh3d( assets.mesh.clone() ), MeshMaterial3d( handle ) ));
+            },
+        }
+    });
+}
+
+
+
+#[cfg(test)]
+mod test
+{
+    use super::*;
+
+    #[test]
+    fn test_scalar_colors() {
+        let color = | map, value | colormap_color( map, normalize_scalar( value, -2.0, 2.0 ) );
+        assert_eq!( color( Colormap::Grayscale, -2.0 ), Vec3::ZERO );
+        assert_eq!( color( Colormap::Grayscale, 0.0 ), Vec3::splat( 0.5 ) );
+        assert_eq!( color( Colormap::Grayscale, 2.0 ), Vec3::ONE );
+
+        assert_eq!( color( Colormap::BlueWhiteRed, -2.0 ), Vec3::Z );
+        assert_eq!( color( Colormap::BlueWhiteRed, 0.0 ), Vec3::ONE );
+        assert_eq!( color( Colormap::BlueWhiteRed, 2.0 ), Vec3::X );
+
+        assert_eq!( color( Colormap::Rainbow, -2.0 ), Vec3::Z );
+        assert_eq!( color( Colormap::Rainbow, 0.0 ), Vec3::Y );
+        assert_eq!( color( Colormap::Rainbow, 2.0 ), Vec3::X );
+        // out of range values saturate
+        assert_eq!( color( Colormap::Rainbow, 5.0 ), Vec3::X );
+    }
+
+    #[test]
+    fn test_scalar_range() {
+        assert_eq!( scalar_range( [ 1.0, 3.0, 2.0 ].into_iter(), false ), (1.0, 3.0) );
+        assert_eq!( scalar_range( [ -1.0, 3.0 ].into_iter(), true ), (-3.0, 3.0) );
+        assert_eq!( scalar_range( std::iter::empty(), false ), (0.0, 0.0) );
+        assert_eq!( normalize_scalar( 1.0, 1.0, 1.0 ), 0.5 );
+        assert_eq!( DebugScalar::Stagnation.next(), DebugScalar::Off );
+    }
+}
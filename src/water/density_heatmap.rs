@@ -0,0 +1,151 @@
+/*
+    Copyright 2023 github.com/programaquatic
+
+   Licensed under the Apache License, Version 2.0 (the "License");
+   you may not use this file except in compliance with the License.
+   You may obtain a copy of the License at
+
+       http://www.apache.org/licenses/LICENSE-2.0
+
+   Unless required by applicable law or agreed to in writing, software
+   distributed under the License is distributed on an "AS IS" BASIS,
+   WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+   See the License for the specific language governing permissions and
+   limitations under the License.
+*/
+
+use bevy::{
+    prelude::*,
+    image::ImageSampler,
+    render::{
+        render_asset::RenderAssetUsages,
+        render_resource::{Extent3d, TextureDimension, TextureFormat},
+    },
+};
+
+use crate::{
+    aqs_utils::constants::{Colormap, Constants, DensityHeatmapConf},
+    water::{
+        debug_scalar::{colormap_color, normalize_scalar},
+        grid::{Grid, GridCellIndex, GridCellType},
+        resources::FluidQuantityMass,
+    },
+};
+
+
+/// mass of the fluid cells summed over y for every (x, z) column; rows along z
+pub fn column_mass(grid: &Grid, mass: &[f32]) -> Vec<f32> {
+    let size = grid.grid_size();
+    (0..size.z as usize).flat_map(| z | {
+        (0..size.x as usize).map(move | x | {
+            (0..size.y as usize).map(| y | mass[ grid.index_of( x, y, z ) ]).sum::<f32>()
+        })
+    }).collect()
+}
+
+/// rgba texel of a column mass; empty columns are transparent
+pub fn mass_color(mass: f32, max_mass: f32, colormap: Colormap, opacity: f32) -> [u8; 4] {
+    if mass <= 0.0 {
+        return [ 0; 4 ];
+    }
+    let c = colormap_color( colormap, normalize_scalar( mass, 0.0, max_mass ) );
+    let to_u8 = | v: f32 | (v.clamp( 0.0, 1.0 ) * 255.0).round() as u8;
+    [ to_u8( c.x ), to_u8( c.y ), to_u8( c.z ), to_u8( opacity ) ]
+}
+
+fn heatmap_image(size: UVec2) -> Image {
+    let mut image = Image::new_fill(
+        Extent3d { width: size.x.max( 1 ), height: size.y.max( 1 ), depth_or_array_layers: 1 },
+        TextureDimension::D2,
+        &[0; 4],
+        TextureFormat::Rgba8UnormSrgb,
+        RenderAssetUsages::default(),
+    );
+    // one texel per column
+    image.sampler = ImageSampler::nearest();
+    image
+}
+
+
+/// top-down heatmap of the water mass per grid column on a quad above the tank (DENSITY_HEATMAP)
+pub fn update_density_heatmap(
+    constants: Res<Constants>,
+    grid: Res<Grid>,
+    mut overlay: Local<Option<(Entity, Handle<Image>)>>,
+    mut images: ResMut<Assets<Image>>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    cells: Query<(&GridCellIndex, &GridCellType, &FluidQuantityMass)>,
+    mut commands: Commands,
+) {
+    let Some( conf ) = &constants.DENSITY_HEATMAP else {
+        // turned off by a config reload
+        if let Some( (quad, _) ) = overlay.take() {
+            commands.entity( quad ).despawn_recursive();
+        }
+        return;
+    };
+    let size = grid.grid_size();
+    let (_, image_hdl) = overlay.get_or_insert_with(|| {
+        let image_hdl = images.add( heatmap_image( UVec2::new( size.x, size.z ) ) );
+        let extent = grid.to_world_coord( size.as_vec3() ) - grid.to_world_coord( Vec3::ZERO );
+        let center = grid.to_world_coord( Vec3::new( size.x as f32 / 2.0, size.y as f32 + 1.0, size.z as f32 / 2.0 ) );
+        let quad = commands.spawn((
+            Name::new("Density_Heatmap"),
+            Mesh3d( meshes.add( Plane3d::default().mesh().size( extent.x, extent.z ) ) ),
+            MeshMaterial3d( materials.add( StandardMaterial {
+                base_color_texture: Some( image_hdl.clone() ),
+                alpha_mode: AlphaMode::Blend,
+                unlit: true,
+                double_sided: true,
+                cull_mode: None,
+                ..default()
+            })),
+            Transform::from_translation( center ),
+        )).id();
+        (quad, image_hdl)
+    });
+
+    let mut mass = vec![ 0.0; grid.cell_count() ];
+    cells.iter()
+        .filter(| (_, gct, _) | **gct == GridCellType::Fluid)
+        .for_each(| (idx, _, m) | mass[ idx.0 ] = m.0 );
+    let columns = column_mass( &grid, &mass );
+    let max_mass = conf.max_mass.unwrap_or_else(|| columns.iter().copied().fold( 0.0, f32::max ));
+
+    let Some( image ) = images.get_mut( image_hdl.id() ) else {
+        return;
+    };
+    image.data = columns.iter()
+        .flat_map(| &m | mass_color( m, max_mass, constants.DEBUG_COLORMAP, conf.opacity ))
+        .collect();
+}
+
+
+
+#[cfg(test)]
+mod test
+{
+    use super::*;
+
+    #[test]
+    fn test_mass_color() {
+        assert_eq!( mass_color( 0.0, 4.0, Colormap::Grayscale, 0.5 ), [ 0, 0, 0, 0 ] );
+        assert_eq!( mass_color( 2.0, 4.0, Colormap::Grayscale, 0.5 ), [ 128, 128, 128, 128 ] );
+        assert_eq!( mass_color( 4.0, 4.0, Colormap::BlueWhiteRed, 1.0 ), [ 255, 0, 0, 255 ] );
+        // above the max saturates
+        assert_eq!( mass_color( 9.0, 4.0, Colormap::Rainbow, 1.0 ), [ 255, 0, 0, 255 ] );
+    }
+
+    #[test]
+    fn test_column_mass() {
+        let grid = Grid::new( UVec3::new( 3, 2, 2 ), 1.0 );
+        let size = *grid.grid_size();
+        let mut mass = vec![ 0.0; grid.cell_count() ];
+        (0..size.y as usize).for_each(| y | mass[ grid.index_of( 1, y, 2 ) ] = 0.5 );
+        let columns = column_mass( &grid, &mass );
+        assert_eq!( columns.len(), (size.x * size.z) as usize );
+        assert_eq!( columns[ 2 * size.x as usize + 1 ], 0.5 * size.y as f32 );
+        assert_eq!( columns.iter().sum::<f32>(), 0.5 * size.y as f32 );
+    }
+}
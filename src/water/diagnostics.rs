@@ -0,0 +1,196 @@
+/*
+    Copyright 2023 github.com/programaquatic
+
+   Licensed under the Apache License, Version 2.0 (the "License");
+   you may not use this file except in compliance with the License.
+   You may obtain a copy of the License at
+
+       http://www.apache.org/licenses/LICENSE-2.0
+
+   Unless required by applicable law or agreed to in writing, software
+   distributed under the License is distributed on an "AS IS" BASIS,
+   WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+   See the License for the specific language governing permissions and
+   limitations under the License.
+*/
+
+use std::sync::atomic::{AtomicU32, Ordering};
+
+use bevy::{
+    prelude::*,
+    math::Vec3A,
+    diagnostic::{DiagnosticPath, Diagnostics},
+    utils::Parallel,
+};
+
+use crate::{
+    water::{
+        grid::GridCellType,
+        resources::{
+            FluidParticleVelocity,
+            FluidQuantityMass,
+            ParticleTag,
+        },
+        sim_time::SimTime,
+    },
+};
+
+/// CFL number above which the simulation is likely to become unstable
+pub const CFL_WARNING_THRESHOLD: f32 = 0.5;
+
+
+/// Per-frame health indicators of the fluid simulation
+#[derive(Resource, Default, Debug)]
+pub struct FluidDiagnostics {
+    pub frame: u64,
+    /// fastest grid cell velocity magnitude (grid units per second)
+    pub max_cell_speed: f32,
+    /// max local CFL number: max_cell_speed * dt / cell_size with the dt of the current step (SimTime)
+    pub max_cfl: f32,
+    /// highest max_cfl since the start
+    pub peak_cfl: f32,
+    /// particles despawned because they left the grid or turned non-finite
+    pub escaped_particles: usize,
+}
+
+/// Conserved quantities of the particles, e.g. to check that a step doesn't inject energy
+#[derive(Resource, Default, Debug)]
+pub struct SimDiagnostics {
+    /// sum of 0.5 * m * |v|^2 (grid units)
+    pub kinetic_energy: f32,
+    /// sum of m * v
+    pub momentum: Vec3A,
+    pub particle_count: usize,
+}
+
+/// bevy diagnostics of SimDiagnostics (printed by the LogDiagnosticsPlugin)
+pub const KINETIC_ENERGY: DiagnosticPath = DiagnosticPath::const_new("fluid/kinetic_energy");
+pub const MOMENTUM: DiagnosticPath = DiagnosticPath::const_new("fluid/momentum");
+pub const PARTICLE_COUNT: DiagnosticPath = DiagnosticPath::const_new("fluid/particle_count");
+
+/// CFL number of a velocity for the given timestep and cell size
+pub fn cfl_number(speed: f32, dt: f32, cell_size: f32) -> f32 {
+    speed * dt / cell_size
+}
+
+
+pub fn update_cfl_diagnostics(
+    time: Res<SimTime>,
+    mut diagnostics: ResMut<FluidDiagnostics>,
+    cells: Query<&FluidParticleVelocity, With<GridCellType>>,
+) {
+    // bit patterns of non-negative floats order like the floats, so an atomic max works as reduction
+    let max_speed = AtomicU32::new( 0 );
+    cells.par_iter().for_each( | vel | {
+        let speed = vel.0.length();
+        if speed.is_finite() {
+            max_speed.fetch_max( speed.to_bits(), Ordering::Relaxed );
+        }
+    });
+
+    let was_above = diagnostics.max_cfl > CFL_WARNING_THRESHOLD;
+    diagnostics.frame += 1;
+    diagnostics.max_cell_speed = f32::from_bits( max_speed.into_inner() );
+    // grid velocities are in grid units, i.e. a cell has size 1
+    diagnostics.max_cfl = cfl_number( diagnostics.max_cell_speed, time.dt, 1.0 );
+    diagnostics.peak_cfl = diagnostics.peak_cfl.max( diagnostics.max_cfl );
+
+    if diagnostics.max_cfl > CFL_WARNING_THRESHOLD && !was_above {
+        warn!("max CFL {:.2} exceeds {}; consider reducing WORLD_DT or increasing the cell size",
+              diagnostics.max_cfl, CFL_WARNING_THRESHOLD);
+    }
+    if diagnostics.frame % 100 == 0 {
+        info!("frame {}, max cell speed {:.3}, max CFL {:.3}",
+              diagnostics.frame, diagnostics.max_cell_speed, diagnostics.max_cfl);
+    }
+}
+
+/// kinetic energy and momentum of all particles (parallel reduction over per-thread partial sums)
+pub fn update_sim_diagnostics(
+    particles: Query<(&FluidQuantityMass, &FluidParticleVelocity), (With<ParticleTag>, Without<GridCellType>)>,
+    mut sim: ResMut<SimDiagnostics>,
+    mut diagnostics: Diagnostics,
+    mut partial: Local<Parallel<(f32, Vec3A, usize)>>,
+) {
+    particles.par_iter().for_each( | (mass, vel) | {
+        partial.scope( | (energy, momentum, count) | {
+            *energy += 0.5 * mass.0 * vel.0.length_squared();
+            *momentum += vel.0 * mass.0;
+            *count += 1;
+        });
+    });
+    let (energy, momentum, count) = partial.iter_mut()
+        .fold( (0.0, Vec3A::ZERO, 0), | acc, part | {
+            let sums = (acc.0 + part.0, acc.1 + part.1, acc.2 + part.2);
+            *part = (0.0, Vec3A::ZERO, 0);
+            sums
+        });
+
+    sim.kinetic_energy = energy;
+    sim.momentum = momentum;
+    sim.particle_count = count;
+    diagnostics.add_measurement( &KINETIC_ENERGY, || energy as f64 );
+    diagnostics.add_measurement( &MOMENTUM, || momentum.length() as f64 );
+    diagnostics.add_measurement( &PARTICLE_COUNT, || count as f64 );
+}
+
+
+
+#[cfg(test)]
+mod test
+{
+    use super::*;
+    use bevy::diagnostic::DiagnosticsStore;
+
+    #[test]
+    fn test_max_cfl() {
+        assert_eq!(cfl_number( 5.0, 0.04, 1.0 ), 0.2);
+        assert_eq!(cfl_number( 5.0, 0.04, 0.5 ), 0.4);
+
+        let mut world = World::new();
+        // a step shortened by the adaptive time step
+        let dt = 0.015;
+        world.insert_resource( SimTime { dt } );
+        world.init_resource::<FluidDiagnostics>();
+        for v in [ Vec3A::ZERO, Vec3A::new( 1.0, 2.0, 2.0 ), Vec3A::new( 0.0, -12.0, 5.0 ) ] {
+            world.spawn(( GridCellType::Fluid, FluidParticleVelocity( v ) ));
+        }
+
+        let mut schedule = Schedule::default();
+        schedule.add_systems( update_cfl_diagnostics );
+        schedule.run( &mut world );
+
+        let diagnostics = world.resource::<FluidDiagnostics>();
+        assert_eq!(diagnostics.max_cell_speed, 13.0);
+        assert!((diagnostics.max_cfl - 13.0 * dt).abs() < 1e-6);
+        assert_eq!(diagnostics.peak_cfl, diagnostics.max_cfl);
+    }
+
+    #[test]
+    fn test_sim_diagnostics() {
+        let mut world = World::new();
+        world.init_resource::<SimDiagnostics>();
+        world.init_resource::<DiagnosticsStore>();
+        for (mass, v) in [ (1.0, Vec3A::new( 1.0, 0.0, 0.0 )),
+                           (2.0, Vec3A::new( 0.0, -3.0, 4.0 )),
+                           (0.5, Vec3A::new( -2.0, 2.0, 0.0 )) ] {
+            world.spawn(( ParticleTag( 0 ), FluidQuantityMass( mass ), FluidParticleVelocity( v ) ));
+        }
+        // grid cells don't count
+        world.spawn(( GridCellType::Fluid, FluidQuantityMass( 9.0 ), FluidParticleVelocity( Vec3A::ONE ) ));
+
+        let mut schedule = Schedule::default();
+        schedule.add_systems( update_sim_diagnostics );
+        schedule.run( &mut world );
+
+        // 0.5 * (1 * 1 + 2 * 25 + 0.5 * 8) = 27.5
+        let sim = world.resource::<SimDiagnostics>();
+        assert!( (sim.kinetic_energy - 27.5).abs() < 1e-5, "energy {}", sim.kinetic_energy );
+        assert!( sim.momentum.abs_diff_eq( Vec3A::new( 0.0, -5.0, 8.0 ), 1e-5 ), "momentum {}", sim.momentum );
+        assert_eq!( sim.particle_count, 3 );
+
+        // the sums start over every frame
+        schedule.run( &mut world );
+        assert!( (world.resource::<SimDiagnostics>().kinetic_energy - 27.5).abs() < 1e-5 );
+    }
+}
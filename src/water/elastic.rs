@@ -0,0 +1,166 @@
+/*
+    Copyright 2023 github.com/programaquatic
+
+   Licensed under the Apache License, Version 2.0 (the "License");
+   you may not use this file except in compliance with the License.
+   You may obtain a copy of the License at
+
+       http://www.apache.org/licenses/LICENSE-2.0
+
+   Unless required by applicable law or agreed to in writing, software
+   distributed under the License is distributed on an "AS IS" BASIS,
+   WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+   See the License for the specific language governing permissions and
+   limitations under the License.
+*/
+
+
+use bevy::{
+    prelude::*,
+    math::{Vec3A, Mat3A},
+};
+
+use crate::{
+    aqs_utils::constants::{Constants, ElasticBlobConf},
+    tech::tank::Tank,
+    water::{
+        grid::Grid,
+        resources,
+        sim_time::SimTime,
+    },
+};
+
+/// entity name of the parent of the elastic particles (same placement as the particle frame)
+pub const ELASTIC_FRAME_NAME: &str = "ElasticBlob_Frame";
+
+/// low discrepancy offsets (R3 sequence) that spread the particles of one cell
+const R3: Vec3 = Vec3::new( 0.819_172_5, 0.671_043_5, 0.549_700_5 );
+
+
+/// particle positions (grid units) in all cells that lie completely within [min..max]
+///   particles_per_cell positions per cell, spread deterministically over the cell
+pub fn elastic_blob_positions(min: Vec3, max: Vec3, particles_per_cell: u32) -> Vec<Vec3> {
+    let first = min.ceil().max( Vec3::ZERO ).as_uvec3();
+    let last = max.floor().max( Vec3::ZERO ).as_uvec3();
+    let mut positions = vec![];
+    for z in first.z..last.z {
+        for y in first.y..last.y {
+            for x in first.x..last.x {
+                let cell = UVec3::new( x, y, z ).as_vec3();
+                positions.extend( (0..particles_per_cell)
+                    .map(| k | cell + (Vec3::splat( 0.5 ) + R3 * k as f32).fract()) );
+            }
+        }
+    }
+    positions
+}
+
+/// fill the configured ELASTIC_BLOB region with solid particles that deform elastically (Neo-Hookean)
+pub fn spawn_elastic_blob(
+    constants: Res<Constants>,
+    grid: Res<Grid>,
+    tank_cfg: Res<Tank>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    mut commands: Commands,
+) {
+    let Some( blob ) = &constants.ELASTIC_BLOB else {
+        return;
+    };
+    let ElasticBlobConf { min, max, particles_per_cell } = *blob;
+    let positions = elastic_blob_positions( min * tank_cfg.scale, max * tank_cfg.scale, particles_per_cell );
+    info!("elastic blob: {} particles", positions.len());
+
+    let frame = commands
+        .spawn((
+            Name::new(ELASTIC_FRAME_NAME),
+            Transform::from_translation( grid.to_world_coord( -Vec3::ONE )),
+            Visibility::default(),
+        ))
+        .id();
+    let mesh = meshes.add( Sphere::new( 0.2 ).mesh().ico( 2 ).unwrap() );
+    let material = materials.add(StandardMaterial {
+        base_color: Color::linear_rgba(0.9, 0.6, 0.2, 1.0),
+        ..default()
+    });
+
+    for (id, position) in positions.into_iter().enumerate() {
+        let particle = commands
+            .spawn((
+                Transform::from_translation( position ),
+                Visibility::default(),
+                Mesh3d( mesh.clone() ),
+                MeshMaterial3d( material.clone() ),
+            ))
+            .insert(resources::SolidParticleTag( id ))
+            .insert(resources::FluidParticlePosition( Vec3A::from( position ) ))
+            .insert(resources::FluidParticleVelocity( Vec3A::ZERO ))
+            .insert(resources::FluidQuantityMass( constants.DEFAULT_PARTICLE_MASS ))
+            .insert(resources::AffineMomentum( Mat3A::ZERO ))
+            .insert(resources::DeformationGradient( constants.ELASTIC_MODEL.deformation_gradient ))
+            .insert(resources::CellMMAccumulation(
+                [resources::CellMMAChange {
+                    cell_idx: 0,
+                    mass: 0.0,
+                    momentum: Vec3A::ZERO,
+                }; 27],
+            ))
+            .id();
+        commands.entity( frame ).add_child( particle );
+    }
+}
+
+/// advance the deformation of the solid particles with the velocity gradient from the grid (F' = (I + dt C) F)
+pub fn update_deformation_gradients(
+    time: Res<SimTime>,
+    mut particles: Query<(&resources::AffineMomentum, &mut resources::DeformationGradient), With<resources::SolidParticleTag>>,
+) {
+    particles.par_iter_mut().for_each(| (affine_momentum, mut deformation) | {
+        deformation.0 = (Mat3A::IDENTITY + affine_momentum.0 * time.dt) * deformation.0;
+    });
+}
+
+
+
+#[cfg(test)]
+mod test
+{
+    use super::*;
+
+    #[test]
+    fn test_blob_particle_count() {
+        // 4 x 3 x 2 cells
+        let positions = elastic_blob_positions( Vec3::new( 2.0, 2.0, 2.0 ), Vec3::new( 6.0, 5.0, 4.0 ), 3 );
+        assert_eq!( positions.len(), 4 * 3 * 2 * 3 );
+        assert!( positions.iter().all(| p | p.cmpge( Vec3::splat( 2.0 ) ).all() && p.cmplt( Vec3::new( 6.0, 5.0, 4.0 ) ).all()) );
+
+        // partially covered cells don't count
+        assert_eq!( elastic_blob_positions( Vec3::new( 1.5, 2.0, 2.0 ), Vec3::new( 6.5, 3.0, 3.0 ), 2 ).len(), 4 * 2 );
+        assert!( elastic_blob_positions( Vec3::ONE, Vec3::splat( 1.9 ), 8 ).is_empty() );
+
+        // the particles of a cell don't pile up
+        let cell = elastic_blob_positions( Vec3::ZERO, Vec3::ONE, 8 );
+        for (i, a) in cell.iter().enumerate() {
+            assert!( cell[i + 1..].iter().all(| b | a.distance( *b ) > 0.1 ) );
+        }
+    }
+
+    #[test]
+    fn test_deformation_follows_velocity_gradient() {
+        let mut world = World::new();
+        world.insert_resource( SimTime { dt: 0.1 } );
+        // stretching along x
+        let gradient = Mat3A::from_diagonal( Vec3::new( 1.0, 0.0, 0.0 ) );
+        let particle = world.spawn(( resources::SolidParticleTag( 0 ),
+                                     resources::AffineMomentum( gradient ),
+                                     resources::DeformationGradient( Mat3A::IDENTITY ) )).id();
+        let mut schedule = Schedule::default();
+        schedule.add_systems( update_deformation_gradients );
+        schedule.run( &mut world );
+        schedule.run( &mut world );
+
+        let deformation = world.get::<resources::DeformationGradient>( particle ).unwrap().0;
+        assert!( (deformation.x_axis.x - 1.1 * 1.1).abs() < 1e-5 );
+        assert_eq!( deformation.y_axis.y, 1.0 );
+    }
+}
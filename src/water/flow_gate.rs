@@ -0,0 +1,150 @@
+/*
+    Copyright 2023 github.com/programaquatic
+
+   Licensed under the Apache License, Version 2.0 (the "License");
+   you may not use this file except in compliance with the License.
+   You may obtain a copy of the License at
+
+       http://www.apache.org/licenses/LICENSE-2.0
+
+   Unless required by applicable law or agreed to in writing, software
+   distributed under the License is distributed on an "AS IS" BASIS,
+   WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+   See the License for the specific language governing permissions and
+   limitations under the License.
+*/
+
+
+use bevy::{
+    prelude::*,
+    math::Vec3A,
+};
+use serde::{Serialize, Deserialize};
+
+use crate::{
+    tech::{
+        pump,
+        tank::Tank,
+    },
+    water::{
+        grid::GridCellType,
+        resources::{
+            FluidParticleVelocity,
+            FluidQuantityMass,
+        },
+    },
+};
+
+
+/// A measurement box that reports the average flow through its cross-section
+///   center and half-extent like an ExternalForceVolume (in cm in the tank config); the flow counts along normal
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct FlowGate {
+    pub center: Vec3,
+    pub extent: Vec3,
+    pub normal: Vec3,
+    #[serde(default)]
+    pub name: Option<String>,
+}
+
+impl FlowGate {
+    /// same test as a VolumeShape::Box ExternalForceVolume
+    pub fn contains(&self, refpoint: Vec3) -> bool {
+        (refpoint - self.center).abs().cmplt( self.extent ).all()
+    }
+
+    pub fn scale(&mut self, scale: f32) {
+        self.center *= scale;
+        self.extent *= scale;
+        // normal is a direction and not scaled
+    }
+
+    /// mean velocity component along the normal over the covered cells that hold fluid
+    ///   cells are given as (position, mass, velocity) in grid units; None if no fluid is in the box
+    pub fn mean_normal_velocity(&self, cells: impl Iterator<Item = (Vec3, f32, Vec3A)>) -> Option<f32> {
+        let normal = Vec3A::from( self.normal ).normalize_or_zero();
+        let (sum, count) = cells
+            .filter(| (position, mass, _) | *mass > 0.0 && self.contains( *position ))
+            .fold( (0.0, 0), | (sum, count), (_, _, velocity) | (sum + velocity.dot( normal ), count + 1) );
+        if count == 0 {
+            return None;
+        }
+        Some( sum / count as f32 )
+    }
+
+    /// volume flow in L/hr for a mean normal velocity (grid units/s) through the cross-section of the box
+    pub fn flow_lph(&self, mean_velocity: f32, world_scale: f32) -> f32 {
+        let area = pump::cross_section( self.extent, Vec3A::from( self.normal ) );
+        mean_velocity * area / world_scale.powi(3) * pump::LPH_PER_CM3_PER_SEC
+    }
+}
+
+
+/// latest measurement of each flow gate (same order as the tank config)
+#[derive(Resource, Default, Debug)]
+pub struct FlowGateReadings(pub Vec<Option<f32>>);
+
+
+pub fn measure_flow_gates(
+    tank_cfg: Res<Tank>,
+    cells: Query<(&Transform, &FluidQuantityMass, &FluidParticleVelocity), With<GridCellType>>,
+    mut readings: ResMut<FlowGateReadings>,
+    mut frame: Local<u32>,
+) {
+    if tank_cfg.flow_gates.is_empty() {
+        return;
+    }
+    *frame += 1;
+    readings.0 = tank_cfg.flow_gates.iter()
+        .map(| gate | {
+            gate.mean_normal_velocity(
+                cells.iter().map(| (pos, mass, velo) | (pos.translation, mass.0, velo.0) ))
+        })
+        .collect();
+
+    if *frame % 100 == 0 {
+        for (idx, (gate, reading)) in tank_cfg.flow_gates.iter().zip( readings.0.iter() ).enumerate() {
+            let name = gate.name.clone().unwrap_or_else(|| format!("gate {}", idx));
+            match reading {
+                Some( velocity ) => info!("flow gate {}: mean normal velocity {:.3}, flow {:.0} L/hr",
+                                          name, velocity, gate.flow_lph( *velocity, tank_cfg.scale )),
+                None => info!("flow gate {}: no fluid", name),
+            }
+        }
+    }
+}
+
+
+
+#[cfg(test)]
+mod test
+{
+    use super::*;
+
+    #[test]
+    fn test_uniform_flow() {
+        let mut gate = FlowGate {
+            center: Vec3::new( 5.0, 5.0, 5.0 ),
+            extent: Vec3::new( 0.5, 2.0, 2.0 ),
+            normal: Vec3::X,
+            name: None,
+        };
+        let velocity = Vec3A::new( 2.0, 0.5, -1.0 );
+        let cells = || (0..10).flat_map(| x | (0..10).flat_map(move | y | (0..10).map(move | z | {
+            (Vec3::new( x as f32, y as f32, z as f32 ), 1.0, velocity)
+        })));
+
+        let mean = gate.mean_normal_velocity( cells() ).unwrap();
+        assert!( (mean - 2.0).abs() < 1e-5, "mean {}", mean );
+        // 4x4 cross-section at 1 cell per cm: 2 cm/s * 16 cm^2 = 32 cm^3/s
+        assert!( (gate.flow_lph( mean, 1.0 ) - 32.0 * 3.6).abs() < 1e-3 );
+
+        // a tilted normal sees the projected velocity
+        gate.normal = Vec3::new( 1.0, 0.0, -1.0 );
+        let mean = gate.mean_normal_velocity( cells() ).unwrap();
+        assert!( (mean - 3.0 / 2f32.sqrt()).abs() < 1e-5, "mean {}", mean );
+
+        // empty cells are ignored
+        assert_eq!( gate.mean_normal_velocity( cells().map(| (p, _, v) | (p, 0.0, v)) ), None );
+    }
+}
@@ -25,18 +25,25 @@
 // Parts of this code are based on github.com/robkau who did this for 2D
 //    see: https://github.com/robkau/mlsmpm-particles-rs
 
+use std::time::Duration;
+
 use bevy::{
     prelude::*, math::{Vec3A, Mat3A},
+    diagnostic::{Diagnostic, RegisterDiagnostic},
 };
 
 use bevy_rapier3d::prelude::*;
-use rand::Rng;
+use rand::{
+    Rng,
+    rngs::StdRng,
+};
 
 use crate::{
-    aqs_utils::constants::Constants,
+    aqs_utils::constants::{Constants, BoundaryMode, FillRegion, ParticleRenderer},
     tech::{
         tank::Tank,
-        pump::Pump,
+        pump::{Pump, PumpStats},
+        pump,
     },
     water::{
         grid::{GridCellType, GridCellIndex, Grid},
@@ -45,11 +52,35 @@ use crate::{
         mlsmpm,
         surface,
         spraybar::SprayBar,
+        setup::{SetupState, SetupProgress, FluidSimSet, PARTICLE_CELLS_PER_FRAME},
+        setup,
+        diagnostics,
+        point_cloud,
+        level_hold,
+        clip_plane,
+        legend,
+        surface_export,
+        screen_space,
+        pockets,
+        debug_scalar,
+        particle_rotation,
+        flow_gate,
+        particle_budget,
+        sim_control,
+        velocity_color,
+        elastic,
+        sim_time,
+        particle_export,
+        recorder,
+        grid_slice,
+        density_heatmap,
+        gravity_tilt,
     },
 };
 
 pub const BOUNDARY_WALL_MARGIN: f32 = 1.5;
 pub const WPARTICLE_RADIUS: f32 = 0.1;
+pub const PARTICLE_FRAME_NAME: &str = "Particle_Frame";
 
 pub struct FluidPlugin;
 
@@ -68,26 +99,54 @@ fn fill_tank(
     mut meshes: ResMut<Assets<Mesh>>,
     mut materials: ResMut<Assets<StandardMaterial>>,
     mut commands: Commands,
-    mut particle_frame: Query<(Entity, &mut resources::ParticleCount), With<resources::ParticleFrameTag>>,
+    mut level_hold: ResMut<level_hold::LevelHold>,
+    mut spawner: ResMut<ParticleSpawner>,
+    mut particle_frame: Query<(Entity, &mut resources::ParticleCount),
+                              (With<resources::ParticleFrameTag>, Without<surface::WaveGridFrameTag>)>,
+    seed: Option<Res<resources::SimSeed>>,
+    pumps: Query<&Pump>,
+    mut spraybar: Local<Option<SprayBar>>,
 ) {
     let visible_particles = usize::min( constants.VISIBLE_PARTICLES, constants.MAX_PARTICLES );
     let inlet = &tank_cfg.get_pump_definition().inlet;
-    let mut spraybar = SprayBar::new( inlet.location, inlet.extent );
+    // one spray bar for the whole run so that a seeded sequence doesn't repeat every frame
+    let spraybar = spraybar.get_or_insert_with(|| SprayBar::new(
+        inlet.location, inlet.extent,
+        seed.as_deref().map(| seed | seed.stream( resources::SEED_STREAM_SPRAY ))));
+
+    // the runtime flow scale and ramp of the filling pump (inlet id 1)
+    let filling = pumps.iter().find(| pump | pump.inlet_id() == 1);
+    let flow_scale = filling.map_or( 1.0, | pump | pump.flow_scale() * pump.ramp() );
 
-    let (id, mut count) = particle_frame.get_single_mut().unwrap();
+    // with level hold, as many as the controller asks for (dropped once MAX_PARTICLES is reached)
+    let spawn = match constants.LEVEL_HOLD {
+        Some( _ ) => level_hold.take_injection(),
+        None => pump::scaled_spawn_count( 10, flow_scale ),
+    };
+    // no filling while the pump is off (the level hold injection gets dropped)
+    if filling.is_some_and(| pump | !pump.is_running()) {
+        return;
+    }
+
+    let (id, mut count) = match particle_frame.get_single_mut() {
+        Ok( frame ) => frame,
+        Err( e ) => panic!("fill_tank: expected exactly one ParticleFrameTag entity: {}", e),
+    };
     if count.0 > constants.MAX_PARTICLES {
         return;
     }
 
     // fake inlet pump (location based)
-    let pump_v = inlet.get_force_for_position(inlet.location) * 0.25; // * constants.WORLD_DT;
+    let pump_v = inlet.get_force_for_position(inlet.location, 0.0) * 0.25 * flow_scale; // * constants.WORLD_DT;
+    // water injected by the filling pump has the temperature of its inlet
+    let injection_temperature = filling.map_or( constants.WATER_TEMPERATURE, | pump | pump.injection_temperature( constants.WATER_TEMPERATURE ));
 
     let particle_radius = WPARTICLE_RADIUS / grid.get_scale();
 
     // spawn N particles
-    for _ in 0..10 {
+    for _ in 0..spawn {
         if count.0 % 1000 == 0 {
-            println!("Particles in play: {}", count.0);
+            debug!("particles in play: {}", count.0);
         }
         let red = if count.0 % (constants.MAX_PARTICLES / constants.VISIBLE_PARTICLES) == 0 { 1.0 } else { 0.0 };
         let water_material = materials.add(StandardMaterial {
@@ -129,10 +188,12 @@ fn fill_tank(
                         momentum: Vec3A::ZERO,
                     }; 27],
                 ))
-                .insert(resources::ParticleTag( count.0 + 100000 ))
+                .insert(spawner.next_tag())
+                .insert(resources::InletSource( 1 ))
+                .insert(resources::FluidTemperature( injection_temperature ))
                 .id()
         } else { //  otherwise spawn a particle without visibility
-            visible = constants.DEBUG_FLUID_PARTICLES.fill;
+            visible = constants.DEBUG_FLUID_PARTICLES.fill && constants.PARTICLE_RENDERER == ParticleRenderer::Spheres;
             commands
                 .spawn((
                     Transform::from_translation( wiggle ),
@@ -149,7 +210,9 @@ fn fill_tank(
                             momentum: Vec3A::ZERO,
                     }; 27],
                 ))
-                .insert(resources::ParticleTag( count.0 ))
+                .insert(spawner.next_tag())
+                .insert(resources::InletSource( 1 ))
+                .insert(resources::FluidTemperature( injection_temperature ))
                 .id()
         };
         // insert particle as children
@@ -166,65 +229,207 @@ fn fill_tank(
 }
 
 
-// derive/create temporary (per iteration) Lagrangian particles with velocities
-fn init_fluid_particle_system(
+/// number of particles seeded into a fluid cell (every 20th cell gets an extra one)
+pub fn particles_per_cell(cell_idx: usize) -> usize {
+    1 + usize::from( cell_idx % 20 == 0 )
+}
+
+/// random particle positions within a fluid cell; positions above fill_height are dropped
+///   jitter scales the random offset from the cell center (0 = center, 1 = anywhere in the cell)
+pub fn seed_cell(position: Vec3, cell_idx: usize, fill_height: f32, jitter: f32, rng: &mut impl Rng) -> Vec<Vec3> {
+    let jitter = jitter.clamp( 0.0, 1.0 );
+    (0..particles_per_cell( cell_idx ))
+        .map(|_| position
+             + Vec3::splat( 0.5 )
+             + jitter * Vec3::new(
+                 rng.gen_range(-0.5..0.5),
+                 rng.gen_range(-0.5..0.5),
+                 rng.gen_range(-0.5..0.5),
+             ))
+        .filter(|wiggle| wiggle.y <= fill_height)
+        .collect()
+}
+
+
+/// random particle positions within a fluid cell that are inside the fill region
+///   without a region, everything below fill_height gets filled
+pub fn seed_cell_in_region(position: Vec3, cell_idx: usize, fill_height: f32, jitter: f32,
+                           region: Option<&FillRegion>, tank_scale: f32, rng: &mut impl Rng) -> Vec<Vec3> {
+    match region {
+        Some( region ) => seed_cell( position, cell_idx, f32::INFINITY, jitter, rng )
+            .into_iter()
+            .filter(| wiggle | region.contains( *wiggle, tank_scale ))
+            .collect(),
+        None => seed_cell( position, cell_idx, fill_height, jitter, rng ),
+    }
+}
+
+
+/// projected size of the simulation before anything gets spawned
+pub struct SetupEstimate {
+    pub particles: usize,
+    pub cells: usize,
+    pub bytes: usize,
+}
+
+/// estimate the number of seeded particles and the memory used by particles and grid cells
+///   uses the boundary-only cell types, so decorations are counted as fluid
+pub fn estimate_setup(grid: &Grid, fill_height: f32) -> SetupEstimate {
+    let particles: f32 = (0..grid.cell_count())
+        .filter(|&idx| grid.initial_cell_type( grid.to_3d( idx ) ) == GridCellType::Fluid)
+        .map(|idx| {
+            // share of the cell that's below the fill height
+            let below = (fill_height - grid.to_3d( idx ).y as f32).clamp(0.0, 1.0);
+            particles_per_cell( idx ) as f32 * below
+        })
+        .sum();
+
+    let particle_bytes = std::mem::size_of::<resources::FluidParticlePosition>()
+        + std::mem::size_of::<resources::FluidParticleVelocity>()
+        + std::mem::size_of::<resources::FluidQuantityMass>()
+        + std::mem::size_of::<resources::AffineMomentum>()
+        + std::mem::size_of::<resources::CellMMAccumulation>()
+        + std::mem::size_of::<resources::ParticleTag>()
+        + std::mem::size_of::<resources::InletSource>()
+        + std::mem::size_of::<resources::FluidTemperature>()
+        + std::mem::size_of::<resources::FluidPhase>()
+        + std::mem::size_of::<Transform>()
+        + std::mem::size_of::<GlobalTransform>()
+        + std::mem::size_of::<Visibility>();
+    let cell_bytes = std::mem::size_of::<GridCellType>()
+        + std::mem::size_of::<GridCellIndex>()
+        + std::mem::size_of::<resources::FluidParticleVelocity>()
+        + std::mem::size_of::<resources::FluidQuantityMass>()
+        + std::mem::size_of::<grid::GridCellAccumulatedForce>()
+        + std::mem::size_of::<grid::ColliderNormals>()
+        + std::mem::size_of::<Transform>()
+        + std::mem::size_of::<GlobalTransform>()
+        + std::mem::size_of::<Visibility>()
+        // grid scratchpads
+        + std::mem::size_of::<Vec3A>()
+        + std::mem::size_of::<f32>();
+
+    let particles = particles.round() as usize;
+    SetupEstimate {
+        particles,
+        cells: grid.cell_count(),
+        bytes: particles * particle_bytes + grid.cell_count() * cell_bytes,
+    }
+}
+
+// the simulation steps with a fixed WORLD_DT regardless of the render frame rate;
+// rendering and camera systems stay in Update and just pick up the latest state
+fn configure_fixed_timestep(
+    constants: Res<Constants>,
+    mut time: ResMut<Time<Fixed>>,
+    mut virtual_time: ResMut<Time<Virtual>>,
+) {
+    time.set_timestep_seconds( constants.WORLD_DT as f64 );
+    // after a stall (spawning, allocations) only advance by the limit instead of catching up with many steps
+    let max_advance = constants.MAX_SIM_ADVANCE_PER_FRAME.max( constants.WORLD_DT );
+    virtual_time.set_max_delta( Duration::from_secs_f32( max_advance ) );
+}
+
+// log the projected particle count and memory; refuse to start if it's over the configured budget
+fn report_setup_estimate(
     grid: Res<Grid>,
     constants: Res<Constants>,
-    cells: Query< (&Transform, &grid::GridCellType, &GridCellIndex)>,
-    mut meshes: ResMut<Assets<Mesh>>,
-    mut materials: ResMut<Assets<StandardMaterial>>,
-    mut commands: Commands,
+    tank_cfg: Res<Tank>,
 ) {
-    let mut rng = rand::thread_rng();
+    let fill_height = constants.fill_spec().grid_height( grid.grid_size().y as f32, tank_cfg.scale );
+    let estimate = estimate_setup( &grid, fill_height );
+    let megabytes = estimate.bytes as f32 / (1024. * 1024.);
+    info!("Estimated particles: {}, grid cells: {}, memory: {:.1} MB",
+          estimate.particles, estimate.cells, megabytes);
 
-    let mut water_material : [Handle<StandardMaterial>; 4] = Default::default();
+    if let Some( budget ) = constants.MEMORY_BUDGET_MB {
+        if megabytes > budget {
+            panic!("Estimated memory of {:.1} MB exceeds MEMORY_BUDGET_MB of {:.1} MB; reduce MAX_GRID_CELLS or the fill level",
+                   megabytes, budget);
+        }
+    }
+}
 
-    for (c, m) in  water_material.iter_mut().enumerate() {
-        *m = materials.add(StandardMaterial {
-            base_color: Color::linear_rgba(0.0, 0.0, 1.0/(c as f32), 0.5),
-            // alpha_mode: AlphaMode::Blend,
-            ..default()
-        });
+
+/// state of the incremental particle spawning during setup
+#[derive(Resource)]
+pub struct ParticleSpawner {
+    next_id: usize,
+    debug_material: Handle<StandardMaterial>,
+    /// continues across the chunks to keep a seeded fill reproducible
+    rng: StdRng,
+}
+
+impl ParticleSpawner {
+    /// unique tag of the next particle; ids are never reused, not even after particles got removed
+    fn next_tag(&mut self) -> resources::ParticleTag {
+        self.next_id += 1;
+        resources::ParticleTag( self.next_id - 1 )
     }
+}
 
+// create the parent frame for all particles before spawning them
+fn init_particle_frame(
+    grid: Res<Grid>,
+    seed: Option<Res<resources::SimSeed>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    mut commands: Commands,
+) {
     let water_material_hdl = materials.add(StandardMaterial {
         base_color: Color::linear_rgba(1.0, 0.0, 0.0, 1.0),
         reflectance: 0.0,
         ..default()
     });
 
-    let mut particle_id = 0;
-
-    let particle_frame = commands
+    commands
         .spawn((
-            Name::new("Particle_Frame"),
+            Name::new(PARTICLE_FRAME_NAME),
             resources::ParticleFrameTag,
             resources::ParticleCount(0),
             Transform::from_translation( grid.to_world_coord( -Vec3::ONE )),
                 // .with_scale( Vec3::splat( grid.get_scale()) ),
             Visibility::default(),
-        ))
-        .id();
+        ));
+    commands.insert_resource(ParticleSpawner {
+        next_id: 0,
+        debug_material: water_material_hdl,
+        rng: resources::stream_rng( seed.as_deref(), resources::SEED_STREAM_INIT ),
+    });
+}
 
+// derive/create temporary (per iteration) Lagrangian particles with velocities
+//   fills the next chunk of grid cells per frame until all cells are done
+fn init_fluid_particle_system(
+    grid: Res<Grid>,
+    constants: Res<Constants>,
+    tank_cfg: Res<Tank>,
+    cells: Query< (&Transform, &grid::GridCellType, &GridCellIndex)>,
+    particle_frame: Query<Entity, With<resources::ParticleFrameTag>>,
+    mut spawner: ResMut<ParticleSpawner>,
+    mut progress: ResMut<SetupProgress>,
+    mut next_state: ResMut<NextState<SetupState>>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut commands: Commands,
+) {
+    let particle_frame = particle_frame.single();
+    let chunk = progress.next_chunk( PARTICLE_CELLS_PER_FRAME );
+    let spawner = &mut *spawner;
+    let rng = &mut spawner.rng;
 
     let _particle_radius = WPARTICLE_RADIUS / grid.get_scale();
-    let fill_height = constants.DEFAULT_FILL_HEIGHT * grid.grid_size().y as f32;
+    let fill_height = constants.fill_spec().grid_height( grid.grid_size().y as f32, tank_cfg.scale );
 
-    cells.iter().for_each(
+    cells.iter().filter(| (_, _, cidx) | chunk.contains( &cidx.0 )).for_each(
         | ( position, gct, cidx ) | if *gct == grid::GridCellType::Fluid {
             // grid::GridCellType::Fluid => {
             // println!("Cell_idx: {}", idx);
-            let extra_particle = i32::from( cidx.0 % 20 == 0 );
-            for _ in 0..1 + extra_particle {
-                let wiggle = position.translation
-                    + Vec3::new(
-                        rng.gen_range(0.0..399.0) / 400.,
-                        rng.gen_range(0.0..399.0) / 400.,
-                        rng.gen_range(0.0..399.0) / 400.,
-                    );
-                if wiggle.y > fill_height {
-                    continue;
-                }
+            for wiggle in seed_cell_in_region( position.translation, cidx.0, fill_height, constants.SEED_JITTER,
+                                               constants.FILL_REGION.as_ref(), tank_cfg.scale, rng ) {
+                let phase = match &constants.SECOND_PHASE {
+                    Some( second ) => second.phase_at( wiggle.y / fill_height, rng.gen::<f32>() ),
+                    None => 0,
+                };
+                let mass = constants.SEDIMENT.particle_mass( constants.phase_particle_mass( phase ), rng.gen::<f32>() );
                 let particle = commands
                             .spawn((
                                 Transform::from_translation( wiggle ),
@@ -232,7 +437,7 @@ fn init_fluid_particle_system(
                             ))
                     .insert(resources::FluidParticlePosition(Vec3A::from(wiggle)))
                     .insert(resources::FluidParticleVelocity(Vec3A::ZERO))
-                    .insert(resources::FluidQuantityMass( constants.DEFAULT_PARTICLE_MASS ))
+                    .insert(resources::FluidQuantityMass( mass ))
                     .insert(resources::AffineMomentum(Mat3A::ZERO))
                     .insert(resources::CellMMAccumulation(
                         [resources::CellMMAChange {
@@ -241,83 +446,247 @@ fn init_fluid_particle_system(
                                     momentum: Vec3A::ZERO,
                         }; 27],
                     ))
-                    .insert(resources::ParticleTag( particle_id ))
+                    .insert(resources::ParticleTag( spawner.next_id ))
+                    .insert(resources::InletSource::default())
+                    .insert(resources::FluidTemperature( constants.WATER_TEMPERATURE ))
+                    .insert(resources::FluidPhase( phase ))
                     .id();
 
-                particle_id += 1;
+                spawner.next_id += 1;
 
                 // insert particle as children
                 commands.entity(particle_frame).add_child(particle);
-                if constants.DEBUG_FLUID_PARTICLES.base {
+                if constants.DEBUG_FLUID_PARTICLES.base && constants.PARTICLE_RENDERER == ParticleRenderer::Spheres {
                     commands.entity(particle)
                     //// Uncomment if you want to see all particles
                         .insert(Mesh3d(meshes.add(Sphere::new(_particle_radius).mesh().ico(4).unwrap())))
-                        .insert(MeshMaterial3d(water_material_hdl.clone()));
+                        .insert(MeshMaterial3d(spawner.debug_material.clone()));
                 }
             }
         }
     );
-    println!("Cells: {}; Particles: {}", grid.cell_count(), particle_id );
+
+    if progress.is_done() {
+        info!("cells: {}; particles: {}", grid.cell_count(), spawner.next_id );
+        progress.restart();
+        next_state.set( SetupState::Running );
+    }
+}
+
+// make sure the particle frame is unique and holds all particles before the simulation starts
+fn check_particle_frame(
+    frames: Query<(Entity, Option<&Name>, Option<&Children>), With<resources::ParticleFrameTag>>,
+    particles: Query<Entity, With<resources::ParticleTag>>,
+) {
+    let frame_count = frames.iter().len();
+    if frame_count != 1 {
+        frames.iter().for_each(| (id, name, _) | {
+            error!("ParticleFrameTag on entity {} ({:?})", id, name);
+        });
+        panic!("Expected exactly one ParticleFrameTag entity, found {}", frame_count);
+    }
+
+    let (id, _, children) = frames.single();
+    let child_count = children.map_or(0, |c| c.len());
+    let particle_count = particles.iter().len();
+    if child_count != particle_count {
+        panic!("Particle frame {} has {} children but there are {} particles", id, child_count, particle_count);
+    }
+}
+
+/// acceleration along gravity of a particle that's heavier (or lighter) than the surrounding fluid
+///   the grid only carries the averaged gravity, so the excess weight is applied per particle
+pub fn excess_weight(mass: f32, base_mass: f32, gravity: f32) -> f32 {
+    if mass <= 0.0 {
+        return 0.0;
+    }
+    gravity * (1.0 - base_mass / mass)
+}
+
+/// mass a particle of `temperature` weighs against water of `reference` temperature
+///   Boussinesq: the density changes by -expansion per °C, everything else keeps the rest density
+pub fn boussinesq_mass(mass: f32, temperature: f32, reference: f32, expansion: f32) -> f32 {
+    mass * (1.0 - expansion * (temperature - reference)).max( 0.0 )
+}
+
+/// move a particle by one time step
+///   order 1 is forward Euler, order 2 samples the velocity again at the midpoint (RK2)
+pub fn advect(location: Vec3A, velocity: Vec3A, dt: f32, order: u32, sample: impl Fn(Vec3A) -> Vec3A) -> Vec3A {
+    if order >= 2 {
+        let midpoint = location + velocity * (0.5 * dt);
+        location + sample( midpoint ) * dt
+    } else {
+        location + velocity * dt
+    }
+}
+
+/// blend of the PIC velocity (sampled from the grid) and the FLIP velocity
+///   FLIP keeps the particle velocity and only adds the grid velocity change of this step
+pub fn flip_blend(particle_velocity: Vec3A, grid_velocity: Vec3A, grid_previous: Vec3A, flip_ratio: f32) -> Vec3A {
+    let flip = particle_velocity + (grid_velocity - grid_previous);
+    grid_velocity.lerp( flip, flip_ratio.clamp( 0.0, 1.0 ) )
 }
 
 pub fn grid_to_particle(
     constants: Res<Constants>,
+    time: Res<sim_time::SimTime>,
+    applied: Res<grid::AppliedForces>,
     mut grid: ResMut<Grid>,
     mut particles: Query<
             (
                 &mut resources::FluidParticlePosition,
                 &mut resources::FluidParticleVelocity,
                 &mut resources::AffineMomentum,
-                &resources::ParticleTag,
-            ), Without<GridCellType>
+                &resources::FluidQuantityMass,
+                Option<&resources::FluidTemperature>,
+            ), (Without<GridCellType>, Or<(With<resources::ParticleTag>, With<resources::SolidParticleTag>)>)
             >,
     cells: Query<(&GridCellIndex,  &resources::FluidParticleVelocity), With<GridCellType>>,
 ) {
+    // the gravity the grid got this step (including tilt and ramp)
+    let gravity = Vec3A::from( applied.gravity( &constants ) );
     // let mut max_vel: f32 = 0.0;
     cells.iter().for_each( | (idx, vel) | {
         grid.get_tmp_velo_mut()[ idx.0 ] = vel.0;
     });
 
     particles.par_iter_mut().for_each(
-        |(mut location, mut velocity, mut affine_momentum, _ptag)| {
-            //// reset particle velocity. we calculate it from scratch each step using the grid
-            velocity.0 = Vec3A::ZERO;
-
-            let cell_pos = location.0.as_uvec3();
-            let cell_diff = location.0 - cell_pos.as_vec3a() - Vec3A::splat(0.5);
-
-            let weights = grid::quadratic_interpolation_weights(cell_diff);
-
-            // affine per-particle momentum matrix from APIC / MLS-MPM.
-            // see APIC paper (https://web.archive.org/web/20190427165435/https://www.math.ucla.edu/~jteran/papers/JSSTS15.pdf), page 6
-            // below equation 11 for clarification. this is calculating C = B * (D^-1) for APIC equation 8,
-            // where B is calculated in the inner loop at (D^-1) = 4 is a constant when using quadratic interpolation functions
-            let mut b = Mat3A::ZERO;
-            // for all surrounding 9 cells
-            for gz in 0..3 {
-                for gy in 0..3 {
-                    for gx in 0..3 {
-                        let weight = weights[gx].x * weights[gy].y * weights[gz].z;
-                        let neighbor = UVec3::new(
-                            (cell_pos.x as i32 + gx as i32 - 1) as u32,
-                            (cell_pos.y as i32 + gy as i32 - 1) as u32,
-                            (cell_pos.z as i32 + gz as i32 - 1) as u32,
-                        );
-                        let cell_dist = (neighbor.as_vec3a() - location.0) + Vec3A::splat(0.5);
-                        let cell_at_index = grid.index_of_vec( &neighbor );
-                        let weighted_velocity = grid.get_tmp_velo()[ cell_at_index ] * weight;
-
-                        b += grid::weighted_velocity_and_cell_dist_to_term(weighted_velocity, cell_dist);
-                        velocity.0 += weighted_velocity;
-                    }
-                }
-            }
+        |(mut location, mut velocity, mut affine_momentum, mass, temperature)| {
+            //// PIC: rebuild the particle velocity from the grid; FLIP: add the grid velocity change
+            let weights = grid::particle_weights( location.0, velocity.0, constants.KERNEL_STRETCH );
+            let (grid_velocity, b) = grid.sample_velocity_weighted( location.0, weights );
             affine_momentum.0 = b * 4.0;
-            location.0 += velocity.0 * constants.WORLD_DT;
+            let grid_velocity = if constants.FLIP_RATIO > 0.0 {
+                flip_blend( velocity.0, grid_velocity, grid.sample_previous_velocity_weighted( location.0, weights ), constants.FLIP_RATIO )
+            } else {
+                grid_velocity
+            };
+
+            // the relative excess weight along the applied gravity; warm water weighs less
+            let mass = temperature.map_or( mass.0, | temperature | boussinesq_mass(
+                mass.0, temperature.0, constants.WATER_TEMPERATURE, constants.THERMAL_EXPANSION ));
+            let excess = gravity * excess_weight( mass, constants.DEFAULT_PARTICLE_MASS, 1.0 ) * time.dt;
+            velocity.0 = grid_velocity + excess;
+            location.0 = advect( location.0, velocity.0, time.dt, constants.ADVECTION_ORDER,
+                                 | midpoint | grid.sample_velocity( grid.clamp_to_interior( midpoint ) ).0 + excess );
         },
     );
 }
 
+/// predictive wall enforcement: if the particle would end up outside the walls after `lookahead` time,
+/// the velocity gets nudged back by the penetration depth scaled with `stiffness`
+pub fn wall_correction(location: Vec3A, velocity: Vec3A,
+                       wall_min: f32, wall_max: Vec3A,
+                       lookahead: f32, stiffness: f32) -> Vec3A {
+    let position_next = location + velocity * lookahead;
+    let below = (Vec3A::splat(wall_min) - position_next).max(Vec3A::ZERO);
+    let above = (wall_max - position_next).min(Vec3A::ZERO);
+    velocity + (below + above) * stiffness
+}
+
+/// wall enforcement of a boundary mode: Separate nudges the particle back (see wall_correction),
+/// the other modes apply their condition on each wall the particle would reach within `lookahead`
+pub fn wall_boundary(mode: BoundaryMode, location: Vec3A, velocity: Vec3A,
+                     wall_min: f32, wall_max: Vec3A,
+                     lookahead: f32, stiffness: f32) -> Vec3A {
+    if mode == BoundaryMode::Separate {
+        return wall_correction( location, velocity, wall_min, wall_max, lookahead, stiffness );
+    }
+    let position_next = location + velocity * lookahead;
+    let mut corrected = velocity;
+    for axis in [ Vec3A::X, Vec3A::Y, Vec3A::Z ] {
+        if position_next.dot( axis ) < wall_min {
+            corrected = mode.apply( corrected, axis );
+        } else if position_next.dot( axis ) > wall_max.dot( axis ) {
+            corrected = mode.apply( corrected, -axis );
+        }
+    }
+    corrected
+}
+
+/// remove the particles within the outlets of draining pumps; fill_tank tops the tank up again
+pub fn drain_pump_outlets(
+    tank_cfg: Res<Tank>,
+    particles: Query<(Entity, &resources::FluidParticlePosition), (With<resources::ParticleTag>, Without<GridCellType>)>,
+    mut particle_frame: Query<&mut resources::ParticleCount, (With<resources::ParticleFrameTag>, Without<surface::WaveGridFrameTag>)>,
+    mut commands: Commands,
+) {
+    let outlets: Vec<_> = tank_cfg.get_pump_definitions().iter()
+        .filter(| definition | definition.drain)
+        .map(| definition | &definition.outlet)
+        .collect();
+    if outlets.is_empty() {
+        return;
+    }
+    let mut drained = 0;
+    particles.iter()
+        .filter(| (_, location) | outlets.iter().any(| outlet | pump::in_volume( outlet, location.0 )))
+        .for_each(| (particle, _) | {
+            commands.entity( particle ).despawn_recursive();
+            drained += 1;
+        });
+    // one update of the count per frame keeps the MAX_PARTICLES cap of fill_tank consistent
+    if drained > 0 {
+        particle_frame.iter_mut().for_each(| mut count | count.0 = count.0.saturating_sub( drained ));
+    }
+}
+
+/// remove the particles that reached the bottom of an overflow shaft
+pub fn drain_outlet_cells(
+    grid: Res<Grid>,
+    particles: Query<(Entity, &resources::FluidParticlePosition), (With<resources::ParticleTag>, Without<GridCellType>)>,
+    mut particle_frame: Query<&mut resources::ParticleCount, (With<resources::ParticleFrameTag>, Without<surface::WaveGridFrameTag>)>,
+    mut commands: Commands,
+) {
+    let mut drained = 0;
+    particles.iter()
+        .filter(| (_, location) | location.0.y < grid::OUTLET_DRAIN_HEIGHT
+            && location.0.cmpge( Vec3A::ZERO ).all()
+            && grid.is_outlet( location.0.as_uvec3() ))
+        .for_each(| (particle, _) | {
+            commands.entity( particle ).despawn_recursive();
+            drained += 1;
+        });
+    if drained > 0 {
+        particle_frame.iter_mut().for_each(| mut count | count.0 = count.0.saturating_sub( drained ));
+    }
+}
+
+/// a particle escaped if its position is non-finite or outside the grid by more than margin cells
+pub fn has_escaped(location: Vec3A, grid_size: Vec3A, margin: f32) -> bool {
+    !location.is_finite()
+        || location.cmplt( Vec3A::splat( -margin ) ).any()
+        || location.cmpgt( grid_size + margin ).any()
+}
+
+/// despawn particles that went rogue before the boundary enforcement clamps them into a corner
+///   solid particles go as well, only the fluid ones count against the ParticleCount
+pub fn despawn_escaped_particles(
+    constants: Res<Constants>,
+    grid: Res<Grid>,
+    mut diagnostics: ResMut<diagnostics::FluidDiagnostics>,
+    particles: Query<(Entity, &resources::FluidParticlePosition, Has<resources::ParticleTag>),
+                     (Or<(With<resources::ParticleTag>, With<resources::SolidParticleTag>)>, Without<GridCellType>)>,
+    mut particle_frame: Query<&mut resources::ParticleCount, (With<resources::ParticleFrameTag>, Without<surface::WaveGridFrameTag>)>,
+    mut commands: Commands,
+) {
+    let grid_size = grid.grid_size().as_vec3a();
+    let (mut escaped, mut escaped_fluid) = (0, 0);
+    particles.iter()
+        .filter(| (_, location, _) | has_escaped( location.0, grid_size, constants.ESCAPE_MARGIN ))
+        .for_each(| (particle, _, fluid) | {
+            commands.entity( particle ).despawn_recursive();
+            escaped += 1;
+            escaped_fluid += fluid as usize;
+        });
+
+    if escaped > 0 {
+        particle_frame.iter_mut().for_each(| mut count | count.0 = count.0.saturating_sub( escaped_fluid ));
+        diagnostics.escaped_particles += escaped;
+        warn!("despawned {} escaped particles", escaped);
+    }
+}
+
 pub fn particle_boundary_enforcement(
     constants: Res<Constants>,
     grid: Res<Grid>,
@@ -326,9 +695,13 @@ pub fn particle_boundary_enforcement(
                 &mut resources::FluidParticlePosition,
                 &mut resources::FluidParticleVelocity,
                 &mut resources::AffineMomentum,
+                Option<&mut resources::InletSource>,
+                Option<&mut resources::FluidTemperature>,
             ), Without<GridCellType>
             >,
     pumping: Query<&Pump>,
+    pump_stats: Res<PumpStats>,
+    time: Res<sim_time::SimTime>,
 ) {
     // predictive boundary velocity cap
     let wall_min: f32 = BOUNDARY_WALL_MARGIN;
@@ -336,41 +709,30 @@ pub fn particle_boundary_enforcement(
         - Vec3A::splat(wall_min);
 
     particles.par_iter_mut().for_each(
-        | (mut location, mut velocity, mut afmom) | {
-            pumping.iter().for_each(| r | {
-                if let Some( ( new_loc, vel_diff) ) = r.particle_pump(location.0) {
-                    location.0 = new_loc;
-                    velocity.0 = vel_diff;
+        | (mut location, mut velocity, mut afmom, source, temperature) | {
+            // only fluid particles (with an inlet source) get pumped, solid ones just stay within the walls
+            if let Some( mut source ) = source {
+                if let Some( pump ) = pump::pump_particle( pumping.iter(), &mut location.0, &mut velocity.0 ) {
                     afmom.0 = Mat3A::ZERO;
+                    pump_stats.count_pumped();
+                    if source.0 != pump.inlet_id() {
+                        source.0 = pump.inlet_id();
+                    }
+                    if let (Some( mut temperature ), Some( inlet_temperature )) = (temperature, pump.inlet_temperature()) {
+                        temperature.0 = inlet_temperature;
+                    }
                 }
-            });
-
-            location.0.x = location.0.x.clamp(1.001, grid.grid_size().x as f32 - 1.001);
-            location.0.y = location.0.y.clamp(1.001, grid.grid_size().y as f32 - 1.001);
-            location.0.z = location.0.z.clamp(1.001, grid.grid_size().z as f32 - 1.001);
+            }
 
-            // apply boundary conditions about 0.1 seconds before reaching edge
-            let dt_multiplier = 0.1 * constants.WORLD_DT;
-            let position_next = location.0 + velocity.0 * dt_multiplier;
+            // the wall vector is the grid size, except for the y-axis with a closed top
+            location.0.x = location.0.x.clamp(1.001, grid.wall_vector().x - 1.001);
+            location.0.y = location.0.y.clamp(1.001, grid.wall_vector().y - 1.001);
+            location.0.z = location.0.z.clamp(1.001, grid.wall_vector().z - 1.001);
 
-            if position_next.x < wall_min {
-                velocity.0.x += wall_min - position_next.x;
-            }
-            if position_next.x > wall_max.x {
-                velocity.0.x += wall_max.x - position_next.x;
-            }
-            if position_next.y < wall_min {
-                velocity.0.y += wall_min - position_next.y;
-            }
-            if position_next.y > wall_max.y {
-                velocity.0.y += wall_max.y - position_next.y;
-            }
-            if position_next.z < wall_min {
-                velocity.0.z += wall_min - position_next.z;
-            }
-            if position_next.z > wall_max.z {
-                velocity.0.z += wall_max.z - position_next.z;
-            }
+            // apply boundary conditions a fraction of a time step before reaching edge
+            let lookahead = constants.BOUNDARY_LOOKAHEAD * time.dt;
+            velocity.0 = wall_boundary( constants.BOUNDARY_MODE, location.0, velocity.0, wall_min, wall_max,
+                                        lookahead, constants.BOUNDARY_STIFFNESS );
         }
     );
 }
@@ -399,11 +761,43 @@ pub fn _collider_update(
     );
 }
 
+// color visible particles by the inlet they came from; only useful with more than one pump
+fn color_by_inlet_source(
+    constants: Res<Constants>,
+    pumps: Query<&Pump>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    mut palette: Local<Vec<Handle<StandardMaterial>>>,
+    mut particles: Query<(&resources::InletSource, &mut MeshMaterial3d<StandardMaterial>), Changed<resources::InletSource>>,
+) {
+    // the speed coloring takes precedence
+    if pumps.iter().len() < 2 || constants.COLOR_VEL_RANGE.is_some() {
+        return;
+    }
+    if palette.is_empty() {
+        // neutral for unpumped particles followed by one hue per inlet
+        palette.push( materials.add(StandardMaterial::from(Color::linear_rgba(0.0, 0.03, 1.0, 0.8))) );
+        for hue in [0.0, 120.0, 60.0, 300.0, 180.0, 30.0] {
+            palette.push( materials.add(StandardMaterial::from(Color::hsl(hue, 0.9, 0.5))) );
+        }
+    }
+    particles.iter_mut().for_each(| (source, mut material) | {
+        material.0 = palette[ source.0 as usize % palette.len() ].clone();
+    });
+}
+
 pub fn particle_world_update(
-    mut particles: Query<(&resources::FluidParticlePosition, &mut Transform)>,
+    clip: Res<clip_plane::ClipPlane>,
+    budget: Res<particle_budget::ParticleBudget>,
+    mut particles: Query<(&resources::FluidParticlePosition, &mut Transform, Option<&mut Visibility>, Option<&resources::ParticleTag>)>,
 ) {
-    particles.par_iter_mut().for_each( |(location, mut transform)| {
+    particles.par_iter_mut().for_each( |(location, mut transform, visibility, tag)| {
         transform.translation = location.0.into();
+        // only touch the visibility on change to keep the visibility propagation cheap
+        if let Some( mut visibility ) = visibility {
+            let hidden = clip.is_clipped( location.0 ) || tag.is_some_and(| tag | !budget.is_shown( tag.0 ));
+            let wanted = if hidden { Visibility::Hidden } else { Visibility::Inherited };
+            visibility.set_if_neq( wanted );
+        }
     });
 }
 
@@ -411,55 +805,189 @@ impl Plugin for FluidPlugin {
     fn build(&self, app: &mut App) {
         app
             .add_plugins(MaterialPlugin::<surface::CustomMaterial>::default())
+            .add_plugins(MaterialPlugin::<screen_space::FluidDepthMaterial>::default())
+            .add_plugins(UiMaterialPlugin::<screen_space::FluidCompositeMaterial>::default())
+            .add_plugins(point_cloud::PointCloudPlugin)
+            .init_state::<SetupState>()
+            .init_resource::<PumpStats>()
+            .init_resource::<surface::SurfaceFlowOverlay>()
+            .init_resource::<diagnostics::FluidDiagnostics>()
+            .init_resource::<diagnostics::SimDiagnostics>()
+            .register_diagnostic(Diagnostic::new(diagnostics::KINETIC_ENERGY))
+            .register_diagnostic(Diagnostic::new(diagnostics::MOMENTUM))
+            .register_diagnostic(Diagnostic::new(diagnostics::PARTICLE_COUNT))
+            .init_resource::<level_hold::LevelHold>()
+            .init_resource::<clip_plane::ClipPlane>()
+            .init_resource::<debug_scalar::DebugScalar>()
+            .init_resource::<particle_rotation::RotationMarkers>()
+            .init_resource::<flow_gate::FlowGateReadings>()
+            .init_resource::<particle_budget::ParticleBudget>()
+            .init_resource::<sim_control::SimControl>()
+            .init_resource::<sim_time::SimTime>()
+            .init_resource::<grid::AppliedForces>()
+            .init_resource::<recorder::Recorder>()
+            .init_resource::<grid_slice::GridSlice>()
+            .init_resource::<gravity_tilt::GravityTilt>()
+            .configure_sets(Update, FluidSimSet.run_if(in_state(SetupState::Running)))
+            // a paused simulation skips the steps; Update keeps rendering the last state
+            .configure_sets(FixedUpdate, FluidSimSet
+                            .run_if(in_state(SetupState::Running))
+                            .run_if(sim_control::sim_running)
+                            .run_if(recorder::not_playing_back))
+            .add_systems(Update, sim_control::control_sim)
+            .add_systems(Update, recorder::control_recorder)
+            .add_systems(FixedUpdate,
+                recorder::record_positions
+                    .after(particle_boundary_enforcement)
+                    .in_set(FluidSimSet))
+            .add_systems(Update,
+                recorder::play_back
+                    .after(recorder::control_recorder)
+                    .before(particle_world_update)
+                    .in_set(FluidSimSet))
+            .add_systems(FixedUpdate, sim_control::finish_single_step.after(FluidSimSet))
             .add_systems(PreStartup, grid::setup_fluid_grid)
+            .add_systems(Startup, configure_fixed_timestep)
             .add_systems(Startup, surface::init_water_surface_system)
-            .add_systems(Startup, grid::grid_initialize_external_forces)
-            .add_systems(Startup, init_fluid_particle_system)
-            .add_systems(Startup,
-                grid::grid_collider_setup
-                    .before(grid::show_grid_cells)
-            )
-            .add_systems(Startup,
-                grid::show_grid_cells
-            )
+            .add_systems(Startup, elastic::spawn_elastic_blob)
+            .add_systems(Startup, setup::spawn_loading_indicator)
+            .add_systems(Startup, report_setup_estimate)
+            .add_systems(Startup, point_cloud::init_point_cloud)
+            .add_systems(Startup, legend::spawn_legend)
+            // needs the camera from the camera plugin
+            .add_systems(PostStartup, screen_space::init_screen_space_fluid)
 
+            // incremental setup: classify colliders, then spawn particles, then run
             .add_systems(Update,
-                grid::reset_fluid_grid_cells
-                    .before(mlsmpm::p2g_stage1))
+                grid::grid_collider_setup
+                    .run_if(in_state(SetupState::ClassifyingColliders)))
+            .add_systems(OnEnter(SetupState::SpawningParticles),
+                (grid::show_grid_cells,
+                 grid::grid_initialize_external_forces,
+                 pockets::report_fluid_pockets,
+                 init_particle_frame))
             .add_systems(Update,
+                init_fluid_particle_system
+                    .run_if(in_state(SetupState::SpawningParticles)))
+            .add_systems(Update, setup::update_loading_indicator)
+            .add_systems(OnEnter(SetupState::Running),
+                (check_particle_frame,
+                 setup::remove_loading_indicator))
+
+            .add_systems(FixedUpdate,
+                sim_time::update_sim_time
+                    .before(mlsmpm::p2g_stage1)
+                    .in_set(FluidSimSet))
+            .add_systems(FixedUpdate,
+                grid::reset_fluid_grid_cells
+                    .before(mlsmpm::p2g_stage1)
+                    .in_set(FluidSimSet))
+            .add_systems(FixedUpdate,
                 mlsmpm::p2g_stage1
-                    .before(mlsmpm::p2g_apply_stage1))
-            .add_systems(Update,
+                    .before(mlsmpm::p2g_apply_stage1)
+                    .in_set(FluidSimSet))
+            .add_systems(FixedUpdate,
                 mlsmpm::p2g_apply_stage1
-                    .before(mlsmpm::p2g_stage2))
-            .add_systems(Update,
+                    .before(mlsmpm::p2g_stage2)
+                    .in_set(FluidSimSet))
+            .add_systems(FixedUpdate,
                 mlsmpm::p2g_stage2
-                    .before(mlsmpm::grid_update))
-            .add_systems(Update,
+                    .before(mlsmpm::grid_update)
+                    .in_set(FluidSimSet))
+            .add_systems(FixedUpdate,
                 mlsmpm::p2g_stage2_solids
-                         .before(grid::wall_to_active_momentum))
-            .add_systems(Update,
+                         .after(mlsmpm::p2g_apply_stage1)
+                         .before(grid::wall_to_active_momentum)
+                         .in_set(FluidSimSet))
+            .add_systems(FixedUpdate,
                          grid::wall_to_active_momentum
-                            .before(mlsmpm::grid_update))
-            .add_systems(Update,
+                            .before(mlsmpm::grid_update)
+                            .in_set(FluidSimSet))
+            .add_systems(FixedUpdate,
                 mlsmpm::grid_update
-                    .before(grid::update_grid_cells))
-            .add_systems(Update,
+                    .before(grid::update_grid_cells)
+                    .in_set(FluidSimSet))
+            .add_systems(FixedUpdate,
+                grid::snapshot_grid_velocity
+                    .after(mlsmpm::grid_update)
+                    .before(grid::update_grid_cells)
+                    .in_set(FluidSimSet))
+            .add_systems(FixedUpdate,
+                grid::grid_update_gravity
+                    .before(grid::update_grid_cells)
+                    .in_set(FluidSimSet))
+            .add_systems(FixedUpdate,
+                grid::push_floating_decorations
+                    .after(mlsmpm::grid_update)
+                    .before(grid::update_grid_cells)
+                    .in_set(FluidSimSet))
+            .add_systems(FixedUpdate,
+                grid::grid_update_oscillating_forces
+                    .after(sim_time::update_sim_time)
+                    .before(grid::update_grid_cells)
+                    .in_set(FluidSimSet))
+            .add_systems(FixedUpdate,
                 grid::update_grid_cells
-                    .before(grid_to_particle))
+                    .before(grid_to_particle)
+                    .in_set(FluidSimSet))
             .add_systems(Update,
                 surface::update_surface
-                    .after(grid::update_grid_cells))
+                    .in_set(FluidSimSet))
+            .add_systems(Update, surface::toggle_surface_flow)
+            .add_systems(Update, surface_export::export_surface_heights.after(surface::update_surface))
+            .add_systems(Update, particle_export::export_particles_on_key)
+            .add_systems(Update, grid::re_voxelize_decorations.in_set(FluidSimSet))
+            .add_systems(Update, legend::toggle_legend.after(surface::toggle_surface_flow))
+            .add_systems(Update, legend::update_legend)
+            .add_systems(Update, legend::draw_scale_bar)
+            .add_systems(Update, clip_plane::control_clip_plane.before(particle_world_update))
+            .add_systems(Update, clip_plane::clip_surface.after(clip_plane::control_clip_plane))
+            .add_systems(Update, grid_slice::control_grid_slice)
+            .add_systems(Update, gravity_tilt::control_gravity_tilt.after(sim_control::control_sim))
+            .add_systems(Update,
+                density_heatmap::update_density_heatmap
+                    .in_set(FluidSimSet))
+            .add_systems(Update,
+                grid_slice::update_grid_slice
+                    .after(grid_slice::control_grid_slice)
+                    .in_set(FluidSimSet))
+            .add_systems(FixedUpdate,
+                diagnostics::update_cfl_diagnostics
+                    .after(grid::update_grid_cells)
+                    .in_set(FluidSimSet))
+            .add_systems(Update,
+                surface::draw_surface_flow
+                    .in_set(FluidSimSet))
             // .add_systems(Update,
             //     grid::external_forces_grid_cells
             //         .label("grid_ext_forces")
             //         .before("g2p"))
-            .add_systems(Update,
+            .add_systems(FixedUpdate,
                 grid_to_particle
-                    .before(particle_boundary_enforcement))
-            .add_systems(Update,
+                    .before(particle_boundary_enforcement)
+                    .in_set(FluidSimSet))
+            .add_systems(FixedUpdate,
+                elastic::update_deformation_gradients
+                    .after(grid_to_particle)
+                    .in_set(FluidSimSet))
+            .add_systems(FixedUpdate,
+                despawn_escaped_particles
+                    .after(grid_to_particle)
+                    .before(particle_boundary_enforcement)
+                    .in_set(FluidSimSet))
+            .add_systems(FixedUpdate,
+                drain_pump_outlets
+                    .after(despawn_escaped_particles)
+                    .before(particle_boundary_enforcement)
+                    .in_set(FluidSimSet))
+            .add_systems(FixedUpdate,
+                drain_outlet_cells
+                    .after(drain_pump_outlets)
+                    .before(particle_boundary_enforcement)
+                    .in_set(FluidSimSet))
+            .add_systems(FixedUpdate,
                 particle_boundary_enforcement
-                .before(particle_world_update))
+                .in_set(FluidSimSet))
             // .add_systems(Update,
             //     _collider_update
             //         .label("collider_update")
@@ -467,11 +995,550 @@ impl Plugin for FluidPlugin {
             //         .before("particle_world_update"))
             .add_systems(Update,
                 grid::debug_grid_cells
-                    .after(grid::update_grid_cells))
+                    .in_set(FluidSimSet))
+            .add_systems(Update, debug_scalar::cycle_debug_scalar)
+            .add_systems(Update,
+                debug_scalar::update_debug_scalar
+                    .after(debug_scalar::cycle_debug_scalar)
+                    .in_set(FluidSimSet))
+            .add_systems(Update, particle_rotation::toggle_rotation_markers)
+            .add_systems(Update,
+                particle_rotation::draw_rotation_markers
+                    .after(particle_world_update)
+                    .in_set(FluidSimSet))
+            .add_systems(Update,
+                particle_budget::update_particle_budget
+                    .before(particle_world_update)
+                    .in_set(FluidSimSet))
             .add_systems(Update,
                 particle_world_update
-            )
-            .add_systems(Update,fill_tank)
+                    .in_set(FluidSimSet))
+            .add_systems(Update,
+                diagnostics::update_sim_diagnostics
+                    .after(particle_world_update)
+                    .in_set(FluidSimSet))
+            .add_systems(Update,
+                point_cloud::update_point_cloud
+                    .in_set(FluidSimSet))
+            .add_systems(Update,
+                screen_space::update_screen_space_fluid
+                    .in_set(FluidSimSet))
+            .add_systems(Update, screen_space::resize_depth_target)
+            .add_systems(Update, pump::control_flow_scale)
+            .add_systems(Update, pump::toggle_pumps)
+            .add_systems(FixedUpdate,
+                pump::update_pump_ramps
+                    .before(particle_boundary_enforcement)
+                    .in_set(FluidSimSet))
+            .add_systems(FixedUpdate,
+                pump::log_pump_stats
+                    .after(particle_boundary_enforcement)
+                    .in_set(FluidSimSet))
+            .add_systems(Update,
+                color_by_inlet_source
+                    .in_set(FluidSimSet))
+            .add_systems(Update,
+                velocity_color::velocity_colormap
+                    .in_set(FluidSimSet))
+            .add_systems(FixedUpdate,
+                flow_gate::measure_flow_gates
+                    .after(grid::update_grid_cells)
+                    .in_set(FluidSimSet))
+            .add_systems(FixedUpdate,
+                level_hold::update_level_hold
+                    .after(particle_boundary_enforcement)
+                    .in_set(FluidSimSet))
+            .add_systems(FixedUpdate,
+                level_hold::drain_particles
+                    .after(level_hold::update_level_hold)
+                    .in_set(FluidSimSet))
+            .add_systems(FixedUpdate,
+                fill_tank
+                    .after(level_hold::update_level_hold)
+                    .in_set(FluidSimSet))
             ;
     }
 }
+
+
+
+#[cfg(test)]
+mod test
+{
+    use super::*;
+    use crate::aqs_utils::constants::SedimentConf;
+
+    #[test]
+    fn test_sediment_seeding() {
+        let sediment = SedimentConf { fraction: 0.25, mass_multiplier: 3.0 };
+        let heavy = (0..100)
+            .map(|i| sediment.particle_mass( 2.0, i as f32 / 100. ))
+            .filter(|&m| m > 2.0)
+            .count();
+        assert_eq!(heavy, 25);
+        assert_eq!(sediment.particle_mass( 2.0, 0.1 ), 6.0);
+        assert_eq!(SedimentConf::default().particle_mass( 2.0, 0.0 ), 2.0);
+    }
+
+    /// heights after `steps` runs of grid_to_particle of (mass, temperature) particles
+    ///   that start at the same spot of a resting grid
+    fn heights_after_steps(constants: Constants, particles: &[(f32, f32)], steps: usize) -> Vec<f32> {
+        let mut world = World::new();
+        world.insert_resource( constants );
+        world.insert_resource( sim_time::SimTime { dt: 0.04 } );
+        world.init_resource::<grid::AppliedForces>();
+        world.insert_resource( Grid::new( UVec3::new( 10, 10, 10 ), 1.0 ) );
+        let start = Vec3A::new( 5.0, 4.0, 5.0 );
+        let entities: Vec<Entity> = particles.iter().enumerate()
+            .map(| (id, &(mass, temperature)) | world.spawn((
+                resources::ParticleTag( id ),
+                resources::FluidParticlePosition( start ),
+                resources::FluidParticleVelocity( Vec3A::ZERO ),
+                resources::AffineMomentum( Mat3A::ZERO ),
+                resources::FluidQuantityMass( mass ),
+                resources::FluidTemperature( temperature ),
+            )).id())
+            .collect();
+
+        let mut schedule = Schedule::default();
+        schedule.add_systems( grid_to_particle );
+        for _ in 0..steps {
+            schedule.run( &mut world );
+        }
+        entities.iter().map(| entity | world.get::<resources::FluidParticlePosition>( *entity ).unwrap().0.y).collect()
+    }
+
+    #[test]
+    fn test_heavier_particles_sink() {
+        // default particles keep their velocity, heavy ones pick up downward speed
+        assert_eq!(excess_weight( 2.0, 2.0, -9.81 ), 0.0);
+        assert!(excess_weight( 6.0, 2.0, -9.81 ) < 0.0);
+
+        // sediment and water side by side in the same resting fluid
+        let mut constants = crate::aqs_utils::config::read_json::<Constants>(String::from("assets/constants.json")).unwrap();
+        constants.SEDIMENT = SedimentConf { fraction: 0.5, mass_multiplier: 2.5 };
+        let temperature = constants.WATER_TEMPERATURE;
+        let water = constants.DEFAULT_PARTICLE_MASS;
+        let sediment = constants.SEDIMENT.particle_mass( water, 0.0 );
+
+        let heights = heights_after_steps( constants, &[ (sediment, temperature), (water, temperature) ], 25 );
+        assert_eq!( heights[1], 4.0 );
+        assert!( heights[0] < heights[1] - 0.1, "{:?}", heights );
+    }
+
+    #[test]
+    fn test_warm_water_rises() {
+        assert_eq!(boussinesq_mass( 2.0, 25.0, 25.0, 0.01 ), 2.0);
+        assert!(boussinesq_mass( 2.0, 30.0, 25.0, 0.01 ) < 2.0);
+        assert!(boussinesq_mass( 2.0, 20.0, 25.0, 0.01 ) > 2.0);
+
+        let mut world = World::new();
+        let mut constants = crate::aqs_utils::config::read_json::<Constants>(String::from("assets/constants.json")).unwrap();
+        constants.THERMAL_EXPANSION = 0.01;
+        let (reference, mass) = (constants.WATER_TEMPERATURE, constants.DEFAULT_PARTICLE_MASS);
+        world.insert_resource( constants );
+        world.insert_resource( sim_time::SimTime { dt: 0.04 } );
+        world.init_resource::<grid::AppliedForces>();
+        world.insert_resource( Grid::new( UVec3::new( 10, 10, 10 ), 1.0 ) );
+
+        // a resting grid: only the temperature tells the two particles apart
+        let start = Vec3A::new( 5.0, 4.0, 5.0 );
+        let mut spawn = | temperature: f32 | world.spawn((
+            resources::ParticleTag( 0 ),
+            resources::FluidParticlePosition( start ),
+            resources::FluidParticleVelocity( Vec3A::ZERO ),
+            resources::AffineMomentum( Mat3A::ZERO ),
+            resources::FluidQuantityMass( mass ),
+            resources::FluidTemperature( temperature ),
+        )).id();
+        let ambient = spawn( reference );
+        let warm = spawn( reference + 10.0 );
+
+        let mut schedule = Schedule::default();
+        schedule.add_systems( grid_to_particle );
+        for _ in 0..25 {
+            schedule.run( &mut world );
+        }
+        let height = | entity | world.get::<resources::FluidParticlePosition>( entity ).unwrap().0.y;
+        assert_eq!( height( ambient ), start.y );
+        assert!( height( warm ) > start.y + 0.01, "warm particle at {}", height( warm ) );
+    }
+
+    #[test]
+    fn test_setup_estimate() {
+        use rand::SeedableRng;
+
+        let grid = Grid::new( UVec3::new( 40, 20, 30 ), 1.0 );
+        let fill_height = 0.6 * grid.grid_size().y as f32;
+        let estimate = estimate_setup( &grid, fill_height );
+
+        let mut rng = rand::rngs::StdRng::seed_from_u64( 42 );
+        let seeded: usize = (0..grid.cell_count())
+            .filter(|&idx| grid.initial_cell_type( grid.to_3d( idx ) ) == GridCellType::Fluid)
+            .map(|idx| seed_cell( grid.to_3d( idx ).as_vec3(), idx, fill_height, 1.0, &mut rng ).len())
+            .sum();
+
+        let margin = (seeded as f32 * 0.02) as usize;
+        assert!( estimate.particles.abs_diff( seeded ) <= margin,
+                 "estimate {} vs seeded {}", estimate.particles, seeded );
+        assert!( estimate.bytes > 0 );
+    }
+
+    #[test]
+    fn test_wall_correction() {
+        let dt = 0.04;
+        let wall_min = BOUNDARY_WALL_MARGIN;
+        let wall_max = Vec3A::splat( 10.0 );
+        let location = Vec3A::new( 9.9, 5.0, 5.0 );
+        let velocity = Vec3A::new( 50.0, 0.0, 0.0 );
+
+        // default settings only nudge the velocity
+        let soft = wall_correction( location, velocity, wall_min, wall_max, 0.1 * dt, 1.0 );
+        assert!( soft.x > 0.0 && soft.x < velocity.x );
+
+        // a stiff correction with a full step of lookahead reflects the particle
+        let stiff = wall_correction( location, velocity, wall_min, wall_max, dt, 40.0 );
+        assert!( stiff.x < 0.0 );
+        let next = location + stiff * dt;
+        assert!( next.x > wall_min && next.x < wall_max.x );
+        assert_eq!( stiff.y, 0.0 );
+
+        // particles away from the walls are untouched
+        let center = Vec3A::splat( 5.0 );
+        assert_eq!( wall_correction( center, velocity * 0.1, wall_min, wall_max, dt, 40.0 ), velocity * 0.1 );
+    }
+
+    #[test]
+    fn test_wall_boundary_modes() {
+        let dt = 0.04;
+        let wall_max = Vec3A::splat( 10.0 );
+        let location = Vec3A::new( 9.9, 5.0, 5.0 );
+        let velocity = Vec3A::new( 5.0, 1.0, 0.0 );
+
+        let slip = wall_boundary( BoundaryMode::Slip, location, velocity, BOUNDARY_WALL_MARGIN, wall_max, dt, 1.0 );
+        assert_eq!( slip, Vec3A::new( 0.0, 1.0, 0.0 ) );
+        let stuck = wall_boundary( BoundaryMode::NoSlip, location, velocity, BOUNDARY_WALL_MARGIN, wall_max, dt, 1.0 );
+        assert_eq!( stuck, Vec3A::ZERO );
+        assert_eq!( wall_boundary( BoundaryMode::Separate, location, velocity, BOUNDARY_WALL_MARGIN, wall_max, dt, 1.0 ),
+                    wall_correction( location, velocity, BOUNDARY_WALL_MARGIN, wall_max, dt, 1.0 ) );
+
+        // away from the walls nothing changes
+        let center = Vec3A::splat( 5.0 );
+        assert_eq!( wall_boundary( BoundaryMode::NoSlip, center, velocity, BOUNDARY_WALL_MARGIN, wall_max, dt, 1.0 ), velocity );
+    }
+
+    #[test]
+    fn test_closed_top_reflects() {
+        let dt = 0.04;
+        let open = Grid::with_air_layers( UVec3::new( 10, 10, 10 ), 1.0, 2 );
+        let closed = Grid::with_air_layers( UVec3::new( 10, 10, 10 ), 1.0, 2 ).with_closed_top( true );
+        assert!( !open.has_closed_top() && closed.has_closed_top() );
+        let top = UVec3::new( 5, closed.grid_size().y - 1, 5 );
+        assert_eq!( open.initial_cell_type( top ), GridCellType::Air );
+        assert_eq!( closed.initial_cell_type( top ), GridCellType::Solid );
+
+        let wall_max = | grid: &Grid | *grid.wall_vector() - Vec3A::splat( BOUNDARY_WALL_MARGIN );
+        let location = Vec3A::new( 5.0, wall_max( &closed ).y - 0.1, 5.0 );
+        let velocity = Vec3A::new( 0.0, 20.0, 0.0 );
+
+        // the lid reflects a fast upward particle before it gets into the top layers
+        let reflected = wall_correction( location, velocity, BOUNDARY_WALL_MARGIN, wall_max( &closed ), dt, 40.0 );
+        assert!( reflected.y < 0.0 );
+        let next = location + reflected * dt;
+        assert_eq!( closed.initial_cell_type( next.as_uvec3() ), GridCellType::Fluid );
+
+        // without the lid the particle keeps going up into the headroom
+        assert_eq!( wall_correction( location, velocity, BOUNDARY_WALL_MARGIN, wall_max( &open ), dt, 40.0 ), velocity );
+    }
+
+    #[test]
+    fn test_rk2_advection_on_circle() {
+        // rigid rotation around the z-axis, particles should stay on the unit circle
+        let rotation = | p: Vec3A | Vec3A::new( -p.y, p.x, 0.0 );
+        let dt = 0.1;
+
+        let mut euler = Vec3A::X;
+        let mut rk2 = Vec3A::X;
+        for _ in 0..63 {  // ~one full revolution
+            euler = advect( euler, rotation( euler ), dt, 1, rotation );
+            rk2 = advect( rk2, rotation( rk2 ), dt, 2, rotation );
+        }
+        let euler_err = (euler.length() - 1.0).abs();
+        let rk2_err = (rk2.length() - 1.0).abs();
+        assert!( rk2_err < euler_err );
+        assert!( rk2_err < 0.01 );
+    }
+
+    #[test]
+    fn test_two_phases_stratify() {
+        use crate::aqs_utils::constants::FluidPhaseConf;
+
+        let second = FluidPhaseConf { layer: Vec2::new( 0.0, 1.0 ), fraction: 0.5, ..default() };
+        assert_eq!(second.phase_at( 0.5, 0.2 ), 1);
+        assert_eq!(second.phase_at( 0.5, 0.7 ), 0);
+        assert_eq!(FluidPhaseConf::default().phase_at( 0.5, 0.0 ), 0);
+
+        // well mixed column: alternating phases, lighter phase mass scaled by its rest density
+        let (gravity, dt, height) = (-9.81, 0.04, 20.0);
+        let base_mass = 2.0;
+        let light_mass = base_mass * second.model.rest_density / 1.0;
+        let mut column: Vec<(u8, f32, f32)> = (0..20)
+            .map(|i| ((i % 2) as u8, i as f32 + 0.5, 0.0))
+            .collect();
+        for _ in 0..200 {
+            column.iter_mut().for_each(| (phase, y, v) | {
+                let mass = if *phase == 1 { light_mass } else { base_mass };
+                *v += excess_weight( mass, base_mass, gravity ) * dt;
+                *y += *v * dt;
+                if *y < 0.0 || *y > height {
+                    *y = y.clamp( 0.0, height );
+                    *v = 0.0;
+                }
+            });
+        }
+        let lowest_light = column.iter().filter(|p| p.0 == 1).map(|p| p.1).fold(f32::MAX, f32::min);
+        let highest_heavy = column.iter().filter(|p| p.0 == 0).map(|p| p.1).fold(f32::MIN, f32::max);
+        assert!( lowest_light > highest_heavy );
+    }
+
+    #[test]
+    fn test_particle_tags_stay_unique() {
+        use rand::SeedableRng;
+
+        let mut spawner = ParticleSpawner {
+            next_id: 0,
+            debug_material: Handle::default(),
+            rng: StdRng::seed_from_u64( 0 ),
+        };
+        // the initial fill, then injected particles after some got drained
+        spawner.next_id = 3;
+        let tags: Vec<usize> = (0..4).map(|_| spawner.next_tag().0).collect();
+        assert_eq!( tags, vec![ 3, 4, 5, 6 ] );
+        assert_eq!( spawner.next_tag().0, 7 );
+    }
+
+    #[test]
+    fn test_escaped_particles_despawn() {
+        let grid = Grid::new( UVec3::new( 10, 10, 10 ), 1.0 );
+        let size = grid.grid_size().as_vec3a();
+        assert!(!has_escaped( Vec3A::splat( 5.0 ), size, 2.0 ));
+        assert!(!has_escaped( Vec3A::new( 11.5, 5.0, -1.5 ), size, 2.0 ));
+        assert!(has_escaped( Vec3A::new( 12.5, 5.0, 5.0 ), size, 2.0 ));
+        assert!(has_escaped( Vec3A::new( f32::NAN, 5.0, 5.0 ), size, 2.0 ));
+
+        let mut world = World::new();
+        world.insert_resource( crate::aqs_utils::config::read_json::<Constants>(String::from("assets/constants.json")).unwrap() );
+        world.insert_resource( grid );
+        world.init_resource::<diagnostics::FluidDiagnostics>();
+        let frame = world.spawn(( resources::ParticleFrameTag, resources::ParticleCount( 2 ) )).id();
+        let inside = world.spawn(( resources::ParticleTag( 0 ), resources::FluidParticlePosition( Vec3A::splat( 5.0 ) ) )).id();
+        let rogue = world.spawn(( resources::ParticleTag( 1 ), resources::FluidParticlePosition( Vec3A::new( 500.0, 5.0, 5.0 ) ) )).id();
+        world.entity_mut( frame ).add_children( &[inside, rogue] );
+
+        let mut schedule = Schedule::default();
+        schedule.add_systems( despawn_escaped_particles );
+        schedule.run( &mut world );
+
+        assert!(world.entities().contains( inside ));
+        assert!(!world.entities().contains( rogue ));
+        assert_eq!(world.get::<resources::ParticleCount>( frame ).unwrap().0, 1);
+        assert_eq!(world.resource::<diagnostics::FluidDiagnostics>().escaped_particles, 1);
+    }
+
+    #[test]
+    fn test_solid_particles_stay_in_bounds() {
+        let mut world = World::new();
+        world.insert_resource( crate::aqs_utils::config::read_json::<Constants>(String::from("assets/constants.json")).unwrap() );
+        world.insert_resource( Grid::new( UVec3::new( 10, 10, 10 ), 1.0 ) );
+        world.insert_resource( sim_time::SimTime { dt: 0.04 } );
+        world.init_resource::<PumpStats>();
+        world.init_resource::<diagnostics::FluidDiagnostics>();
+        let solid = | location: Vec3A | (
+            resources::SolidParticleTag( 0 ),
+            resources::FluidParticlePosition( location ),
+            resources::FluidParticleVelocity( Vec3A::new( 0.0, -3.0, 0.0 ) ),
+            resources::AffineMomentum( Mat3A::ZERO ),
+        );
+        // sunk into the floor and far outside the grid
+        let at_floor = world.spawn( solid( Vec3A::new( 5.0, 0.2, 5.0 ) ) ).id();
+        let rogue = world.spawn( solid( Vec3A::new( 5.0, -50.0, 5.0 ) ) ).id();
+
+        let mut schedule = Schedule::default();
+        schedule.add_systems( (despawn_escaped_particles, particle_boundary_enforcement).chain() );
+        schedule.run( &mut world );
+
+        assert!( world.get::<resources::FluidParticlePosition>( at_floor ).unwrap().0.y >= 1.001 );
+        assert!( world.get::<resources::FluidParticleVelocity>( at_floor ).unwrap().0.y > -3.0 );
+        assert!( !world.entities().contains( rogue ) );
+        assert_eq!( world.resource::<diagnostics::FluidDiagnostics>().escaped_particles, 1 );
+    }
+
+    #[test]
+    fn test_absolute_fill_level() {
+        use rand::SeedableRng;
+        use crate::aqs_utils::constants::FillSpec;
+
+        let grid = Grid::new( UVec3::new( 20, 40, 10 ), 1.0 );
+        let tank_scale = 0.5;  // 2cm per grid cell
+        let fill_height = FillSpec::Absolute( 30.0 ).grid_height( grid.grid_size().y as f32, tank_scale );
+        assert_eq!( fill_height, 15.0 );
+        assert_eq!( FillSpec::Fraction( 0.5 ).grid_height( 44.0, tank_scale ), 22.0 );
+
+        let mut rng = rand::rngs::StdRng::seed_from_u64( 3 );
+        let heights: Vec<f32> = (0..grid.cell_count())
+            .filter(| idx | grid.initial_cell_type( grid.to_3d( *idx ) ) == GridCellType::Fluid)
+            .flat_map(| idx | seed_cell( grid.to_3d( idx ).as_vec3(), idx, fill_height, 1.0, &mut rng ))
+            .map(| p | p.y)
+            .collect();
+        assert!( heights.iter().all(| &y | y <= fill_height) );
+        assert!( heights.iter().any(| &y | y > fill_height - 1.0) );
+    }
+
+    #[test]
+    fn test_flip_preserves_kinetic_energy() {
+        use rand::SeedableRng;
+
+        // one transfer particles -> grid -> gravity -> particles; positions stay fixed (the whole blob falls)
+        fn transfer(grid: &mut Grid, positions: &[Vec3A], velocities: &mut [Vec3A], flip_ratio: f32) {
+            let mut momentum = vec![ Vec3A::ZERO; grid.cell_count() ];
+            let mut mass = vec![ 0.0; grid.cell_count() ];
+            for (pos, vel) in positions.iter().zip( velocities.iter() ) {
+                let cell_pos = pos.as_uvec3();
+                let weights = grid::quadratic_interpolation_weights( *pos - cell_pos.as_vec3a() - Vec3A::splat(0.5) );
+                for gz in 0..3 {
+                    for gy in 0..3 {
+                        for gx in 0..3 {
+                            let weight = weights[gx].x * weights[gy].y * weights[gz].z;
+                            let idx = grid.index_of( cell_pos.x as usize + gx - 1,
+                                                     cell_pos.y as usize + gy - 1,
+                                                     cell_pos.z as usize + gz - 1 );
+                            momentum[ idx ] += *vel * weight;
+                            mass[ idx ] += weight;
+                        }
+                    }
+                }
+            }
+            let previous: Vec<Vec3A> = momentum.iter().zip( mass.iter() )
+                .map(| (p, &m) | if m > 0.0 { *p / m } else { Vec3A::ZERO })
+                .collect();
+            let updated: Vec<Vec3A> = previous.iter().zip( mass.iter() )
+                .map(| (v, &m) | if m > 0.0 { *v + Vec3A::Y * -9.81 * 0.04 } else { Vec3A::ZERO })
+                .collect();
+            grid.get_prev_velo_mut().copy_from_slice( &previous );
+            grid.get_tmp_velo_mut().copy_from_slice( &updated );
+
+            for (pos, vel) in positions.iter().zip( velocities.iter_mut() ) {
+                let weights = grid::particle_weights( *pos, *vel, 1.0 );
+                let (grid_velocity, _) = grid.sample_velocity_weighted( *pos, weights );
+                *vel = flip_blend( *vel, grid_velocity, grid.sample_previous_velocity_weighted( *pos, weights ), flip_ratio );
+            }
+        }
+
+        // kinetic energy relative to the common free-fall motion
+        fn internal_energy(velocities: &[Vec3A]) -> f32 {
+            let mean = velocities.iter().copied().sum::<Vec3A>() / velocities.len() as f32;
+            velocities.iter().map(| v | 0.5 * (*v - mean).length_squared() ).sum()
+        }
+
+        let mut grid = Grid::new( UVec3::new( 10, 10, 10 ), 1.0 );
+        let mut rng = rand::rngs::StdRng::seed_from_u64( 5 );
+        let positions: Vec<Vec3A> = (0..400)
+            .map(|_| Vec3A::new( rng.gen_range(3.0..8.0), rng.gen_range(3.0..8.0), rng.gen_range(3.0..8.0) ))
+            .collect();
+        let initial: Vec<Vec3A> = (0..400)
+            .map(|_| Vec3A::new( rng.gen_range(-1.0..1.0), rng.gen_range(-1.0..1.0), rng.gen_range(-1.0..1.0) ))
+            .collect();
+        let initial_energy = internal_energy( &initial );
+
+        let (mut pic, mut flip) = (initial.clone(), initial.clone());
+        for _ in 0..5 {
+            transfer( &mut grid, &positions, &mut pic, 0.0 );
+            transfer( &mut grid, &positions, &mut flip, 1.0 );
+        }
+        let pic_energy = internal_energy( &pic );
+        let flip_energy = internal_energy( &flip );
+        assert!( flip_energy > 0.99 * initial_energy, "flip {} of {}", flip_energy, initial_energy );
+        assert!( pic_energy < 0.5 * initial_energy, "pic {} of {}", pic_energy, initial_energy );
+
+        // both fall the same way
+        let mean = | v: &[Vec3A] | v.iter().copied().sum::<Vec3A>() / v.len() as f32;
+        assert!( (mean( &pic ).y - mean( &flip ).y).abs() < 0.1 );
+    }
+
+    #[test]
+    fn test_stalled_frame_advance_is_clamped() {
+        use bevy::time::{TimePlugin, TimeUpdateStrategy};
+
+        #[derive(Resource, Default)]
+        struct Steps(usize);
+
+        let mut constants = crate::aqs_utils::config::read_json::<Constants>(String::from("assets/constants.json")).unwrap();
+        constants.WORLD_DT = 0.04;
+        constants.MAX_SIM_ADVANCE_PER_FRAME = 0.2;
+
+        let mut app = App::new();
+        app.add_plugins( TimePlugin )
+            .insert_resource( constants )
+            .init_resource::<Steps>()
+            // every frame stalls for 2 seconds
+            .insert_resource( TimeUpdateStrategy::ManualDuration( Duration::from_secs( 2 ) ) )
+            .add_systems( Startup, configure_fixed_timestep )
+            .add_systems( FixedUpdate, | mut steps: ResMut<Steps> | steps.0 += 1 );
+
+        for _ in 0..4 {
+            app.update();
+        }
+        let elapsed = app.world().resource::<Time<Virtual>>().elapsed_secs();
+        let steps = app.world().resource::<Steps>().0;
+        assert!( elapsed <= 4.0 * 0.2 + 1e-3, "virtual time advanced by {}", elapsed );
+        assert!( steps <= 4 * 5, "{} steps instead of at most 5 per frame", steps );
+        assert!( steps > 0 );
+    }
+
+    #[test]
+    fn test_box_fill_region() {
+        use rand::SeedableRng;
+
+        let grid = Grid::new( UVec3::new( 40, 30, 20 ), 1.0 );
+        let tank_scale = 0.5;  // 2cm per grid cell
+        // dam break: left quarter of the tank up to 40cm
+        let region = FillRegion::Box { min: Vec3::ZERO, max: Vec3::new( 20.0, 40.0, 40.0 ) };
+
+        let mut rng = rand::rngs::StdRng::seed_from_u64( 11 );
+        let seeded: Vec<Vec3> = (0..grid.cell_count())
+            .filter(| idx | grid.initial_cell_type( grid.to_3d( *idx ) ) == GridCellType::Fluid)
+            .flat_map(| idx | seed_cell_in_region( grid.to_3d( idx ).as_vec3(), idx, 0.0, 1.0, Some( &region ), tank_scale, &mut rng ))
+            .collect();
+        assert!( !seeded.is_empty() );
+        // box in grid units: x <= 10, y <= 20
+        assert!( seeded.iter().all(| p | p.x <= 10.0 && p.y <= 20.0 ) );
+        assert!( seeded.iter().any(| p | p.x > 9.0 ) && seeded.iter().any(| p | p.y > 19.0 ) );
+
+        let sphere = FillRegion::Sphere { center: Vec3::splat( 10.0 ), radius: 2.0 };
+        assert!( sphere.contains( Vec3::splat( 5.0 ), tank_scale ) );
+        assert!( !sphere.contains( Vec3::new( 5.0, 7.0, 5.0 ), tank_scale ) );
+
+        let half = FillRegion::HalfSpace { normal: Vec3::X, offset: 10.0 };
+        assert!( half.contains( Vec3::new( 4.0, 30.0, 30.0 ), tank_scale ) );
+        assert!( !half.contains( Vec3::new( 6.0, 0.0, 0.0 ), tank_scale ) );
+    }
+
+    #[test]
+    fn test_seed_jitter() {
+        use rand::SeedableRng;
+
+        let mut rng = rand::rngs::StdRng::seed_from_u64( 5 );
+        let cell = Vec3::new( 3.0, 4.0, 5.0 );
+        // without jitter every particle sits at the cell center
+        let on_lattice: Vec<Vec3> = (0..200).flat_map(| idx | seed_cell( cell, idx, f32::INFINITY, 0.0, &mut rng )).collect();
+        assert!( on_lattice.iter().all(| p | *p == cell + Vec3::splat( 0.5 )) );
+
+        // full jitter covers the whole cell and stays inside of it
+        let spread: Vec<Vec3> = (0..2000).flat_map(| idx | seed_cell( cell, idx, f32::INFINITY, 1.0, &mut rng )).collect();
+        let min = spread.iter().fold( Vec3::MAX, | m, p | m.min( *p ) );
+        let max = spread.iter().fold( Vec3::MIN, | m, p | m.max( *p ) );
+        assert!( min.cmpge( cell ).all() && max.cmplt( cell + Vec3::ONE ).all() );
+        assert!( (min - cell).max_element() < 0.05 && (cell + Vec3::ONE - max).max_element() < 0.05,
+                 "spread {:?} .. {:?}", min, max );
+    }
+}
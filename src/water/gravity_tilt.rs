@@ -0,0 +1,108 @@
+/*
+    Copyright 2023 github.com/programaquatic
+
+   Licensed under the Apache License, Version 2.0 (the "License");
+   you may not use this file except in compliance with the License.
+   You may obtain a copy of the License at
+
+       http://www.apache.org/licenses/LICENSE-2.0
+
+   Unless required by applicable law or agreed to in writing, software
+   distributed under the License is distributed on an "AS IS" BASIS,
+   WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+   See the License for the specific language governing permissions and
+   limitations under the License.
+*/
+
+use bevy::prelude::*;
+
+use crate::water::sim_control::SimControl;
+
+/// max tilt of gravity while an arrow key is held (radians)
+pub const MAX_TILT: f32 = 0.35;
+/// how fast gravity tilts toward the held direction and back (radians per second)
+const TILT_RATE: f32 = 0.5;
+
+
+/// Temporary tilt of gravity for a sloshing demo
+///   while an arrow key is held gravity leans toward it (seen from the front), releasing it levels out again
+#[derive(Resource, Debug, Default)]
+pub struct GravityTilt {
+    /// rotation around the x and z axis (radians)
+    pub angles: Vec2,
+}
+
+impl GravityTilt {
+    /// the gravity vector rotated by the current tilt
+    pub fn apply(&self, gravity: Vec3) -> Vec3 {
+        Quat::from_rotation_z( self.angles.y ) * Quat::from_rotation_x( self.angles.x ) * gravity
+    }
+}
+
+/// tilt angles for the held arrow keys; opposite keys cancel out
+pub fn tilt_target(left: bool, right: bool, up: bool, down: bool) -> Vec2 {
+    let axis = | neg: bool, pos: bool | (pos as i32 - neg as i32) as f32 * MAX_TILT;
+    Vec2::new( axis( down, up ), axis( left, right ) )
+}
+
+/// move the angles toward the target by at most max_step per axis
+pub fn step_towards(current: Vec2, target: Vec2, max_step: f32) -> Vec2 {
+    current + (target - current).clamp( Vec2::splat( -max_step ), Vec2::splat( max_step ) )
+}
+
+
+pub fn control_gravity_tilt(
+    keys: Res<ButtonInput<KeyCode>>,
+    time: Res<Time>,
+    control: Res<SimControl>,
+    mut tilt: ResMut<GravityTilt>,
+) {
+    // the right arrow single-steps a paused simulation
+    if control.paused {
+        return;
+    }
+    let target = tilt_target(
+        keys.pressed( KeyCode::ArrowLeft ),
+        keys.pressed( KeyCode::ArrowRight ),
+        keys.pressed( KeyCode::ArrowUp ),
+        keys.pressed( KeyCode::ArrowDown ),
+    );
+    let angles = step_towards( tilt.angles, target, TILT_RATE * time.delta_secs() );
+    // only touch the resource on a change; grid_update_gravity compares the resulting vector anyway
+    if angles != tilt.angles {
+        tilt.angles = angles;
+    }
+}
+
+
+
+#[cfg(test)]
+mod test
+{
+    use super::*;
+
+    #[test]
+    fn test_tilt_gravity() {
+        let gravity = Vec3::new( 0.0, -9.81, 0.0 );
+        assert_eq!( GravityTilt::default().apply( gravity ), gravity );
+
+        // the water runs toward the held arrow
+        let left = GravityTilt { angles: tilt_target( true, false, false, false ) }.apply( gravity );
+        assert!( left.x < 0.0 && left.z.abs() < 1e-5 );
+        let up = GravityTilt { angles: tilt_target( false, false, true, false ) }.apply( gravity );
+        assert!( up.z < 0.0 && up.x.abs() < 1e-5 );
+        // tilting keeps the magnitude
+        assert!( (left.length() - 9.81).abs() < 1e-4 );
+
+        assert_eq!( tilt_target( true, true, false, false ), Vec2::ZERO );
+    }
+
+    #[test]
+    fn test_step_towards() {
+        let target = Vec2::new( MAX_TILT, -MAX_TILT );
+        let step = step_towards( Vec2::ZERO, target, 0.1 );
+        assert_eq!( step, Vec2::new( 0.1, -0.1 ) );
+        assert_eq!( step_towards( step, target, 1.0 ), target );
+        assert_eq!( step_towards( target, Vec2::ZERO, 1.0 ), Vec2::ZERO );
+    }
+}
@@ -25,6 +25,8 @@
 // Parts of this code are based on github.com/robkau who did this for 2D
 //    see: https://github.com/robkau/mlsmpm-particles-rs
 
+use std::collections::{HashMap, HashSet};
+
 use bevy::{
     prelude::*,
     math::{UVec3, Mat3A, Vec3A},
@@ -33,19 +35,35 @@ use bevy_rapier3d::prelude::*;
 
 use crate::{
     tech::tank::Tank,
-    decoration::types::DecorationTag,
+    decoration::types::{DecorationTag, DecorationShape, FloatingDecoration},
     aqs_utils::{
-        constants::Constants,
+        constants::{Constants, BoundaryMode},
         extforcevol::ExternalForceVolume,
         coneshape::ZCone,
+        scale::Scaler,
     },
-    water::resources::{
-        FluidParticleVelocity,
-        FluidQuantityMass,
+    water::{
+        resources::{
+            FluidParticleVelocity,
+            FluidQuantityMass,
+        },
+        setup::{SetupState, SetupProgress, COLLIDER_CELLS_PER_FRAME},
+        sim_time::SimTime,
+        gravity_tilt::GravityTilt,
     },
 };
 
 pub const DEBUG_GRID: bool = false;
+/// number of Air layers at the top of the grid if not configured
+pub const DEFAULT_AIR_LAYERS: u32 = 1;
+/// padding cells above the tank volume
+const MIN_TOP_PADDING: u32 = 3;
+/// share of the incoming velocity an Outlet cell removes per step
+pub const OUTLET_DAMPING: f32 = 0.5;
+/// downward velocity an Outlet cell adds on top of the damped velocity (cells per second)
+pub const OUTLET_DRAIN_SPEED: f32 = 2.0;
+/// particles in an Outlet column below this height left the tank through the drain
+pub const OUTLET_DRAIN_HEIGHT: f32 = 2.0;
 
 
 #[derive( Component, Clone, PartialEq, Eq, Debug )]
@@ -53,6 +71,8 @@ pub enum GridCellType {
     Solid,
     Fluid,
     Air,
+    /// fluid cell inside an overflow shaft that pulls the water down into the drain
+    Outlet,
 }
 
 #[derive( Component, Clone, PartialEq, Eq, Debug )]
@@ -63,6 +83,22 @@ pub struct GridCellIndex(pub usize);
 #[derive( Component, Debug)]
 pub struct GridCellAccumulatedForce(Vec3A);
 
+/// State the accumulated forces of the fluid cells are at
+///   cells that turn fluid at runtime start out with the same gravity and force volume time
+#[derive(Resource, Debug, Default)]
+pub struct AppliedForces {
+    /// gravity including tilt and ramp; None until grid_update_gravity ran (the cells start with the full gravity)
+    pub gravity: Option<Vec3>,
+    /// simulation time the oscillating force volumes are at
+    pub time: f32,
+}
+
+impl AppliedForces {
+    pub fn gravity(&self, constants: &Constants) -> Vec3 {
+        self.gravity.unwrap_or( constants.gravity() )
+    }
+}
+
 /// Stores the normals from wher the cell touches a collider
 #[derive( Component, Debug)]
 pub struct ColliderNormals( Vec<Vec3A> );
@@ -78,18 +114,25 @@ pub struct GridFluidNeighbors( Vec<usize> );
 pub struct Grid {
     /// Number of grid cells per dimension
     grid_dim: UVec3,
-    /// scaling factor to translate from unit-grid to graphics
-    /// store as f32 because it's often used to translate Vec3 coordinates
-    scale: f32,
-    // /// the real-world coordinates of the center of the tank's grid
-    // /// todo: this is technically the wrong place for this item
-    // grid_center: Vec3,
+    /// per-axis scaling to translate from unit-grid to graphics
+    scale: Scaler,
+    /// world space location of the grid origin (the tank's world offset)
+    origin: Vec3,
     /// grid cell array
     cells: Vec< Entity >,
 
     /// scratchpads for velocity and mass to more efficiently iterate over grid cells
     tmp_velo: Vec< Vec3A >,
     tmp_mass: Vec< f32 >,
+    /// grid velocity before forces and boundaries got applied (for the FLIP update)
+    prev_velo: Vec< Vec3A >,
+
+    /// number of Air layers at the top of the grid
+    air_layers: u32,
+    /// the top layers are Solid (a lid) instead of Air
+    closed_top: bool,
+    /// xz-areas (min, max) of the overflow shafts; the fluid cells inside are Outlets
+    outlets: Vec<(Vec2, Vec2)>,
 
     /// current level of water surface
     _surface_level: f32,
@@ -100,26 +143,84 @@ pub struct Grid {
 
 impl Grid {
     pub fn new(space: UVec3, cell_scale: f32) -> Self {
-        let tank_space = space;
-        let cell_count_v = (tank_space.as_vec3() / cell_scale).as_uvec3();
-        if (cell_count_v * cell_scale as u32) != tank_space {
-            println!("WARNING: Grid and Tank Spec are not compatible!");
+        Grid::with_air_layers(space, cell_scale, DEFAULT_AIR_LAYERS)
+    }
+
+    /// grid with `air_layers` layers of Air cells at the top as headroom for splashes
+    ///   the top padding grows with the air layers, the bottom keeps one solid layer
+    pub fn with_air_layers(space: UVec3, cell_scale: f32, air_layers: u32) -> Self {
+        Grid::with_scaler(space, Scaler::from_scale( cell_scale ), air_layers)
+    }
+
+    /// grid with per-axis cell sizes; `scale` translates unit-grid into `space` coordinates
+    ///   the simulation itself always runs on the unit-grid
+    pub fn with_scaler(space: UVec3, scale: Scaler, air_layers: u32) -> Self {
+        let tank_space = space.as_vec3();
+        let cell_count_v = scale.from( tank_space ).as_uvec3();
+        if (scale.to( cell_count_v.as_vec3() ) - tank_space).abs().max_element() > 1e-3 {
+            warn!("Grid and Tank Spec are not compatible!");
         }
-        let grid_size = cell_count_v + UVec3{ x: 2, y: 4, z: 2 };
+        let air_layers = air_layers.max(1);
+        let top_padding = u32::max(MIN_TOP_PADDING, air_layers);
+        let grid_size = cell_count_v + UVec3{ x: 2, y: 1 + top_padding, z: 2 };
         let cell_count = grid_size.x * grid_size.y * grid_size.z;
-        println!("INFO: GridSize ({},{},{}); cells={}, scale={}", grid_size.x, grid_size.y, grid_size.z, cell_count, cell_scale);
+        info!("GridSize ({},{},{}); cells={}, scale={}", grid_size.x, grid_size.y, grid_size.z, cell_count, scale.get_scale());
         Grid {
             grid_dim: grid_size,
             cells: Vec::with_capacity( cell_count as usize ),
             tmp_velo: vec![ Vec3A::ZERO; cell_count as usize ],
             tmp_mass: vec![ 0.0; cell_count as usize ],
-            scale: cell_scale,
-            // grid_center: (cell_count_v + UVec3::splat(2)).as_vec3() * cell_scale / 2.,
+            prev_velo: vec![ Vec3A::ZERO; cell_count as usize ],
+            scale,
+            origin: Vec3::ZERO,
+            air_layers,
+            closed_top: false,
+            outlets: vec![],
             _surface_level: 0.0,
             wall_limit: grid_size.as_vec3a(),
         }
     }
 
+    /// close the top with a lid: the air layers turn Solid and particles bounce off below them
+    pub fn with_closed_top(mut self, closed: bool) -> Self {
+        self.closed_top = closed;
+        self.wall_limit.y = if closed {
+            // same distance to the lid as to the solid cells of the side walls
+            (self.grid_dim.y - self.air_layers + 1) as f32
+        } else {
+            self.grid_dim.y as f32
+        };
+        self
+    }
+
+    /// true if the air layers are covered by a lid
+    pub fn has_closed_top(&self) -> bool {
+        self.closed_top
+    }
+
+    /// drain the water through the given xz-areas (min, max), e.g. the overflow shafts of the tank
+    pub fn with_outlets(mut self, areas: Vec<(Vec2, Vec2)>) -> Self {
+        self.outlets = areas;
+        self
+    }
+
+    /// true if the column of the cell lies strictly inside one of the outlet areas
+    pub fn is_outlet(&self, xyz: UVec3) -> bool {
+        let p = Vec2::new( xyz.x as f32, xyz.z as f32 );
+        self.outlets.iter().any(| (min, max) | p.cmpgt( *min ).all() && p.cmplt( *max ).all() )
+    }
+
+    pub fn with_origin(mut self, origin: Vec3) -> Self {
+        self.origin = origin;
+        self
+    }
+
+    pub fn get_origin(&self) -> Vec3 {
+        self.origin
+    }
+
+    /// index of the cell; out of range coordinates silently end up in another cell (or the last one)
+    ///   only for lookups that can't leave the grid; see try_index_of
     pub fn index_of(&self, x: usize, y: usize, z: usize) -> usize {
         let index= (self.grid_dim.x as usize * self.grid_dim.y as usize* z)
             + (self.grid_dim.x as usize * y) + x;
@@ -129,11 +230,25 @@ impl Grid {
             index
         }
     }
+    /// index of the cell or None if any of the coordinates is outside of the grid
+    pub fn try_index_of(&self, x: usize, y: usize, z: usize) -> Option<usize> {
+        let dim = self.grid_dim;
+        if x >= dim.x as usize || y >= dim.y as usize || z >= dim.z as usize {
+            return None;
+        }
+        Some( (dim.x as usize * dim.y as usize * z) + (dim.x as usize * y) + x )
+    }
+
+    /// like try_index_of; neighbor coordinates below 0 wrap around to large values and are outside as well
+    pub fn try_index_of_vec(&self, xyz: &UVec3) -> Option<usize> {
+        self.try_index_of( xyz.x as usize, xyz.y as usize, xyz.z as usize )
+    }
+
     pub fn index_of_vec(&self, xyz: &UVec3) -> usize {
         let idx = self.index_of( xyz.x as usize, xyz.y as usize, xyz.z as usize );
         // since type is usize, we skip: idx >= 0 test
         if idx >= self.cell_count() {
-            println!(" Cell out of range: {} -> {}/{}, {}", xyz, idx, self.cell_count()
+            error!("cell out of range: {} -> {}/{}, {}", xyz, idx, self.cell_count()
                      , self.grid_dim);
             assert!( idx < self.cell_count() );
         }
@@ -148,13 +263,28 @@ impl Grid {
         UVec3::new( xi, yi, zi )
     }
 
+    /// uniform cell size for isotropic quantities (e.g. radii); the geometric mean for per-axis scaling
     pub fn get_scale(&self) -> f32 {
-        self.scale
-        // 1.0
+        let scale = self.scale.get_scale();
+        if self.scale.is_isometric() {
+            scale.x
+        } else {
+            (scale.x * scale.y * scale.z).cbrt()
+        }
+    }
+
+    pub fn get_scaler(&self) -> &Scaler {
+        &self.scale
     }
 
     pub fn to_world_coord(&self, input: Vec3) -> Vec3 {
-        input * self.get_scale() // - self.grid_center
+        self.scale.to( input ) + self.origin
+    }
+
+    /// inverse of to_world_coord
+    #[cfg(test)]
+    pub fn world_to_grid(&self, input: Vec3) -> Vec3 {
+        self.scale.from( input - self.origin )
     }
 
     pub fn cell_count(&self) -> usize {
@@ -182,6 +312,9 @@ impl Grid {
     pub fn get_tmp_mass_mut(&mut self) -> &mut Vec<f32> {
         &mut self.tmp_mass
     }
+    pub fn get_prev_velo_mut(&mut self) -> &mut Vec<Vec3A> {
+        &mut self.prev_velo
+    }
 
 
     pub fn reset_tmp_mass(&mut self) {
@@ -197,6 +330,92 @@ impl Grid {
         self.grid_size().y as f32 - 5.
     }
 
+    /// clamp a location into the region where all 27 neighbor cells are valid
+    pub fn clamp_to_interior(&self, location: Vec3A) -> Vec3A {
+        location.clamp( Vec3A::splat(1.001), self.grid_dim.as_vec3a() - 1.001 )
+    }
+
+    /// interpolate the grid velocity (from tmp_velo) at the location
+    ///   returns the velocity and the APIC B matrix of the surrounding cells
+    pub fn sample_velocity(&self, location: Vec3A) -> (Vec3A, Mat3A) {
+        let cell_diff = location - location.as_uvec3().as_vec3a() - Vec3A::splat(0.5);
+        self.sample_velocity_weighted( location, quadratic_interpolation_weights(cell_diff) )
+    }
+
+    /// like sample_velocity, with the interpolation weights of the particle (see particle_weights)
+    pub fn sample_velocity_weighted(&self, location: Vec3A, weights: [Vec3A; 3]) -> (Vec3A, Mat3A) {
+        let cell_pos = location.as_uvec3();
+
+        // affine per-particle momentum matrix from APIC / MLS-MPM.
+        // see APIC paper (https://web.archive.org/web/20190427165435/https://www.math.ucla.edu/~jteran/papers/JSSTS15.pdf), page 6
+        // below equation 11 for clarification. this is calculating C = B * (D^-1) for APIC equation 8,
+        // where B is calculated in the inner loop at (D^-1) = 4 is a constant when using quadratic interpolation functions
+        let mut b = Mat3A::ZERO;
+        let mut velocity = Vec3A::ZERO;
+        // for all surrounding 9 cells
+        for gz in 0..3 {
+            for gy in 0..3 {
+                for gx in 0..3 {
+                    let weight = weights[gx].x * weights[gy].y * weights[gz].z;
+                    let neighbor = UVec3::new(
+                        (cell_pos.x as i32 + gx as i32 - 1) as u32,
+                        (cell_pos.y as i32 + gy as i32 - 1) as u32,
+                        (cell_pos.z as i32 + gz as i32 - 1) as u32,
+                    );
+                    let cell_dist = (neighbor.as_vec3a() - location) + Vec3A::splat(0.5);
+                    let Some( cell_at_index ) = self.try_index_of_vec( &neighbor ) else {
+                        continue;
+                    };
+                    let weighted_velocity = self.tmp_velo[ cell_at_index ] * weight;
+
+                    b += weighted_velocity_and_cell_dist_to_term(weighted_velocity, cell_dist);
+                    velocity += weighted_velocity;
+                }
+            }
+        }
+        (velocity, b)
+    }
+
+    /// interpolate the grid velocity from before the grid update (see snapshot_grid_velocity)
+    ///   takes the same weights as sample_velocity_weighted, otherwise FLIP picks up the kernel difference
+    pub fn sample_previous_velocity_weighted(&self, location: Vec3A, weights: [Vec3A; 3]) -> Vec3A {
+        let cell_pos = location.as_uvec3();
+        let mut velocity = Vec3A::ZERO;
+        for gz in 0..3 {
+            for gy in 0..3 {
+                for gx in 0..3 {
+                    let weight = weights[gx].x * weights[gy].y * weights[gz].z;
+                    let neighbor = UVec3::new(
+                        (cell_pos.x as i32 + gx as i32 - 1) as u32,
+                        (cell_pos.y as i32 + gy as i32 - 1) as u32,
+                        (cell_pos.z as i32 + gz as i32 - 1) as u32,
+                    );
+                    if let Some( cell_at_index ) = self.try_index_of_vec( &neighbor ) {
+                        velocity += self.prev_velo[ cell_at_index ] * weight;
+                    }
+                }
+            }
+        }
+        velocity
+    }
+
+    /// cell type from the grid boundaries alone (before any colliders are considered)
+    pub fn initial_cell_type(&self, xyz: UVec3) -> GridCellType {
+        let mut gct = GridCellType::Fluid;
+        if xyz.x * xyz.y * xyz.z == 0 ||
+            xyz.x >= self.grid_dim.x - 1 || xyz.z >= self.grid_dim.z - 1
+        {
+            gct = GridCellType::Solid;
+        }
+        if xyz.y >= self.grid_dim.y - self.air_layers {
+            gct = if self.has_closed_top() { GridCellType::Solid } else { GridCellType::Air };
+        }
+        if gct == GridCellType::Fluid && self.is_outlet( xyz ) {
+            gct = GridCellType::Outlet;
+        }
+        gct
+    }
+
     // actually prepare the grid cells with data
     pub fn initialize(&mut self, cells: Vec::<Entity>) {
         self.cells = cells;
@@ -208,7 +427,7 @@ impl Grid {
 
 pub fn quadratic_interpolation_weights(cell_diff: Vec3A) -> [Vec3A; 3] {
     if cell_diff.x.abs() > 1.5 || cell_diff.y.abs() > 1.5 || cell_diff.z.abs() > 1.5 {
-        println!("WARNING: cell_diff distance is more than expected");
+        warn!("cell_diff distance is more than expected");
     }
     [
         // cell to the 'left'/'previous'/idx-1: cell_diff would be
@@ -230,6 +449,41 @@ pub fn quadratic_interpolation_weights(cell_diff: Vec3A) -> [Vec3A; 3] {
     ]
 }
 
+/// linear hat weights of the 3 cells around the particle along each axis (a partition of unity like the quadratic ones)
+fn linear_interpolation_weights(cell_diff: Vec3A) -> [Vec3A; 3] {
+    [
+        (-cell_diff).max( Vec3A::ZERO ),
+        Vec3A::ONE - cell_diff.abs(),
+        cell_diff.max( Vec3A::ZERO ),
+    ]
+}
+
+/// quadratic weights sharpened across the (normalized) direction to resolve thin, fast streams
+///   per axis, the weights blend towards the linear hat kernel by (1 - 1/stretch) times the share of the axis
+///   perpendicular to the direction; blending two partitions of unity keeps the partition of unity.
+///   stretch 1 (or no direction) gives the plain quadratic kernel
+///   note: the APIC terms keep D^-1 = 4 of the quadratic kernel, i.e. strongly stretched kernels lose some accuracy
+pub fn anisotropic_interpolation_weights(cell_diff: Vec3A, direction: Vec3A, stretch: f32) -> [Vec3A; 3] {
+    let quadratic = quadratic_interpolation_weights( cell_diff );
+    if stretch <= 1.0 || direction == Vec3A::ZERO {
+        return quadratic;
+    }
+    let across = Vec3A::ONE - direction * direction;
+    let sharpness = across * (1.0 - 1.0 / stretch);
+    let linear = linear_interpolation_weights( cell_diff );
+    [
+        quadratic[0] + (linear[0] - quadratic[0]) * sharpness,
+        quadratic[1] + (linear[1] - quadratic[1]) * sharpness,
+        quadratic[2] + (linear[2] - quadratic[2]) * sharpness,
+    ]
+}
+
+/// interpolation weights of a particle; KERNEL_STRETCH > 1 stretches the kernel along its velocity
+pub fn particle_weights(location: Vec3A, velocity: Vec3A, stretch: f32) -> [Vec3A; 3] {
+    let cell_diff = location - location.as_uvec3().as_vec3a() - Vec3A::splat(0.5);
+    anisotropic_interpolation_weights( cell_diff, velocity.normalize_or_zero(), stretch )
+}
+
 
 pub fn weighted_velocity_and_cell_dist_to_term(
     weighted_velocity: Vec3A,
@@ -243,16 +497,92 @@ pub fn weighted_velocity_and_cell_dist_to_term(
 }
 
 
+/// fluid cells around a wall cell that receive its momentum (see wall_to_active_momentum)
+fn wall_fluid_neighbors(grid: &Grid, xyz: UVec3, is_fluid: impl Fn(usize) -> bool) -> Vec<usize> {
+    let mut neighbors = vec![];
+    for z in 0..3 {
+        for y in 0..3 {
+            for x in 0..3 {
+                let tcell_xyz = xyz + UVec3{x, y, z};
+                if tcell_xyz.x * tcell_xyz.y * tcell_xyz.z != 0 {
+                    let ocell_xyz = tcell_xyz - UVec3::splat(1);
+                    if let Some( ocidx ) = grid.try_index_of_vec(&ocell_xyz) {
+                        if is_fluid( ocidx ) {
+                            neighbors.push( ocidx );
+                        }
+                    }
+                }
+            }
+        }
+    }
+    neighbors
+}
+
+/// sub-cell sample points per axis for the coverage estimate
+const COVERAGE_SAMPLES: usize = 3;
+
+/// fraction of the cell around center that is inside any of the colliders (sampled on a regular sub-grid)
+pub fn cell_coverage(center: Vec3, colliders: &[(&Transform, &Collider)]) -> f32 {
+    let step = 1.0 / COVERAGE_SAMPLES as f32;
+    let mut inside = 0;
+    for z in 0..COVERAGE_SAMPLES {
+        for y in 0..COVERAGE_SAMPLES {
+            for x in 0..COVERAGE_SAMPLES {
+                // centers of the sub-cells
+                let sample = center + (Vec3::new( x as f32, y as f32, z as f32 ) + 0.5) * step - 0.5;
+                if colliders.iter().any(| (cloc, c) | c.contains_point( cloc.translation, cloc.rotation, sample )) {
+                    inside += 1;
+                }
+            }
+        }
+    }
+    inside as f32 / COVERAGE_SAMPLES.pow( 3 ) as f32
+}
+
+/// test a cell center against decoration colliders
+///   returns whether the cell is solid and the normals towards colliders close by;
+///   with a coverage threshold, the cell has to be covered at least that much; otherwise any collider within half a cell counts
+pub fn classify_against_colliders<'a>(
+    center: Vec3,
+    colliders: impl Iterator<Item = (&'a Transform, &'a Collider)>,
+    coverage: Option<f32>,
+) -> (bool, Vec<Vec3A>) {
+    let dist_thresh = 0.5;
+    let colliders: Vec<(&Transform, &Collider)> = colliders.collect();
+    let mut solid = coverage.is_some_and(| threshold | cell_coverage( center, &colliders ) >= threshold );
+    let mut normals = vec![];
+    colliders.iter().for_each(| (cloc, c) | {
+        let (_sc, ro, _tr) = (cloc.scale, cloc.rotation, cloc.translation);
+        if coverage.is_none() && c.project_point_with_max_dist( cloc.translation, ro,
+                                                                 center, false,
+                                                                 dist_thresh).is_some() {
+            solid = true;
+        } else if let Some( pp ) = c.project_point_with_max_dist( cloc.translation, ro,
+                                                                  center, false,
+                                                                  dist_thresh*2.0 /*f32::sqrt(2.0)*0.75*/) {
+            normals.push( Vec3A::from( (pp.point - center).normalize_or_zero() ) );
+        }
+    });
+    (solid, normals)
+}
+
 pub fn setup_fluid_grid(
+    constants: Res<Constants>,
     tank_cfg: Res<Tank>,
     mut commands: Commands,
 ) {
+    let _span = info_span!("setup_fluid_grid").entered();
     let tank_size = tank_cfg.get_size();
 
-    let mut grid = Grid::new(
+    // the tank is already scaled into grid units (per axis with GRID_ALLOCATION PerAxis)
+    let mut grid = Grid::with_scaler(
         tank_size.as_uvec3(),
-        1.0,
-    );
+        Scaler::default(),
+        constants.AIR_LAYERS,
+    )
+        .with_closed_top( constants.CLOSED_TOP )
+        .with_outlets( tank_cfg.shaft_areas() )
+        .with_origin( tank_cfg.world_offset );
     let ptank = tank_cfg.get_tank_parent();
 
     let mut cells = Vec::<Entity>::with_capacity( grid.cell_count() );
@@ -262,15 +592,7 @@ pub fn setup_fluid_grid(
         let xyz = grid.to_3d(idx);
 
         // determine grid cell type
-        let mut gct = GridCellType::Fluid;
-        if xyz.x * xyz.y * xyz.z == 0 ||
-            xyz.x >= grid.grid_dim.x - 1 || xyz.z >= grid.grid_dim.z - 1
-        {
-            gct = GridCellType::Solid;
-        }
-        if xyz.y >= grid.grid_dim.y - 1 {
-            gct = GridCellType::Air;
-        }
+        let gct = grid.initial_cell_type( xyz );
 
         let cell_id = commands
             .spawn((
@@ -290,51 +612,45 @@ pub fn setup_fluid_grid(
     for idx in 0..grid.cell_count() {
         let xyz = grid.to_3d(idx);
         if temp_type_info[ idx ].1 == GridCellType::Solid {
-            let mut neighbors = GridFluidNeighbors( vec![] );
-            for z in 0..3 {
-                for y in 0..3 {
-                    for x in 0..3 {
-                        let tcell_xyz = xyz + UVec3{x, y, z};
-                        if tcell_xyz.x * tcell_xyz.y * tcell_xyz.z != 0 &&
-                            tcell_xyz.x <= grid.grid_dim.x && tcell_xyz.z <= grid.grid_dim.z
-                        {
-                            let ocell_xyz = tcell_xyz - UVec3::splat(1);
-                            let ocidx = grid.index_of_vec(&ocell_xyz);
-                            if temp_type_info[ocidx].1 == GridCellType::Fluid {
-                                neighbors.0.push( ocidx );
-                            }
-                        }
-                    }
-                }
-            }
+            let neighbors = wall_fluid_neighbors( &grid, xyz, | ocidx | temp_type_info[ ocidx ].1 == GridCellType::Fluid );
             commands.entity( temp_type_info[ idx ].0 )
-                .insert(neighbors);
+                .insert(GridFluidNeighbors( neighbors ));
         }
     }
 
     commands.entity( ptank ).add_children( &cells );
+    commands.insert_resource(SetupProgress {
+        next_cell: 0,
+        total_cells: grid.cell_count(),
+    });
     grid.initialize( cells );
     commands.insert_resource(grid);
 }
 
 
+/// accumulated external force of a fluid cell: gravity plus all force volumes at the given time
+fn cell_external_force<'a>(position: Vec3, gravity: Vec3, volumes: impl Iterator<Item = &'a ExternalForceVolume>, time: f32) -> Vec3 {
+    volumes.fold( gravity, | acc, volume | acc + volume.get_force_for_position( position, time ) )
+}
+
 pub fn grid_initialize_external_forces(
     constants: Res<Constants>,
     mut commands: Commands,
     mut cells: Query<(Entity, &Transform, &GridCellType)>,
     ext_forces: Query< &ExternalForceVolume >,
+    floating: Query<(&Transform, &Collider), With<FloatingDecoration>>,
 ) {
-    let gravity = Vec3::Y * constants.DEFAULT_GRAVITY;
+    let gravity = constants.gravity();
     // walk through all cells
     cells.iter_mut().for_each( | ( cid, pos, gct ) | {
 
+        // cells under a floating decoration turn into fluid once it moved away
+        let floating_solid = *gct == GridCellType::Solid
+            && classify_against_colliders( pos.translation, floating.iter(), constants.SOLID_COVERAGE ).0;
+
         // determine position-dependent external forces
-        let ext_f = if *gct == GridCellType::Fluid {
-            let mut acc_force = gravity;
-            ext_forces.iter().for_each( | force_location | {
-                acc_force += force_location.get_force_for_position( pos.translation )
-            });
-            acc_force
+        let ext_f = if *gct == GridCellType::Fluid || floating_solid {
+            cell_external_force( pos.translation, gravity, ext_forces.iter(), 0.0 )
         } else {
             Vec3::ZERO
         };
@@ -346,31 +662,252 @@ pub fn grid_initialize_external_forces(
     });
 }
 
+/// change of the summed force of the time varying volumes at position between two simulation times
+pub fn oscillating_force_delta<'a>(volumes: impl Iterator<Item = &'a ExternalForceVolume>,
+                                   position: Vec3, previous: f32, time: f32) -> Vec3 {
+    volumes
+        .filter(| volume | volume.direction.is_time_varying())
+        .map(| volume | volume.get_force_for_position( position, time ) - volume.get_force_for_position( position, previous ))
+        .sum()
+}
+
+/// let the oscillating force volumes (wavemakers) follow the simulation time
+///   grid_initialize_external_forces applied them at time 0; every step adds the change since the last one
+pub fn grid_update_oscillating_forces(
+    sim_time: Res<SimTime>,
+    mut applied: ResMut<AppliedForces>,
+    mut cells: Query<(&Transform, &mut GridCellAccumulatedForce, &GridCellType)>,
+    ext_forces: Query< &ExternalForceVolume >,
+) {
+    if !ext_forces.iter().any(| volume | volume.direction.is_time_varying()) {
+        return;
+    }
+    // the steps are as long as the (possibly adaptive) simulation time step
+    let previous = applied.time;
+    let time = previous + sim_time.dt;
+    cells.par_iter_mut().for_each(| (pos, mut force, gct) | {
+        if *gct == GridCellType::Fluid {
+            force.0 += Vec3A::from( oscillating_force_delta( ext_forces.iter(), pos.translation, previous, time ) );
+        }
+    });
+    applied.time = time;
+}
+
+/// share of the gravity applied in the given simulation step (counting from 1) while ramping up over ramp_frames
+pub fn gravity_ramp(frame: u32, ramp_frames: u32) -> f32 {
+    if ramp_frames == 0 {
+        return 1.0;
+    }
+    (frame as f32 / ramp_frames as f32).min( 1.0 )
+}
+
+/// keep the accumulated forces of the fluid cells in sync when gravity changes at runtime
+///   also ramps gravity up over the first GRAVITY_RAMP_FRAMES steps to let a full tank settle gently
+///   and follows the arrow key tilt of the sloshing demo
+pub fn grid_update_gravity(
+    constants: Res<Constants>,
+    tilt: Res<GravityTilt>,
+    mut frame: Local<u32>,
+    mut applied: ResMut<AppliedForces>,
+    mut cells: Query<(&mut GridCellAccumulatedForce, &GridCellType)>,
+) {
+    *frame = frame.saturating_add( 1 );
+    // grid_initialize_external_forces starts out with the full, untilted gravity
+    let previous = applied.gravity( &constants );
+    let gravity = tilt.apply( constants.gravity() ) * gravity_ramp( *frame, constants.GRAVITY_RAMP_FRAMES );
+    if previous == gravity {
+        return;
+    }
+    let delta = Vec3A::from( gravity - previous );
+    cells.par_iter_mut().for_each(| (mut force, gct) | {
+        if *gct == GridCellType::Fluid {
+            force.0 += delta;
+        }
+    });
+    applied.gravity = Some( gravity );
+}
+
+/// classifies the next chunk of grid cells against the decoration colliders
+///   runs once per frame until all cells are done, then moves on to spawning particles
 pub fn grid_collider_setup(
-    mut cells: Query<(&mut GridCellType, &Transform, &mut ColliderNormals)>,
+    constants: Res<Constants>,
+    mut progress: ResMut<SetupProgress>,
+    mut next_state: ResMut<NextState<SetupState>>,
+    mut cells: Query<(&mut GridCellType, &Transform, &mut ColliderNormals, &GridCellIndex)>,
     colliders: Query<(&Transform, &Collider), With<DecorationTag>>,
 ) {
-    let dist_thresh = 0.5;
+    let chunk = progress.next_chunk( COLLIDER_CELLS_PER_FRAME );
 
-    // walk through all cells
-    cells.iter_mut().for_each( | (mut gct, pos, mut cnorm) | {
+    // walk through the cells of this chunk
+    cells.iter_mut().filter(| (_, _, _, idx) | chunk.contains( &idx.0 )).for_each( | (mut gct, pos, mut cnorm, _) | {
 
         // and check for all colliders whether the cell touches that collider in any way
-        colliders.iter().for_each(| (cloc, c) | {
-            let (_sc, ro, _tr) = (cloc.scale, cloc.rotation, cloc.translation);
-            let ccenter = pos.translation;
-            if let Some( _pp ) = c.project_point_with_max_dist( cloc.translation, ro,
-                                                                ccenter, false,
-                                                                dist_thresh) {
-                // println!("GRID: {} close to collider at: {}: {}", pos.translation, pp.is_inside, pp.point );
-                *gct = GridCellType::Solid;
-            } else if let Some( pp ) = c.project_point_with_max_dist( cloc.translation, ro,
-                                                                      ccenter, false,
-                                                                      dist_thresh*2.0 /*f32::sqrt(2.0)*0.75*/) {
-                cnorm.0.push( Vec3A::from( (pp.point - ccenter).normalize_or_zero() ) );
+        let (solid, normals) = classify_against_colliders( pos.translation, colliders.iter(), constants.SOLID_COVERAGE );
+        if solid {
+            *gct = GridCellType::Solid;
+        }
+        cnorm.0.extend( normals );
+    });
+
+    if progress.is_done() {
+        progress.restart();
+        next_state.set( SetupState::SpawningParticles );
+    }
+}
+
+/// reclassify the grid after decorations got moved or removed at runtime
+///   cells fall back to their boundary type and get tested against the current decoration shapes;
+///   the first run after setup is skipped because grid_collider_setup already did the work;
+///   cells that change their type get the external forces of their new type and the wall lists around them get rebuilt
+pub fn re_voxelize_decorations(
+    mut primed: Local<bool>,
+    constants: Res<Constants>,
+    grid: Res<Grid>,
+    applied: Res<AppliedForces>,
+    changed: Query<(), (With<DecorationTag>, Changed<Transform>)>,
+    mut removed: RemovedComponents<DecorationTag>,
+    decorations: Query<(&Transform, AnyOf<(&Collider, &DecorationShape)>, Has<FloatingDecoration>), With<DecorationTag>>,
+    ext_forces: Query< &ExternalForceVolume >,
+    mut cells: Query<(Entity, &mut GridCellType, &Transform, &mut ColliderNormals, &GridCellIndex,
+                      &mut GridCellAccumulatedForce, Option<&mut GridFluidNeighbors>),
+                     Without<DecorationTag>>,
+    mut commands: Commands,
+) {
+    let removed_any = removed.read().count() > 0;
+    if !*primed {
+        *primed = true;
+        return;
+    }
+    if changed.is_empty() && !removed_any {
+        return;
+    }
+
+    // decorations keep their shape after the physics collider got removed
+    let shapes: Vec<(Transform, Collider, bool)> = decorations.iter()
+        .filter_map(| (cloc, (collider, shape), floating) | collider.or( shape.map(| s | &s.0) ).map(| c | (*cloc, c.clone(), floating)))
+        .collect();
+    let gravity = applied.gravity( &constants );
+
+    let mut fluid = vec![ false; grid.cell_count() ];
+    let mut affected = vec![ false; grid.cell_count() ];
+    cells.iter_mut().for_each(| (_, mut gct, pos, mut cnorm, idx, mut force, _) | {
+        let (solid, normals) = classify_against_colliders( pos.translation, shapes.iter().map(| (t, c, _) | (t, c)),
+                                                       constants.SOLID_COVERAGE );
+        let cell_type = if solid { GridCellType::Solid } else { grid.initial_cell_type( grid.to_3d( idx.0 ) ) };
+        if cell_type != *gct {
+            // same as grid_initialize_external_forces: fluid and cells under a floating decoration feel the forces
+            let floating_solid = solid && classify_against_colliders(
+                pos.translation, shapes.iter().filter(| (_, _, floating) | *floating ).map(| (t, c, _) | (t, c)),
+                constants.SOLID_COVERAGE ).0;
+            force.0 = if cell_type == GridCellType::Fluid || floating_solid {
+                Vec3A::from( cell_external_force( pos.translation, gravity, ext_forces.iter(), applied.time ) )
+            } else {
+                Vec3A::ZERO
+            };
+            *gct = cell_type;
+            // the wall lists of the cell and of all its neighbors depend on the change
+            let xyz = grid.to_3d( idx.0 ).as_ivec3();
+            for offset in (0..27).map(| n | IVec3::new( n % 3 - 1, n / 3 % 3 - 1, n / 9 - 1 )) {
+                let neighbor = xyz + offset;
+                if neighbor.cmpge( IVec3::ZERO ).all() {
+                    if let Some( nidx ) = grid.try_index_of_vec( &neighbor.as_uvec3() ) {
+                        affected[ nidx ] = true;
+                    }
+                }
+            }
+        }
+        cnorm.0 = normals;
+        fluid[ idx.0 ] = *gct == GridCellType::Fluid;
+    });
+
+    // walls only hand momentum to cells that are still fluid
+    cells.iter_mut()
+        .filter(| (_, _, _, _, idx, _, _) | affected[ idx.0 ])
+        .for_each(| (cell, gct, _, _, idx, _, neighbors) | {
+            let solid = *gct == GridCellType::Solid;
+            match neighbors {
+                Some( mut neighbors ) if solid =>
+                    neighbors.0 = wall_fluid_neighbors( &grid, grid.to_3d( idx.0 ), | ocidx | fluid[ ocidx ] ),
+                None if solid => {
+                    commands.entity( cell ).insert(
+                        GridFluidNeighbors( wall_fluid_neighbors( &grid, grid.to_3d( idx.0 ), | ocidx | fluid[ ocidx ] ) ));
+                },
+                Some( _ ) => {
+                    commands.entity( cell ).remove::<GridFluidNeighbors>();
+                },
+                None => (),
             }
         });
+    info!("re-voxelized {} decorations", shapes.len());
+}
+
+/// world (grid) space bounding box of a collider
+pub fn collider_bounds(transform: &Transform, collider: &Collider) -> (Vec3, Vec3) {
+    let aabb = collider.raw.compute_local_aabb();
+    let center = Vec3::new( aabb.center().x, aabb.center().y, aabb.center().z );
+    let half = Vec3::new( aabb.half_extents().x, aabb.half_extents().y, aabb.half_extents().z );
+    let center = transform.transform_point( center );
+    // extent of the rotated and scaled box along the world axes
+    let rotation = Mat3::from_quat( transform.rotation );
+    let half = rotation.abs() * (half * transform.scale.abs());
+    (center - half, center + half)
+}
+
+/// force of the fluid on a body from the cells it covers, given as (momentum, submerged)
+///   the body takes over the momentum that ran into it within dt and
+///   every submerged cell displaces a cell of water at rest (buoyancy)
+pub fn floating_body_force(body_cells: impl Iterator<Item = (Vec3A, bool)>, rest_mass: f32, gravity: Vec3A, dt: f32) -> Vec3A {
+    let (momentum, submerged) = body_cells.fold( (Vec3A::ZERO, 0), | (momentum, submerged), (cell_momentum, wet) | {
+        (momentum + cell_momentum, submerged + wet as u32)
     });
+    momentum / dt - gravity * rest_mass * submerged as f32
+}
+
+/// a cell of a body is under water if one of its face neighbors outside the body holds at least half a cell of water
+fn body_cell_submerged(grid: &Grid, idx: usize, mass: &[f32], in_body: &HashSet<usize>, rest_mass: f32) -> bool {
+    let xyz = grid.to_3d( idx ).as_ivec3();
+    [ IVec3::X, IVec3::NEG_X, IVec3::Y, IVec3::NEG_Y, IVec3::Z, IVec3::NEG_Z ].iter()
+        .map(| offset | xyz + *offset )
+        .filter(| neighbor | neighbor.cmpge( IVec3::ZERO ).all())
+        .filter_map(| neighbor | grid.try_index_of_vec( &neighbor.as_uvec3() ))
+        .any(| nidx | !in_body.contains( &nidx ) && mass[ nidx ] >= 0.5 * rest_mass )
+}
+
+/// push the floating decorations with the momentum of the fluid that runs into them and lift them by buoyancy
+///   runs between the grid update (cells hold momentum) and update_grid_cells (solid cells drop it);
+///   only the cells covered by the body count, the moving body makes re_voxelize_decorations update them
+pub fn push_floating_decorations(
+    constants: Res<Constants>,
+    time: Res<SimTime>,
+    grid: Res<Grid>,
+    applied: Res<AppliedForces>,
+    cells: Query<(&Transform, &FluidQuantityMass, &FluidParticleVelocity, &GridCellIndex), With<GridCellType>>,
+    mut bodies: Query<(&Transform, &Collider, &mut ExternalForce), With<FloatingDecoration>>,
+) {
+    if bodies.is_empty() {
+        return;
+    }
+    let mut mass = vec![ 0.0; grid.cell_count() ];
+    cells.iter().for_each(| (_, cell_mass, _, idx) | mass[ idx.0 ] = cell_mass.0 );
+    // grid cells have unit volume
+    let rest_mass = constants.FLUID_MODEL.rest_density;
+    let gravity = Vec3A::from( applied.gravity( &constants ) );
+
+    for (transform, collider, mut force) in bodies.iter_mut() {
+        // cells are solid up to half a cell outside of the collider
+        let (min, max) = collider_bounds( transform, collider );
+        let (min, max) = (min - Vec3::splat( 0.5 ), max + Vec3::splat( 0.5 ));
+        let body: HashMap<usize, Vec3A> = cells.iter()
+            .filter(| (pos, _, _, _) | pos.translation.cmpge( min ).all() && pos.translation.cmple( max ).all())
+            .filter(| (pos, _, _, _) | classify_against_colliders(
+                pos.translation, std::iter::once( (transform, collider) ), constants.SOLID_COVERAGE ).0)
+            .map(| (_, _, momentum, idx) | (idx.0, momentum.0))
+            .collect();
+        let in_body: HashSet<usize> = body.keys().copied().collect();
+        force.force = Vec3::from( floating_body_force(
+            body.iter().map(| (&idx, &momentum) | (momentum, body_cell_submerged( &grid, idx, &mass, &in_body, rest_mass ))),
+            rest_mass, gravity, time.dt ));
+    }
 }
 
 pub fn reset_fluid_grid_cells(
@@ -407,61 +944,183 @@ pub fn wall_to_active_momentum(
     )
 }
 
+/// divergence of the velocity field by central differences (grid units); zero at the grid border
+pub fn divergence(grid: &Grid, velo: &[Vec3A]) -> Vec<f32> {
+    let mut div = vec![ 0.0; velo.len() ];
+    let size = grid.grid_size();
+    for z in 1..size.z as usize - 1 {
+        for y in 1..size.y as usize - 1 {
+            for x in 1..size.x as usize - 1 {
+                div[ grid.index_of( x, y, z ) ] =
+                    (velo[ grid.index_of( x + 1, y, z ) ].x - velo[ grid.index_of( x - 1, y, z ) ].x
+                     + velo[ grid.index_of( x, y + 1, z ) ].y - velo[ grid.index_of( x, y - 1, z ) ].y
+                     + velo[ grid.index_of( x, y, z + 1 ) ].z - velo[ grid.index_of( x, y, z - 1 ) ].z) * 0.5;
+            }
+        }
+    }
+    div
+}
+
+/// curl of the velocity field by central differences (grid units); zero at the grid border
+pub fn vorticity(grid: &Grid, velo: &[Vec3A]) -> Vec<Vec3A> {
+    let mut curl = vec![ Vec3A::ZERO; velo.len() ];
+    let size = grid.grid_size();
+    for z in 1..size.z as usize - 1 {
+        for y in 1..size.y as usize - 1 {
+            for x in 1..size.x as usize - 1 {
+                let ddx = (velo[ grid.index_of( x + 1, y, z ) ] - velo[ grid.index_of( x - 1, y, z ) ]) * 0.5;
+                let ddy = (velo[ grid.index_of( x, y + 1, z ) ] - velo[ grid.index_of( x, y - 1, z ) ]) * 0.5;
+                let ddz = (velo[ grid.index_of( x, y, z + 1 ) ] - velo[ grid.index_of( x, y, z - 1 ) ]) * 0.5;
+                curl[ grid.index_of( x, y, z ) ] = Vec3A::new( ddy.z - ddz.y, ddz.x - ddx.z, ddx.y - ddy.x );
+            }
+        }
+    }
+    curl
+}
+
+/// vorticity confinement force per cell: epsilon * (N x curl) with N pointing towards higher vorticity
+///   counteracts the numerical dissipation of small eddies
+pub fn vorticity_confinement(grid: &Grid, velo: &[Vec3A], epsilon: f32) -> Vec<Vec3A> {
+    let curl = vorticity( grid, velo );
+    let mut force = vec![ Vec3A::ZERO; velo.len() ];
+    let size = grid.grid_size();
+    for z in 1..size.z as usize - 1 {
+        for y in 1..size.y as usize - 1 {
+            for x in 1..size.x as usize - 1 {
+                let magnitude = | x: usize, y: usize, z: usize | curl[ grid.index_of( x, y, z ) ].length();
+                let gradient = Vec3A::new(
+                    magnitude( x + 1, y, z ) - magnitude( x - 1, y, z ),
+                    magnitude( x, y + 1, z ) - magnitude( x, y - 1, z ),
+                    magnitude( x, y, z + 1 ) - magnitude( x, y, z - 1 ) ) * 0.5;
+                let idx = grid.index_of( x, y, z );
+                force[ idx ] = gradient.normalize_or_zero().cross( curl[ idx ] ) * epsilon;
+            }
+        }
+    }
+    force
+}
+
+/// Jacobi-style relaxation that pushes the velocity of the fluid cells down the gradient of the divergence
+///   every pass reduces the summed squared divergence of the fluid cells; other cells keep their velocity
+pub fn relax_divergence(grid: &Grid, velo: &mut [Vec3A], fluid: &[bool], iterations: usize) {
+    let size = grid.grid_size();
+    for _ in 0..iterations {
+        let mut div = divergence( grid, velo );
+        div.iter_mut().zip( fluid ).for_each(| (d, &is_fluid) | if !is_fluid { *d = 0.0 } );
+        for z in 1..size.z as usize - 1 {
+            for y in 1..size.y as usize - 1 {
+                for x in 1..size.x as usize - 1 {
+                    let idx = grid.index_of( x, y, z );
+                    if !fluid[ idx ] {
+                        continue;
+                    }
+                    // 1/6 keeps the step below the stability limit of the 3D central difference stencil
+                    velo[ idx ] += Vec3A::new(
+                        div[ grid.index_of( x + 1, y, z ) ] - div[ grid.index_of( x - 1, y, z ) ],
+                        div[ grid.index_of( x, y + 1, z ) ] - div[ grid.index_of( x, y - 1, z ) ],
+                        div[ grid.index_of( x, y, z + 1 ) ] - div[ grid.index_of( x, y, z - 1 ) ] ) / 6.0;
+                }
+            }
+        }
+    }
+}
+
+/// keep the grid velocity before forces and boundaries get applied; the FLIP update uses the difference
+pub fn snapshot_grid_velocity(
+    constants: Res<Constants>,
+    mut grid: ResMut<Grid>,
+    cells: Query<(&FluidQuantityMass, &FluidParticleVelocity, &GridCellIndex), With<GridCellType>>,
+) {
+    if constants.FLIP_RATIO <= 0.0 {
+        return;
+    }
+    let prev = grid.get_prev_velo_mut();
+    cells.iter().for_each(| (mass, momentum, idx) | {
+        // cells hold momentum until update_grid_cells
+        prev[ idx.0 ] = if mass.0 > 0.0 { momentum.0 / mass.0 } else { Vec3A::ZERO };
+    });
+}
+
+/// velocity of an Outlet cell: damp the incoming velocity and always drain downward
+pub fn outlet_velocity(velocity: Vec3A) -> Vec3A {
+    let damped = velocity * (1.0 - OUTLET_DAMPING);
+    Vec3A::new( damped.x, damped.y.min( 0.0 ) - OUTLET_DRAIN_SPEED, damped.z )
+}
+
 pub fn update_grid_cells(
     constants: Res<Constants>,
+    time: Res<SimTime>,
+    grid: Res<Grid>,
     mut cells: Query<(&FluidQuantityMass,
                       &mut FluidParticleVelocity,
                       &GridCellAccumulatedForce,
                       &GridCellType,
                       &ColliderNormals,
+                      &GridCellIndex,
     )>,
 ) {
     let _lookahead = 1.0;
 
     cells.par_iter_mut().for_each(
-        | ( mass, mut vel, ext_f, gct, cnorm ) | {
+        | ( mass, mut vel, ext_f, gct, cnorm, _ ) | {
 
             if *gct == GridCellType::Solid {
                 vel.0 = Vec3A::ZERO;
+            } else if *gct == GridCellType::Outlet {
+                // the drain takes over: no external forces or collider handling
+                let incoming = if mass.0 > 0.0 { vel.0 / mass.0 } else { Vec3A::ZERO };
+                vel.0 = outlet_velocity( incoming );
             } else {
                 // convert momentum to velocity and apply external force and dampening
                 if mass.0 > 0.0 {
                     vel.0 *= 1.0/mass.0;
-                    vel.0 += ext_f.0 * constants.WORLD_DT;
+                    vel.0 += ext_f.0 * time.dt;
 
                     if ! cnorm.0.is_empty() {
-                        // collect all projected velocities
-                        // let mut proj_vect = vec![];
-                        let mut vel_new = vel.0;
-                        cnorm.0.iter().for_each(| &norm | {
-                            if norm != Vec3A::ZERO {
-                                // dot product: projecting velocity onto the normal
-                                // (this is going to be the part of the velocity that we're going to loose because of the collision)
-                                // And make sure we're only considering situations where the velocity goes against the direction of the normal
-                                let dot_prod = vel_new.dot( norm );
-                                if dot_prod < 0.0 {
-                                    let delta = norm * dot_prod;  // delta is the result of projecting vel.0 onto the neg. norm unit vector
-                                    vel_new -= delta;             // subtract the projected amount of velocity from the remaining velocity
-                                    // proj_vect.push(norm * dot_prod);
-                                }
-                            }
-                        });
-                        let delta_v_factor = f32::sqrt( vel.0.length_squared() / vel_new.length_squared() ) * 0.5;
-                        // let delta_v_factor = if !cnorm.0.is_empty() && vel_new != Vec3::ZERO {
-                        //     if vel.0.length() < vel_new.length() {
-                        //         println!("{} < {}", vel.0.length(), vel_new.length() );
-                        //     }
-                        //     assert!( vel.0.length() >= vel_new.length() * 0.999 );
-                        //     (vel.0.length() - vel_new.length() * 0.999) / vel_new.length()
-                        // } else {
-                        //     1.0
-                        // };
-                        vel.0 = vel_new * delta_v_factor; // * constants.DEFAULT_DAMPENING;
+                        let mode = constants.BOUNDARY_MODE;
+                        let vel_new = cnorm.0.iter()
+                            .filter(| &&norm | norm != Vec3A::ZERO)
+                            .fold( vel.0, | v, &norm | mode.apply( v, norm ) );
+                        vel.0 = match mode {
+                            // keep some of the speed that got lost against the collider
+                            BoundaryMode::Separate => vel_new * f32::sqrt( vel.0.length_squared() / vel_new.length_squared() ) * 0.5,
+                            _ => vel_new,
+                        };
                     }
                 }
             }
         }
     );
+
+    if constants.VORTICITY_CONFINEMENT > 0.0 {
+        let mut velo = vec![ Vec3A::ZERO; grid.cell_count() ];
+        cells.iter().for_each(| (_, vel, _, gct, _, idx) | {
+            if *gct == GridCellType::Fluid {
+                velo[ idx.0 ] = vel.0;
+            }
+        });
+        let force = vorticity_confinement( &grid, &velo, constants.VORTICITY_CONFINEMENT );
+        cells.par_iter_mut().for_each(| (mass, mut vel, _, gct, _, idx) | {
+            if *gct == GridCellType::Fluid && mass.0 > 0.0 {
+                vel.0 += force[ idx.0 ] * time.dt;
+            }
+        });
+    }
+
+    if constants.RELAX_ITERS > 0 {
+        let mut velo = vec![ Vec3A::ZERO; grid.cell_count() ];
+        let mut fluid = vec![ false; grid.cell_count() ];
+        cells.iter().for_each(| (mass, vel, _, gct, _, idx) | {
+            velo[ idx.0 ] = vel.0;
+            fluid[ idx.0 ] = *gct == GridCellType::Fluid && mass.0 > 0.0;
+        });
+        relax_divergence( &grid, &mut velo, &fluid, constants.RELAX_ITERS );
+        cells.par_iter_mut().for_each(| (_, mut vel, _, _, _, idx) | {
+            if fluid[ idx.0 ] {
+                vel.0 = velo[ idx.0 ];
+            }
+        });
+    }
 }
 
 
@@ -508,7 +1167,7 @@ pub fn show_grid_cells(
                 commands.entity(item).insert((
                     Mesh3d( grid_center_mesh.clone() ),
                     MeshMaterial3d( match gct {
-                        GridCellType::Fluid => grid_fluid_material_hdl.clone(),
+                        GridCellType::Fluid | GridCellType::Outlet => grid_fluid_material_hdl.clone(),
                         GridCellType::Air => grid_air_material_hdl.clone(),
                         GridCellType::Solid => grid_center_material_hdl.clone(),
                     }),
@@ -559,6 +1218,154 @@ mod test
         assert_eq!(grid.cell_count(), expected_cells as usize);
     }
 
+    #[test]
+    fn test_index_of_at_edges() {
+        let grid = Grid::new( UVec3::new( 6, 4, 5 ), 1.0 );
+        let size = *grid.grid_size();
+        let (x, y, z) = (size.x as usize, size.y as usize, size.z as usize);
+
+        // inside: same as index_of, incl. the corners
+        assert_eq!( grid.try_index_of( 0, 0, 0 ), Some( 0 ) );
+        assert_eq!( grid.try_index_of( x - 1, y - 1, z - 1 ), Some( grid.cell_count() - 1 ) );
+        assert_eq!( grid.try_index_of( x - 1, 2, 1 ), Some( grid.index_of( x - 1, 2, 1 ) ) );
+
+        // one past any edge is outside, also the neighbor below 0 (wrapped u32)
+        assert_eq!( grid.try_index_of( x, 0, 0 ), None );
+        assert_eq!( grid.try_index_of( 0, y, 0 ), None );
+        assert_eq!( grid.try_index_of( 0, 0, z ), None );
+        assert_eq!( grid.try_index_of_vec( &UVec3::new( (0i32 - 1) as u32, 1, 1 ) ), None );
+
+        // index_of instead wraps into the next row or clamps to the last cell
+        assert_eq!( grid.index_of( x, 0, 0 ), grid.index_of( 0, 1, 0 ) );
+        assert_eq!( grid.index_of( x, y, z ), grid.cell_count() - 1 );
+    }
+
+    #[test]
+    fn test_sampling_at_the_edge_ignores_outside_cells() {
+        let mut grid = Grid::new( UVec3::new( 6, 4, 5 ), 1.0 );
+        grid.get_tmp_velo_mut().iter_mut().for_each(| v | *v = Vec3A::X );
+        // the last cell would otherwise pick up the neighbors outside of the grid
+        let last = grid.cell_count() - 1;
+        grid.get_tmp_velo_mut()[ last ] = Vec3A::splat( 100.0 );
+
+        let corner = Vec3A::new( 0.5, 0.5, 0.5 );
+        let weights = quadratic_interpolation_weights( Vec3A::ZERO );
+        let (velocity, _) = grid.sample_velocity_weighted( corner, weights );
+        // only the in-grid part of the kernel contributes (weights 0.75 + 0.125 per axis)
+        assert!( (velocity - Vec3A::X * 0.875f32.powi( 3 )).length() < 1e-5, "{}", velocity );
+    }
+
+    #[test]
+    fn test_vorticity_confinement_keeps_vortex() {
+        let grid = Grid::new( UVec3::new( 16, 4, 16 ), 1.0 );
+        let center = Vec3A::new( 8.5, 0.0, 8.5 );
+        // gaussian swirl around the y-axis
+        let swirl: Vec<Vec3A> = (0..grid.cell_count()).map(| idx | {
+            let r = grid.to_3d( idx ).as_vec3a() + 0.5 - center;
+            let r = Vec3A::new( r.x, 0.0, r.z );
+            Vec3A::new( -r.z, 0.0, r.x ) * (-r.length_squared() / 8.0).exp()
+        }).collect();
+
+        // diffuse the velocity as stand-in for the numerical dissipation
+        let run = | epsilon: f32 | {
+            let mut velo = swirl.clone();
+            for _ in 0..20 {
+                let force = vorticity_confinement( &grid, &velo, epsilon );
+                let previous = velo.clone();
+                let size = grid.grid_size();
+                for z in 1..size.z as usize - 1 {
+                    for y in 1..size.y as usize - 1 {
+                        for x in 1..size.x as usize - 1 {
+                            let avg = (previous[ grid.index_of( x + 1, y, z ) ] + previous[ grid.index_of( x - 1, y, z ) ]
+                                       + previous[ grid.index_of( x, y, z + 1 ) ] + previous[ grid.index_of( x, y, z - 1 ) ]) * 0.25;
+                            let idx = grid.index_of( x, y, z );
+                            velo[ idx ] = previous[ idx ] * 0.5 + avg * 0.5 + force[ idx ] * 0.1;
+                        }
+                    }
+                }
+            }
+            vorticity( &grid, &velo ).iter().map(| w | w.length()).sum::<f32>()
+        };
+        let initial = vorticity( &grid, &swirl ).iter().map(| w | w.length()).sum::<f32>();
+        let dissipated = run( 0.0 );
+        let confined = run( 0.5 );
+        assert!( dissipated < initial );
+        assert!( confined > dissipated );
+    }
+
+    #[test]
+    fn test_relaxation_reduces_divergence() {
+        let grid = Grid::new( UVec3::new( 12, 12, 12 ), 1.0 );
+        let center = Vec3A::splat( 5.5 );
+        // gaussian sink: the fluid gets squeezed towards the center
+        let squeezed: Vec<Vec3A> = (0..grid.cell_count()).map(| idx | {
+            let r = grid.to_3d( idx ).as_vec3a() - center;
+            -r * 0.5 * (-r.length_squared() / 8.0).exp()
+        }).collect();
+        let size = grid.grid_size();
+        let fluid: Vec<bool> = (0..grid.cell_count()).map(| idx | {
+            let xyz = grid.to_3d( idx );
+            xyz.cmpgt( UVec3::ZERO ).all() && xyz.cmplt( *size - 1 ).all()
+        }).collect();
+
+        let max_divergence = | iterations: usize | {
+            let mut velo = squeezed.clone();
+            relax_divergence( &grid, &mut velo, &fluid, iterations );
+            divergence( &grid, &velo ).iter().fold( 0.0f32, | m, d | m.max( d.abs() ) )
+        };
+        let initial = max_divergence( 0 );
+        let few = max_divergence( 4 );
+        let many = max_divergence( 16 );
+        assert!( few < initial, "{} !< {}", few, initial );
+        assert!( many < 0.25 * few, "{} !< {}", many, few );
+    }
+
+    #[test]
+    fn test_air_layers() {
+        let count_air = | grid: &Grid | (0..grid.grid_size().y)
+            .filter(|&y| grid.initial_cell_type( UVec3::new( 3, y, 3 ) ) == GridCellType::Air)
+            .count();
+
+        // default keeps a single air layer within the original padding
+        let grid = Grid::new( UVec3::new( 20, 10, 10 ), 1.0 );
+        assert_eq!( grid.grid_size().y, 14 );
+        assert_eq!( count_air( &grid ), 1 );
+
+        let grid = Grid::with_air_layers( UVec3::new( 20, 10, 10 ), 1.0, 3 );
+        assert_eq!( grid.grid_size().y, 14 );
+        assert_eq!( count_air( &grid ), 3 );
+        assert_eq!( grid.initial_cell_type( UVec3::new( 3, 10, 3 ) ), GridCellType::Fluid );
+
+        // more air layers than padding extend the grid
+        let grid = Grid::with_air_layers( UVec3::new( 20, 10, 10 ), 1.0, 5 );
+        assert_eq!( grid.grid_size().y, 16 );
+        assert_eq!( count_air( &grid ), 5 );
+    }
+
+    #[test]
+    fn test_world_offset() {
+        let offset = Vec3::new( 100., -20., 3.5 );
+        let grid = Grid::new( UVec3::new( 160, 80, 75 ), 5. );
+        let shifted = Grid::new( UVec3::new( 160, 80, 75 ), 5. ).with_origin( offset );
+
+        let cell = grid.to_3d( 1234 ).as_vec3();
+        assert_eq!( shifted.to_world_coord( cell ) - grid.to_world_coord( cell ), offset );
+    }
+
+    #[test]
+    fn test_world_to_grid() {
+        let scale = Scaler::from_vecs( Vec3::new( 10., 4., 8. ), Vec3::new( 20., 4., 2. ) );
+        let grid = Grid::with_scaler( UVec3::new( 40, 16, 8 ), scale, 1 )
+            .with_origin( Vec3::new( 100., -20., 3.5 ) );
+        // 2 x 1 x 0.25 world units per cell
+        assert_eq!( *grid.grid_size(), UVec3::new( 22, 20, 34 ) );
+
+        let point = Vec3::new( 3.25, 7.5, 12.0 );
+        let world = grid.to_world_coord( point );
+        assert_eq!( world, Vec3::new( 106.5, -12.5, 6.5 ) );
+        assert!( grid.world_to_grid( world ).abs_diff_eq( point, 1e-5 ) );
+    }
+
     #[test]
     fn test_normals() {
         let cn = Vec3::new( 0.5, 0.0, 0.0).normalize();
@@ -567,4 +1374,323 @@ mod test
         println!("dot: {}, {}", vel.dot( cn ), cn );
         println!("projected: {}", vel - vel.dot( cn ) * cn);
     }
+
+    #[test]
+    fn test_gravity_ramp() {
+        assert_eq!( gravity_ramp( 7, 0 ), 1.0 );
+        assert_eq!( gravity_ramp( 1, 4 ), 0.25 );
+        assert_eq!( gravity_ramp( 9, 4 ), 1.0 );
+
+        let mut world = World::new();
+        world.init_resource::<Constants>();
+        world.init_resource::<GravityTilt>();
+        world.init_resource::<AppliedForces>();
+        world.resource_mut::<Constants>().GRAVITY_RAMP_FRAMES = 10;
+        let gravity = world.resource::<Constants>().DEFAULT_GRAVITY;
+        // as set up by grid_initialize_external_forces
+        let fluid = world.spawn(( GridCellAccumulatedForce( Vec3A::Y * gravity ), GridCellType::Fluid )).id();
+        let solid = world.spawn(( GridCellAccumulatedForce( Vec3A::ZERO ), GridCellType::Solid )).id();
+
+        let mut schedule = Schedule::default();
+        schedule.add_systems( grid_update_gravity );
+        let applied = | world: &World, cell: Entity | world.get::<GridCellAccumulatedForce>( cell ).unwrap().0.y;
+        schedule.run( &mut world );
+        assert!( (applied( &world, fluid ) - 0.1 * gravity).abs() < 1e-5 );
+        for _ in 1..5 {
+            schedule.run( &mut world );
+        }
+        assert!( (applied( &world, fluid ) - 0.5 * gravity).abs() < 1e-5, "{}", applied( &world, fluid ) );
+        for _ in 5..20 {
+            schedule.run( &mut world );
+        }
+        assert!( (applied( &world, fluid ) - gravity).abs() < 1e-5 );
+        assert_eq!( applied( &world, solid ), 0.0 );
+    }
+
+    #[test]
+    fn test_external_forces_use_gravity_vector() {
+        let mut world = World::new();
+        world.init_resource::<Constants>();
+        let gravity = Vec3::new( 1.5, -9.5, -0.5 );
+        world.resource_mut::<Constants>().GRAVITY = Some( gravity );
+        let fluid = world.spawn(( Transform::from_xyz( 3.0, 3.0, 3.0 ), GridCellType::Fluid )).id();
+        let solid = world.spawn(( Transform::from_xyz( 0.0, 0.0, 0.0 ), GridCellType::Solid )).id();
+
+        let mut schedule = Schedule::default();
+        schedule.add_systems( grid_initialize_external_forces );
+        schedule.run( &mut world );
+        let applied = | world: &World, cell: Entity | world.get::<GridCellAccumulatedForce>( cell ).unwrap().0;
+        assert_eq!( applied( &world, fluid ), Vec3A::from( gravity ) );
+        assert_eq!( applied( &world, solid ), Vec3A::ZERO );
+
+        // without the vector gravity points down with DEFAULT_GRAVITY
+        world.resource_mut::<Constants>().GRAVITY = None;
+        let default_gravity = world.resource::<Constants>().DEFAULT_GRAVITY;
+        schedule.run( &mut world );
+        assert_eq!( applied( &world, fluid ), Vec3A::Y * default_gravity );
+    }
+
+    #[test]
+    fn test_outlet_cells() {
+        let grid = Grid::new( UVec3::new( 20, 10, 10 ), 1.0 )
+            .with_outlets( vec![ ( Vec2::new( 0., 0. ), Vec2::new( 5., 4. ) ) ] );
+        assert_eq!( grid.initial_cell_type( UVec3::new( 2, 3, 2 ) ), GridCellType::Outlet );
+        assert_eq!( grid.initial_cell_type( UVec3::new( 5, 3, 2 ) ), GridCellType::Fluid );
+        // walls and air layers stay what they are
+        assert_eq!( grid.initial_cell_type( UVec3::new( 2, 0, 2 ) ), GridCellType::Solid );
+        assert_eq!( grid.initial_cell_type( UVec3::new( 2, 13, 2 ) ), GridCellType::Air );
+    }
+
+    #[test]
+    fn test_outlet_drains_downward() {
+        let grid = Grid::new( UVec3::new( 10, 10, 10 ), 1.0 );
+        let mut world = World::new();
+        world.init_resource::<Constants>();
+        world.insert_resource( SimTime { dt: 0.01 } );
+        let momenta = [
+            ( 1.0, Vec3A::new( 0.0, 5.0, 0.0 ) ),
+            ( 2.0, Vec3A::new( 3.0, 0.5, -1.0 ) ),
+            ( 0.5, Vec3A::new( 0.0, -1.0, 0.0 ) ),
+            ( 0.0, Vec3A::ZERO ),
+        ];
+        let cells: Vec<Entity> = momenta.iter().enumerate()
+            .map(| (i, &(mass, momentum)) | world.spawn((
+                FluidQuantityMass( mass ),
+                FluidParticleVelocity( momentum ),
+                GridCellAccumulatedForce( Vec3A::ZERO ),
+                GridCellType::Outlet,
+                ColliderNormals( vec![] ),
+                GridCellIndex( grid.index_of( 2, 2 + i, 2 ) ),
+            )).id())
+            .collect();
+        world.insert_resource( grid );
+
+        let mut schedule = Schedule::default();
+        schedule.add_systems( update_grid_cells );
+        schedule.run( &mut world );
+        for cell in cells {
+            let vel = world.get::<FluidParticleVelocity>( cell ).unwrap().0;
+            assert!( vel.y < 0.0, "{}", vel );
+        }
+        // horizontal motion gets damped
+        assert_eq!( outlet_velocity( Vec3A::new( 4.0, 0.0, -2.0 ) ), Vec3A::new( 2.0, -OUTLET_DRAIN_SPEED, -1.0 ) );
+    }
+
+    #[test]
+    fn test_floating_body_force() {
+        let gravity = Vec3A::new( 0.0, -10.0, 0.0 );
+        // momentum over dt, no weight of the water
+        let cells = [ (Vec3A::new( 1.0, 0.0, 0.0 ), false), (Vec3A::new( 0.5, 0.5, 0.0 ), false) ];
+        let force = floating_body_force( cells.into_iter(), 1.0, gravity, 0.1 );
+        assert!( force.abs_diff_eq( Vec3A::new( 15.0, 5.0, 0.0 ), 1e-5 ), "{}", force );
+        // two submerged cells displace two cells of water
+        let cells = [ (Vec3A::ZERO, true), (Vec3A::ZERO, true), (Vec3A::ZERO, false) ];
+        assert_eq!( floating_body_force( cells.into_iter(), 2.0, gravity, 0.1 ), Vec3A::new( 0.0, 40.0, 0.0 ) );
+        assert_eq!( floating_body_force( std::iter::empty(), 1.0, gravity, 0.1 ), Vec3A::ZERO );
+
+        let (min, max) = collider_bounds( &Transform::from_xyz( 5.0, 5.0, 5.0 ), &Collider::ball( 2.0 ) );
+        assert!( min.abs_diff_eq( Vec3::splat( 3.0 ), 1e-5 ) && max.abs_diff_eq( Vec3::splat( 7.0 ), 1e-5 ) );
+    }
+
+    #[test]
+    fn test_light_body_floats_up() {
+        let grid = Grid::new( UVec3::new( 10, 10, 10 ), 1.0 );
+        let mut world = World::new();
+        world.init_resource::<Constants>();
+        world.init_resource::<AppliedForces>();
+        world.insert_resource( SimTime { dt: 0.04 } );
+        let rest_mass = world.resource::<Constants>().FLUID_MODEL.rest_density;
+        let gravity = world.resource::<Constants>().gravity();
+        let coverage = world.resource::<Constants>().SOLID_COVERAGE;
+
+        let radius = 1.5;
+        let center = Vec3::new( 5.0, 5.0, 5.0 );
+        let body = ( Transform::from_translation( center ), Collider::ball( radius ) );
+        // still water up to y = 8, nothing inside the body
+        for idx in 0..grid.cell_count() {
+            let xyz = grid.to_3d( idx );
+            let position = xyz.as_vec3();
+            let inside = classify_against_colliders( position, std::iter::once( (&body.0, &body.1) ), coverage ).0;
+            let mass = if xyz.y <= 8 && !inside { rest_mass } else { 0.0 };
+            world.spawn(( Transform::from_translation( position ),
+                          FluidQuantityMass( mass ),
+                          FluidParticleVelocity( Vec3A::ZERO ),
+                          GridCellIndex( idx ),
+                          grid.initial_cell_type( xyz ) ));
+        }
+        world.insert_resource( grid );
+        let floating = world.spawn(( body.0, body.1, ExternalForce::default(), FloatingDecoration )).id();
+
+        let mut schedule = Schedule::default();
+        schedule.add_systems( push_floating_decorations );
+        schedule.run( &mut world );
+        let force = world.get::<ExternalForce>( floating ).unwrap().force;
+        // weight of a body with half the density of water
+        let weight = 0.5 * rest_mass * 4.0 / 3.0 * std::f32::consts::PI * radius.powi( 3 ) * gravity.length();
+        assert!( force.y > weight, "{} vs {}", force, weight );
+        assert!( force.x.abs() < 1e-3 && force.z.abs() < 1e-3 );
+    }
+
+    #[test]
+    fn test_moving_decoration_revoxelizes() {
+        let grid = Grid::new( UVec3::new( 10, 10, 10 ), 1.0 );
+        let mut world = World::new();
+        for idx in 0..grid.cell_count() {
+            let xyz = grid.to_3d( idx );
+            world.spawn(( Transform::from_translation( xyz.as_vec3() ),
+                          grid.initial_cell_type( xyz ),
+                          ColliderNormals( vec![] ),
+                          GridCellIndex( idx ),
+                          GridCellAccumulatedForce( Vec3A::ZERO ) ));
+        }
+        let cell_type = | world: &mut World, x: usize | {
+            let idx = world.resource::<Grid>().index_of( x, 5, 5 );
+            world.query::<(&GridCellType, &GridCellIndex)>().iter( world )
+                .find(| (_, i) | i.0 == idx ).unwrap().0.clone()
+        };
+        world.insert_resource( grid );
+        world.init_resource::<Constants>();
+        world.init_resource::<AppliedForces>();
+        let rock = world.spawn(( Transform::from_xyz( 3.0, 5.0, 5.0 ), Collider::ball( 0.3 ), DecorationTag )).id();
+
+        let mut schedule = Schedule::default();
+        schedule.add_systems( re_voxelize_decorations );
+        // first run only primes the system; the setup already classified the cells
+        schedule.run( &mut world );
+        assert_eq!( cell_type( &mut world, 3 ), GridCellType::Fluid );
+
+        world.get_mut::<Transform>( rock ).unwrap().translation = Vec3::new( 7.0, 5.0, 5.0 );
+        schedule.run( &mut world );
+        assert_eq!( cell_type( &mut world, 7 ), GridCellType::Solid );
+        assert_eq!( cell_type( &mut world, 3 ), GridCellType::Fluid );
+
+        world.get_mut::<Transform>( rock ).unwrap().translation = Vec3::new( 3.0, 5.0, 5.0 );
+        schedule.run( &mut world );
+        assert_eq!( cell_type( &mut world, 3 ), GridCellType::Solid );
+        assert_eq!( cell_type( &mut world, 7 ), GridCellType::Fluid );
+
+        world.despawn( rock );
+        schedule.run( &mut world );
+        assert_eq!( cell_type( &mut world, 3 ), GridCellType::Fluid );
+    }
+
+    #[test]
+    fn test_revoxelize_updates_forces_and_walls() {
+        let grid = Grid::new( UVec3::new( 10, 10, 10 ), 1.0 );
+        let mut world = World::new();
+        world.init_resource::<Constants>();
+        let gravity = world.resource::<Constants>().gravity();
+        // as left by setup_fluid_grid and grid_initialize_external_forces
+        for idx in 0..grid.cell_count() {
+            let xyz = grid.to_3d( idx );
+            let gct = grid.initial_cell_type( xyz );
+            let force = if gct == GridCellType::Fluid { Vec3A::from( gravity ) } else { Vec3A::ZERO };
+            let cell = world.spawn(( Transform::from_translation( xyz.as_vec3() ),
+                                     gct.clone(),
+                                     ColliderNormals( vec![] ),
+                                     GridCellIndex( idx ),
+                                     GridCellAccumulatedForce( force ) )).id();
+            if gct == GridCellType::Solid {
+                let neighbors = wall_fluid_neighbors( &grid, xyz, | ocidx | grid.initial_cell_type( grid.to_3d( ocidx ) ) == GridCellType::Fluid );
+                world.entity_mut( cell ).insert( GridFluidNeighbors( neighbors ) );
+            }
+        }
+        let cell_state = | world: &mut World, x: usize | {
+            let idx = world.resource::<Grid>().index_of( x, 5, 5 );
+            world.query::<(&GridCellType, &GridCellIndex, &GridCellAccumulatedForce, Option<&GridFluidNeighbors>)>().iter( world )
+                .find(| (_, i, _, _) | i.0 == idx )
+                .map(| (gct, _, force, neighbors) | (gct.clone(), force.0, neighbors.map(| n | n.0.len() )))
+                .unwrap()
+        };
+        world.insert_resource( grid );
+        world.init_resource::<AppliedForces>();
+        let rock = world.spawn(( Transform::from_xyz( 7.0, 5.0, 5.0 ), Collider::ball( 0.3 ), DecorationTag )).id();
+
+        let mut schedule = Schedule::default();
+        schedule.add_systems( re_voxelize_decorations );
+        schedule.run( &mut world );
+        world.get_mut::<Transform>( rock ).unwrap().translation = Vec3::new( 7.1, 5.0, 5.0 );
+        schedule.run( &mut world );
+        // the new solid cell drops the forces and hands its momentum to all of its 26 fluid neighbors
+        assert_eq!( cell_state( &mut world, 7 ), (GridCellType::Solid, Vec3A::ZERO, Some( 26 )) );
+
+        world.get_mut::<Transform>( rock ).unwrap().translation = Vec3::new( 3.0, 5.0, 5.0 );
+        schedule.run( &mut world );
+        // the freed cell feels gravity again and is no wall anymore
+        assert_eq!( cell_state( &mut world, 7 ), (GridCellType::Fluid, Vec3A::from( gravity ), None) );
+        assert_eq!( cell_state( &mut world, 3 ), (GridCellType::Solid, Vec3A::ZERO, Some( 26 )) );
+    }
+
+    #[test]
+    fn test_partial_coverage_threshold() {
+        // a big box ending just past the middle of the cell at x = 5
+        let wall = Collider::cuboid( 50.0, 50.0, 50.0 );
+        let wall_loc = Transform::from_xyz( -44.9, 0.0, 0.0 );
+        let half_covered = Vec3::new( 5.0, 0.0, 0.0 );
+
+        // 3x3x3 samples: two of the three layers are inside
+        let coverage = cell_coverage( half_covered, &[(&wall_loc, &wall)] );
+        assert!( (coverage - 2.0 / 3.0).abs() < 1e-5, "coverage {}", coverage );
+
+        let classify = | threshold | classify_against_colliders( half_covered, [(&wall_loc, &wall)].into_iter(), threshold ).0;
+        assert!( classify( Some( 0.5 ) ) );
+        assert!( !classify( Some( 0.9 ) ) );
+        // binary classification by distance to the center
+        assert!( classify( None ) );
+
+        // a barely touched cell only keeps the wall normal
+        let (solid, normals) = classify_against_colliders( Vec3::new( 5.9, 0.0, 0.0 ), [(&wall_loc, &wall)].into_iter(), Some( 0.5 ) );
+        assert!( !solid );
+        assert_eq!( normals.len(), 1 );
+    }
+
+    #[test]
+    fn test_anisotropic_kernel() {
+        let sum = | w: [Vec3A; 3] | {
+            let mut total = 0.0;
+            for gz in 0..3 {
+                for gy in 0..3 {
+                    for gx in 0..3 {
+                        total += w[gx].x * w[gy].y * w[gz].z;
+                    }
+                }
+            }
+            total
+        };
+        let diffs = [ Vec3A::ZERO, Vec3A::new( 0.3, -0.2, 0.45 ), Vec3A::new( -0.49, 0.1, -0.33 ) ];
+        let directions = [ Vec3A::X, Vec3A::new( 1.0, 2.0, -0.5 ).normalize(), Vec3A::ZERO ];
+        for diff in diffs {
+            // stretch 1 is the isotropic kernel
+            assert_eq!( anisotropic_interpolation_weights( diff, Vec3A::Y, 1.0 ), quadratic_interpolation_weights( diff ) );
+            for direction in directions {
+                for stretch in [ 1.0, 2.0, 8.0 ] {
+                    let weights = anisotropic_interpolation_weights( diff, direction, stretch );
+                    assert!( (sum( weights ) - 1.0).abs() < 1e-5, "diff {} dir {} stretch {}", diff, direction, stretch );
+                    assert!( weights.iter().all(| w | w.cmpge( Vec3A::ZERO ).all() ) );
+                }
+            }
+        }
+
+        // along the flow the kernel stays quadratic, across it gets sharper (more weight on the nearest cell)
+        let diff = Vec3A::splat( 0.3 );
+        let quadratic = quadratic_interpolation_weights( diff );
+        let stretched = anisotropic_interpolation_weights( diff, Vec3A::X, 4.0 );
+        assert_eq!( stretched[2].x, quadratic[2].x );
+        assert!( stretched[0].y < quadratic[0].y );
+    }
+
+    #[test]
+    fn test_previous_velocity_uses_particle_weights() {
+        // unchanged grid: the FLIP delta has to vanish for the stretched kernel too
+        let mut grid = Grid::new( UVec3::new( 8, 8, 8 ), 1.0 );
+        let field: Vec<Vec3A> = (0..grid.cell_count()).map(| idx | grid.to_3d( idx ).as_vec3a() * Vec3A::new( 1.0, -0.5, 0.25 )).collect();
+        grid.get_tmp_velo_mut().copy_from_slice( &field );
+        grid.get_prev_velo_mut().copy_from_slice( &field );
+
+        let location = Vec3A::new( 4.3, 3.8, 4.6 );
+        let weights = particle_weights( location, Vec3A::new( 1.0, 0.5, 0.0 ), 4.0 );
+        let (current, _) = grid.sample_velocity_weighted( location, weights );
+        let previous = grid.sample_previous_velocity_weighted( location, weights );
+        assert!( (current - previous).length() < 1e-6, "{} != {}", current, previous );
+    }
 }
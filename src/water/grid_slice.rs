@@ -0,0 +1,167 @@
+/*
+    Copyright 2023 github.com/programaquatic
+
+   Licensed under the Apache License, Version 2.0 (the "License");
+   you may not use this file except in compliance with the License.
+   You may obtain a copy of the License at
+
+       http://www.apache.org/licenses/LICENSE-2.0
+
+   Unless required by applicable law or agreed to in writing, software
+   distributed under the License is distributed on an "AS IS" BASIS,
+   WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+   See the License for the specific language governing permissions and
+   limitations under the License.
+*/
+
+use bevy::prelude::*;
+
+use crate::{
+    aqs_utils::{
+        constants::Constants,
+        coneshape::ZCone,
+    },
+    water::{
+        debug_scalar::{colormap_color, normalize_scalar},
+        grid::{Grid, GridCellIndex, GridCellType},
+        resources::FluidParticleVelocity,
+    },
+};
+
+/// number of materials the speed range gets quantized into
+const PALETTE_SIZE: usize = 32;
+
+
+/// Slice of the grid perpendicular to z that shows the cell velocities as cones
+///   V toggles, comma and period move the slice by one cell (the brackets belong to the clip plane)
+#[derive(Resource, Debug, Default)]
+pub struct GridSlice {
+    pub enabled: bool,
+    pub z: u32,
+}
+
+/// cone of a grid cell on the slice (child of the cell)
+#[derive(Component)]
+pub struct GridSliceArrow(pub Entity);
+
+
+/// the cell with the given index is on the slice
+pub fn in_slice(grid: &Grid, idx: usize, z: u32) -> bool {
+    grid.to_3d( idx ).z == z
+}
+
+
+pub fn control_grid_slice(
+    keys: Res<ButtonInput<KeyCode>>,
+    grid: Res<Grid>,
+    mut slice: ResMut<GridSlice>,
+) {
+    let depth = grid.grid_size().z;
+    if keys.just_pressed( KeyCode::KeyV ) {
+        slice.enabled = !slice.enabled;
+        if slice.enabled {
+            slice.z = depth / 2;
+        }
+        info!("velocity slice {}", if slice.enabled { "on" } else { "off" });
+    }
+    if !slice.enabled {
+        return;
+    }
+    if keys.just_pressed( KeyCode::Comma ) {
+        slice.z = slice.z.saturating_sub( 1 );
+    }
+    if keys.just_pressed( KeyCode::Period ) {
+        slice.z = (slice.z + 1).min( depth.saturating_sub( 1 ) );
+    }
+}
+
+/// respawn the cones when the slice moved; orient and color them by the cell velocity every frame
+pub fn update_grid_slice(
+    constants: Res<Constants>,
+    slice: Res<GridSlice>,
+    grid: Res<Grid>,
+    mut assets: Local<Option<(Handle<Mesh>, Vec<Handle<StandardMaterial>>)>>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    cells: Query<(Entity, &GridCellIndex, &GridCellType, &FluidParticleVelocity)>,
+    mut arrows: Query<(Entity, &GridSliceArrow, &mut Transform, &mut Visibility, &mut MeshMaterial3d<StandardMaterial>)>,
+    mut commands: Commands,
+) {
+    if slice.is_changed() {
+        arrows.iter().for_each(| (arrow, ..) | commands.entity( arrow ).despawn_recursive() );
+        if !slice.enabled {
+            return;
+        }
+        let (mesh, palette) = assets.get_or_insert_with(|| {
+            let colormap = constants.DEBUG_COLORMAP;
+            (meshes.add( Mesh::from( ZCone { radius: 0.15, height: 0.8, subdivisions: 5 } ) ),
+             (0..PALETTE_SIZE)
+                 .map(| i | {
+                     let c = colormap_color( colormap, i as f32 / (PALETTE_SIZE - 1) as f32 );
+                     materials.add( StandardMaterial { base_color: Color::linear_rgb( c.x, c.y, c.z ), unlit: true, ..default() } )
+                 })
+                 .collect())
+        });
+        cells.iter()
+            .filter(| (_, idx, ..) | in_slice( &grid, idx.0, slice.z ))
+            .for_each(| (cell, ..) | {
+                let arrow = commands.spawn((
+                    GridSliceArrow( cell ),
+                    Mesh3d( mesh.clone() ),
+                    MeshMaterial3d( palette[ 0 ].clone() ),
+                    Transform::default(),
+                    Visibility::Hidden,
+                )).id();
+                commands.entity( cell ).add_child( arrow );
+            });
+        // the new cones get their orientation in the next frame
+        return;
+    }
+    if !slice.enabled {
+        return;
+    }
+    let Some( (_, palette) ) = assets.as_ref() else {
+        return;
+    };
+
+    let max_speed = arrows.iter()
+        .filter_map(| (_, arrow, ..) | cells.get( arrow.0 ).ok())
+        .filter(| (_, _, gct, _) | **gct == GridCellType::Fluid)
+        .fold( 0.0f32, | max, (.., vel) | max.max( vel.0.length() ));
+    arrows.iter_mut().for_each(| (_, arrow, mut transform, mut visibility, mut material) | {
+        let Ok( (_, _, gct, vel) ) = cells.get( arrow.0 ) else {
+            return;
+        };
+        let fluid = *gct == GridCellType::Fluid;
+        visibility.set_if_neq( if fluid { Visibility::Inherited } else { Visibility::Hidden } );
+        if !fluid {
+            return;
+        }
+        // the cone points along +z, i.e. looking towards -velocity
+        *transform = Transform::default().looking_to( -Vec3::from( vel.0 ), Vec3::Y );
+        let bucket = (normalize_scalar( vel.0.length(), 0.0, max_speed ) * (PALETTE_SIZE - 1) as f32).round() as usize;
+        if material.0 != palette[ bucket ] {
+            material.0 = palette[ bucket ].clone();
+        }
+    });
+}
+
+
+
+#[cfg(test)]
+mod test
+{
+    use super::*;
+
+    #[test]
+    fn test_in_slice() {
+        let grid = Grid::new( UVec3::new( 6, 4, 5 ), 1.0 );
+        let size = *grid.grid_size();
+        for z in [ 0, 2, size.z - 1 ] {
+            let on_slice: Vec<usize> = (0..grid.cell_count()).filter(| &idx | in_slice( &grid, idx, z )).collect();
+            assert_eq!( on_slice.len(), (size.x * size.y) as usize );
+            assert!( on_slice.iter().all(| &idx | grid.to_3d( idx ).z == z) );
+        }
+        assert!( !(0..grid.cell_count()).any(| idx | in_slice( &grid, idx, size.z )) );
+    }
+}
@@ -0,0 +1,227 @@
+/*
+    Copyright 2023 github.com/programaquatic
+
+   Licensed under the Apache License, Version 2.0 (the "License");
+   you may not use this file except in compliance with the License.
+   You may obtain a copy of the License at
+
+       http://www.apache.org/licenses/LICENSE-2.0
+
+   Unless required by applicable law or agreed to in writing, software
+   distributed under the License is distributed on an "AS IS" BASIS,
+   WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+   See the License for the specific language governing permissions and
+   limitations under the License.
+*/
+
+use bevy::prelude::*;
+
+use crate::{
+    aqs_utils::constants::{
+        Constants,
+        SurfaceColorConf,
+        SurfaceColorMode,
+    },
+    tech::tank::Tank,
+    water::{
+        grid::Grid,
+        surface::{
+            self,
+            SurfaceFlowOverlay,
+        },
+    },
+};
+
+/// number of color swatches that make up the gradient bar
+const LEGEND_SWATCHES: usize = 16;
+
+
+#[derive(Component)]
+pub struct LegendTag;
+
+/// text elements of the legend that follow the color config and tank size
+#[derive(Component, Clone, Copy)]
+pub enum LegendText {
+    Title,
+    Min,
+    Max,
+    Scale,
+}
+
+/// one swatch of the gradient bar at position t in [0, 1]
+#[derive(Component)]
+pub struct LegendSwatch(pub f32);
+
+
+/// label and value range of the active surface color mapping
+pub fn legend_range(conf: &SurfaceColorConf) -> (&'static str, f32, f32) {
+    match conf.mode {
+        SurfaceColorMode::VerticalVelocity => ("vertical velocity", 0.0, 1.0),
+        SurfaceColorMode::Height => ("surface height", -conf.range, conf.range),
+        SurfaceColorMode::Speed => ("horizontal speed", 0.0, conf.range),
+    }
+}
+
+/// color at position t of the legend; goes through surface_color to match the rendered surface
+pub fn legend_color(conf: &SurfaceColorConf, t: f32) -> Color {
+    let (_, min, max) = legend_range( conf );
+    let value = min + (max - min) * t;
+    let c = match conf.mode {
+        SurfaceColorMode::Speed => surface::surface_color( conf, 0.0, Vec2::new( value, 0.0 ) ),
+        _ => surface::surface_color( conf, value, Vec2::ZERO ),
+    };
+    Color::linear_rgb( c[0], c[1], c[2] )
+}
+
+/// length of the scale bar: the largest 1/2/5 * 10^n cm that fits a quarter of the tank width
+pub fn scale_bar_cm(tank_width_cm: f32) -> f32 {
+    let limit = tank_width_cm / 4.0;
+    if limit <= 0.0 {
+        return 0.0;
+    }
+    let magnitude = 10f32.powf( limit.log10().floor() );
+    [5.0, 2.0, 1.0].iter()
+        .map(| step | step * magnitude)
+        .find(| &length | length <= limit)
+        .unwrap_or( magnitude )
+}
+
+
+pub fn spawn_legend(
+    constants: Res<Constants>,
+    overlay: Res<SurfaceFlowOverlay>,
+    mut commands: Commands,
+) {
+    let font = TextFont { font_size: 14.0, ..default() };
+    commands
+        .spawn((
+            Name::new("Color_Legend"),
+            LegendTag,
+            Node {
+                position_type: PositionType::Absolute,
+                bottom: Val::Px(10.0),
+                right: Val::Px(10.0),
+                flex_direction: FlexDirection::Column,
+                ..default()
+            },
+            BackgroundColor( Color::srgba(0.0, 0.0, 0.0, 0.4) ),
+            if overlay.enabled { Visibility::Inherited } else { Visibility::Hidden },
+        ))
+        .with_children(| legend | {
+            legend.spawn(( Text::new(""), font.clone(), LegendText::Title ));
+            legend
+                .spawn( Node { flex_direction: FlexDirection::Row, ..default() } )
+                .with_children(| bar | {
+                    for i in 0..LEGEND_SWATCHES {
+                        let t = i as f32 / (LEGEND_SWATCHES - 1) as f32;
+                        bar.spawn((
+                            Node { width: Val::Px(10.0), height: Val::Px(12.0), ..default() },
+                            BackgroundColor( legend_color( &constants.SURFACE_COLOR, t ) ),
+                            LegendSwatch( t ),
+                        ));
+                    }
+                });
+            legend
+                .spawn( Node { flex_direction: FlexDirection::Row, justify_content: JustifyContent::SpaceBetween, ..default() } )
+                .with_children(| labels | {
+                    labels.spawn(( Text::new(""), font.clone(), LegendText::Min ));
+                    labels.spawn(( Text::new(""), font.clone(), LegendText::Max ));
+                });
+            legend.spawn(( Text::new(""), font.clone(), LegendText::Scale ));
+        });
+}
+
+/// show the legend together with the debug overlay (toggle with F)
+pub fn toggle_legend(
+    overlay: Res<SurfaceFlowOverlay>,
+    mut legend: Query<&mut Visibility, With<LegendTag>>,
+) {
+    if !overlay.is_changed() {
+        return;
+    }
+    legend.iter_mut().for_each(| mut visibility | {
+        *visibility = if overlay.enabled { Visibility::Inherited } else { Visibility::Hidden };
+    });
+}
+
+/// refresh labels and colors whenever the color config changes (e.g. its normalization range)
+pub fn update_legend(
+    constants: Res<Constants>,
+    tank: Res<Tank>,
+    mut texts: Query<(&mut Text, &LegendText)>,
+    mut swatches: Query<(&mut BackgroundColor, &LegendSwatch)>,
+) {
+    if !constants.is_changed() {
+        return;
+    }
+    let conf = &constants.SURFACE_COLOR;
+    let (title, min, max) = legend_range( conf );
+    texts.iter_mut().for_each(| (mut text, field) | {
+        text.0 = match field {
+            LegendText::Title => format!("surface: {}", title),
+            LegendText::Min => format!("{:.2}", min),
+            LegendText::Max => format!("{:.2}", max),
+            LegendText::Scale => format!("scale bar: {} cm", scale_bar_cm( tank.get_size_cm().x )),
+        };
+    });
+    swatches.iter_mut().for_each(| (mut color, swatch) | {
+        color.0 = legend_color( conf, swatch.0 );
+    });
+}
+
+/// scale bar along the front bottom edge of the tank
+pub fn draw_scale_bar(
+    overlay: Res<SurfaceFlowOverlay>,
+    tank: Res<Tank>,
+    grid: Res<Grid>,
+    mut gizmos: Gizmos,
+) {
+    if !overlay.enabled {
+        return;
+    }
+    let length = scale_bar_cm( tank.get_size_cm().x ) * tank.scale;
+    let start = Vec3::new( 1.0, 1.0, grid.grid_size().z as f32 - 1.0 );
+    gizmos.line(
+        grid.to_world_coord( start ),
+        grid.to_world_coord( start + Vec3::X * length ),
+        Color::WHITE,
+    );
+}
+
+
+
+#[cfg(test)]
+mod test
+{
+    use super::*;
+
+    fn close(color: Color, expected: Vec3) -> bool {
+        let c = color.to_linear();
+        Vec3::new( c.red, c.green, c.blue ).abs_diff_eq( expected, 1e-5 )
+    }
+
+    #[test]
+    fn test_legend_follows_color_config() {
+        let mut conf = SurfaceColorConf { mode: SurfaceColorMode::Speed, ..default() };
+        assert_eq!( legend_range( &conf ), ("horizontal speed", 0.0, 1.0) );
+        assert!( close( legend_color( &conf, 0.0 ), conf.deep ) );
+        assert!( close( legend_color( &conf, 1.0 ), conf.shallow ) );
+
+        // the labeled range follows the normalization
+        conf.range = 2.5;
+        assert_eq!( legend_range( &conf ).2, 2.5 );
+        assert!( close( legend_color( &conf, 1.0 ), conf.shallow ) );
+
+        conf.mode = SurfaceColorMode::Height;
+        assert_eq!( legend_range( &conf ), ("surface height", -2.5, 2.5) );
+        assert!( close( legend_color( &conf, 0.0 ), conf.deep ) );
+    }
+
+    #[test]
+    fn test_scale_bar() {
+        assert_eq!( scale_bar_cm( 160.0 ), 20.0 );
+        assert_eq!( scale_bar_cm( 60.0 ), 10.0 );
+        assert_eq!( scale_bar_cm( 20.0 ), 5.0 );
+        assert_eq!( scale_bar_cm( 12.0 ), 2.0 );
+    }
+}
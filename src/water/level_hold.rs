@@ -0,0 +1,227 @@
+/*
+    Copyright 2023 github.com/programaquatic
+
+   Licensed under the Apache License, Version 2.0 (the "License");
+   you may not use this file except in compliance with the License.
+   You may obtain a copy of the License at
+
+       http://www.apache.org/licenses/LICENSE-2.0
+
+   Unless required by applicable law or agreed to in writing, software
+   distributed under the License is distributed on an "AS IS" BASIS,
+   WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+   See the License for the specific language governing permissions and
+   limitations under the License.
+*/
+
+use bevy::prelude::*;
+
+use crate::{
+    aqs_utils::constants::{
+        Constants,
+        LevelHoldConf,
+    },
+    tech::tank::Tank,
+    water::{
+        grid::{
+            Grid,
+            GridCellType,
+        },
+        resources,
+        surface,
+        sim_time::SimTime,
+    },
+};
+
+
+/// fluid level in grid units measured from the grid mass
+///   per interior column: the topmost cell with fluid plus its fill estimated from the mass;
+///   `cell_mass` is the mass of a full cell; columns without fluid are ignored
+pub fn measure_level(grid: &Grid, cell_mass: f32) -> Option<f32> {
+    let size = grid.grid_size();
+    let mass = grid.get_tmp_mass();
+    let threshold = 0.1 * cell_mass;
+
+    let mut sum = 0.0;
+    let mut columns = 0;
+    for x in 1..(size.x - 1) as usize {
+        for z in 1..(size.z - 1) as usize {
+            let top = (0..size.y as usize).rev()
+                .find(| &y | mass[ grid.index_of( x, y, z ) ] > threshold );
+            if let Some( y ) = top {
+                let fill = (mass[ grid.index_of( x, y, z ) ] / cell_mass).min( 1.0 );
+                sum += y as f32 + fill;
+                columns += 1;
+            }
+        }
+    }
+    if columns == 0 {
+        return None;
+    }
+    Some( sum / columns as f32 )
+}
+
+
+/// PID controller with anti-windup (no integration while the output is saturated)
+#[derive(Default, Debug)]
+pub struct PidController {
+    integral: f32,
+    prev_error: Option<f32>,
+}
+
+impl PidController {
+    /// controller output (level change rate) for the current error, limited to +/- max_rate
+    pub fn update(&mut self, conf: &LevelHoldConf, error: f32, dt: f32) -> f32 {
+        let derivative = self.prev_error.map_or( 0.0, | prev | (error - prev) / dt );
+        self.prev_error = Some( error );
+
+        let integral = self.integral + error * dt;
+        let output = conf.kp * error + conf.ki * integral + conf.kd * derivative;
+        let limited = output.clamp( -conf.max_rate, conf.max_rate );
+        if output == limited {
+            self.integral = integral;
+        }
+        limited
+    }
+}
+
+
+/// State of the level-hold loop; the particles to inject/drain accumulate until they add up to whole particles
+#[derive(Resource, Default, Debug)]
+pub struct LevelHold {
+    pub controller: PidController,
+    /// last measured level and its target (grid units)
+    pub level: Option<f32>,
+    pub target: f32,
+    /// particles to add (positive) or remove (negative)
+    pending: f32,
+}
+
+impl LevelHold {
+    /// run the controller for a measured level; the rate gets converted into particles for `columns` columns
+    pub fn update(&mut self, conf: &LevelHoldConf, level: f32, target: f32, dt: f32, columns: f32) {
+        self.level = Some( level );
+        self.target = target;
+        let rate = self.controller.update( conf, target - level, dt );
+        self.pending += rate * dt * columns;
+    }
+
+    /// number of whole particles to inject now
+    pub fn take_injection(&mut self) -> usize {
+        let count = self.pending.max( 0.0 ).floor();
+        self.pending -= count;
+        count as usize
+    }
+
+    /// number of whole particles to drain now
+    pub fn take_drain(&mut self) -> usize {
+        let count = (-self.pending).max( 0.0 ).floor();
+        self.pending += count;
+        count as usize
+    }
+}
+
+
+pub fn update_level_hold(
+    constants: Res<Constants>,
+    time: Res<SimTime>,
+    tank: Res<Tank>,
+    grid: Res<Grid>,
+    mut hold: ResMut<LevelHold>,
+) {
+    let Some( conf ) = constants.LEVEL_HOLD else {
+        return;
+    };
+    // a full cell holds DEFAULT_PPC particles
+    let cell_mass = constants.DEFAULT_PARTICLE_MASS * constants.DEFAULT_PPC.max( 1 ) as f32;
+    let Some( level ) = measure_level( &grid, cell_mass ) else {
+        return;
+    };
+    let size = grid.grid_size();
+    // same reference as the initial fill height (see init_fluid_particle_system)
+    let target = conf.target.grid_height( size.y as f32, tank.scale );
+    let columns = ((size.x - 2) * (size.z - 2)) as f32;
+    // raising the level by one cell takes DEFAULT_PPC particles per column
+    hold.update( &conf, level, target, time.dt, columns * constants.DEFAULT_PPC.max( 1 ) as f32 );
+}
+
+
+/// remove the particles the level-hold controller asks for, skimming them from the top
+pub fn drain_particles(
+    mut hold: ResMut<LevelHold>,
+    particles: Query<(Entity, &resources::FluidParticlePosition), (With<resources::ParticleTag>, Without<GridCellType>)>,
+    mut particle_frame: Query<&mut resources::ParticleCount, (With<resources::ParticleFrameTag>, Without<surface::WaveGridFrameTag>)>,
+    mut commands: Commands,
+) {
+    let drain = hold.take_drain();
+    if drain == 0 {
+        return;
+    }
+    let mut by_height: Vec<(Entity, f32)> = particles.iter()
+        .map(| (particle, location) | (particle, location.0.y))
+        .collect();
+    by_height.sort_unstable_by(| a, b | b.1.total_cmp( &a.1 ));
+    by_height.truncate( drain );
+    by_height.iter().for_each(| (particle, _) | commands.entity( *particle ).despawn_recursive() );
+
+    let drained = by_height.len();
+    particle_frame.iter_mut().for_each(| mut count | count.0 = count.0.saturating_sub( drained ));
+}
+
+
+
+#[cfg(test)]
+mod test
+{
+    use super::*;
+
+    #[test]
+    fn test_measure_level() {
+        let mut grid = Grid::new( UVec3::new( 6, 10, 6 ), 1.0 );
+        assert_eq!( measure_level( &grid, 2.0 ), None );
+
+        let size = *grid.grid_size();
+        for x in 1..(size.x - 1) as usize {
+            for z in 1..(size.z - 1) as usize {
+                for y in 1..5 {
+                    let idx = grid.index_of( x, y, z );
+                    grid.get_tmp_mass_mut()[ idx ] = 2.0;
+                }
+                // half filled top cell
+                let idx = grid.index_of( x, 5, z );
+                grid.get_tmp_mass_mut()[ idx ] = 1.0;
+            }
+        }
+        let level = measure_level( &grid, 2.0 ).unwrap();
+        assert!( (level - 5.5).abs() < 1e-5, "level {}", level );
+    }
+
+    #[test]
+    fn test_level_converges_despite_perturbation() {
+        let conf = LevelHoldConf::default();
+        let dt = 0.04;
+        let columns = 100.0;
+        let target = 20.0;
+        let mut hold = LevelHold::default();
+
+        // the plant: every particle raises the level of all columns by 1/columns
+        let mut particles: f32 = 1700.0;
+        let leak_per_step = 2.0;
+        for step in 0..6000 {
+            let level = particles / columns;
+            hold.update( &conf, level, target, dt, columns );
+            particles += hold.take_injection() as f32;
+            particles -= hold.take_drain() as f32;
+
+            // constant leak and a sudden loss half way
+            particles -= leak_per_step;
+            if step == 3000 {
+                particles -= 300.0;
+            }
+            if (2000..3000).contains( &step ) || step >= 5000 {
+                assert!( (particles / columns - target).abs() < 0.1,
+                         "step {}: level {} not held at {}", step, particles / columns, target );
+            }
+        }
+    }
+}
@@ -40,6 +40,7 @@ use crate::aqs_utils::constants;
 
 use crate::water::{
     resources,
+    sim_time,
     grid,
     grid::{GridCellType, GridCellIndex},
 };
@@ -52,6 +53,7 @@ use crate::water::{
 // STEP: 1
 // Collecting the grid quantities onto each cell cmma
 pub fn p2g_stage1(
+    constants: Res<constants::Constants>,
     grid: Res<grid::Grid>,
     mut particles: Query<
         (
@@ -61,16 +63,14 @@ pub fn p2g_stage1(
             &resources::AffineMomentum,
             &mut resources::CellMMAccumulation,
         ),
-        With<resources::ParticleTag>,
+        Or<(With<resources::ParticleTag>, With<resources::SolidParticleTag>)>,
         >,
 ) {
     particles.par_iter_mut().for_each(
         |(location, velocity, mass, affine_momentum, mut cmma)| {
             // assert_eq!(location.0.is_nan(), false);
             let cell_idx = location.0.as_uvec3();
-            let cell_diff = (location.0 - cell_idx.as_vec3a()) - 0.5;
-
-            let weights = grid::quadratic_interpolation_weights(cell_diff);
+            let weights = grid::particle_weights( location.0, velocity.0, constants.KERNEL_STRETCH );
             // if !grid.cell_at_vec_is_fluid( &cell_idx ) {
             //     println!("{}, {}", cell_idx, location.0 );
             //     assert!( false );
@@ -87,7 +87,11 @@ pub fn p2g_stage1(
                             (cell_idx.z as i32 + gz as i32 - 1) as u32,
                         );
                         let cell_dist = (cell_pos.as_vec3a() - location.0) + Vec3A::splat(0.5);
-                        let cell_at_index = grid.index_of_vec( &cell_pos );
+                        let Some( cell_at_index ) = grid.try_index_of_vec( &cell_pos ) else {
+                            // outside of the grid: no contribution instead of one to the last cell
+                            cmma.0[gx + 3 * gy + 9 * gz] = resources::CellMMAChange::NONE;
+                            continue;
+                        };
 
                         let q = affine_momentum.0 * cell_dist;
                         let mass_contrib = weight * mass.0;
@@ -108,7 +112,7 @@ pub fn p2g_stage1(
 // Helper system to go over each particle and accumulate the grid-cell computation results
 pub fn p2g_apply_stage1(
     mut grid: ResMut<grid::Grid>,
-    particles: Query<(&resources::CellMMAccumulation,), With<resources::ParticleTag>>,
+    particles: Query<(&resources::CellMMAccumulation,), Or<(With<resources::ParticleTag>, With<resources::SolidParticleTag>)>>,
 ) {
     particles.iter().for_each(|cmma| {
         for change in cmma.0 .0.iter() {
@@ -121,25 +125,29 @@ pub fn p2g_apply_stage1(
 // STEP: 2
 pub fn p2g_stage2(
     constants: Res<constants::Constants>,
+    time: Res<sim_time::SimTime>,
     grid: Res<grid::Grid>,
     mut flparticles: Query<
         (
             &resources::FluidParticlePosition,
+            &resources::FluidParticleVelocity,
             &resources::FluidQuantityMass,
             &resources::AffineMomentum,
             &mut resources::CellMMAccumulation,
+            Option<&resources::FluidPhase>,
         ),
         With<resources::ParticleTag>,
         >,
 ) {
     flparticles.par_iter_mut().for_each(
-        |(location, quantity, affmom, mut cmma)| {
+        |(location, velocity, quantity, affmom, mut cmma, phase)| {
+            let model = constants.fluid_model( phase.map_or( 0, |p| p.0 ) );
+
             let mut density: f32 = 0.0;
 
             let cell_idx = location.0.as_uvec3();
-            let cell_diff = (location.0 - cell_idx.as_vec3a()) - 0.5;
-
-            let weights = grid::quadratic_interpolation_weights(cell_diff);
+            // same weights as in stage 1
+            let weights = grid::particle_weights( location.0, velocity.0, constants.KERNEL_STRETCH );
 
             // println!("----- next particle {} -> {}-------", location.0, cell_pos);
             for gz in 0..3 {
@@ -151,7 +159,9 @@ pub fn p2g_stage2(
                             (cell_idx.y as i32 + gy as i32 - 1) as u32,
                             (cell_idx.z as i32 + gz as i32 - 1) as u32,
                         );
-                        let cell_at_index = grid.index_of_vec( &cell_pos );
+                        let Some( cell_at_index ) = grid.try_index_of_vec( &cell_pos ) else {
+                            continue;
+                        };
 
                         density += grid.get_tmp_mass()[ cell_at_index ] * weight;
                     }
@@ -163,9 +173,9 @@ pub fn p2g_stage2(
             // fluid constitutive model
             let pressure = f32::max(
                 -0.1,
-                constants.FLUID_MODEL.eos_stiffness
-                    * (f32::powf(density / constants.FLUID_MODEL.rest_density,
-                                 constants.FLUID_MODEL.eos_power) - 1.0),
+                model.eos_stiffness
+                    * (f32::powf(density / model.rest_density,
+                                 model.eos_power) - 1.0),
             );
             let mut stress = Mat3A::from_cols(
                 Vec3A::new(-pressure, 0.0, 0.0),
@@ -177,10 +187,10 @@ pub fn p2g_stage2(
             strain.z_axis.x = trace;
             strain.y_axis.y = trace;
             strain.x_axis.z = trace;
-            let viscosity_term: Mat3A = strain * constants.FLUID_MODEL.dynamic_viscosity;
+            let viscosity_term: Mat3A = strain * model.dynamic_viscosity;
             stress += viscosity_term;
 
-            let eq_16_term_0 = -volume * 4.0 * stress * constants.WORLD_DT;
+            let eq_16_term_0 = -volume * 4.0 * stress * time.dt;
 
             // for all surrounding 27 cells
             for gz in 0..3 {
@@ -194,7 +204,11 @@ pub fn p2g_stage2(
                         );
 
                         let cell_dist = (cell_pos.as_vec3a() - location.0) + Vec3A::splat(0.5);
-                        let cell_at_index = grid.index_of_vec( &cell_pos );
+                        let Some( cell_at_index ) = grid.try_index_of_vec( &cell_pos ) else {
+                            // outside of the grid: no contribution instead of one to the last cell
+                            cmma.0[gx + 3 * gy + 9 * gz] = resources::CellMMAChange::NONE;
+                            continue;
+                        };
                         let new_momentum = (eq_16_term_0 * weight) * cell_dist;
                         cmma.0[gx + 3 * gy + 9 * gz] = resources::CellMMAChange {
                             cell_idx: cell_at_index,
@@ -211,12 +225,14 @@ pub fn p2g_stage2(
 
 pub fn p2g_stage2_solids(
     constants: Res<constants::Constants>,
+    time: Res<sim_time::SimTime>,
     grid: Res<grid::Grid>,
     mut sdparticles: Query<
             (
                 &resources::FluidParticlePosition,
                 &resources::FluidQuantityMass,
                 &resources::AffineMomentum,
+                &resources::DeformationGradient,
                 &mut resources::CellMMAccumulation,
             ),
         With<resources::SolidParticleTag>,
@@ -227,7 +243,7 @@ pub fn p2g_stage2_solids(
         return;
     }
     sdparticles.par_iter_mut().for_each(
-        |(location, mass, _, mut mmc)| {
+        |(location, mass, _, deformation, mut mmc)| {
             let mut density: f32 = 0.0;
 
             let cell_idx = location.0.as_uvec3();
@@ -245,7 +261,9 @@ pub fn p2g_stage2_solids(
                             (cell_idx.y as i32 + gy as i32 - 1) as u32,
                             (cell_idx.z as i32 + gz as i32 - 1) as u32,
                         );
-                        let cell_at_index = grid.index_of_vec( &cell_pos );
+                        let Some( cell_at_index ) = grid.try_index_of_vec( &cell_pos ) else {
+                            continue;
+                        };
 
                         density += grid.get_tmp_mass()[ cell_at_index ] * weight;
                     }
@@ -255,19 +273,19 @@ pub fn p2g_stage2_solids(
             let volume = mass.0 / density;
 
             let pp = &constants.ELASTIC_MODEL;
-            let j: f32 = pp.deformation_gradient.determinant();
+            let j: f32 = deformation.0.determinant();
             let volume_scaled = volume * j;
 
-            let f_t: Mat3A = pp.deformation_gradient.transpose();
+            let f_t: Mat3A = deformation.0.transpose();
             let f_inv_t = f_t.inverse();
-            let f_minus_f_inv_t = pp.deformation_gradient.sub(f_inv_t);
+            let f_minus_f_inv_t = deformation.0.sub(f_inv_t);
 
             let p_term_0: Mat3A = f_minus_f_inv_t.mul_scalar(pp.elastic_mu);
             let p_term_1: Mat3A = f_inv_t.mul_scalar(j.ln() * pp.elastic_lambda);
             let p_combined: Mat3A = p_term_0.add_mat3(&p_term_1);
 
             let stress: Mat3A = p_combined.mul_mat3(&f_t).mul_scalar(1.0 / j);
-            let eq_16_term_0 = stress * (-volume_scaled * 4.0 * constants.WORLD_DT);
+            let eq_16_term_0 = stress * (-volume_scaled * 4.0 * time.dt);
 
             // for all surrounding 27 cells
             for gz in 0..3 {
@@ -281,7 +299,11 @@ pub fn p2g_stage2_solids(
                         );
 
                         let cell_dist = (cell_pos.as_vec3a() - location.0) + Vec3A::splat(0.5);
-                        let cell_at_index = grid.index_of_vec( &cell_pos );
+                        let Some( cell_at_index ) = grid.try_index_of_vec( &cell_pos ) else {
+                            // outside of the grid: no contribution instead of one to the last cell
+                            mmc.0[gx + 3 * gy + 9 * gz] = resources::CellMMAChange::NONE;
+                            continue;
+                        };
 
                         // store the fused force/momentum update from MLS-MPM to apply onto grid later.
                         // todo combine into grid(x,y) = total changes as they come in here...?
@@ -300,7 +322,7 @@ pub fn p2g_stage2_solids(
 
 pub fn grid_update(
     mut grid: ResMut<grid::Grid>,
-    particles: Query<(&resources::CellMMAccumulation,), With<resources::ParticleTag>>,
+    particles: Query<(&resources::CellMMAccumulation,), Or<(With<resources::ParticleTag>, With<resources::SolidParticleTag>)>>,
     mut cells: Query<(
         &mut resources::FluidParticleVelocity,
         &mut resources::FluidQuantityMass,
@@ -20,3 +20,27 @@ pub mod surface;
 pub mod fluid;
 pub mod mlsmpm;
 pub mod spraybar;
+pub mod validate;
+pub mod setup;
+pub mod diagnostics;
+pub mod spatial_hash;
+pub mod point_cloud;
+pub mod level_hold;
+pub mod clip_plane;
+pub mod legend;
+pub mod surface_export;
+pub mod screen_space;
+pub mod pockets;
+pub mod debug_scalar;
+pub mod particle_rotation;
+pub mod flow_gate;
+pub mod particle_budget;
+pub mod sim_control;
+pub mod velocity_color;
+pub mod elastic;
+pub mod sim_time;
+pub mod particle_export;
+pub mod recorder;
+pub mod grid_slice;
+pub mod density_heatmap;
+pub mod gravity_tilt;
@@ -0,0 +1,155 @@
+/*
+    Copyright 2023 github.com/programaquatic
+
+   Licensed under the Apache License, Version 2.0 (the "License");
+   you may not use this file except in compliance with the License.
+   You may obtain a copy of the License at
+
+       http://www.apache.org/licenses/LICENSE-2.0
+
+   Unless required by applicable law or agreed to in writing, software
+   distributed under the License is distributed on an "AS IS" BASIS,
+   WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+   See the License for the specific language governing permissions and
+   limitations under the License.
+*/
+
+
+use bevy::{
+    prelude::*,
+    diagnostic::{DiagnosticsStore, FrameTimeDiagnosticsPlugin},
+};
+
+use crate::{
+    aqs_utils::constants::{
+        Constants,
+        ParticleBudgetConf,
+    },
+    water::resources::ParticleTag,
+};
+
+/// spreads the shown share evenly over the particle ids (golden ratio sequence)
+const ID_SPREAD: f64 = 0.618_033_988_749_895;
+
+
+/// new visible count for a measured frame time (ms); unchanged while within the tolerance of the target
+pub fn adjust_budget(conf: &ParticleBudgetConf, visible: usize, frame_time_ms: f32) -> usize {
+    let target_ms = 1000.0 / conf.target_fps.max( 1.0 );
+    let factor = if frame_time_ms > target_ms * (1.0 + conf.tolerance) {
+        1.0 - conf.step
+    } else if frame_time_ms < target_ms * (1.0 - conf.tolerance) {
+        1.0 + conf.step
+    } else {
+        1.0
+    };
+    // round away from the current value so that small counts can still grow
+    let scaled = visible as f32 * factor;
+    let adjusted = if factor > 1.0 { scaled.ceil() } else { scaled.floor() } as usize;
+    adjusted.clamp( conf.min_visible, conf.max_visible.max( conf.min_visible ) )
+}
+
+
+/// Number of particles to show out of those with a mesh; all of them without PARTICLE_BUDGET
+#[derive(Resource, Debug)]
+pub struct ParticleBudget {
+    /// current budget; 0 until the first adjustment
+    pub visible: usize,
+    /// share of the meshed particles that gets shown
+    pub fraction: f32,
+}
+
+impl Default for ParticleBudget {
+    fn default() -> Self {
+        ParticleBudget { visible: 0, fraction: 1.0 }
+    }
+}
+
+impl ParticleBudget {
+    /// stable selection: a particle stays shown as long as the fraction doesn't drop below its slot
+    pub fn is_shown(&self, particle_id: usize) -> bool {
+        self.fraction >= 1.0 || (particle_id as f64 * ID_SPREAD).fract() < self.fraction as f64
+    }
+}
+
+
+pub fn update_particle_budget(
+    constants: Res<Constants>,
+    time: Res<Time>,
+    diagnostics: Option<Res<DiagnosticsStore>>,
+    meshed: Query<(), (With<ParticleTag>, With<Mesh3d>)>,
+    mut budget: ResMut<ParticleBudget>,
+    mut elapsed: Local<f32>,
+) {
+    let Some( conf ) = constants.PARTICLE_BUDGET else {
+        return;
+    };
+    *elapsed += time.delta_secs();
+    if *elapsed < conf.interval {
+        return;
+    }
+    *elapsed = 0.0;
+
+    let Some( frame_time ) = diagnostics.as_ref()
+        .and_then(| store | store.get( &FrameTimeDiagnosticsPlugin::FRAME_TIME ))
+        .and_then(| frame_time | frame_time.smoothed()) else {
+        return;
+    };
+    let available = meshed.iter().count();
+    if available == 0 {
+        return;
+    }
+    let current = if budget.visible == 0 { available } else { budget.visible };
+    let visible = adjust_budget( &conf, current, frame_time as f32 ).min( available );
+    if visible != budget.visible {
+        info!("particle budget: {} of {} particles visible at {:.1} ms/frame", visible, available, frame_time);
+    }
+    budget.visible = visible;
+    budget.fraction = visible as f32 / available as f32;
+}
+
+
+
+#[cfg(test)]
+mod test
+{
+    use super::*;
+
+    #[test]
+    fn test_budget_follows_load() {
+        let conf = ParticleBudgetConf { max_visible: 1500, ..default() };
+        let target_ms = 1000.0 / conf.target_fps;
+        // throttled GPU: every visible particle costs render time
+        let frame_time = | visible: usize, cost: f32 | 5.0 + visible as f32 * cost;
+
+        let mut visible = conf.max_visible;
+        for _ in 0..20 {
+            visible = adjust_budget( &conf, visible, frame_time( visible, 0.02 ) );
+        }
+        assert!( visible < conf.max_visible );
+        assert!( frame_time( visible, 0.02 ) <= target_ms * (1.0 + conf.tolerance), "visible {}", visible );
+
+        // load decreases: the count recovers to the upper bound
+        for _ in 0..20 {
+            visible = adjust_budget( &conf, visible, frame_time( visible, 0.005 ) );
+        }
+        assert_eq!( visible, conf.max_visible );
+
+        // never below the lower bound
+        assert_eq!( adjust_budget( &conf, conf.min_visible, 100.0 ), conf.min_visible );
+    }
+
+    #[test]
+    fn test_budget_selection() {
+        let mut budget = ParticleBudget::default();
+        assert!( (0..1000).all(| id | budget.is_shown( id )) );
+
+        budget.fraction = 0.25;
+        let shown = (100000..104000).filter(| &id | budget.is_shown( id )).count();
+        assert!( (shown as i32 - 1000).abs() < 50, "shown {}", shown );
+
+        // shrinking the fraction only hides particles
+        let before: Vec<usize> = (0..1000).filter(| &id | budget.is_shown( id )).collect();
+        budget.fraction = 0.1;
+        assert!( (0..1000).filter(| &id | budget.is_shown( id )).all(| id | before.contains( &id )) );
+    }
+}
@@ -0,0 +1,104 @@
+/*
+    Copyright 2023 github.com/programaquatic
+
+   Licensed under the Apache License, Version 2.0 (the "License");
+   you may not use this file except in compliance with the License.
+   You may obtain a copy of the License at
+
+       http://www.apache.org/licenses/LICENSE-2.0
+
+   Unless required by applicable law or agreed to in writing, software
+   distributed under the License is distributed on an "AS IS" BASIS,
+   WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+   See the License for the specific language governing permissions and
+   limitations under the License.
+*/
+
+use bevy::{
+    prelude::*,
+    core::FrameCount,
+};
+
+use std::{
+    fs::{self, File},
+    io::{BufWriter, Write},
+    path::Path,
+};
+
+use crate::{
+    aqs_utils::constants::Constants,
+    water::{
+        grid::Grid,
+        resources::{
+            FluidParticlePosition,
+            FluidParticleVelocity,
+            FluidQuantityMass,
+            ParticleTag,
+        },
+    },
+};
+
+
+pub const PARTICLE_CSV_HEADER: &str = "id,x,y,z,vx,vy,vz,mass";
+
+/// one csv line of a particle; position and velocity in world units
+pub fn particle_csv_row(id: usize, position: Vec3, velocity: Vec3, mass: f32) -> String {
+    format!("{},{},{},{},{},{},{},{}",
+            id, position.x, position.y, position.z, velocity.x, velocity.y, velocity.z, mass)
+}
+
+fn write_snapshot<'a>(
+    path: &Path,
+    grid: &Grid,
+    particles: impl Iterator<Item = (&'a ParticleTag, &'a FluidParticlePosition, &'a FluidParticleVelocity, &'a FluidQuantityMass)>,
+) -> std::io::Result<usize> {
+    if let Some( dir ) = path.parent() {
+        fs::create_dir_all( dir )?;
+    }
+    // streamed line by line; there can be millions of particles
+    let mut out = BufWriter::new( File::create( path )? );
+    writeln!( out, "{}", PARTICLE_CSV_HEADER )?;
+    let mut count = 0;
+    for (tag, position, velocity, mass) in particles {
+        let world_position = grid.to_world_coord( position.0.into() );
+        let world_velocity = grid.get_scaler().to( velocity.0.into() );
+        writeln!( out, "{}", particle_csv_row( tag.0, world_position, world_velocity, mass.0 ) )?;
+        count += 1;
+    }
+    out.flush()?;
+    Ok( count )
+}
+
+
+/// E writes all fluid particles to PARTICLE_EXPORT_DIR/particles_<frame>.csv
+pub fn export_particles_on_key(
+    keys: Res<ButtonInput<KeyCode>>,
+    constants: Res<Constants>,
+    grid: Res<Grid>,
+    frame: Res<FrameCount>,
+    particles: Query<(&ParticleTag, &FluidParticlePosition, &FluidParticleVelocity, &FluidQuantityMass)>,
+) {
+    if !keys.just_pressed( KeyCode::KeyE ) {
+        return;
+    }
+    let path = Path::new( &constants.PARTICLE_EXPORT_DIR ).join( format!("particles_{}.csv", frame.0) );
+    match write_snapshot( &path, &grid, particles.iter() ) {
+        Ok( count ) => info!("exported {} particles to {}", count, path.display()),
+        Err( e ) => warn!("particle export to {} failed: {}", path.display(), e),
+    }
+}
+
+
+
+#[cfg(test)]
+mod test
+{
+    use super::*;
+
+    #[test]
+    fn test_particle_csv_row() {
+        let row = particle_csv_row( 7, Vec3::new( 1.5, -2.0, 3.0 ), Vec3::new( 0.0, 0.25, -1.0 ), 0.125 );
+        assert_eq!( row, "7,1.5,-2,3,0,0.25,-1,0.125" );
+        assert_eq!( row.split( ',' ).count(), PARTICLE_CSV_HEADER.split( ',' ).count() );
+    }
+}
@@ -0,0 +1,112 @@
+/*
+    Copyright 2023 github.com/programaquatic
+
+   Licensed under the Apache License, Version 2.0 (the "License");
+   you may not use this file except in compliance with the License.
+   You may obtain a copy of the License at
+
+       http://www.apache.org/licenses/LICENSE-2.0
+
+   Unless required by applicable law or agreed to in writing, software
+   distributed under the License is distributed on an "AS IS" BASIS,
+   WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+   See the License for the specific language governing permissions and
+   limitations under the License.
+*/
+
+use bevy::{
+    prelude::*,
+    math::{Mat3A, Vec3A},
+};
+
+use crate::water::resources::{AffineMomentum, ParticleTag};
+
+/// marker length per unit of angular velocity (grid units) and its upper limit
+const MARKER_SCALE: f32 = 2.0;
+const MARKER_MAX_LENGTH: f32 = 1.0;
+
+
+/// Shows the local rotation of the visible particles as arrows along their rotation axis (R toggles)
+#[derive(Resource, Debug, Default)]
+pub struct RotationMarkers {
+    pub enabled: bool,
+}
+
+
+/// angular velocity from the antisymmetric part of the affine momentum C
+///   W = (C - C^T) / 2 is the rotation part of the velocity gradient; its axial vector is the angular velocity
+pub fn angular_velocity(c: &Mat3A) -> Vec3A {
+    Vec3A::new(
+        c.col( 1 ).z - c.col( 2 ).y,
+        c.col( 2 ).x - c.col( 0 ).z,
+        c.col( 0 ).y - c.col( 1 ).x,
+    ) * 0.5
+}
+
+
+pub fn toggle_rotation_markers(
+    keys: Res<ButtonInput<KeyCode>>,
+    mut markers: ResMut<RotationMarkers>,
+) {
+    if keys.just_pressed( KeyCode::KeyR ) {
+        markers.enabled = !markers.enabled;
+    }
+}
+
+/// arrows point along the rotation axis (right hand rule); blue for slow, red for fast rotation
+pub fn draw_rotation_markers(
+    markers: Res<RotationMarkers>,
+    particles: Query<(&AffineMomentum, &GlobalTransform, &Visibility), (With<ParticleTag>, With<Mesh3d>)>,
+    mut gizmos: Gizmos,
+) {
+    if !markers.enabled {
+        return;
+    }
+    particles.iter()
+        .filter(| (_, _, visibility) | **visibility != Visibility::Hidden)
+        .for_each(| (affine, transform, _) | {
+            let omega = Vec3::from( angular_velocity( &affine.0 ) ) * MARKER_SCALE;
+            let length = omega.length().min( MARKER_MAX_LENGTH );
+            if length < 1e-3 {
+                return;
+            }
+            let strength = length / MARKER_MAX_LENGTH;
+            gizmos.arrow(
+                transform.translation(),
+                transform.transform_point( omega.normalize() * length ),
+                Color::linear_rgb( strength, 0.0, 1.0 - strength ),
+            );
+        });
+}
+
+
+
+#[cfg(test)]
+mod test
+{
+    use super::*;
+    use crate::water::grid::Grid;
+
+    #[test]
+    fn test_vortex_rotation_direction() {
+        let mut grid = Grid::new( UVec3::new( 12, 12, 12 ), 1.0 );
+        let center = Vec3A::splat( 6.0 );
+        let omega = Vec3A::new( 0.0, 0.0, 0.3 );
+        // rigid rotation around the z-axis through the center
+        for idx in 0..grid.cell_count() {
+            let cell_center = grid.to_3d( idx ).as_vec3a() + 0.5;
+            grid.get_tmp_velo_mut()[ idx ] = omega.cross( cell_center - center );
+        }
+
+        // particles all over the vortex see the same rotation
+        for location in [ Vec3A::new( 4.3, 5.1, 6.0 ), Vec3A::new( 7.8, 3.2, 5.5 ), Vec3A::new( 6.5, 8.4, 4.2 ) ] {
+            let (_, b) = grid.sample_velocity( location );
+            let measured = angular_velocity( &(b * 4.0) );
+            assert!( measured.abs_diff_eq( omega, 1e-4 ), "{} at {}", measured, location );
+        }
+
+        // a pure shear has no rotation
+        let shear = Mat3A::from_cols( Vec3A::new( 0.0, 1.0, 0.0 ), Vec3A::new( 1.0, 0.0, 0.0 ), Vec3A::ZERO );
+        assert_eq!( angular_velocity( &shear ), Vec3A::ZERO );
+    }
+}
@@ -0,0 +1,125 @@
+/*
+    Copyright 2023 github.com/programaquatic
+
+   Licensed under the Apache License, Version 2.0 (the "License");
+   you may not use this file except in compliance with the License.
+   You may obtain a copy of the License at
+
+       http://www.apache.org/licenses/LICENSE-2.0
+
+   Unless required by applicable law or agreed to in writing, software
+   distributed under the License is distributed on an "AS IS" BASIS,
+   WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+   See the License for the specific language governing permissions and
+   limitations under the License.
+*/
+
+use bevy::prelude::*;
+
+use std::collections::VecDeque;
+
+use crate::water::grid::{
+    Grid,
+    GridCellIndex,
+    GridCellType,
+};
+
+
+/// A connected region of non-solid cells that holds fluid
+#[derive(Debug, Clone, PartialEq)]
+pub struct FluidComponent {
+    /// number of fluid cells in the region
+    pub fluid_cells: usize,
+    /// bounding box of the region's fluid cells (grid coordinates)
+    pub min: UVec3,
+    pub max: UVec3,
+}
+
+/// connected components of the fluid, largest first
+///   cells connect through their 6 face neighbors if neither is solid, i.e. fluid connected through air counts as one body
+pub fn fluid_components(grid: &Grid, cell_types: &[GridCellType]) -> Vec<FluidComponent> {
+    let size = grid.grid_size().as_ivec3();
+    let mut visited = vec![ false; cell_types.len() ];
+    let mut components = vec![];
+
+    for start in 0..cell_types.len() {
+        if visited[ start ] || cell_types[ start ] != GridCellType::Fluid {
+            continue;
+        }
+        let mut component = FluidComponent { fluid_cells: 0, min: UVec3::MAX, max: UVec3::ZERO };
+        let mut queue = VecDeque::from( [ start ] );
+        visited[ start ] = true;
+        while let Some( idx ) = queue.pop_front() {
+            let xyz = grid.to_3d( idx );
+            if cell_types[ idx ] == GridCellType::Fluid {
+                component.fluid_cells += 1;
+                component.min = component.min.min( xyz );
+                component.max = component.max.max( xyz );
+            }
+            for offset in [ IVec3::X, IVec3::NEG_X, IVec3::Y, IVec3::NEG_Y, IVec3::Z, IVec3::NEG_Z ] {
+                let neighbor = xyz.as_ivec3() + offset;
+                if neighbor.cmplt( IVec3::ZERO ).any() || neighbor.cmpge( size ).any() {
+                    continue;
+                }
+                let nidx = grid.index_of_vec( &neighbor.as_uvec3() );
+                if !visited[ nidx ] && cell_types[ nidx ] != GridCellType::Solid {
+                    visited[ nidx ] = true;
+                    queue.push_back( nidx );
+                }
+            }
+        }
+        components.push( component );
+    }
+    components.sort_by(| a, b | b.fluid_cells.cmp( &a.fluid_cells ));
+    components
+}
+
+
+/// log fluid regions that are cut off from the main body (usually a decoration or shaft placement mistake)
+///   runs once after the decorations got voxelized
+pub fn report_fluid_pockets(
+    grid: Res<Grid>,
+    cells: Query<(&GridCellType, &GridCellIndex)>,
+) {
+    let mut cell_types = vec![ GridCellType::Solid; grid.cell_count() ];
+    cells.iter().for_each(| (gct, idx) | cell_types[ idx.0 ] = gct.clone() );
+
+    let components = fluid_components( &grid, &cell_types );
+    for pocket in components.iter().skip( 1 ) {
+        warn!("isolated fluid pocket of {} cells between {} and {} (grid cells)",
+              pocket.fluid_cells, pocket.min, pocket.max);
+    }
+    if components.len() > 1 {
+        warn!("{} fluid regions are disconnected from the main body ({} cells)",
+              components.len() - 1, components[0].fluid_cells);
+    }
+}
+
+
+
+#[cfg(test)]
+mod test
+{
+    use super::*;
+
+    #[test]
+    fn test_walled_off_pocket() {
+        let grid = Grid::new( UVec3::new( 12, 8, 8 ), 1.0 );
+        let mut cell_types: Vec<GridCellType> = (0..grid.cell_count())
+            .map(| idx | grid.initial_cell_type( grid.to_3d( idx ) ))
+            .collect();
+        assert_eq!( fluid_components( &grid, &cell_types ).len(), 1 );
+
+        // a wall from bottom to top (incl. the air) cuts off the right side
+        for idx in 0..grid.cell_count() {
+            if grid.to_3d( idx ).x == 8 {
+                cell_types[ idx ] = GridCellType::Solid;
+            }
+        }
+        let components = fluid_components( &grid, &cell_types );
+        assert_eq!( components.len(), 2 );
+        assert!( components[0].fluid_cells > components[1].fluid_cells );
+        assert_eq!( components[1].min.x, 9 );
+        assert!( components[0].max.x < 8 );
+    }
+}
@@ -0,0 +1,350 @@
+/*
+    Copyright 2023 github.com/programaquatic
+
+   Licensed under the Apache License, Version 2.0 (the "License");
+   you may not use this file except in compliance with the License.
+   You may obtain a copy of the License at
+
+       http://www.apache.org/licenses/LICENSE-2.0
+
+   Unless required by applicable law or agreed to in writing, software
+   distributed under the License is distributed on an "AS IS" BASIS,
+   WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+   See the License for the specific language governing permissions and
+   limitations under the License.
+*/
+
+use bevy::{
+    prelude::*,
+    core_pipeline::core_3d::Transparent3d,
+    ecs::{
+        query::QueryItem,
+        system::{lifetimeless::{Read, SRes}, SystemParamItem},
+    },
+    pbr::{MeshPipeline, MeshPipelineKey, RenderMeshInstances, SetMeshBindGroup, SetMeshViewBindGroup},
+    render::{
+        extract_component::{ExtractComponent, ExtractComponentPlugin},
+        mesh::{allocator::MeshAllocator, MeshVertexBufferLayoutRef, RenderMesh, RenderMeshBufferInfo},
+        render_asset::RenderAssets,
+        render_phase::{
+            AddRenderCommand, DrawFunctions, PhaseItem, PhaseItemExtraIndex, RenderCommand,
+            RenderCommandResult, SetItemPipeline, TrackedRenderPass, ViewSortedRenderPhases,
+        },
+        render_resource::{
+            BufferInitDescriptor, BufferUsages, PipelineCache, RenderPipelineDescriptor, SpecializedMeshPipeline,
+            SpecializedMeshPipelineError, SpecializedMeshPipelines, VertexAttribute, VertexBufferLayout,
+            VertexFormat, VertexStepMode,
+        },
+        renderer::RenderDevice,
+        sync_world::MainEntity,
+        view::{ExtractedView, NoFrustumCulling},
+        Render, RenderApp, RenderSet,
+    },
+};
+use bytemuck::{Pod, Zeroable};
+
+use crate::{
+    aqs_utils::{
+        billboard::billboard_mesh,
+        constants::{Constants, ParticleRenderer},
+    },
+    water::{
+        grid::{Grid, GridCellType},
+        resources,
+        clip_plane::ClipPlane,
+    },
+};
+
+pub const POINT_CLOUD_NAME: &str = "Particle_Cloud";
+const POINT_CLOUD_SHADER: &str = "shaders/point_cloud.wgsl";
+const POINT_CLOUD_COLOR: [f32; 4] = [ 0.1, 0.3, 1.0, 1.0 ];
+
+
+/// Per particle data of the instanced draw: world position + radius and color
+#[derive(Clone, Copy, Debug, PartialEq, Pod, Zeroable)]
+#[repr(C)]
+pub struct PointInstance {
+    pub position_radius: [f32; 4],
+    pub color: [f32; 4],
+}
+
+/// The instances of the point cloud; extracted into the render world every frame
+///   the entity holds a single billboard quad that gets drawn once per instance
+#[derive(Component, Default, Clone)]
+pub struct PointCloudInstances(pub Vec<PointInstance>);
+
+impl ExtractComponent for PointCloudInstances {
+    type QueryData = &'static PointCloudInstances;
+    type QueryFilter = ();
+    type Out = Self;
+
+    fn extract_component(item: QueryItem<'_, Self::QueryData>) -> Option<Self> {
+        Some( item.clone() )
+    }
+}
+
+
+/// instance data of the particles at the given world positions
+pub fn point_instances(positions: impl Iterator<Item = Vec3>, radius: f32) -> Vec<PointInstance> {
+    positions
+        .map(| p | PointInstance { position_radius: [ p.x, p.y, p.z, radius ], color: POINT_CLOUD_COLOR })
+        .collect()
+}
+
+
+/// draws all particles of the point cloud with a single instanced draw call
+pub struct PointCloudPlugin;
+
+impl Plugin for PointCloudPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_plugins(ExtractComponentPlugin::<PointCloudInstances>::default());
+        // no render world in headless runs
+        let Some( render_app ) = app.get_sub_app_mut( RenderApp ) else {
+            return;
+        };
+        render_app
+            .add_render_command::<Transparent3d, DrawPointCloud>()
+            .init_resource::<SpecializedMeshPipelines<PointCloudPipeline>>()
+            .add_systems(Render, (
+                queue_point_cloud.in_set(RenderSet::QueueMeshes),
+                prepare_instance_buffers.in_set(RenderSet::PrepareResources),
+            ));
+    }
+
+    fn finish(&self, app: &mut App) {
+        // the mesh pipeline only exists once the renderer is up
+        if let Some( render_app ) = app.get_sub_app_mut( RenderApp ) {
+            render_app.init_resource::<PointCloudPipeline>();
+        }
+    }
+}
+
+
+pub fn init_point_cloud(
+    constants: Res<Constants>,
+    grid: Res<Grid>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut commands: Commands,
+) {
+    if constants.PARTICLE_RENDERER != ParticleRenderer::PointCloud {
+        return;
+    }
+    // the shader turns the unit quad towards the camera and scales it by the particle radius
+    let quad = meshes.add( billboard_mesh( &[ Vec3::ZERO ], 1.0, Vec3::X, Vec3::Y ) );
+    // same placement as the particle frame, but not a child of it (that one only holds particles)
+    commands.spawn((
+        Name::new( POINT_CLOUD_NAME ),
+        Mesh3d( quad ),
+        Transform::from_translation( grid.to_world_coord( -Vec3::ONE ) ),
+        PointCloudInstances::default(),
+        // the quad at the origin doesn't bound the particles
+        NoFrustumCulling,
+    ));
+}
+
+/// refill the instances from the current particle positions
+pub fn update_point_cloud(
+    constants: Res<Constants>,
+    grid: Res<Grid>,
+    mut cloud: Query<(&GlobalTransform, &mut PointCloudInstances)>,
+    clip: Res<ClipPlane>,
+    particles: Query<&resources::FluidParticlePosition, (With<resources::ParticleTag>, Without<GridCellType>)>,
+) {
+    let Ok( (placement, mut instances) ) = cloud.get_single_mut() else {
+        return;
+    };
+    instances.0 = point_instances(
+        particles.iter()
+            .filter(| p | !clip.is_clipped( p.0 ))
+            .map(| p | placement.transform_point( Vec3::from( p.0 ) )),
+        constants.POINT_CLOUD_RADIUS / grid.get_scale() );
+}
+
+
+/// GPU copy of the instances of a point cloud entity
+#[derive(Component)]
+struct PointInstanceBuffer {
+    buffer: bevy::render::render_resource::Buffer,
+    length: usize,
+}
+
+fn prepare_instance_buffers(
+    mut commands: Commands,
+    clouds: Query<(Entity, &PointCloudInstances)>,
+    render_device: Res<RenderDevice>,
+) {
+    for (entity, instances) in &clouds {
+        let buffer = render_device.create_buffer_with_data( &BufferInitDescriptor {
+            label: Some( "point cloud instance buffer" ),
+            contents: bytemuck::cast_slice( instances.0.as_slice() ),
+            usage: BufferUsages::VERTEX | BufferUsages::COPY_DST,
+        });
+        commands.entity( entity ).insert( PointInstanceBuffer { buffer, length: instances.0.len() } );
+    }
+}
+
+fn queue_point_cloud(
+    draw_functions: Res<DrawFunctions<Transparent3d>>,
+    cloud_pipeline: Res<PointCloudPipeline>,
+    mut pipelines: ResMut<SpecializedMeshPipelines<PointCloudPipeline>>,
+    pipeline_cache: Res<PipelineCache>,
+    meshes: Res<RenderAssets<RenderMesh>>,
+    render_mesh_instances: Res<RenderMeshInstances>,
+    clouds: Query<(Entity, &MainEntity), With<PointCloudInstances>>,
+    mut phases: ResMut<ViewSortedRenderPhases<Transparent3d>>,
+    views: Query<(Entity, &ExtractedView, &Msaa)>,
+) {
+    let draw_point_cloud = draw_functions.read().id::<DrawPointCloud>();
+    for (view_entity, view, msaa) in &views {
+        let Some( phase ) = phases.get_mut( &view_entity ) else {
+            continue;
+        };
+        let view_key = MeshPipelineKey::from_msaa_samples( msaa.samples() ) | MeshPipelineKey::from_hdr( view.hdr );
+        let rangefinder = view.rangefinder3d();
+        for (entity, main_entity) in &clouds {
+            let Some( mesh_instance ) = render_mesh_instances.render_mesh_queue_data( *main_entity ) else {
+                continue;
+            };
+            let Some( mesh ) = meshes.get( mesh_instance.mesh_asset_id ) else {
+                continue;
+            };
+            let key = view_key | MeshPipelineKey::from_primitive_topology( mesh.primitive_topology() );
+            let pipeline = match pipelines.specialize( &pipeline_cache, &cloud_pipeline, key, &mesh.layout ) {
+                Ok( pipeline ) => pipeline,
+                Err( e ) => {
+                    error!("point cloud pipeline: {}", e);
+                    continue;
+                },
+            };
+            phase.add( Transparent3d {
+                entity: (entity, *main_entity),
+                pipeline,
+                draw_function: draw_point_cloud,
+                distance: rangefinder.distance_translation( &mesh_instance.translation ),
+                batch_range: 0..1,
+                extra_index: PhaseItemExtraIndex::NONE,
+            });
+        }
+    }
+}
+
+
+/// the mesh pipeline with the point cloud shader and the instance buffer as second vertex buffer
+#[derive(Resource)]
+struct PointCloudPipeline {
+    shader: Handle<Shader>,
+    mesh_pipeline: MeshPipeline,
+}
+
+impl FromWorld for PointCloudPipeline {
+    fn from_world(world: &mut World) -> Self {
+        PointCloudPipeline {
+            shader: world.load_asset( POINT_CLOUD_SHADER ),
+            mesh_pipeline: world.resource::<MeshPipeline>().clone(),
+        }
+    }
+}
+
+impl SpecializedMeshPipeline for PointCloudPipeline {
+    type Key = MeshPipelineKey;
+
+    fn specialize(
+        &self,
+        key: Self::Key,
+        layout: &MeshVertexBufferLayoutRef,
+    ) -> Result<RenderPipelineDescriptor, SpecializedMeshPipelineError> {
+        let mut descriptor = self.mesh_pipeline.specialize( key, layout )?;
+        descriptor.vertex.shader = self.shader.clone();
+        descriptor.vertex.buffers.push( VertexBufferLayout {
+            array_stride: std::mem::size_of::<PointInstance>() as u64,
+            step_mode: VertexStepMode::Instance,
+            attributes: vec![
+                VertexAttribute { format: VertexFormat::Float32x4, offset: 0, shader_location: 3 },
+                VertexAttribute { format: VertexFormat::Float32x4, offset: VertexFormat::Float32x4.size(), shader_location: 4 },
+            ],
+        });
+        if let Some( fragment ) = descriptor.fragment.as_mut() {
+            fragment.shader = self.shader.clone();
+        }
+        Ok( descriptor )
+    }
+}
+
+type DrawPointCloud = (
+    SetItemPipeline,
+    SetMeshViewBindGroup<0>,
+    SetMeshBindGroup<1>,
+    DrawQuadInstanced,
+);
+
+/// one draw of the quad with an instance per particle
+struct DrawQuadInstanced;
+
+impl<P: PhaseItem> RenderCommand<P> for DrawQuadInstanced {
+    type Param = (SRes<RenderAssets<RenderMesh>>, SRes<RenderMeshInstances>, SRes<MeshAllocator>);
+    type ViewQuery = ();
+    type ItemQuery = Read<PointInstanceBuffer>;
+
+    fn render<'w>(
+        item: &P,
+        _view: (),
+        instances: Option<&'w PointInstanceBuffer>,
+        (meshes, render_mesh_instances, mesh_allocator): SystemParamItem<'w, '_, Self::Param>,
+        pass: &mut TrackedRenderPass<'w>,
+    ) -> RenderCommandResult {
+        let mesh_allocator = mesh_allocator.into_inner();
+        let Some( mesh_instance ) = render_mesh_instances.render_mesh_queue_data( item.main_entity() ) else {
+            return RenderCommandResult::Skip;
+        };
+        let Some( gpu_mesh ) = meshes.into_inner().get( mesh_instance.mesh_asset_id ) else {
+            return RenderCommandResult::Skip;
+        };
+        let Some( instances ) = instances else {
+            return RenderCommandResult::Skip;
+        };
+        if instances.length == 0 {
+            return RenderCommandResult::Skip;
+        }
+        let Some( vertices ) = mesh_allocator.mesh_vertex_slice( &mesh_instance.mesh_asset_id ) else {
+            return RenderCommandResult::Skip;
+        };
+
+        pass.set_vertex_buffer( 0, vertices.buffer.slice(..) );
+        pass.set_vertex_buffer( 1, instances.buffer.slice(..) );
+        let instance_range = 0..instances.length as u32;
+        match &gpu_mesh.buffer_info {
+            RenderMeshBufferInfo::Indexed { index_format, count } => {
+                let Some( indices ) = mesh_allocator.mesh_index_slice( &mesh_instance.mesh_asset_id ) else {
+                    return RenderCommandResult::Skip;
+                };
+                pass.set_index_buffer( indices.buffer.slice(..), 0, *index_format );
+                pass.draw_indexed( indices.range.start..(indices.range.start + count), vertices.range.start as i32, instance_range );
+            },
+            RenderMeshBufferInfo::NonIndexed => {
+                pass.draw( vertices.range, instance_range );
+            },
+        }
+        RenderCommandResult::Success
+    }
+}
+
+
+
+#[cfg(test)]
+mod test
+{
+    use super::*;
+
+    #[test]
+    fn test_point_instances() {
+        let positions = [ Vec3::ZERO, Vec3::new( 5.0, 1.0, 2.0 ) ];
+        let instances = point_instances( positions.into_iter(), 0.1 );
+        assert_eq!( instances.len(), 2 );
+        assert_eq!( instances[1].position_radius, [ 5.0, 1.0, 2.0, 0.1 ] );
+        // tightly packed for the vertex buffer: two vec4 per instance
+        assert_eq!( std::mem::size_of::<PointInstance>(), 32 );
+        assert_eq!( bytemuck::cast_slice::<PointInstance, f32>( &instances ).len(), 16 );
+
+        assert!( point_instances( std::iter::empty(), 0.1 ).is_empty() );
+    }
+}
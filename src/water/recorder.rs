@@ -0,0 +1,234 @@
+/*
+    Copyright 2023 github.com/programaquatic
+
+   Licensed under the Apache License, Version 2.0 (the "License");
+   you may not use this file except in compliance with the License.
+   You may obtain a copy of the License at
+
+       http://www.apache.org/licenses/LICENSE-2.0
+
+   Unless required by applicable law or agreed to in writing, software
+   distributed under the License is distributed on an "AS IS" BASIS,
+   WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+   See the License for the specific language governing permissions and
+   limitations under the License.
+*/
+
+use bevy::{
+    prelude::*,
+    utils::HashMap,
+};
+use serde::{Serialize, Deserialize};
+
+use std::{
+    fs,
+    path::Path,
+};
+
+use crate::water::resources::{
+    FluidParticlePosition,
+    ParticleTag,
+};
+
+
+/// file the recording gets written to when recording stops and read from if playback starts without one
+pub const RECORDING_FILE: &str = "recording.bin";
+
+
+/// particle positions of a run, one frame per simulation step
+#[derive(Serialize, Deserialize, Debug, Default, Clone, PartialEq)]
+pub struct Recording {
+    /// time between two frames (seconds)
+    pub dt: f32,
+    /// (unique particle tag, grid position) of all particles per frame
+    pub frames: Vec<Vec<(usize, Vec3A)>>,
+}
+
+impl Recording {
+    pub fn to_bytes(&self) -> Result<Vec<u8>, String> {
+        bincode::serialize( self ).map_err(| e | e.to_string())
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, String> {
+        bincode::deserialize( bytes ).map_err(| e | e.to_string())
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), String> {
+        fs::write( path, self.to_bytes()? ).map_err(| e | format!("{}: {}", path.display(), e))
+    }
+
+    pub fn load(path: &Path) -> Result<Self, String> {
+        Self::from_bytes( &fs::read( path ).map_err(| e | format!("{}: {}", path.display(), e))? )
+    }
+
+    pub fn duration(&self) -> f32 {
+        self.dt * self.frames.len().saturating_sub( 1 ) as f32
+    }
+
+    /// positions at the given time, linearly interpolated between the two neighboring frames;
+    ///   particles missing from the later frame stay at their earlier position
+    pub fn sample(&self, time: f32) -> HashMap<usize, Vec3A> {
+        let Some( last ) = self.frames.len().checked_sub( 1 ) else {
+            return HashMap::default();
+        };
+        let position = if self.dt > 0.0 { (time / self.dt).clamp( 0.0, last as f32 ) } else { 0.0 };
+        let index = (position.floor() as usize).min( last );
+        let alpha = position - index as f32;
+        let next: HashMap<usize, Vec3A> = self.frames[ (index + 1).min( last ) ].iter().copied().collect();
+        self.frames[ index ].iter()
+            .map(| (tag, location) | {
+                let target = next.get( tag ).copied().unwrap_or( *location );
+                (*tag, location.lerp( target, alpha ))
+            })
+            .collect()
+    }
+}
+
+
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub enum RecorderMode {
+    #[default]
+    Idle,
+    Recording,
+    /// replay of the recording instead of running the solver
+    Playback,
+}
+
+/// Record/playback of particle trajectories (T toggles recording, P toggles playback)
+#[derive(Resource, Debug, Default)]
+pub struct Recorder {
+    pub mode: RecorderMode,
+    pub recording: Recording,
+    /// position within the recording during playback (seconds)
+    pub playback_time: f32,
+}
+
+
+/// run condition of the simulation steps; the solver is off during playback
+pub fn not_playing_back(recorder: Res<Recorder>) -> bool {
+    recorder.mode != RecorderMode::Playback
+}
+
+pub fn control_recorder(
+    keys: Res<ButtonInput<KeyCode>>,
+    fixed_time: Res<Time<Fixed>>,
+    mut recorder: ResMut<Recorder>,
+) {
+    if keys.just_pressed( KeyCode::KeyT ) {
+        match recorder.mode {
+            RecorderMode::Recording => {
+                recorder.mode = RecorderMode::Idle;
+                match recorder.recording.save( Path::new( RECORDING_FILE ) ) {
+                    Ok( () ) => info!("recorded {} frames to {}", recorder.recording.frames.len(), RECORDING_FILE),
+                    Err( e ) => warn!("failed to save the recording: {}", e),
+                }
+            },
+            _ => {
+                recorder.mode = RecorderMode::Recording;
+                recorder.recording = Recording { dt: fixed_time.timestep().as_secs_f32(), frames: vec![] };
+                info!("recording started");
+            },
+        }
+    }
+    if keys.just_pressed( KeyCode::KeyP ) {
+        if recorder.mode == RecorderMode::Playback {
+            recorder.mode = RecorderMode::Idle;
+            info!("playback stopped");
+            return;
+        }
+        if recorder.recording.frames.is_empty() {
+            match Recording::load( Path::new( RECORDING_FILE ) ) {
+                Ok( recording ) => recorder.recording = recording,
+                Err( e ) => {
+                    warn!("nothing to play back: {}", e);
+                    return;
+                }
+            }
+        }
+        recorder.mode = RecorderMode::Playback;
+        recorder.playback_time = 0.0;
+        info!("playing back {} frames", recorder.recording.frames.len());
+    }
+}
+
+/// append the particle positions of the finished simulation step
+pub fn record_positions(
+    mut recorder: ResMut<Recorder>,
+    particles: Query<(&ParticleTag, &FluidParticlePosition)>,
+) {
+    if recorder.mode != RecorderMode::Recording {
+        return;
+    }
+    let frame = particles.iter().map(| (tag, location) | (tag.0, location.0)).collect();
+    recorder.recording.frames.push( frame );
+}
+
+/// move the particles along the recording (looping); particle_world_update takes it from there
+pub fn play_back(
+    time: Res<Time>,
+    mut recorder: ResMut<Recorder>,
+    mut particles: Query<(&ParticleTag, &mut FluidParticlePosition)>,
+) {
+    if recorder.mode != RecorderMode::Playback {
+        return;
+    }
+    let duration = recorder.recording.duration();
+    recorder.playback_time += time.delta_secs();
+    if recorder.playback_time > duration {
+        recorder.playback_time = 0.0;
+    }
+    let positions = recorder.recording.sample( recorder.playback_time );
+    particles.par_iter_mut().for_each(| (tag, mut location) | {
+        if let Some( recorded ) = positions.get( &tag.0 ) {
+            location.0 = *recorded;
+        }
+    });
+}
+
+
+
+#[cfg(test)]
+mod test
+{
+    use super::*;
+
+    fn test_recording() -> Recording {
+        Recording {
+            dt: 0.5,
+            frames: vec![
+                vec![ (0, Vec3A::new( 1.0, 2.0, 3.0 )), (1, Vec3A::ZERO) ],
+                vec![ (0, Vec3A::new( 2.0, 2.0, 3.0 )), (1, Vec3A::ONE) ],
+                vec![ (1, Vec3A::splat( 2.0 )), (0, Vec3A::new( 4.0, 2.0, 3.0 )) ],
+            ],
+        }
+    }
+
+    #[test]
+    fn test_recording_round_trip() {
+        let recording = test_recording();
+        let bytes = recording.to_bytes().unwrap();
+        assert_eq!( Recording::from_bytes( &bytes ).unwrap(), recording );
+
+        let path = std::env::temp_dir().join( format!("lisal_recording_{}.bin", std::process::id()) );
+        recording.save( &path ).unwrap();
+        let loaded = Recording::load( &path );
+        std::fs::remove_file( &path ).unwrap();
+        assert_eq!( loaded.unwrap(), recording );
+
+        assert!( Recording::from_bytes( &bytes[ ..bytes.len() / 2 ] ).is_err() );
+    }
+
+    #[test]
+    fn test_sample_interpolates() {
+        let recording = test_recording();
+        assert_eq!( recording.duration(), 1.0 );
+        assert_eq!( recording.sample( 0.0 )[ &0 ], Vec3A::new( 1.0, 2.0, 3.0 ) );
+        assert_eq!( recording.sample( 0.25 )[ &0 ], Vec3A::new( 1.5, 2.0, 3.0 ) );
+        // matched by tag even if the order changed
+        assert_eq!( recording.sample( 0.75 )[ &0 ], Vec3A::new( 3.0, 2.0, 3.0 ) );
+        assert_eq!( recording.sample( 0.75 )[ &1 ], Vec3A::splat( 1.5 ) );
+        // clamped to the last frame
+        assert_eq!( recording.sample( 5.0 )[ &1 ], Vec3A::splat( 2.0 ) );
+        assert!( Recording::default().sample( 0.0 ).is_empty() );
+    }
+}
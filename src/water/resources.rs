@@ -17,16 +17,49 @@
 use bevy::{
     prelude::*, math::{Vec3A, Mat3A},
 };
+use rand::{
+    SeedableRng,
+    rngs::StdRng,
+};
+
+/// random stream of the initial particle positions
+pub const SEED_STREAM_INIT: u64 = 0;
+/// random stream of the particles injected by the spray bar
+pub const SEED_STREAM_SPRAY: u64 = 1;
+
+/// Seed for reproducible runs (--seed); without it the random streams are seeded from entropy
+#[derive(Resource, Debug, Clone, Copy)]
+pub struct SimSeed(pub u64);
+
+impl SimSeed {
+    /// independent seed of one random stream
+    pub fn stream(&self, stream: u64) -> u64 {
+        self.0 ^ stream.wrapping_mul( 0x9E37_79B9_7F4A_7C15 )
+    }
+}
+
+/// rng of a random stream; seeded if a SimSeed is set
+pub fn stream_rng(seed: Option<&SimSeed>, stream: u64) -> StdRng {
+    match seed {
+        Some( seed ) => StdRng::seed_from_u64( seed.stream( stream ) ),
+        None => StdRng::from_entropy(),
+    }
+}
 
 #[derive(Component)]
 pub struct ParticleFrameTag;
 
+/// unique id of a fluid particle (handed out by the ParticleSpawner)
 #[derive(Component)]
 pub struct ParticleTag(pub usize);
 
 #[derive(Component)]
 pub struct SolidParticleTag(pub usize);
 
+/// deformation gradient F of an elastic solid particle
+#[derive(Component, Debug)]
+pub struct DeformationGradient(pub Mat3A);
+
 #[derive(Component, Debug)]
 pub struct FluidParticlePosition(pub Vec3A);
 
@@ -36,6 +69,18 @@ pub struct FluidParticleVelocity(pub Vec3A);
 #[derive(Component, Debug)]
 pub struct FluidQuantityMass(pub f32);
 
+/// id of the inlet a particle last came out of (0 if it hasn't been pumped)
+#[derive(Component, Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct InletSource(pub u8);
+
+/// water temperature of a particle in °C
+#[derive(Component, Debug, Clone, Copy, PartialEq)]
+pub struct FluidTemperature(pub f32);
+
+/// fluid phase of a particle; selects the constitutive model (0 = FLUID_MODEL)
+#[derive(Component, Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct FluidPhase(pub u8);
+
 
 // computed changes to-be-applied to grid on next steps
 #[derive(Clone, Copy)]
@@ -45,6 +90,11 @@ pub struct CellMMAChange {
     pub momentum: Vec3A,
 }
 
+impl CellMMAChange {
+    /// no change (e.g. for neighbors outside of the grid)
+    pub const NONE: CellMMAChange = CellMMAChange { cell_idx: 0, mass: 0.0, momentum: Vec3A::ZERO };
+}
+
 #[derive(Component)]
 pub struct CellMMAccumulation(pub(super) [CellMMAChange; 27]);
 
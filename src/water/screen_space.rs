@@ -0,0 +1,251 @@
+/*
+    Copyright 2023 github.com/programaquatic
+
+   Licensed under the Apache License, Version 2.0 (the "License");
+   you may not use this file except in compliance with the License.
+   You may obtain a copy of the License at
+
+       http://www.apache.org/licenses/LICENSE-2.0
+
+   Unless required by applicable law or agreed to in writing, software
+   distributed under the License is distributed on an "AS IS" BASIS,
+   WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+   See the License for the specific language governing permissions and
+   limitations under the License.
+*/
+
+use bevy::{
+    prelude::*,
+    core_pipeline::tonemapping::{DebandDither, Tonemapping},
+    render::{
+        camera::RenderTarget,
+        render_asset::RenderAssetUsages,
+        render_resource::{
+            AsBindGroup,
+            Extent3d,
+            ShaderRef,
+            TextureDimension,
+            TextureFormat,
+            TextureUsages,
+        },
+        view::{NoFrustumCulling, RenderLayers},
+    },
+    window::PrimaryWindow,
+};
+
+use crate::{
+    aqs_utils::{
+        billboard::billboard_mesh,
+        constants::{Constants, ParticleRenderer},
+    },
+    water::{
+        grid::{Grid, GridCellType},
+        resources,
+        clip_plane::ClipPlane,
+    },
+};
+
+pub const SCREEN_SPACE_FLUID_NAME: &str = "Screen_Space_Fluid";
+/// render layer of the particle billboards; only the depth camera sees them
+const DEPTH_LAYER: usize = 1;
+
+
+/// camera that renders the view-space depth of the particles into the depth target
+#[derive(Component)]
+pub struct FluidDepthCamera;
+
+#[derive(Component)]
+pub struct ScreenSpaceFluidTag(Handle<Mesh>);
+
+/// offscreen target of the depth pass and the material that composes it onto the screen
+#[derive(Resource)]
+pub struct FluidDepthTarget {
+    pub image: Handle<Image>,
+    composite: Handle<FluidCompositeMaterial>,
+}
+
+
+/// writes the linear view depth of a sphere per particle (red) and its coverage (alpha)
+#[derive(AsBindGroup, TypePath, Debug, Clone, Asset)]
+pub struct FluidDepthMaterial {
+    /// x: particle radius (world units)
+    #[uniform(0)]
+    params: Vec4,
+}
+
+impl Material for FluidDepthMaterial {
+    fn fragment_shader() -> ShaderRef {
+        "shaders/fluid_depth.wgsl".into()
+    }
+}
+
+/// bilateral blur of the depth, normal reconstruction and shading of the fluid surface
+#[derive(AsBindGroup, TypePath, Debug, Clone, Asset)]
+pub struct FluidCompositeMaterial {
+    #[uniform(0)]
+    color: LinearRgba,
+    /// x: blur radius (texels), y: depth falloff (1/world units), z: tan(fov/2), w: aspect ratio
+    #[uniform(1)]
+    params: Vec4,
+    #[texture(2)]
+    depth: Handle<Image>,
+}
+
+impl UiMaterial for FluidCompositeMaterial {
+    fn fragment_shader() -> ShaderRef {
+        "shaders/fluid_composite.wgsl".into()
+    }
+}
+
+
+/// float target for the depth pass; alpha 0 where no particle got rendered
+fn depth_target_image(size: UVec2) -> Image {
+    let mut image = Image::new_fill(
+        Extent3d { width: size.x.max( 1 ), height: size.y.max( 1 ), depth_or_array_layers: 1 },
+        TextureDimension::D2,
+        &[0; 8],
+        TextureFormat::Rgba16Float,
+        RenderAssetUsages::default(),
+    );
+    image.texture_descriptor.usage = TextureUsages::TEXTURE_BINDING | TextureUsages::COPY_DST | TextureUsages::RENDER_ATTACHMENT;
+    image
+}
+
+
+/// depth camera as a child of the main camera (same view) plus the full screen composite
+///   runs after the camera plugin spawned the main camera
+pub fn init_screen_space_fluid(
+    constants: Res<Constants>,
+    grid: Res<Grid>,
+    window: Query<&Window, With<PrimaryWindow>>,
+    main_camera: Query<Entity, (With<Camera3d>, Without<FluidDepthCamera>)>,
+    mut images: ResMut<Assets<Image>>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut depth_materials: ResMut<Assets<FluidDepthMaterial>>,
+    mut composite_materials: ResMut<Assets<FluidCompositeMaterial>>,
+    mut commands: Commands,
+) {
+    if constants.PARTICLE_RENDERER != ParticleRenderer::ScreenSpace {
+        return;
+    }
+    let (Ok( window ), Ok( main_camera )) = (window.get_single(), main_camera.get_single()) else {
+        warn!("screen space fluid needs a window and a single 3D camera");
+        return;
+    };
+    let conf = &constants.SCREEN_SPACE_FLUID;
+    let image = images.add( depth_target_image( window.physical_size() ) );
+
+    let mesh_hdl = meshes.add( billboard_mesh( &[], 0.0, Vec3::X, Vec3::Y ) );
+    commands.spawn((
+        Name::new( SCREEN_SPACE_FLUID_NAME ),
+        Mesh3d( mesh_hdl.clone() ),
+        MeshMaterial3d( depth_materials.add( FluidDepthMaterial {
+            params: Vec4::new( conf.particle_radius, 0.0, 0.0, 0.0 ),
+        })),
+        Transform::from_translation( grid.to_world_coord( -Vec3::ONE ) ),
+        RenderLayers::layer( DEPTH_LAYER ),
+        // the bounds of the initially empty mesh don't follow the particles
+        NoFrustumCulling,
+        ScreenSpaceFluidTag( mesh_hdl ),
+    ));
+
+    let depth_camera = commands.spawn((
+        Name::new( "Fluid_Depth_Camera" ),
+        Camera3d::default(),
+        Camera {
+            order: -1,
+            // keeps the float precision of the depth; tonemapping and dithering would alter the values
+            hdr: true,
+            target: RenderTarget::Image( image.clone() ),
+            clear_color: ClearColorConfig::Custom( Color::NONE ),
+            ..default()
+        },
+        Tonemapping::None,
+        DebandDither::Disabled,
+        Msaa::Off,
+        RenderLayers::layer( DEPTH_LAYER ),
+        FluidDepthCamera,
+    )).id();
+    commands.entity( main_camera ).add_child( depth_camera );
+
+    let composite = composite_materials.add( FluidCompositeMaterial {
+        color: LinearRgba::new( conf.color.x, conf.color.y, conf.color.z, conf.alpha ),
+        params: Vec4::new( conf.blur_radius as f32, conf.depth_falloff, 0.0, 1.0 ),
+        depth: image.clone(),
+    });
+    commands.spawn((
+        Name::new( "Fluid_Composite" ),
+        Node {
+            position_type: PositionType::Absolute,
+            width: Val::Percent( 100.0 ),
+            height: Val::Percent( 100.0 ),
+            ..default()
+        },
+        MaterialNode( composite.clone() ),
+        // behind the other UI elements
+        GlobalZIndex( -1 ),
+    ));
+    commands.insert_resource( FluidDepthTarget { image, composite } );
+}
+
+
+/// rebuild the billboards facing the main camera from the current particle positions
+pub fn update_screen_space_fluid(
+    constants: Res<Constants>,
+    grid: Res<Grid>,
+    fluid: Query<&ScreenSpaceFluidTag>,
+    camera: Query<&GlobalTransform, (With<Camera3d>, Without<FluidDepthCamera>)>,
+    clip: Res<ClipPlane>,
+    particles: Query<&resources::FluidParticlePosition, (With<resources::ParticleTag>, Without<GridCellType>)>,
+    mut meshes: ResMut<Assets<Mesh>>,
+) {
+    let (Ok( fluid ), Ok( camera )) = (fluid.get_single(), camera.get_single()) else {
+        return;
+    };
+    let positions: Vec<Vec3> = particles.iter()
+        .filter(| p | !clip.is_clipped( p.0 ))
+        .map(| p | Vec3::from( p.0 ))
+        .collect();
+    if let Some( mesh ) = meshes.get_mut( &fluid.0 ) {
+        *mesh = billboard_mesh( &positions,
+                                constants.SCREEN_SPACE_FLUID.particle_radius / grid.get_scale(),
+                                *camera.right(), *camera.up() );
+    }
+}
+
+
+/// keep the depth target at window resolution and the composite in sync with the camera projection
+pub fn resize_depth_target(
+    target: Option<Res<FluidDepthTarget>>,
+    window: Query<&Window, With<PrimaryWindow>>,
+    projection: Query<&Projection, (With<Camera3d>, Without<FluidDepthCamera>)>,
+    mut images: ResMut<Assets<Image>>,
+    mut composite_materials: ResMut<Assets<FluidCompositeMaterial>>,
+) {
+    let (Some( target ), Ok( window )) = (target, window.get_single()) else {
+        return;
+    };
+    let size = window.physical_size().max( UVec2::ONE );
+    let Some( image ) = images.get_mut( &target.image ) else {
+        return;
+    };
+    let fov = match projection.get_single() {
+        Ok( Projection::Perspective( perspective ) ) => perspective.fov,
+        _ => PerspectiveProjection::default().fov,
+    };
+    let resized = image.size() != size;
+    if resized {
+        image.resize( Extent3d { width: size.x, height: size.y, depth_or_array_layers: 1 } );
+    }
+    let tan_half_fov = (fov * 0.5).tan();
+    let aspect = size.x as f32 / size.y as f32;
+    let outdated = composite_materials.get( &target.composite )
+        .is_some_and(| composite | composite.params.z != tan_half_fov || composite.params.w != aspect);
+    // touching the material also rebinds the resized depth texture
+    if resized || outdated {
+        if let Some( composite ) = composite_materials.get_mut( &target.composite ) {
+            composite.params.z = tan_half_fov;
+            composite.params.w = aspect;
+        }
+    }
+}
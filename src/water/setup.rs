@@ -0,0 +1,138 @@
+/*
+    Copyright 2023 github.com/programaquatic
+
+   Licensed under the Apache License, Version 2.0 (the "License");
+   you may not use this file except in compliance with the License.
+   You may obtain a copy of the License at
+
+       http://www.apache.org/licenses/LICENSE-2.0
+
+   Unless required by applicable law or agreed to in writing, software
+   distributed under the License is distributed on an "AS IS" BASIS,
+   WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+   See the License for the specific language governing permissions and
+   limitations under the License.
+*/
+
+use std::ops::Range;
+
+use bevy::prelude::*;
+
+/// number of grid cells classified against decoration colliders per frame
+pub const COLLIDER_CELLS_PER_FRAME: usize = 2000;
+/// number of grid cells filled with particles per frame
+pub const PARTICLE_CELLS_PER_FRAME: usize = 5000;
+
+
+/// The fluid setup is spread across multiple frames to keep the window responsive
+#[derive(States, Default, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum SetupState {
+    #[default]
+    ClassifyingColliders,
+    SpawningParticles,
+    Running,
+}
+
+/// System set of all per-frame simulation systems; only runs once setup is complete
+#[derive(SystemSet, Debug, Clone, PartialEq, Eq, Hash)]
+pub struct FluidSimSet;
+
+/// Progress of the current incremental setup step
+#[derive(Resource, Default)]
+pub struct SetupProgress {
+    /// first grid cell index of the next chunk
+    pub next_cell: usize,
+    /// total cells to process in the current step
+    pub total_cells: usize,
+}
+
+impl SetupProgress {
+    /// the range of cell indices to process this frame; advances the progress
+    pub fn next_chunk(&mut self, chunk_size: usize) -> Range<usize> {
+        let start = self.next_cell;
+        let end = usize::min(start + chunk_size, self.total_cells);
+        self.next_cell = end;
+        start..end
+    }
+
+    pub fn is_done(&self) -> bool {
+        self.next_cell >= self.total_cells
+    }
+
+    pub fn percent(&self) -> f32 {
+        if self.total_cells == 0 {
+            return 100.0;
+        }
+        self.next_cell as f32 * 100.0 / self.total_cells as f32
+    }
+
+    pub fn restart(&mut self) {
+        self.next_cell = 0;
+    }
+}
+
+#[derive(Component)]
+pub struct LoadingIndicatorTag;
+
+
+pub fn spawn_loading_indicator(
+    mut commands: Commands,
+) {
+    commands.spawn((
+        Name::new("Loading_Indicator"),
+        LoadingIndicatorTag,
+        Text::new("Loading..."),
+        Node {
+            position_type: PositionType::Absolute,
+            top: Val::Px(10.0),
+            left: Val::Px(10.0),
+            ..default()
+        },
+    ));
+}
+
+pub fn update_loading_indicator(
+    state: Res<State<SetupState>>,
+    progress: Res<SetupProgress>,
+    mut indicator: Query<&mut Text, With<LoadingIndicatorTag>>,
+) {
+    let step = match state.get() {
+        SetupState::ClassifyingColliders => "classifying colliders",
+        SetupState::SpawningParticles => "spawning particles",
+        SetupState::Running => return,
+    };
+    indicator.iter_mut().for_each(| mut text | {
+        text.0 = format!("Loading: {} {:.0}%", step, progress.percent());
+    });
+}
+
+pub fn remove_loading_indicator(
+    mut commands: Commands,
+    indicator: Query<Entity, With<LoadingIndicatorTag>>,
+) {
+    indicator.iter().for_each(| item | {
+        commands.entity(item).despawn_recursive();
+    });
+}
+
+
+
+#[cfg(test)]
+mod test
+{
+    use super::*;
+
+    #[test]
+    fn test_progress_chunks() {
+        let mut progress = SetupProgress { next_cell: 0, total_cells: 5 };
+        assert_eq!(progress.next_chunk(2), 0..2);
+        assert_eq!(progress.next_chunk(2), 2..4);
+        assert!(!progress.is_done());
+        assert_eq!(progress.next_chunk(2), 4..5);
+        assert!(progress.is_done());
+        assert_eq!(progress.percent(), 100.0);
+
+        progress.restart();
+        assert_eq!(progress.percent(), 0.0);
+    }
+}
@@ -0,0 +1,102 @@
+/*
+    Copyright 2023 github.com/programaquatic
+
+   Licensed under the Apache License, Version 2.0 (the "License");
+   you may not use this file except in compliance with the License.
+   You may obtain a copy of the License at
+
+       http://www.apache.org/licenses/LICENSE-2.0
+
+   Unless required by applicable law or agreed to in writing, software
+   distributed under the License is distributed on an "AS IS" BASIS,
+   WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+   See the License for the specific language governing permissions and
+   limitations under the License.
+*/
+
+
+use bevy::prelude::*;
+
+
+/// Pause and single-step control of the simulation (space pauses, right arrow steps while paused)
+#[derive(Resource, Debug, Default)]
+pub struct SimControl {
+    pub paused: bool,
+    /// run one simulation step while paused; cleared after the step
+    pub single_step: bool,
+}
+
+impl SimControl {
+    pub fn should_step(&self) -> bool {
+        !self.paused || self.single_step
+    }
+}
+
+
+/// run condition of the simulation step systems
+pub fn sim_running(control: Res<SimControl>) -> bool {
+    control.should_step()
+}
+
+/// clear a single step request once all step systems had their turn
+pub fn finish_single_step(mut control: ResMut<SimControl>) {
+    if control.single_step {
+        control.single_step = false;
+    }
+}
+
+pub fn control_sim(
+    keys: Res<ButtonInput<KeyCode>>,
+    mut control: ResMut<SimControl>,
+) {
+    if keys.just_pressed( KeyCode::Space ) {
+        control.paused = !control.paused;
+        info!("simulation {}", if control.paused { "paused" } else { "resumed" });
+    }
+    if control.paused && keys.just_pressed( KeyCode::ArrowRight ) {
+        control.single_step = true;
+    }
+}
+
+
+
+#[cfg(test)]
+mod test
+{
+    use super::*;
+
+    #[derive(Resource, Default)]
+    struct Steps(usize);
+
+    fn count_step(mut steps: ResMut<Steps>) {
+        steps.0 += 1;
+    }
+
+    #[test]
+    fn test_pause_and_single_step() {
+        let mut world = World::new();
+        world.init_resource::<SimControl>();
+        world.init_resource::<Steps>();
+        let mut schedule = Schedule::default();
+        schedule.add_systems((
+            count_step.run_if( sim_running ),
+            finish_single_step.after( count_step ),
+        ));
+
+        schedule.run( &mut world );
+        assert_eq!( world.resource::<Steps>().0, 1 );
+
+        world.resource_mut::<SimControl>().paused = true;
+        schedule.run( &mut world );
+        schedule.run( &mut world );
+        assert_eq!( world.resource::<Steps>().0, 1 );
+
+        // a step request runs exactly one frame
+        world.resource_mut::<SimControl>().single_step = true;
+        schedule.run( &mut world );
+        assert_eq!( world.resource::<Steps>().0, 2 );
+        assert!( !world.resource::<SimControl>().single_step );
+        schedule.run( &mut world );
+        assert_eq!( world.resource::<Steps>().0, 2 );
+    }
+}
@@ -0,0 +1,74 @@
+/*
+    Copyright 2023 github.com/programaquatic
+
+   Licensed under the Apache License, Version 2.0 (the "License");
+   you may not use this file except in compliance with the License.
+   You may obtain a copy of the License at
+
+       http://www.apache.org/licenses/LICENSE-2.0
+
+   Unless required by applicable law or agreed to in writing, software
+   distributed under the License is distributed on an "AS IS" BASIS,
+   WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+   See the License for the specific language governing permissions and
+   limitations under the License.
+*/
+
+
+use bevy::prelude::*;
+
+use crate::{
+    aqs_utils::constants::Constants,
+    water::resources,
+};
+
+
+/// Time step of the current simulation step (seconds)
+///   WORLD_DT unless ADAPTIVE_DT shortens it; set by update_sim_time at the start of every step
+#[derive(Resource, Debug, Default, Clone, Copy)]
+pub struct SimTime {
+    pub dt: f32,
+}
+
+/// stable time step for the max particle speed: dt = cfl * cell_size / max_speed, clamped to [dt_min..dt_max]
+pub fn cfl_timestep(cfl: f32, cell_size: f32, max_speed: f32, dt_min: f32, dt_max: f32) -> f32 {
+    if max_speed <= 0.0 {
+        return dt_max;
+    }
+    (cfl * cell_size / max_speed).clamp( dt_min.min( dt_max ), dt_max )
+}
+
+pub fn update_sim_time(
+    constants: Res<Constants>,
+    particles: Query<&resources::FluidParticleVelocity, Or<(With<resources::ParticleTag>, With<resources::SolidParticleTag>)>>,
+    mut time: ResMut<SimTime>,
+) {
+    time.dt = match &constants.ADAPTIVE_DT {
+        Some( conf ) => {
+            let max_speed = particles.iter().map(| velocity | velocity.0.length()).fold( 0.0, f32::max );
+            // particle velocities are in grid units, i.e. the cell size is 1
+            cfl_timestep( conf.cfl, 1.0, max_speed, conf.dt_min, constants.WORLD_DT )
+        },
+        None => constants.WORLD_DT,
+    };
+}
+
+
+
+#[cfg(test)]
+mod test
+{
+    use super::*;
+
+    #[test]
+    fn test_cfl_timestep() {
+        // slow enough: the full step
+        assert_eq!( cfl_timestep( 0.5, 1.0, 5.0, 0.005, 0.04 ), 0.04 );
+        assert_eq!( cfl_timestep( 0.5, 1.0, 0.0, 0.005, 0.04 ), 0.04 );
+        // fast: at most half a cell per step
+        assert!( (cfl_timestep( 0.5, 1.0, 25.0, 0.005, 0.04 ) - 0.02).abs() < 1e-6 );
+        assert!( (cfl_timestep( 0.5, 2.0, 25.0, 0.005, 0.04 ) - 0.04).abs() < 1e-6 );
+        // way too fast: never below dt_min
+        assert_eq!( cfl_timestep( 0.5, 1.0, 1000.0, 0.005, 0.04 ), 0.005 );
+    }
+}
@@ -0,0 +1,163 @@
+/*
+    Copyright 2023 github.com/programaquatic
+
+   Licensed under the Apache License, Version 2.0 (the "License");
+   you may not use this file except in compliance with the License.
+   You may obtain a copy of the License at
+
+       http://www.apache.org/licenses/LICENSE-2.0
+
+   Unless required by applicable law or agreed to in writing, software
+   distributed under the License is distributed on an "AS IS" BASIS,
+   WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+   See the License for the specific language governing permissions and
+   limitations under the License.
+*/
+
+use bevy::{
+    prelude::*,
+    math::Vec3A,
+    tasks::{ComputeTaskPool, ParallelSlice, TaskPool},
+};
+
+use crate::water::{
+    grid::{Grid, GridCellType},
+    resources,
+};
+
+/// particles per task when computing the bucket keys in parallel
+const KEYS_PER_TASK: usize = 4096;
+
+
+/// Particles bucketed by the grid cell they are in, for neighbor queries between particles
+///   stored as a counting sort: entries of cell i are entries[cell_start[i]..cell_start[i+1]]
+///   the buffers are kept between frames to avoid reallocation
+///   not registered yet: init the resource and schedule rebuild_spatial_hash together with its first consumer
+#[allow(dead_code)]
+#[derive(Resource, Default)]
+pub struct ParticleSpatialHash {
+    grid_size: UVec3,
+    cell_start: Vec<usize>,
+    entries: Vec<(Entity, Vec3A)>,
+    /// scratch buffer of (cell, entity, position) used during rebuild
+    keyed: Vec<(usize, Entity, Vec3A)>,
+}
+
+#[allow(dead_code)]
+impl ParticleSpatialHash {
+    fn cell_of(&self, position: Vec3A) -> UVec3 {
+        position
+            .max(Vec3A::ZERO)
+            .as_uvec3()
+            .min(self.grid_size.saturating_sub(UVec3::ONE))
+    }
+
+    fn index_of(&self, cell: UVec3) -> usize {
+        (cell.x + cell.y * self.grid_size.x + cell.z * self.grid_size.x * self.grid_size.y) as usize
+    }
+
+    /// rebuild the buckets from (entity, position) pairs in grid coordinates
+    pub fn rebuild(&mut self, grid_size: UVec3, particles: impl Iterator<Item = (Entity, Vec3A)>) {
+        self.grid_size = grid_size;
+        let cell_count = (grid_size.x * grid_size.y * grid_size.z) as usize;
+
+        let positions: Vec<(Entity, Vec3A)> = particles.collect();
+        let keys: Vec<Vec<usize>> = positions.par_chunk_map( ComputeTaskPool::get_or_init( TaskPool::default ), KEYS_PER_TASK,
+            | _, chunk | chunk.iter().map(| (_, pos) | self.index_of( self.cell_of( *pos ) )).collect() );
+
+        self.keyed.clear();
+        self.keyed.extend( keys.into_iter().flatten().zip( positions ).map(| (cell, (e, p)) | (cell, e, p)) );
+
+        // count per cell, then prefix sum into start offsets
+        self.cell_start.clear();
+        self.cell_start.resize( cell_count + 1, 0 );
+        for (cell, _, _) in self.keyed.iter() {
+            self.cell_start[ cell + 1 ] += 1;
+        }
+        for i in 1..self.cell_start.len() {
+            self.cell_start[ i ] += self.cell_start[ i - 1 ];
+        }
+
+        let mut fill = self.cell_start.clone();
+        self.entries.clear();
+        self.entries.resize( self.keyed.len(), (Entity::PLACEHOLDER, Vec3A::ZERO) );
+        for (cell, e, p) in self.keyed.iter() {
+            self.entries[ fill[ *cell ] ] = (*e, *p);
+            fill[ *cell ] += 1;
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// all particles within `radius` of `position` (grid coordinates)
+    #[cfg(test)]
+    pub fn neighbors_within(&self, position: Vec3A, radius: f32) -> impl Iterator<Item = Entity> + '_ {
+        let (lo, hi) = if self.entries.is_empty() {
+            (UVec3::ONE, UVec3::ZERO)  // empty range
+        } else {
+            (self.cell_of( position - radius ), self.cell_of( position + radius ))
+        };
+        let radius_sq = radius * radius;
+        (lo.z..=hi.z).flat_map( move | z | (lo.y..=hi.y).flat_map( move | y | (lo.x..=hi.x).map( move | x | UVec3::new( x, y, z ) ) ) )
+            .flat_map( move | cell | {
+                let idx = self.index_of( cell );
+                self.entries[ self.cell_start[ idx ]..self.cell_start[ idx + 1 ] ].iter()
+            })
+            .filter( move | (_, p) | p.distance_squared( position ) <= radius_sq )
+            .map(| (e, _) | *e)
+    }
+}
+
+
+/// rebuild after particle_boundary_enforcement, before the consumers of the hash
+#[allow(dead_code)]
+pub fn rebuild_spatial_hash(
+    grid: Res<Grid>,
+    mut hash: ResMut<ParticleSpatialHash>,
+    particles: Query<(Entity, &resources::FluidParticlePosition), (With<resources::ParticleTag>, Without<GridCellType>)>,
+) {
+    hash.rebuild( *grid.grid_size(), particles.iter().map(| (e, pos) | (e, pos.0)) );
+}
+
+
+
+#[cfg(test)]
+mod test
+{
+    use super::*;
+
+    #[test]
+    fn test_neighbors_within() {
+        let mut world = World::new();
+        let positions = [
+            Vec3A::new( 5.5, 5.5, 5.5 ),   // 0: query center
+            Vec3A::new( 5.9, 5.1, 5.5 ),   // 1: same cell
+            Vec3A::new( 6.6, 5.5, 5.5 ),   // 2: neighbor cell, within radius
+            Vec3A::new( 4.6, 4.8, 5.5 ),   // 3: diagonal neighbor cell, within radius
+            Vec3A::new( 7.0, 5.5, 5.5 ),   // 4: neighbor cell, just outside radius
+            Vec3A::new( 15.5, 2.5, 1.5 ),  // 5: far away
+        ];
+        let entities: Vec<Entity> = positions.iter().map(|_| world.spawn_empty().id()).collect();
+
+        let mut hash = ParticleSpatialHash::default();
+        hash.rebuild( UVec3::new( 20, 10, 10 ), entities.iter().copied().zip( positions ) );
+        assert_eq!(hash.len(), 6);
+
+        let mut found: Vec<Entity> = hash.neighbors_within( positions[0], 1.45 ).collect();
+        found.sort();
+        let mut expected = vec![ entities[0], entities[1], entities[2], entities[3] ];
+        expected.sort();
+        assert_eq!(found, expected);
+
+        // rebuilding reuses the buffers and replaces the content
+        hash.rebuild( UVec3::new( 20, 10, 10 ), entities.iter().copied().zip( positions ).skip( 5 ) );
+        assert_eq!(hash.neighbors_within( positions[0], 1.45 ).count(), 0);
+        assert_eq!(hash.neighbors_within( positions[5], 0.1 ).collect::<Vec<_>>(), vec![ entities[5] ]);
+    }
+}
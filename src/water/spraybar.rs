@@ -17,6 +17,7 @@
 use bevy::math::Vec3;
 use rand::{
     Rng,
+    SeedableRng,
     rngs::StdRng,
 };
 
@@ -30,13 +31,17 @@ pub struct SprayBar {
 }
 
 impl SprayBar {
-    pub fn new( center: Vec3, extent: Vec3 ) -> Self {
+    /// the positions repeat for the same seed; None seeds from entropy
+    pub fn new( center: Vec3, extent: Vec3, seed: Option<u64> ) -> Self {
         Self {
             center,
             extent,
             precalc: vec![ Vec3::ZERO; 1 ],
             precalc_count: 1,
-            rng: rand::SeedableRng::from_entropy(),
+            rng: match seed {
+                Some( seed ) => StdRng::seed_from_u64( seed ),
+                None => StdRng::from_entropy(),
+            },
         }
     }
 
@@ -70,3 +75,28 @@ impl SprayBar {
     }
 
 }
+
+
+
+#[cfg(test)]
+mod test
+{
+    use super::*;
+
+    #[test]
+    fn test_seeded_spraybar() {
+        let center = Vec3::new( 10., 70., 57. );
+        let extent = Vec3::new( 5., 5., 10. );
+        let mut first = SprayBar::new( center, extent, Some( 42 ) );
+        let mut second = SprayBar::new( center, extent, Some( 42 ) );
+        let mut other = SprayBar::new( center, extent, Some( 43 ) );
+
+        let mut differs = false;
+        for _ in 0..100 {
+            let position = first.new_position();
+            assert_eq!( position.to_array().map( f32::to_bits ), second.new_position().to_array().map( f32::to_bits ) );
+            differs |= position != other.new_position();
+        }
+        assert!( differs );
+    }
+}
@@ -16,17 +16,29 @@
 
 use bevy::{
     prelude::*,
+    pbr::{MaterialPipeline, MaterialPipelineKey},
     render::{
-        render_resource::AsBindGroup,
-        mesh::VertexAttributeValues,
+        render_resource::{
+            AsBindGroup,
+            RenderPipelineDescriptor,
+            ShaderRef,
+            SpecializedMeshPipelineError,
+        },
+        mesh::{MeshVertexBufferLayoutRef, VertexAttributeValues},
     },
     reflect::TypePath,
 };
 
 use crate::{
-    water::grid::*,
+    water::{
+        grid::*,
+        screen_space::FluidDepthCamera,
+    },
     tech::tank,
-    aqs_utils::mesh_of_squares::MeshOfSquares,
+    aqs_utils::{
+        mesh_of_squares::MeshOfSquares,
+        constants::{Constants, SurfaceColorConf, SurfaceColorMode, SurfaceFlowConf, SurfaceLodBand},
+    },
 };
 
 
@@ -39,13 +51,32 @@ pub struct WaveGridCellTag(pub Handle<Mesh>);
 #[derive(Component)]
 pub struct WaveGridFrameTag;
 
+/// entity name of the surface frame; has to differ from the particle frame name
+pub const WAVEGRID_FRAME_NAME: &str = "WaveGrid_Frame";
+
+/// runtime state of the surface flow arrow overlay
+#[derive(Resource)]
+pub struct SurfaceFlowOverlay {
+    pub enabled: bool,
+}
+
+impl FromWorld for SurfaceFlowOverlay {
+    fn from_world( world: &mut World ) -> Self {
+        SurfaceFlowOverlay {
+            enabled: world.resource::<Constants>().SURFACE_FLOW.enabled,
+        }
+    }
+}
+
+/// fragment shader of the water surface
+pub const SURFACE_SHADER: &str = "shaders/custom_material.wgsl";
+
 /// The Material trait is very configurable, but comes with sensible defaults for all methods.
 /// You only need to implement functions for features that need non-default behavior. See the Material api docs for details!
 impl Material for CustomMaterial {
-    // fn fragment_shader() -> ShaderRef {
-    //     // "shaders/surface_vertex_shader.wgsl".into()
-    //     "shaders/custom_material.wgsl".into()
-    // }
+    fn fragment_shader() -> ShaderRef {
+        SURFACE_SHADER.into()
+    }
 
     // fn vertex_shader() -> ShaderRef {
     //     "shaders/surface_vertex_shader.wgsl".into()
@@ -54,24 +85,42 @@ impl Material for CustomMaterial {
     fn alpha_mode(&self) -> AlphaMode {
         self.alpha_mode
     }
+
+    // the surface stays visible from below
+    fn specialize(
+        _pipeline: &MaterialPipeline<Self>,
+        descriptor: &mut RenderPipelineDescriptor,
+        _layout: &MeshVertexBufferLayoutRef,
+        _key: MaterialPipelineKey<Self>,
+    ) -> Result<(), SpecializedMeshPipelineError> {
+        descriptor.primitive.cull_mode = None;
+        Ok(())
+    }
 }
 
 // This is the struct that will be passed to your shader
+//   the vertex colors of update_surface tint the refracted image; color is the fallback without them
 #[derive(AsBindGroup, TypePath, Debug, Clone, Asset)]
 pub struct CustomMaterial {
     #[uniform(0)]
     color: LinearRgba,
+    /// x: refraction strength (screen space offset per unit of horizontal normal)
+    #[uniform(1)]
+    params: Vec4,
+    /// image behind the surface, sampled at the (shifted) screen location
     #[texture(2)]
     #[sampler(3)]
-    color_texture: Option<Handle<Image>>,
+    refraction_texture: Option<Handle<Image>>,
     alpha_mode: AlphaMode,
 }
 
 pub fn init_water_surface_system(
     grid: Res<Grid>,
     tank_cfg: Res<tank::Tank>,
+    constants: Res<Constants>,
+    asset_server: Res<AssetServer>,
     mut meshes: ResMut<Assets<Mesh>>,
-    mut materials: ResMut<Assets<StandardMaterial>>,
+    mut materials: ResMut<Assets<CustomMaterial>>,
     mut commands: Commands,
 ) {
     let offset = Vec3::Y * 2.0 * 2.0;
@@ -79,30 +128,27 @@ pub fn init_water_surface_system(
     // create a non-visible parent frame for offsetting and proper scaling of the surface
     let wavegrid_frame = commands
         .spawn((
-            Name::new("Particle_Frame"),
+            Name::new(WAVEGRID_FRAME_NAME),
             WaveGridFrameTag,
-            Transform::from_translation( -offset )
+            Transform::from_translation( grid.get_origin() - offset )
                 .with_scale( tank_cfg.get_size() / (grid.grid_size().as_vec3()*2.-3.) ),
             Visibility::default(),
         ))
         .id();
 
-    let sgrid_size = UVec2{x: grid.grid_size().x, y: grid.grid_size().z} * 2 - 2;
+    let sgrid_size = surface_mesh_size( &grid );
     let sgrid_scale = Vec2::splat( 1.0 );
     let sgrid_uv_scale = Vec2::new(1. / sgrid_size.x as f32, 1. / sgrid_size.y as f32);
     // let sgrid_uv_scale = Vec2::splat(1.0);
     let surface_mesh = MeshOfSquares::new( sgrid_size, sgrid_scale, sgrid_uv_scale).into_mesh();
     let smesh_hdl = meshes.add(surface_mesh);
 
-    let mt_hdl = materials.add(StandardMaterial {
-        // color: Color::rgba(0.0, 0.0, 0.2, 0.5),
-        // color_texture: Some(asset_server.load("textures/wgenerated.png")),
+    let refraction = &constants.SURFACE_REFRACTION;
+    let mt_hdl = materials.add(CustomMaterial {
+        color: LinearRgba::new(0.0, 0.0, 0.8, 0.7),
+        params: Vec4::new(refraction.strength, 0.0, 0.0, 0.0),
+        refraction_texture: Some(asset_server.load(&refraction.texture)),
         alpha_mode: AlphaMode::Blend,
-        reflectance: 1.0,
-        metallic: 0.4,
-        double_sided: true,
-        // height: 0.0,
-        ..default()
     });
 
     let surface_plane = commands
@@ -117,8 +163,120 @@ pub fn init_water_surface_system(
 }
 
 
+/// vertices of the surface mesh along x and z (two per grid cell without the walls)
+pub fn surface_mesh_size(grid: &Grid) -> UVec2 {
+    UVec2{x: grid.grid_size().x, y: grid.grid_size().z} * 2 - 2
+}
+
+/// unit normals of a row-major height field mesh with size.x vertices per row
+///   central differences of the neighbor heights (one-sided at the edges)
+pub fn height_field_normals(positions: &[[f32; 3]], size: UVec2) -> Vec<[f32; 3]> {
+    let (width, depth) = (size.x as usize, size.y as usize);
+    let at = | x: usize, z: usize | Vec3::from_array( positions[ z * width + x ] );
+    let slope = | a: Vec3, b: Vec3, axis: fn(Vec3) -> f32 | {
+        let run = axis( b ) - axis( a );
+        if run.abs() > f32::EPSILON { (b.y - a.y) / run } else { 0.0 }
+    };
+    (0..depth).flat_map(| z | (0..width).map(move | x | (x, z)))
+        .map(| (x, z) | {
+            let dx = slope( at( x.saturating_sub( 1 ), z ), at( (x + 1).min( width - 1 ), z ), | v | v.x );
+            let dz = slope( at( x, z.saturating_sub( 1 ) ), at( x, (z + 1).min( depth - 1 ) ), | v | v.z );
+            Vec3::new( -dx, 1.0, -dz ).normalize().to_array()
+        })
+        .collect()
+}
+
+
+/// map the surface height and horizontal velocity of a vertex to its color
+pub fn surface_color(conf: &SurfaceColorConf, height: f32, horizontal: Vec2) -> [f32; 4] {
+    let gradient = | value: f32 | {
+        let t = (value / conf.range).clamp(0.0, 1.0);
+        let c = conf.deep.lerp(conf.shallow, t);
+        [ c.x, c.y, c.z, conf.alpha ]
+    };
+    match conf.mode {
+        SurfaceColorMode::VerticalVelocity => [ conf.tint.x, height, conf.tint.z, conf.alpha ],
+        SurfaceColorMode::Height => gradient( height * 0.5 + conf.range * 0.5 ),
+        SurfaceColorMode::Speed => gradient( horizontal.length() ),
+    }
+}
+
+
+/// collect velocity and mass of all grid cells into vectors indexed by the cell index
+fn gather_cell_data<'a>(
+    cells: impl ExactSizeIterator<Item = (&'a FluidQuantityMass, &'a FluidParticleVelocity, &'a GridCellIndex)>
+) -> (Vec<Vec3>, Vec<f32>) {
+    let mut cell_velo = vec![ Vec3::ZERO; cells.len() ];
+    let mut cell_mass = vec![ 0.0; cells.len() ];
+    cells.for_each(
+        | ( mass, vel, idx ) | {
+            cell_velo[ idx.0 ] = vel.0.into();
+            cell_mass[ idx.0 ] = mass.0;
+        }
+    );
+    (cell_velo, cell_mass)
+}
+
+/// arrow anchors (in grid coordinates) and horizontal velocities on a coarse grid at the surface level
+pub fn surface_flow_arrows(grid: &Grid, velo: &[Vec3], spacing: u32) -> Vec<(Vec3, Vec3)> {
+    let spacing = spacing.max( 1 ) as usize;
+    let size = grid.grid_size();
+    let level = grid.get_surface_level();
+    let mut arrows = vec![];
+    for z in (1..size.z as usize - 1).step_by( spacing ) {
+        for x in (1..size.x as usize - 1).step_by( spacing ) {
+            let v = velo[ grid.index_of( x, level as usize, z ) ];
+            arrows.push( (Vec3::new( x as f32 + 0.5, level, z as f32 + 0.5 ), Vec3::new( v.x, 0.0, v.z )) );
+        }
+    }
+    arrows
+}
+
+pub fn toggle_surface_flow(
+    keys: Res<ButtonInput<KeyCode>>,
+    mut overlay: ResMut<SurfaceFlowOverlay>,
+) {
+    if keys.just_pressed( KeyCode::KeyF ) {
+        overlay.enabled = !overlay.enabled;
+    }
+}
+
+pub fn draw_surface_flow(
+    constants: Res<Constants>,
+    grid: Res<Grid>,
+    overlay: Res<SurfaceFlowOverlay>,
+    cells: Query<(&FluidQuantityMass, &FluidParticleVelocity, &GridCellIndex), With<GridCellType>>,
+    mut gizmos: Gizmos,
+) {
+    if !overlay.enabled {
+        return;
+    }
+    let conf: &SurfaceFlowConf = &constants.SURFACE_FLOW;
+    let (cell_velo, _) = gather_cell_data( cells.iter() );
+    for (anchor, velocity) in surface_flow_arrows( &grid, &cell_velo, conf.spacing ) {
+        let start = grid.to_world_coord( anchor );
+        let end = start + velocity * conf.scale * grid.get_scale();
+        let color = Color::srgb( 1.0, 1.0 - (velocity.length() * conf.scale).clamp( 0.0, 1.0 ), 0.0 );
+        gizmos.arrow( start, end, color );
+    }
+}
+
+
+/// number of frames between surface updates for a camera at the given distance
+///   the band with the largest distance below the camera distance applies; closer views update every frame
+pub fn surface_update_interval(bands: &[SurfaceLodBand], distance: f32) -> u32 {
+    bands.iter()
+        .filter(| band | band.distance <= distance)
+        .max_by(| a, b | a.distance.total_cmp( &b.distance ))
+        .map_or( 1, | band | band.interval.max( 1 ) )
+}
+
+
 pub fn update_surface(
+    constants: Res<Constants>,
     grid: Res<Grid>,
+    mut frame: Local<u32>,
+    camera: Query<&GlobalTransform, (With<Camera3d>, Without<FluidDepthCamera>)>,
     cells: Query<(Entity, &FluidQuantityMass, &FluidParticleVelocity, &GridCellIndex), With<GridCellType>>,
     mut meshes: ResMut<Assets<Mesh>>,
     mesh_handles: Query<&WaveGridCellTag>,
@@ -146,15 +304,18 @@ pub fn update_surface(
         [ x, 0.75*avg_velocity.y, z, avg_velocity.x*0.2, avg_velocity.z*0.2 ]
     }
 
-    let mut cell_velo = vec![ Vec3::ZERO; cells.iter().len() ];
-    let mut cell_mass = vec![ 0.0; cells.iter().len() ];
-    cells.iter().for_each(
-        | ( _, mass, vel, idx ) | {
-            cell_velo[ idx.0 ] = vel.0.into();
-            cell_mass[ idx.0 ] = mass.0;
+    // level of detail: far away views skip frames
+    *frame = frame.wrapping_add( 1 );
+    if let Ok( camera ) = camera.get_single() {
+        let center = grid.to_world_coord( grid.grid_size().as_vec3() * 0.5 );
+        let interval = surface_update_interval( &constants.SURFACE_LOD, camera.translation().distance( center ) );
+        if *frame % interval != 0 {
+            return;
         }
-    );
-    
+    }
+
+    let (cell_velo, cell_mass) = gather_cell_data( cells.iter().map(| (_, mass, vel, idx) | (mass, vel, idx)) );
+
     surface_frames.par_iter_mut().for_each( | mut transform | {
         transform.translation.y = grid.to_world_coord( Vec3::splat(grid.get_surface_level()) ).y;
     });
@@ -179,19 +340,10 @@ pub fn update_surface(
                 .collect();
             let colors: Vec<[f32; 4]> = updates
                 .iter()
-                .map(|[_,g,_,_,_]| [
-                    0.0, //*g,
-                    *g,
-                    0.8,
-                    0.7 ])
-                .collect();
-            let normals: Vec<[f32; 3]> = updates
-                    .iter()
-                .map(|[_,_,_,x,z]| [
-                    *x,
-                    1.0,
-                    *z ])
+                .map(|[_,g,_,x,z]| surface_color(&constants.SURFACE_COLOR, *g, Vec2::new(*x, *z)))
                 .collect();
+            // second pass once all heights are known
+            let normals = height_field_normals( &heights, surface_mesh_size( &grid ) );
             mesh.insert_attribute(Mesh::ATTRIBUTE_POSITION, heights);
             mesh.insert_attribute(Mesh::ATTRIBUTE_COLOR, colors);
             mesh.insert_attribute(Mesh::ATTRIBUTE_NORMAL, normals);
@@ -205,6 +357,59 @@ pub fn update_surface(
 mod test {
     use super::*;
 
+    #[test]
+    fn test_surface_lod() {
+        let bands = [
+            SurfaceLodBand { distance: 600.0, interval: 4 },
+            SurfaceLodBand { distance: 300.0, interval: 2 },
+        ];
+        assert_eq!( surface_update_interval( &[], 1000.0 ), 1 );
+        assert_eq!( surface_update_interval( &bands, 100.0 ), 1 );
+        assert_eq!( surface_update_interval( &bands, 300.0 ), 2 );
+        assert_eq!( surface_update_interval( &bands, 450.0 ), 2 );
+        assert_eq!( surface_update_interval( &bands, 5000.0 ), 4 );
+        assert_eq!( surface_update_interval( &[ SurfaceLodBand { distance: 0.0, interval: 0 } ], 10.0 ), 1 );
+    }
+
+    #[test]
+    fn test_height_field_normals() {
+        // rises by 0.5 per unit along x, flat along z
+        let size = UVec2::new(5, 4);
+        let positions: Vec<[f32; 3]> = (0..size.y)
+            .flat_map(|z| (0..size.x).map(move |x| [x as f32 * 2.0, x as f32, z as f32]))
+            .collect();
+        let normals = height_field_normals(&positions, size);
+
+        assert_eq!(normals.len(), positions.len());
+        let expected = Vec3::new(-0.5, 1.0, 0.0).normalize();
+        for normal in normals.iter().map(|n| Vec3::from_array(*n)) {
+            assert!((normal.length() - 1.0).abs() < 1e-5);
+            assert!(normal.abs_diff_eq(expected, 1e-5), "{} != {}", normal, expected);
+        }
+
+        // a flat surface points straight up
+        let flat: Vec<[f32; 3]> = positions.iter().map(|[x, _, z]| [*x, 0.3, *z]).collect();
+        assert!(height_field_normals(&flat, size).iter().all(|n| *n == [0.0, 1.0, 0.0]));
+    }
+
+    #[test]
+    fn test_material_bindings() {
+        // compile check: the derive covers the uniforms and the texture/sampler pair
+        fn material_bind_group<M: Material + AsBindGroup>() {}
+        material_bind_group::<CustomMaterial>();
+
+        assert!(matches!(CustomMaterial::fragment_shader(),
+                         ShaderRef::Path(path) if path.path() == std::path::Path::new(SURFACE_SHADER)));
+
+        // the shader declares exactly the bindings of the struct attributes
+        let shader = std::fs::read_to_string(format!("assets/{}", SURFACE_SHADER)).unwrap();
+        let bindings: Vec<u32> = shader.lines()
+            .filter_map(|line| line.trim().strip_prefix("@group(2) @binding("))
+            .filter_map(|rest| rest.split(')').next()?.parse().ok())
+            .collect();
+        assert_eq!(bindings, vec![0, 1, 2, 3]);
+    }
+
     #[test]
     fn test_surface_indices() {
         let size = UVec2::new(5, 4);
@@ -214,5 +419,67 @@ mod test {
         let meshy = MeshOfSquares::new(size, loc_scale, uv_scale).into_mesh();
         dbg!(meshy.clone());
         assert!(meshy.primitive_topology().is_strip());
+        assert_eq!(meshy.indices().unwrap().len(), crate::aqs_utils::mesh_of_squares::strip_index_count(size));
+    }
+
+    #[test]
+    fn test_surface_color_modes() {
+        let mut conf = SurfaceColorConf::default();
+        // default keeps the original mapping
+        assert_eq!(surface_color(&conf, 0.3, Vec2::ZERO), [0.0, 0.3, 0.8, 0.7]);
+
+        let vertical = surface_color(&conf, 0.3, Vec2::new(0.5, 0.0));
+        conf.mode = SurfaceColorMode::Height;
+        let height = surface_color(&conf, 0.3, Vec2::new(0.5, 0.0));
+        conf.mode = SurfaceColorMode::Speed;
+        let speed = surface_color(&conf, 0.3, Vec2::new(0.5, 0.0));
+        assert_ne!(vertical, height);
+        assert_ne!(height, speed);
+
+        // gradient saturates at the configured range
+        assert_eq!(surface_color(&conf, 0.0, Vec2::new(5.0, 0.0))[0], conf.shallow.x);
+    }
+
+    #[test]
+    fn test_frame_names_and_tags() {
+        use crate::water::{resources::ParticleFrameTag, fluid::PARTICLE_FRAME_NAME};
+
+        assert_ne!(WAVEGRID_FRAME_NAME, PARTICLE_FRAME_NAME);
+
+        let mut world = World::new();
+        let particle_frame = world.spawn((Name::new(PARTICLE_FRAME_NAME), ParticleFrameTag)).id();
+        let wave_frame = world.spawn((Name::new(WAVEGRID_FRAME_NAME), WaveGridFrameTag)).id();
+
+        let mut particle_frames = world.query_filtered::<Entity, (With<ParticleFrameTag>, Without<WaveGridFrameTag>)>();
+        assert_eq!(particle_frames.single(&world), particle_frame);
+        let mut wave_frames = world.query_filtered::<Entity, With<WaveGridFrameTag>>();
+        assert_eq!(wave_frames.single(&world), wave_frame);
+
+        let mut name_query = world.query::<&Name>();
+        let mut names: Vec<&str> = name_query.iter(&world).map(|n| n.as_str()).collect();
+        let total = names.len();
+        names.sort();
+        names.dedup();
+        assert_eq!(names.len(), total);
+    }
+
+    #[test]
+    fn test_surface_flow_arrows() {
+        let grid = Grid::new( UVec3::new( 20, 12, 16 ), 1.0 );
+        let level = grid.get_surface_level() as usize;
+        let mut velo = vec![ Vec3::ZERO; grid.cell_count() ];
+        velo[ grid.index_of( 5, level, 5 ) ] = Vec3::new( 2.0, 1.0, -1.0 );
+
+        let arrows = surface_flow_arrows( &grid, &velo, 4 );
+        assert_eq!( arrows.len(), 5 * 4 );
+        assert!( arrows.iter().all(|(anchor, _)| anchor.y == grid.get_surface_level()) );
+
+        // only the agitated cell has a (horizontal) arrow
+        let moving: Vec<_> = arrows.iter().filter(|(_, v)| v.length() > 0.0).collect();
+        assert_eq!( moving.len(), 1 );
+        assert_eq!( moving[0].1, Vec3::new( 2.0, 0.0, -1.0 ) );
+
+        // denser spacing gives more arrows
+        assert!( surface_flow_arrows( &grid, &velo, 2 ).len() > arrows.len() );
     }
 }
@@ -0,0 +1,155 @@
+/*
+    Copyright 2023 github.com/programaquatic
+
+   Licensed under the Apache License, Version 2.0 (the "License");
+   you may not use this file except in compliance with the License.
+   You may obtain a copy of the License at
+
+       http://www.apache.org/licenses/LICENSE-2.0
+
+   Unless required by applicable law or agreed to in writing, software
+   distributed under the License is distributed on an "AS IS" BASIS,
+   WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+   See the License for the specific language governing permissions and
+   limitations under the License.
+*/
+
+use bevy::{
+    prelude::*,
+    render::{
+        mesh::VertexAttributeValues,
+        render_asset::RenderAssetUsages,
+        render_resource::{Extent3d, TextureDimension, TextureFormat},
+    },
+};
+
+use std::{
+    fs,
+    path::Path,
+};
+
+use crate::{
+    aqs_utils::constants::{
+        Constants,
+        SurfaceExportConf,
+        SurfaceExportFormat,
+    },
+    water::surface::WaveGridCellTag,
+};
+
+
+/// heights of the surface mesh vertices as a row-major 2D array (x along the rows, z across)
+///   vertices are placed by their mesh position, so the result is in grid order regardless of the vertex order
+pub fn height_field(positions: &[[f32; 3]], size: UVec2) -> Vec<f32> {
+    let mut field = vec![ 0.0; (size.x * size.y) as usize ];
+    positions.iter().for_each(| [x, y, z] | {
+        let (col, row) = (x.round() as u32, z.round() as u32);
+        if col < size.x && row < size.y {
+            field[ (row * size.x + col) as usize ] = *y;
+        }
+    });
+    field
+}
+
+/// extent of the surface mesh in vertices (the largest position + 1 per axis)
+pub fn field_size(positions: &[[f32; 3]]) -> UVec2 {
+    positions.iter().fold( UVec2::ZERO, | size, [x, _, z] | {
+        size.max( UVec2::new( x.round() as u32 + 1, z.round() as u32 + 1 ) )
+    })
+}
+
+pub fn to_csv(field: &[f32], size: UVec2) -> String {
+    field.chunks( size.x.max( 1 ) as usize )
+        .map(| row | row.iter().map(| h | format!("{}", h)).collect::<Vec<_>>().join(","))
+        .collect::<Vec<_>>()
+        .join("\n") + "\n"
+}
+
+/// heights in [-range, range] mapped to [0, 255]; a fixed range keeps the frames of a sequence comparable
+pub fn to_grayscale(field: &[f32], range: f32) -> Vec<u8> {
+    field.iter()
+        .map(| h | ((h / range.max( f32::EPSILON ) * 0.5 + 0.5).clamp( 0.0, 1.0 ) * 255.0).round() as u8)
+        .collect()
+}
+
+fn write_frame(conf: &SurfaceExportConf, frame: usize, field: &[f32], size: UVec2) -> Result<(), String> {
+    let dir = Path::new( &conf.directory );
+    fs::create_dir_all( dir ).map_err(| e | e.to_string())?;
+    match conf.format {
+        SurfaceExportFormat::Csv => {
+            let path = dir.join( format!("surface_{:06}.csv", frame) );
+            fs::write( &path, to_csv( field, size ) ).map_err(| e | format!("{}: {}", path.display(), e))
+        },
+        SurfaceExportFormat::Png => {
+            let path = dir.join( format!("surface_{:06}.png", frame) );
+            let image = Image::new(
+                Extent3d { width: size.x, height: size.y, depth_or_array_layers: 1 },
+                TextureDimension::D2,
+                to_grayscale( field, conf.range ),
+                TextureFormat::R8Unorm,
+                RenderAssetUsages::default(),
+            );
+            image.try_into_dynamic()
+                .map_err(| e | e.to_string())?
+                .save( &path )
+                .map_err(| e | format!("{}: {}", path.display(), e))
+        },
+    }
+}
+
+
+/// dump the surface height field every `interval` frames (after update_surface moved the vertices)
+///   an IO error stops the export instead of repeating the warning every frame
+pub fn export_surface_heights(
+    constants: Res<Constants>,
+    meshes: Res<Assets<Mesh>>,
+    mesh_handles: Query<&WaveGridCellTag>,
+    mut frame: Local<usize>,
+    mut failed: Local<bool>,
+) {
+    let Some( conf ) = &constants.SURFACE_EXPORT else {
+        return;
+    };
+    *frame += 1;
+    if *failed || *frame % conf.interval.max( 1 ) as usize != 0 {
+        return;
+    }
+    let Some( mesh ) = mesh_handles.get_single().ok().and_then(| hdl | meshes.get( &hdl.0 )) else {
+        return;
+    };
+    if let Some( VertexAttributeValues::Float32x3( positions ) ) = mesh.attribute( Mesh::ATTRIBUTE_POSITION ) {
+        let size = field_size( positions );
+        if let Err( e ) = write_frame( conf, *frame, &height_field( positions, size ), size ) {
+            warn!("surface export stopped: {}", e);
+            *failed = true;
+        }
+    }
+}
+
+
+
+#[cfg(test)]
+mod test
+{
+    use super::*;
+
+    #[test]
+    fn test_height_field_in_grid_order() {
+        // serpentine vertex order like the triangle strip of the surface mesh
+        let positions = [
+            [0.0, 0.1, 0.0], [1.0, 0.2, 0.0], [2.0, 0.3, 0.0],
+            [2.0, 0.6, 1.0], [1.0, 0.5, 1.0], [0.0, 0.4, 1.0],
+        ];
+        let size = field_size( &positions );
+        assert_eq!( size, UVec2::new( 3, 2 ) );
+
+        let field = height_field( &positions, size );
+        assert_eq!( field, vec![ 0.1, 0.2, 0.3, 0.4, 0.5, 0.6 ] );
+        assert_eq!( to_csv( &field, size ), "0.1,0.2,0.3\n0.4,0.5,0.6\n" );
+    }
+
+    #[test]
+    fn test_grayscale() {
+        assert_eq!( to_grayscale( &[ -2.0, -1.0, 0.0, 1.0, 2.0 ], 1.0 ), vec![ 0, 0, 128, 255, 255 ] );
+    }
+}
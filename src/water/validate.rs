@@ -0,0 +1,437 @@
+/*
+    Copyright 2023 github.com/programaquatic
+
+   Licensed under the Apache License, Version 2.0 (the "License");
+   you may not use this file except in compliance with the License.
+   You may obtain a copy of the License at
+
+       http://www.apache.org/licenses/LICENSE-2.0
+
+   Unless required by applicable law or agreed to in writing, software
+   distributed under the License is distributed on an "AS IS" BASIS,
+   WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+   See the License for the specific language governing permissions and
+   limitations under the License.
+*/
+
+use std::{
+    collections::VecDeque,
+    path::PathBuf,
+    time::{Duration, Instant},
+};
+
+use bevy::{
+    prelude::*,
+    app::ScheduleRunnerPlugin,
+    ecs::schedule::ExecutorKind,
+    render::{
+        RenderPlugin,
+        settings::WgpuSettings,
+    },
+    window::ExitCondition,
+    winit::WinitPlugin,
+};
+use bevy_rapier3d::plugin::*;
+use serde::Serialize;
+
+use crate::{
+    aqs_utils::constants::Constants,
+    tech::{
+        self,
+        templates::SelectedTemplate,
+    },
+    decoration,
+    water::{
+        diagnostics::FluidDiagnostics,
+        fluid,
+        grid::{self, GridCellType},
+        mlsmpm,
+        resources,
+        setup::FluidSimSet,
+        sim_time,
+    },
+};
+
+pub const DEFAULT_VALIDATION_STEPS: usize = 100;
+
+/// the run counts as settled if the kinetic energy changed less than SETTLED_TOLERANCE over the last SETTLED_WINDOW steps
+const SETTLED_WINDOW: usize = 50;
+const SETTLED_TOLERANCE: f32 = 0.05;
+
+
+/// Tracks the stability of a headless validation run
+#[derive(Resource, Default)]
+pub struct ValidationState {
+    max_steps: usize,
+    step: usize,
+    peak_ke: f32,
+    /// step and reason of the first detected instability
+    failure: Option<(usize, String)>,
+    /// kinetic energy of the last SETTLED_WINDOW steps
+    recent_ke: VecDeque<f32>,
+    has_nan: bool,
+    /// wall-clock time of the first step (the setup doesn't count)
+    started: Option<Instant>,
+    /// where the summary report goes at the end of the run
+    report: Option<PathBuf>,
+}
+
+
+/// Machine-readable summary of a headless run (see --report)
+#[derive(Serialize, Debug)]
+pub struct RunReport {
+    pub steps: usize,
+    pub wall_clock_secs: f64,
+    pub steps_per_sec: f64,
+    pub peak_ke: f32,
+    pub final_ke: f32,
+    pub peak_cfl: f32,
+    pub particles: usize,
+    pub nan_detected: bool,
+    pub escaped_particles: usize,
+    pub settled: bool,
+    /// reason of the first instability
+    pub failure: Option<String>,
+}
+
+impl ValidationState {
+    pub fn new(max_steps: usize) -> Self {
+        ValidationState {
+            max_steps,
+            ..default()
+        }
+    }
+
+    pub fn with_report(mut self, report: Option<PathBuf>) -> Self {
+        self.report = report;
+        self
+    }
+
+    /// record the state of one step and return whether the sim is still considered stable
+    ///   the sim is unstable if values turn non-finite or particles on average move faster than one cell per step
+    pub fn record(&mut self, kinetic_energy: f32, has_nan: bool, ke_limit: f32) -> bool {
+        self.step += 1;
+        self.started.get_or_insert_with( Instant::now );
+        self.has_nan |= has_nan;
+        self.recent_ke.push_back( kinetic_energy );
+        if self.recent_ke.len() > SETTLED_WINDOW {
+            self.recent_ke.pop_front();
+        }
+        if self.failure.is_some() {
+            return false;
+        }
+        if has_nan || !kinetic_energy.is_finite() {
+            self.failure = Some((self.step, "NaN in particle state".to_string()));
+        } else if kinetic_energy > ke_limit {
+            self.failure = Some((self.step, format!("kinetic energy {} exceeds limit {}", kinetic_energy, ke_limit)));
+        }
+        self.peak_ke = self.peak_ke.max(kinetic_energy);
+        self.failure.is_none()
+    }
+
+    pub fn is_done(&self) -> bool {
+        self.failure.is_some() || self.step >= self.max_steps
+    }
+
+    pub fn summary(&self) -> String {
+        match &self.failure {
+            None => format!("PASS: {} steps, peak KE: {}", self.step, self.peak_ke),
+            Some((step, reason)) => format!("FAIL: diverged at step {}: {}; peak KE: {}", step, reason, self.peak_ke),
+        }
+    }
+
+    /// the kinetic energy stayed within SETTLED_TOLERANCE over a full window
+    pub fn is_settled(&self) -> bool {
+        if self.failure.is_some() || self.recent_ke.len() < SETTLED_WINDOW {
+            return false;
+        }
+        let (min, max) = self.recent_ke.iter().fold( (f32::MAX, f32::MIN), | (min, max), &ke | (min.min( ke ), max.max( ke )) );
+        max - min <= SETTLED_TOLERANCE * max.max( f32::EPSILON )
+    }
+
+    pub fn report(&self, diagnostics: &FluidDiagnostics, particles: usize) -> RunReport {
+        let wall_clock_secs = self.started.map_or( 0.0, | started | started.elapsed().as_secs_f64() );
+        RunReport {
+            steps: self.step,
+            wall_clock_secs,
+            steps_per_sec: if wall_clock_secs > 0.0 { self.step as f64 / wall_clock_secs } else { 0.0 },
+            peak_ke: self.peak_ke,
+            final_ke: self.recent_ke.back().copied().unwrap_or( 0.0 ),
+            peak_cfl: diagnostics.peak_cfl,
+            particles,
+            nan_detected: self.has_nan,
+            escaped_particles: diagnostics.escaped_particles,
+            settled: self.is_settled(),
+            failure: self.failure.as_ref().map(| (step, reason) | format!("step {}: {}", step, reason)),
+        }
+    }
+}
+
+
+/// Accumulated wall-clock time of the solver stages of a --bench run
+#[derive(Resource, Default, Debug)]
+pub struct StageTimings {
+    started: Option<Instant>,
+    /// stage name, total time and number of runs in the order of the first run
+    totals: Vec<(&'static str, Duration, u32)>,
+}
+
+impl StageTimings {
+    pub fn begin(&mut self) {
+        self.started = Some( Instant::now() );
+    }
+
+    pub fn end(&mut self, stage: &'static str) {
+        if let Some( started ) = self.started.take() {
+            self.add( stage, started.elapsed() );
+        }
+    }
+
+    pub fn add(&mut self, stage: &'static str, elapsed: Duration) {
+        match self.totals.iter_mut().find(| (name, _, _) | *name == stage) {
+            Some( (_, total, count) ) => {
+                *total += elapsed;
+                *count += 1;
+            },
+            None => self.totals.push( (stage, elapsed, 1) ),
+        }
+    }
+
+    /// average time per run of each stage in ms
+    pub fn averages(&self) -> Vec<(&'static str, f64)> {
+        self.totals.iter()
+            .map(| (name, total, count) | (*name, total.as_secs_f64() * 1000.0 / (*count).max( 1 ) as f64))
+            .collect()
+    }
+
+    pub fn summary(&self) -> String {
+        self.averages().iter()
+            .map(| (name, ms) | format!("  {:<18} {:>9.3} ms", name, ms))
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+}
+
+fn stage_begin(mut timings: ResMut<StageTimings>) {
+    timings.begin();
+}
+
+fn stage_end(stage: &'static str) -> impl FnMut(ResMut<StageTimings>) {
+    move | mut timings | timings.end( stage )
+}
+
+/// time the main solver stages; FixedUpdate runs single-threaded so that no other system runs between the markers
+///   (systems without an order to a stage can still end up in between, so the numbers are an upper bound)
+fn add_stage_timers(app: &mut App) {
+    app.edit_schedule(FixedUpdate, | schedule | {
+        schedule.set_executor_kind( ExecutorKind::SingleThreaded );
+    });
+    app
+        .init_resource::<StageTimings>()
+        .add_systems(FixedUpdate, (
+            stage_begin
+                .after(sim_time::update_sim_time)
+                .after(grid::reset_fluid_grid_cells)
+                .before(mlsmpm::p2g_stage1),
+            (stage_end("p2g_stage1"), stage_begin).chain()
+                .after(mlsmpm::p2g_stage1)
+                .before(mlsmpm::p2g_apply_stage1),
+            (stage_end("p2g_apply_stage1"), stage_begin).chain()
+                .after(mlsmpm::p2g_apply_stage1)
+                .before(mlsmpm::p2g_stage2),
+            (stage_end("p2g_stage2"), stage_begin).chain()
+                .after(mlsmpm::p2g_stage2)
+                .after(grid::wall_to_active_momentum)
+                .before(mlsmpm::grid_update),
+            stage_end("grid_update").after(mlsmpm::grid_update).before(grid::update_grid_cells),
+            stage_begin.after(grid::update_grid_cells).before(fluid::grid_to_particle),
+            stage_end("grid_to_particle").after(fluid::grid_to_particle),
+        ).in_set(FluidSimSet));
+}
+
+
+fn validate_step(
+    constants: Res<Constants>,
+    diagnostics: Res<FluidDiagnostics>,
+    timings: Option<Res<StageTimings>>,
+    mut state: ResMut<ValidationState>,
+    particles: Query<(&resources::FluidParticlePosition,
+                      &resources::FluidParticleVelocity,
+                      &resources::FluidQuantityMass), (With<resources::ParticleTag>, Without<GridCellType>)>,
+    mut exit: EventWriter<AppExit>,
+) {
+    let mut kinetic_energy = 0.0;
+    let mut has_nan = false;
+    particles.iter().for_each(| (pos, vel, mass) | {
+        has_nan |= !pos.0.is_finite() || !vel.0.is_finite();
+        kinetic_energy += 0.5 * mass.0 * vel.0.length_squared();
+    });
+
+    // a particle moving one grid cell per step is the upper bound for a stable run
+    let max_speed = 1.0 / constants.WORLD_DT;
+    let ke_limit = 0.5 * constants.DEFAULT_PARTICLE_MASS * max_speed * max_speed * particles.iter().len() as f32;
+    state.record(kinetic_energy, has_nan, ke_limit);
+
+    if state.is_done() {
+        println!("{}", state.summary());
+        if let Some( timings ) = timings {
+            println!("average stage times:\n{}", timings.summary());
+        }
+        if let Some( path ) = &state.report {
+            let report = state.report( &diagnostics, particles.iter().len() );
+            match serde_json::to_string_pretty( &report ).map_err( std::io::Error::from )
+                .and_then(| json | std::fs::write( path, json )) {
+                Ok( _ ) => info!("wrote run report to {}", path.display()),
+                Err( e ) => warn!("unable to write run report to {}: {}", path.display(), e),
+            }
+        }
+        exit.send(if state.failure.is_none() { AppExit::Success } else { AppExit::error() });
+    }
+}
+
+
+/// the simulation without window and GPU; `profile` adds the per-stage timings
+///   DefaultPlugins without a wgpu backend still provide Assets<Mesh> and the materials,
+///   so the setup spawns its (never drawn) meshes the same way as the windowed app
+pub fn headless_app(steps: usize, template: Option<SelectedTemplate>, seed: Option<resources::SimSeed>,
+                    report: Option<PathBuf>, profile: bool) -> App {
+    let mut app = App::new();
+    if let Some( template ) = template {
+        app.insert_resource(template);
+    }
+    if let Some( seed ) = seed {
+        app.insert_resource(seed);
+    }
+    app
+        .add_plugins(DefaultPlugins
+                     .set(RenderPlugin {
+                         render_creation: WgpuSettings {
+                             backends: None,
+                             ..default()
+                         }.into(),
+                         ..default()
+                     })
+                     .set(WindowPlugin {
+                         primary_window: None,
+                         exit_condition: ExitCondition::DontExit,
+                         close_when_requested: false,
+                     })
+                     .disable::<WinitPlugin>())
+        .add_plugins(ScheduleRunnerPlugin::run_loop(Duration::ZERO))
+        .add_plugins(RapierPhysicsPlugin::<NoUserData>::default())
+        .add_plugins(tech::tank::TankPlugin)
+        .add_plugins(decoration::decoplugin::DecorationPlugin)
+        .add_plugins(fluid::FluidPlugin)
+        .insert_resource(ValidationState::new(steps).with_report(report))
+        .add_systems(FixedUpdate,
+                     validate_step
+                         .after(fluid::particle_boundary_enforcement)
+                         .in_set(FluidSimSet));
+    if profile {
+        add_stage_timers( &mut app );
+    }
+    app
+}
+
+/// run the fluid simulation headless for `steps` updates and report whether it stayed stable
+///   with a report path, a JSON summary of the run gets written at the end; `profile` prints the stage timings
+pub fn run(steps: usize, template: Option<SelectedTemplate>, seed: Option<resources::SimSeed>,
+           report: Option<PathBuf>, profile: bool) -> AppExit {
+    headless_app( steps, template, seed, report, profile ).run()
+}
+
+
+
+#[cfg(test)]
+mod test
+{
+    use super::*;
+
+    #[test]
+    fn test_validation_state() {
+        let mut state = ValidationState::new(3);
+        assert!(state.record(1.0, false, 10.0));
+        assert!(state.record(5.0, false, 10.0));
+        assert!(!state.is_done());
+        assert!(!state.record(50.0, false, 10.0));
+        assert!(state.is_done());
+        assert!(state.summary().starts_with("FAIL: diverged at step 3"));
+
+        let mut state = ValidationState::new(2);
+        assert!(!state.record(f32::NAN, false, 10.0));
+        assert!(state.summary().starts_with("FAIL: diverged at step 1"));
+    }
+
+    #[test]
+    fn test_run_report() {
+        let mut state = ValidationState::new(2 * SETTLED_WINDOW);
+        for step in 0..2 * SETTLED_WINDOW {
+            // decaying slosh that comes to rest
+            state.record(if step < SETTLED_WINDOW { 10.0 - step as f32 * 0.1 } else { 5.0 }, false, 100.0);
+        }
+        assert!(state.is_done());
+        assert!(state.is_settled());
+
+        let diagnostics = FluidDiagnostics { peak_cfl: 0.3, escaped_particles: 2, ..default() };
+        let report = serde_json::to_value(state.report(&diagnostics, 1234)).unwrap();
+        for field in ["steps", "wall_clock_secs", "steps_per_sec", "peak_ke", "final_ke", "peak_cfl",
+                      "particles", "nan_detected", "escaped_particles", "settled", "failure"] {
+            assert!(report.get(field).is_some(), "missing {}", field);
+        }
+        assert_eq!(report["steps"], 2 * SETTLED_WINDOW);
+        assert_eq!(report["peak_ke"], 10.0);
+        assert_eq!(report["final_ke"], 5.0);
+        assert_eq!(report["escaped_particles"], 2);
+        assert_eq!(report["settled"], true);
+        assert!(report["failure"].is_null());
+    }
+
+    #[test]
+    fn test_stage_timings() {
+        let mut timings = StageTimings::default();
+        timings.add("p2g_stage1", Duration::from_millis(2));
+        timings.add("grid_update", Duration::from_millis(1));
+        timings.add("p2g_stage1", Duration::from_millis(4));
+        // an end without begin doesn't count
+        timings.end("grid_update");
+        assert_eq!(timings.averages(), vec![("p2g_stage1", 3.0), ("grid_update", 1.0)]);
+    }
+
+    #[test]
+    fn test_headless_app_steps() {
+        use bevy::time::TimeUpdateStrategy;
+        use crate::water::setup::SetupState;
+
+        let template = SelectedTemplate(tech::templates::TankTemplate::Nano20);
+        let mut app = headless_app(2, Some(template), Some(resources::SimSeed(1)), None, true);
+        // every update advances by more than one fixed step
+        let world_dt = app.world().resource::<Constants>().WORLD_DT;
+        app.insert_resource(TimeUpdateStrategy::ManualDuration(Duration::from_secs_f32(world_dt * 1.5)));
+        app.finish();
+        app.cleanup();
+
+        let running = | app: &App | *app.world().resource::<State<SetupState>>().get() == SetupState::Running;
+        for _ in 0..1000 {
+            if running(&app) {
+                break;
+            }
+            app.update();
+        }
+        assert!(running(&app), "setup didn't finish");
+
+        for _ in 0..10 {
+            if app.world().resource::<ValidationState>().step > 0 {
+                break;
+            }
+            app.update();
+        }
+        let state = app.world().resource::<ValidationState>();
+        assert!(state.step > 0);
+        assert!(state.failure.is_none(), "{}", state.summary());
+
+        let averages = app.world().resource::<StageTimings>().averages();
+        let stages: Vec<&str> = averages.iter().map(| (name, _) | *name).collect();
+        assert_eq!(stages, vec!["p2g_stage1", "p2g_apply_stage1", "p2g_stage2", "grid_update", "grid_to_particle"]);
+        assert!(averages.iter().all(| (_, ms) | *ms > 0.0), "{:?}", averages);
+    }
+}
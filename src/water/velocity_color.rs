@@ -0,0 +1,99 @@
+/*
+    Copyright 2023 github.com/programaquatic
+
+   Licensed under the Apache License, Version 2.0 (the "License");
+   you may not use this file except in compliance with the License.
+   You may obtain a copy of the License at
+
+       http://www.apache.org/licenses/LICENSE-2.0
+
+   Unless required by applicable law or agreed to in writing, software
+   distributed under the License is distributed on an "AS IS" BASIS,
+   WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+   See the License for the specific language governing permissions and
+   limitations under the License.
+*/
+
+
+use bevy::prelude::*;
+
+use crate::{
+    aqs_utils::constants::Constants,
+    water::{
+        debug_scalar::normalize_scalar,
+        resources::{
+            FluidParticleVelocity,
+            ParticleTag,
+        },
+    },
+};
+
+/// number of pre-created materials the speeds get quantized to
+pub const VELOCITY_PALETTE_SIZE: usize = 16;
+
+
+/// palette bucket of a speed within range (min, max); speeds outside the range go to the first/last bucket
+pub fn speed_bucket(speed: f32, range: Vec2, buckets: usize) -> usize {
+    let t = normalize_scalar( speed, range.x, range.y );
+    ((t * buckets as f32) as usize).min( buckets - 1 )
+}
+
+/// blue -> green -> red gradient at t in [0, 1]
+pub fn speed_color(t: f32) -> Color {
+    let t = t.clamp( 0.0, 1.0 );
+    let c = if t < 0.5 {
+        Vec3::new( 0.0, 0.0, 1.0 ).lerp( Vec3::new( 0.0, 1.0, 0.0 ), t * 2.0 )
+    } else {
+        Vec3::new( 0.0, 1.0, 0.0 ).lerp( Vec3::new( 1.0, 0.0, 0.0 ), t * 2.0 - 1.0 )
+    };
+    Color::linear_rgba( c.x, c.y, c.z, 0.8 )
+}
+
+
+/// color the visible particles by their speed (if COLOR_VEL_RANGE is set)
+pub fn velocity_colormap(
+    constants: Res<Constants>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    mut palette: Local<Vec<Handle<StandardMaterial>>>,
+    mut particles: Query<(&FluidParticleVelocity, &mut MeshMaterial3d<StandardMaterial>), With<ParticleTag>>,
+) {
+    let Some( range ) = constants.COLOR_VEL_RANGE else {
+        return;
+    };
+    if palette.is_empty() {
+        for bucket in 0..VELOCITY_PALETTE_SIZE {
+            let t = (bucket as f32 + 0.5) / VELOCITY_PALETTE_SIZE as f32;
+            palette.push( materials.add( StandardMaterial::from( speed_color( t ) ) ) );
+        }
+    }
+    particles.par_iter_mut().for_each(| (velocity, mut material) | {
+        let handle = &palette[ speed_bucket( velocity.0.length(), range, VELOCITY_PALETTE_SIZE ) ];
+        if material.0 != *handle {
+            material.0 = handle.clone();
+        }
+    });
+}
+
+
+
+#[cfg(test)]
+mod test
+{
+    use super::*;
+
+    #[test]
+    fn test_speed_bucket() {
+        let range = Vec2::new( 1.0, 5.0 );
+        assert_eq!( speed_bucket( 0.0, range, 16 ), 0 );
+        assert_eq!( speed_bucket( 1.0, range, 16 ), 0 );
+        assert_eq!( speed_bucket( 1.3, range, 16 ), 1 );
+        assert_eq!( speed_bucket( 3.0, range, 16 ), 8 );
+        assert_eq!( speed_bucket( 5.0, range, 16 ), 15 );
+        assert_eq!( speed_bucket( 50.0, range, 16 ), 15 );
+
+        // slow is blue, fast is red
+        assert_eq!( speed_color( 0.0 ).to_linear().blue, 1.0 );
+        assert_eq!( speed_color( 0.5 ).to_linear().green, 1.0 );
+        assert_eq!( speed_color( 1.0 ).to_linear().red, 1.0 );
+    }
+}